@@ -0,0 +1,76 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use stract::config::CrossEncoderConfig;
+use stract::ranking::models::cross_encoder::{CrossEncoder, CrossEncoderModel};
+
+const MODEL_PATH: &str = "../../data/cross_encoder";
+const QUERY: &str = "how many people live in paris";
+
+fn snippets(n: usize) -> Vec<String> {
+    (0..n)
+        .map(|i| {
+            format!(
+                "there are currently {i} people living in paris according to the latest census \
+                 which was conducted by the national statistics office of france in the year {}",
+                2000 + i
+            )
+        })
+        .collect()
+}
+
+fn open(quantize: bool, cache_size: usize) -> CrossEncoderModel {
+    CrossEncoderModel::open_with_config(
+        MODEL_PATH,
+        &CrossEncoderConfig {
+            quantize,
+            cache_size,
+            ..Default::default()
+        },
+    )
+    .expect("failed to open cross encoder")
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let bodies = snippets(20);
+
+    let model = open(false, 0);
+    c.bench_function("cross encoder f16 (20 snippets)", |b| {
+        b.iter(|| model.run(QUERY, &bodies))
+    });
+
+    let model = open(true, 0);
+    c.bench_function("cross encoder int8 (20 snippets)", |b| {
+        b.iter(|| model.run(QUERY, &bodies))
+    });
+
+    let model = open(true, 1_000);
+    c.bench_function("cross encoder int8 cached (20 snippets)", |b| {
+        b.iter(|| model.run(QUERY, &bodies))
+    });
+
+    let model = std::sync::Arc::new(open(true, 0));
+    c.bench_function(
+        "cross encoder int8 4 concurrent requests (20 snippets)",
+        |b| {
+            b.iter(|| {
+                let handles: Vec<_> = (0..4)
+                    .map(|i| {
+                        let model = model.clone();
+                        let bodies = bodies.clone();
+                        std::thread::spawn(move || model.run(&format!("{QUERY} {i}"), &bodies))
+                    })
+                    .collect();
+
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            })
+        },
+    );
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = criterion_benchmark
+}
+criterion_main!(benches);
@@ -28,6 +28,9 @@ use utoipa_swagger_ui::SwaggerUi;
             search::spellcheck,
             webgraph::host::similar,
             webgraph::host::knows,
+            webgraph::host::community,
+            webgraph::host::path,
+            webgraph::host::neighbourhood,
             webgraph::host::ingoing_hosts,
             webgraph::host::outgoing_hosts,
             webgraph::page::ingoing_pages,
@@ -84,6 +87,10 @@ use utoipa_swagger_ui::SwaggerUi;
                 webgraph::host::SimilarHostsParams,
                 webgraph::KnowsHost,
                 crate::entrypoint::webgraph_server::ScoredHost,
+                crate::entrypoint::webgraph_server::Community,
+                crate::entrypoint::webgraph_server::Neighbourhood,
+                crate::entrypoint::webgraph_server::NeighbourhoodNode,
+                crate::entrypoint::webgraph_server::LinksPage,
 
                 autosuggest::Suggestion,
 
@@ -92,6 +99,9 @@ use utoipa_swagger_ui::SwaggerUi;
 
                 crate::webgraph::Node,
                 crate::webgraph::FullEdge,
+                crate::webgraph::EdgeLabel,
+                crate::webpage::RelFlags,
+                crate::webpage::LinkPosition,
             ),
         ),
         modifiers(&ApiModifier),
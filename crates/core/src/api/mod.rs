@@ -31,7 +31,7 @@ use crate::{
     },
     improvement::{store_improvements_loop, ImprovementEvent},
     leaky_queue::LeakyQueue,
-    ranking::models::lambdamart::LambdaMART,
+    ranking::{models::lambdamart::LambdaMART, signal_provider::configure_custom_signals},
     searcher::{api::ApiSearcher, live::LiveSearcher, DistributedSearcher},
 };
 
@@ -91,6 +91,8 @@ pub async fn favicon() -> impl IntoResponse {
 }
 
 pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
+    configure_custom_signals(&config.custom_signals)?;
+
     let autosuggest = Autosuggest::load_csv(&config.queries_csv_path)?;
 
     let lambda_model = match &config.lambda_model_path {
@@ -126,7 +128,10 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
         let mut cross_encoder = None;
 
         if let Some(path) = config.crossencoder_model_path.as_ref() {
-            cross_encoder = Some(CrossEncoderModel::open(path)?);
+            cross_encoder = Some(CrossEncoderModel::open_with_config(
+                path,
+                &config.crossencoder,
+            )?);
         }
 
         let searcher = ApiSearcher::new(
@@ -182,6 +187,15 @@ pub async fn router(config: &ApiConfig, counters: Counters) -> Result<Router> {
                 .route("/api/summarize", get(summarize::summarize_route))
                 .route("/api/webgraph/host/similar", post(webgraph::host::similar))
                 .route("/api/webgraph/host/knows", post(webgraph::host::knows))
+                .route(
+                    "/api/webgraph/host/community",
+                    post(webgraph::host::community),
+                )
+                .route("/api/webgraph/host/path", post(webgraph::host::path))
+                .route(
+                    "/api/webgraph/host/neighbourhood",
+                    post(webgraph::host::neighbourhood),
+                )
                 .route(
                     "/api/webgraph/host/ingoing",
                     post(webgraph::host::ingoing_hosts),
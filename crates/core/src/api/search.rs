@@ -53,6 +53,12 @@ pub struct ApiSearchQuery {
 
     #[serde(default = "defaults::SearchQuery::count_results")]
     pub count_results: bool,
+
+    pub signal_coefficient_profile: Option<String>,
+
+    /// Diversify the results using maximal marginal relevance. A lambda of 1.0
+    /// ranks purely by relevance while lower values favour diverse results.
+    pub diversity: Option<f64>,
 }
 
 impl TryFrom<ApiSearchQuery> for SearchQuery {
@@ -77,6 +83,8 @@ impl TryFrom<ApiSearchQuery> for SearchQuery {
             return_ranking_signals: api.return_ranking_signals,
             safe_search: api.safe_search.unwrap_or(default.safe_search),
             count_results: api.count_results,
+            signal_coefficient_profile: api.signal_coefficient_profile,
+            diversity: api.diversity,
         })
     }
 }
@@ -14,16 +14,32 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{extract, response::IntoResponse, Json};
 use http::StatusCode;
+use itertools::Itertools;
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    config::WebgraphGranularity,
-    distributed::{cluster::Cluster, member::Service, retry_strategy::ExponentialBackoff, sonic},
-    webgraph::{FullEdge, Node},
+    config::{defaults, WebgraphGranularity},
+    distributed::{
+        cluster::Cluster,
+        member::Service,
+        retry_strategy::ExponentialBackoff,
+        sonic::{
+            self,
+            replication::{
+                AllShardsSelector, RandomReplicaSelector, RemoteClient, ReplicatedClient, Shard,
+                ShardSelector, ShardedClient,
+            },
+        },
+    },
+    entrypoint::webgraph_server::{
+        Community, HostCommunity, HostNeighbourhood, HostPath, IngoingLinks, IngoingLinksByHost,
+        LinkCursor, LinksPage, Neighbourhood, OutgoingLinks, WebGraphService,
+    },
+    webgraph::{shard::WebgraphShard, EdgeFilter, FullEdge, Node, NodeID},
 };
 
 use super::State;
@@ -43,10 +59,224 @@ impl RemoteWebgraph {
             .await
             .iter()
             .find_map(|member| match member.service {
-                Service::Webgraph { host, granularity } if granularity == level => Some(host),
+                Service::Webgraph {
+                    host, granularity, ..
+                } if granularity == level => Some(host),
                 _ => None,
             })
     }
+
+    /// A client for all the shards of the graph. Fails unless every shard `0..num_shards`
+    /// has a registered replica, since a missing shard would silently give partial results.
+    async fn client(
+        &self,
+        level: WebgraphGranularity,
+    ) -> anyhow::Result<ShardedClient<WebGraphService, WebgraphShard>> {
+        let mut shards = HashMap::new();
+        for member in self.cluster.members().await {
+            if let Service::Webgraph {
+                host,
+                granularity,
+                shard,
+            } = member.service
+            {
+                if granularity == level {
+                    shards.entry(shard).or_insert_with(Vec::new).push(host);
+                }
+            }
+        }
+
+        let num_shards = match shards.keys().map(|shard| shard.num_shards).dedup().at_most_one() {
+            Ok(Some(num_shards)) => num_shards,
+            Ok(None) => anyhow::bail!("no remote webgraph for granularity {level:?}"),
+            Err(_) => anyhow::bail!(
+                "the shards of the remote webgraph for granularity {level:?} disagree on the number of shards"
+            ),
+        };
+
+        if let Some(missing) = (0..num_shards).find(|id| {
+            !shards.contains_key(&WebgraphShard {
+                id: *id,
+                num_shards,
+            })
+        }) {
+            anyhow::bail!(
+                "shard {missing} of {num_shards} of the remote webgraph for granularity {level:?} is missing"
+            );
+        }
+
+        let mut shard_clients = Vec::new();
+
+        for (id, replicas) in shards {
+            let replicated =
+                ReplicatedClient::new(replicas.into_iter().map(RemoteClient::new).collect());
+            shard_clients.push(Shard::new(id, replicated));
+        }
+
+        Ok(ShardedClient::new(shard_clients))
+    }
+
+    /// Send the request to a random replica of each of the shards picked by the selector.
+    async fn send<Req, Sel>(
+        &self,
+        level: WebgraphGranularity,
+        req: &Req,
+        selector: &Sel,
+    ) -> anyhow::Result<Vec<Req::Response>>
+    where
+        Req: sonic::service::Wrapper<WebGraphService>,
+        Sel: ShardSelector<WebGraphService, WebgraphShard>,
+    {
+        let client = self.client(level).await?;
+
+        Ok(client
+            .send(req, selector, &RandomReplicaSelector)
+            .await?
+            .into_iter()
+            .flat_map(|(_, res)| res)
+            .collect())
+    }
+
+    /// Send the request to a server of the host graph. The connection is retried a few times.
+    async fn send_to_host_graph<Req>(&self, req: &Req) -> anyhow::Result<Req::Response>
+    where
+        Req: sonic::service::Wrapper<WebGraphService>,
+    {
+        let host = self
+            .host(WebgraphGranularity::Host)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no remote webgraph for granularity Host"))?;
+
+        let retry = ExponentialBackoff::from_millis(30)
+            .with_limit(Duration::from_millis(200))
+            .take(5);
+
+        let conn = sonic::service::ResilientConnection::create_with_timeout(
+            host,
+            Duration::from_secs(30),
+            retry,
+        )
+        .await?;
+
+        Ok(conn.send_with_timeout(req, Duration::from_secs(60)).await?)
+    }
+
+    /// The community of the host and the other hosts in it.
+    pub async fn community(&self, host: String) -> anyhow::Result<Option<Community>> {
+        self.send_to_host_graph(&HostCommunity { host }).await
+    }
+
+    /// The hosts on the shortest link path between the two hosts.
+    pub async fn path(&self, from: String, to: String) -> anyhow::Result<Vec<Node>> {
+        self.send_to_host_graph(&HostPath { from, to }).await
+    }
+
+    /// The hosts within `max_distance` links from the host and the links between them.
+    pub async fn neighbourhood(
+        &self,
+        host: String,
+        max_distance: u8,
+    ) -> anyhow::Result<Neighbourhood> {
+        self.send_to_host_graph(&HostNeighbourhood { host, max_distance })
+            .await
+    }
+
+    /// The ingoing links of the node. The links can come from any host, so they are
+    /// collected from all the shards of the graph.
+    pub async fn ingoing_links(
+        &self,
+        level: WebgraphGranularity,
+        node: Node,
+        page: LinksPageParams,
+    ) -> anyhow::Result<LinksPage> {
+        let limit = page.limit;
+        let res = self
+            .send(
+                level,
+                &IngoingLinks {
+                    node,
+                    filter: page.filter,
+                    cursor: page.cursor,
+                    limit: page.limit,
+                },
+                &AllShardsSelector,
+            )
+            .await?;
+
+        Ok(LinksPage::merge(res, limit))
+    }
+
+    /// The outgoing links of the node, which are all stored in the shard of its host.
+    pub async fn outgoing_links(
+        &self,
+        level: WebgraphGranularity,
+        node: Node,
+        page: LinksPageParams,
+    ) -> anyhow::Result<LinksPage> {
+        let limit = page.limit;
+        let selector = HostShardSelector(node.clone().into_host().id());
+        let res = self
+            .send(
+                level,
+                &OutgoingLinks {
+                    node,
+                    filter: page.filter,
+                    cursor: page.cursor,
+                    limit: page.limit,
+                },
+                &selector,
+            )
+            .await?;
+
+        Ok(LinksPage::merge(res, limit))
+    }
+
+    /// The ingoing links of all the pages on the host, collected from all the shards of the page graph.
+    pub async fn ingoing_edges_by_host(
+        &self,
+        host: Node,
+        limit: usize,
+    ) -> anyhow::Result<Vec<FullEdge>> {
+        let res = self
+            .send(
+                WebgraphGranularity::Page,
+                &IngoingLinksByHost { host, limit },
+                &AllShardsSelector,
+            )
+            .await?;
+
+        // every shard returns its first edges in the order used by the shards, so the first
+        // edges of the merged result are all among them
+        let mut edges: Vec<_> = res
+            .into_iter()
+            .flatten()
+            .map(|edge| ((edge.from.id(), edge.to.id()), edge))
+            .collect();
+        edges.sort_by_key(|(key, _)| *key);
+        edges.dedup_by_key(|(key, _)| *key);
+        edges.truncate(limit);
+
+        Ok(edges.into_iter().map(|(_, edge)| edge).collect())
+    }
+}
+
+/// Routes the requests for a host to the shard of the webgraph that stores
+/// the outgoing edges of the host.
+pub struct HostShardSelector(pub NodeID);
+
+impl<S> ShardSelector<S, WebgraphShard> for HostShardSelector
+where
+    S: sonic::service::Service,
+{
+    fn select<'a>(
+        &self,
+        shards: &'a [Shard<S, WebgraphShard>],
+    ) -> Vec<&'a Shard<S, WebgraphShard>> {
+        shards
+            .iter()
+            .filter(|shard| shard.id().contains(&self.0))
+            .collect()
+    }
 }
 
 pub mod host {
@@ -67,10 +297,59 @@ pub mod host {
         pub host: String,
     }
 
+    #[derive(serde::Deserialize, IntoParams)]
+    #[serde(rename_all = "camelCase")]
+    pub struct HostCommunityParams {
+        pub host: String,
+    }
+
+    #[derive(serde::Deserialize, IntoParams)]
+    #[serde(rename_all = "camelCase")]
+    pub struct HostPathParams {
+        pub from: String,
+        pub to: String,
+    }
+
+    #[derive(serde::Deserialize, IntoParams)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NeighbourhoodParams {
+        pub host: String,
+        #[serde(default = "defaults::WebgraphServer::neighbourhood_max_distance")]
+        pub max_distance: u8,
+    }
+
     #[derive(serde::Deserialize, IntoParams)]
     #[serde(rename_all = "camelCase")]
     pub struct HostLinksParams {
         pub host: String,
+        pub cursor: Option<String>,
+        #[serde(default = "defaults::WebgraphServer::links_limit")]
+        pub limit: usize,
+        /// Only return links where the anchor text contains this text.
+        pub text: Option<String>,
+        /// Skip links between hosts on the same domain.
+        #[serde(default)]
+        pub exclude_same_domain: bool,
+    }
+
+    impl HostLinksParams {
+        fn links_page(&self) -> std::result::Result<LinksPageParams, StatusCode> {
+            let cursor = self
+                .cursor
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+            Ok(LinksPageParams {
+                filter: EdgeFilter {
+                    text: self.text.clone(),
+                    exclude_same_domain: self.exclude_same_domain,
+                },
+                cursor,
+                limit: self.limit,
+            })
+        }
     }
 
     #[utoipa::path(post,
@@ -166,11 +445,80 @@ pub mod host {
         }
     }
 
+    #[utoipa::path(post,
+        path = "/beta/api/webgraph/host/community",
+        params(HostCommunityParams),
+        responses(
+            (status = 200, description = "The community of the host and the other hosts in it", body = Option<Community>),
+        )
+    )]
+    pub async fn community(
+        extract::State(state): extract::State<Arc<State>>,
+        extract::Query(params): extract::Query<HostCommunityParams>,
+    ) -> std::result::Result<impl IntoResponse, StatusCode> {
+        let res = state
+            .remote_webgraph
+            .community(params.host)
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to send request to webgraph: {}", err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        Ok(Json(res))
+    }
+
+    #[utoipa::path(post,
+        path = "/beta/api/webgraph/host/path",
+        params(HostPathParams),
+        responses(
+            (status = 200, description = "Hosts on the shortest link path between two hosts. Empty if there is no path", body = Vec<Node>),
+        )
+    )]
+    pub async fn path(
+        extract::State(state): extract::State<Arc<State>>,
+        extract::Query(params): extract::Query<HostPathParams>,
+    ) -> std::result::Result<impl IntoResponse, StatusCode> {
+        let res = state
+            .remote_webgraph
+            .path(params.from, params.to)
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to send request to webgraph: {}", err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        Ok(Json(res))
+    }
+
+    #[utoipa::path(post,
+        path = "/beta/api/webgraph/host/neighbourhood",
+        params(NeighbourhoodParams),
+        responses(
+            (status = 200, description = "Hosts within a number of links from a host and the links between them", body = Neighbourhood),
+        )
+    )]
+    pub async fn neighbourhood(
+        extract::State(state): extract::State<Arc<State>>,
+        extract::Query(params): extract::Query<NeighbourhoodParams>,
+    ) -> std::result::Result<impl IntoResponse, StatusCode> {
+        let res = state
+            .remote_webgraph
+            .neighbourhood(params.host, params.max_distance)
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to send request to webgraph: {}", err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        Ok(Json(res))
+    }
+
     #[utoipa::path(post,
         path = "/beta/api/webgraph/host/ingoing",
         params(HostLinksParams),
         responses(
-            (status = 200, description = "Incoming links for a particular host", body = Vec<FullEdge>),
+            (status = 200, description = "A page of the incoming links for a particular host. Breaking change: this used to be a list of edges, which are now in `links`", body = LinksPage),
         )
     )]
     pub async fn ingoing_hosts(
@@ -180,7 +528,10 @@ pub mod host {
         let url = Url::parse(&("http://".to_string() + params.host.as_str()))
             .map_err(|_| StatusCode::BAD_REQUEST)?;
         let node = Node::from(url).into_host();
-        let links = ingoing_links(state, node, WebgraphGranularity::Host)
+        let page = params.links_page()?;
+        let links = state
+            .remote_webgraph
+            .ingoing_links(WebgraphGranularity::Host, node, page)
             .await
             .map_err(|_| {
                 tracing::error!("Failed to send request to webgraph");
@@ -194,7 +545,7 @@ pub mod host {
         path = "/beta/api/webgraph/host/outgoing",
         params(HostLinksParams),
         responses(
-            (status = 200, description = "Outgoing links for a particular host", body = Vec<FullEdge>),
+            (status = 200, description = "A page of the outgoing links for a particular host. Breaking change: this used to be a list of edges, which are now in `links`", body = LinksPage),
         )
     )]
     pub async fn outgoing_hosts(
@@ -204,7 +555,10 @@ pub mod host {
         let url = Url::parse(&("http://".to_string() + params.host.as_str()))
             .map_err(|_| StatusCode::BAD_REQUEST)?;
         let node = Node::from(url).into_host();
-        let links = outgoing_links(state, node, WebgraphGranularity::Host)
+        let page = params.links_page()?;
+        let links = state
+            .remote_webgraph
+            .outgoing_links(WebgraphGranularity::Host, node, page)
             .await
             .map_err(|_| {
                 tracing::error!("Failed to send request to webgraph");
@@ -222,21 +576,52 @@ pub mod page {
     #[serde(rename_all = "camelCase")]
     pub struct PageLinksParams {
         pub page: String,
+        pub cursor: Option<String>,
+        #[serde(default = "defaults::WebgraphServer::links_limit")]
+        pub limit: usize,
+        /// Only return links where the anchor text contains this text.
+        pub text: Option<String>,
+        /// Skip links between pages on the same domain.
+        #[serde(default)]
+        pub exclude_same_domain: bool,
+    }
+
+    impl PageLinksParams {
+        fn links_page(&self) -> std::result::Result<LinksPageParams, StatusCode> {
+            let cursor = self
+                .cursor
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+            Ok(LinksPageParams {
+                filter: EdgeFilter {
+                    text: self.text.clone(),
+                    exclude_same_domain: self.exclude_same_domain,
+                },
+                cursor,
+                limit: self.limit,
+            })
+        }
     }
 
     #[utoipa::path(post,
         path = "/beta/api/webgraph/page/ingoing",
         params(PageLinksParams),
         responses(
-            (status = 200, description = "Incoming links for a particular page", body = Vec<FullEdge>),
+            (status = 200, description = "A page of the incoming links for a particular page. Breaking change: this used to be a list of edges, which are now in `links`", body = LinksPage),
         )
     )]
     pub async fn ingoing_pages(
         extract::State(state): extract::State<Arc<State>>,
         extract::Query(params): extract::Query<PageLinksParams>,
     ) -> std::result::Result<impl IntoResponse, StatusCode> {
-        let node = Node::from(params.page);
-        let links = ingoing_links(state, node, WebgraphGranularity::Page)
+        let node = Node::from(params.page.clone());
+        let page = params.links_page()?;
+        let links = state
+            .remote_webgraph
+            .ingoing_links(WebgraphGranularity::Page, node, page)
             .await
             .map_err(|_| {
                 tracing::error!("Failed to send request to webgraph");
@@ -250,15 +635,18 @@ pub mod page {
         path = "/beta/api/webgraph/page/outgoing",
         params(PageLinksParams),
         responses(
-            (status = 200, description = "Outgoing links for a particular page", body = Vec<FullEdge>),
+            (status = 200, description = "A page of the outgoing links for a particular page. Breaking change: this used to be a list of edges, which are now in `links`", body = LinksPage),
         )
     )]
     pub async fn outgoing_pages(
         extract::State(state): extract::State<Arc<State>>,
         extract::Query(params): extract::Query<PageLinksParams>,
     ) -> std::result::Result<impl IntoResponse, StatusCode> {
-        let node = Node::from(params.page);
-        let links = outgoing_links(state, node, WebgraphGranularity::Page)
+        let node = Node::from(params.page.clone());
+        let page = params.links_page()?;
+        let links = state
+            .remote_webgraph
+            .outgoing_links(WebgraphGranularity::Page, node, page)
             .await
             .map_err(|_| {
                 tracing::error!("Failed to send request to webgraph");
@@ -269,68 +657,11 @@ pub mod page {
     }
 }
 
-async fn ingoing_links(
-    state: Arc<State>,
-    node: Node,
-    level: WebgraphGranularity,
-) -> anyhow::Result<Vec<FullEdge>> {
-    let host = state
-        .remote_webgraph
-        .host(level)
-        .await
-        .ok_or(anyhow::anyhow!(
-            "no remote webgraph for granularity {level:?}"
-        ))?;
-
-    let retry = ExponentialBackoff::from_millis(30)
-        .with_limit(Duration::from_millis(200))
-        .take(5);
-
-    let conn = sonic::service::ResilientConnection::create_with_timeout(
-        host,
-        Duration::from_secs(30),
-        retry,
-    )
-    .await?;
-
-    Ok(conn
-        .send_with_timeout(
-            &crate::entrypoint::webgraph_server::IngoingLinks { node },
-            Duration::from_secs(60),
-        )
-        .await?)
-}
-
-async fn outgoing_links(
-    state: Arc<State>,
-    node: Node,
-    level: WebgraphGranularity,
-) -> anyhow::Result<Vec<FullEdge>> {
-    let host = state
-        .remote_webgraph
-        .host(level)
-        .await
-        .ok_or(anyhow::anyhow!(
-            "no remote webgraph for granularity {level:?}"
-        ))?;
-
-    let retry = ExponentialBackoff::from_millis(30)
-        .with_limit(Duration::from_millis(200))
-        .take(5);
-
-    let conn = sonic::service::ResilientConnection::create_with_timeout(
-        host,
-        Duration::from_secs(30),
-        retry,
-    )
-    .await?;
-
-    Ok(conn
-        .send_with_timeout(
-            &crate::entrypoint::webgraph_server::OutgoingLinks { node },
-            Duration::from_secs(60),
-        )
-        .await?)
+/// The filter and the page of links to request from the webgraph.
+pub struct LinksPageParams {
+    filter: EdgeFilter,
+    cursor: Option<LinkCursor>,
+    limit: usize,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, ToSchema)]
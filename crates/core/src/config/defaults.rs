@@ -38,6 +38,26 @@ impl Collector {
     }
 }
 
+pub struct CrossEncoder;
+
+impl CrossEncoder {
+    pub fn quantize() -> bool {
+        false
+    }
+
+    pub fn max_batch_size() -> usize {
+        64
+    }
+
+    pub fn batch_timeout_ms() -> u64 {
+        5
+    }
+
+    pub fn cache_size() -> usize {
+        100_000
+    }
+}
+
 pub struct Api;
 
 impl Api {
@@ -116,6 +136,10 @@ impl Crawler {
     pub fn dry_run() -> bool {
         false
     }
+
+    pub fn max_warc_file_bytes() -> usize {
+        100_000_000
+    }
 }
 
 pub struct WebgraphServer;
@@ -124,6 +148,14 @@ impl WebgraphServer {
     pub fn max_similar_hosts() -> usize {
         1_000
     }
+
+    pub fn neighbourhood_max_distance() -> u8 {
+        2
+    }
+
+    pub fn links_limit() -> usize {
+        100
+    }
 }
 
 pub struct SearchQuery;
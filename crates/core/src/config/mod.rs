@@ -19,7 +19,9 @@ pub mod defaults;
 use super::Result;
 use crate::feed::scheduler::SplitId;
 use crate::searcher::ShardId;
+use crate::webgraph::shard::WebgraphShard;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::net::SocketAddr;
@@ -38,12 +40,20 @@ pub struct IndexingLocalConfig {
     pub page_centrality_store_path: Option<String>,
     pub safety_classifier_path: Option<String>,
     pub minimum_clean_words: Option<usize>,
+    pub dual_encoder_model_path: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct WebgraphConstructConfig {
     pub host_graph_base_path: String,
     pub page_graph_base_path: String,
+    /// Split the page graph by the host of the source of each edge into this many shards, so it
+    /// can be served by multiple webgraph servers. Each shard is written to a `shard_<id>` folder.
+    pub page_graph_shards: Option<u64>,
+    /// Update the existing page graph at this path instead of only building a new one. The links
+    /// of every crawled page replace the links the page has in the existing graph. Pages crawled
+    /// more than once by the same update keep the links from all of their crawls.
+    pub page_graph_update_path: Option<String>,
     pub warc_source: WarcSource,
     pub limit_warc_files: Option<usize>,
     pub skip_warc_files: Option<usize>,
@@ -69,7 +79,19 @@ impl WarcSource {
                 }
             }
             WarcSource::Local(config) => {
-                warc_paths = config.names.clone();
+                if config.names.is_empty() {
+                    for entry in std::fs::read_dir(&config.folder)? {
+                        let name = entry?.file_name().to_string_lossy().to_string();
+
+                        if name.ends_with("warc.gz") {
+                            warc_paths.push(name);
+                        }
+                    }
+
+                    warc_paths.sort();
+                } else {
+                    warc_paths = config.names.clone();
+                }
             }
             WarcSource::S3(config) => {
                 let bucket = s3::Bucket::new(
@@ -120,6 +142,8 @@ impl WarcSource {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LocalConfig {
     pub folder: String,
+    /// The warc files to read from the folder. All warc files in the folder are read if empty.
+    #[serde(default)]
     pub names: Vec<String>,
 }
 
@@ -193,6 +217,32 @@ pub struct LLMConfig {
     pub api_key: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CrossEncoderConfig {
+    #[serde(default = "defaults::CrossEncoder::quantize")]
+    pub quantize: bool,
+
+    #[serde(default = "defaults::CrossEncoder::max_batch_size")]
+    pub max_batch_size: usize,
+
+    #[serde(default = "defaults::CrossEncoder::batch_timeout_ms")]
+    pub batch_timeout_ms: u64,
+
+    #[serde(default = "defaults::CrossEncoder::cache_size")]
+    pub cache_size: usize,
+}
+
+impl Default for CrossEncoderConfig {
+    fn default() -> Self {
+        Self {
+            quantize: defaults::CrossEncoder::quantize(),
+            max_batch_size: defaults::CrossEncoder::max_batch_size(),
+            batch_timeout_ms: defaults::CrossEncoder::batch_timeout_ms(),
+            cache_size: defaults::CrossEncoder::cache_size(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ApiConfig {
     pub summarizer_path: String,
@@ -202,6 +252,7 @@ pub struct ApiConfig {
     pub crossencoder_model_path: Option<String>,
     pub lambda_model_path: Option<String>,
     pub spell_checker_path: Option<String>,
+    pub signal_coefficient_profiles_path: Option<String>,
     pub bangs_path: String,
     pub query_store_db_host: Option<String>,
     pub cluster_id: String,
@@ -213,6 +264,9 @@ pub struct ApiConfig {
     #[serde(default)]
     pub collector: CollectorConfig,
 
+    #[serde(default)]
+    pub crossencoder: CrossEncoderConfig,
+
     #[serde(default)]
     pub thresholds: ApiThresholds,
 
@@ -220,6 +274,10 @@ pub struct ApiConfig {
 
     #[serde(default)]
     pub correction_config: CorrectionConfig,
+
+    /// Names of the custom ranking signals. The id of each signal is its position in the list.
+    #[serde(default)]
+    pub custom_signals: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -272,6 +330,8 @@ pub struct SearchServerConfig {
     pub host_centrality_store_path: Option<String>,
     pub linear_model_path: Option<String>,
     pub lambda_model_path: Option<String>,
+    pub dual_encoder_model_path: Option<String>,
+    pub signal_coefficient_profiles_path: Option<String>,
     pub host: SocketAddr,
 
     #[serde(default)]
@@ -279,6 +339,24 @@ pub struct SearchServerConfig {
 
     #[serde(default)]
     pub snippet: SnippetConfig,
+
+    /// Names of the custom ranking signals. The id of each signal is its position in the list.
+    #[serde(default)]
+    pub custom_signals: Vec<String>,
+
+    /// Custom signals whose value is read from a fast field. They are registered at startup.
+    #[serde(default)]
+    pub fast_field_signals: Vec<FastFieldSignalConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FastFieldSignalConfig {
+    /// Name of the signal. It must be listed in `custom_signals`.
+    pub name: String,
+    /// Name of the fast field, e.g. `num_path_and_query_slashes`.
+    pub fast_field: String,
+    #[serde(default)]
+    pub default_coefficient: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -294,6 +372,19 @@ pub struct EntitySearchServerConfig {
 pub struct CrawlCoordinatorConfig {
     pub job_queue: String,
     pub host: SocketAddr,
+
+    /// Path where the crawl traps reported by the workers are stored.
+    pub crawl_traps_path: Option<String>,
+
+    /// Address to serve the http admin api of the coordinator on.
+    pub admin_host: Option<SocketAddr>,
+
+    /// Bearer token required by the endpoints of the admin api that change the crawl.
+    /// Must be set unless the admin api is bound to a loopback address.
+    pub admin_token: Option<String>,
+
+    /// File where the seed jobs injected through the admin api are persisted until they are handed out.
+    pub seeds_path: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -332,8 +423,86 @@ pub struct CrawlerConfig {
     pub dry_run: bool,
 
     pub timeout_seconds: u64,
-    pub s3: S3Config,
+
+    /// Older configs have an untagged `[s3]` table instead of `[output]`.
+    #[serde(alias = "s3", deserialize_with = "deserialize_warc_output")]
+    pub output: WarcOutputConfig,
     pub router_hosts: Vec<String>,
+
+    /// Path to the database with the state of previously crawled urls.
+    /// When set, re-crawls are conditional on the stored `ETag` and `Last-Modified` headers.
+    pub crawl_state_path: Option<String>,
+
+    /// Address to serve the prometheus metrics of the crawler on.
+    pub prometheus_host: Option<SocketAddr>,
+
+    /// Path to the database where the relevance of the crawled hosts to the topic
+    /// of a focused crawl is recorded.
+    pub host_relevance_path: Option<String>,
+
+    /// Replay the crawl from a local WARC corpus instead of fetching pages from the web.
+    pub replay: Option<ReplayConfig>,
+}
+
+/// Topic of a focused crawl.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "type")]
+pub enum TopicConfig {
+    /// Pages are scored by the rules of an optic.
+    Optic { path: String },
+    /// Pages are scored by a classifier trained with `stract crawler train-topic`.
+    /// Pages classified as `label` are on-topic.
+    Classifier { model_path: String, label: String },
+}
+
+/// A WARC corpus that the crawler fetches pages from in replay mode.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplayConfig {
+    pub warc_source: WarcSource,
+
+    /// Simulated latency of every fetch.
+    #[serde(default)]
+    pub latency_ms: u64,
+
+    /// The robots.txt of each host, overriding any robots.txt in the corpus.
+    #[serde(default)]
+    pub robots_txt: HashMap<String, String>,
+}
+
+/// Where the crawler stores the WARC files.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum WarcOutputConfig {
+    S3(S3Config),
+    Local(LocalWarcOutputConfig),
+}
+
+fn deserialize_warc_output<'de, D>(
+    deserializer: D,
+) -> std::result::Result<WarcOutputConfig, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Output {
+        Tagged(WarcOutputConfig),
+        Legacy(S3Config),
+    }
+
+    Ok(match Output::deserialize(deserializer)? {
+        Output::Tagged(output) => output,
+        Output::Legacy(s3) => WarcOutputConfig::S3(s3),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LocalWarcOutputConfig {
+    pub folder: String,
+
+    /// A new (gzipped) warc file is started once the current file exceeds this size.
+    #[serde(default = "defaults::Crawler::max_warc_file_bytes")]
+    pub max_file_bytes: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -363,6 +532,11 @@ pub struct WebgraphServerConfig {
     pub graph_path: String,
     pub granularity: WebgraphGranularity,
     pub inbound_similarity_path: Option<String>,
+    pub community_path: Option<String>,
+    pub host_centrality_path: Option<String>,
+    /// The shard of the graph at `graph_path`. Graphs that are not split into shards are the single shard `0` of `1`.
+    #[serde(default)]
+    pub shard: WebgraphShard,
 
     pub cluster_id: String,
     pub gossip_seed_nodes: Option<Vec<SocketAddr>>,
@@ -394,6 +568,23 @@ pub struct CrawlPlannerConfig {
     pub top_host_fraction: f64,
     pub wander_fraction: f64,
     pub top_n_hosts_surplus: usize,
+
+    /// Paths to the crawl states recorded by each of the crawlers. When set, urls are
+    /// prioritised by their estimated probability of having changed since the last crawl.
+    #[serde(default)]
+    pub crawl_state_paths: Vec<String>,
+
+    /// Path to the crawl traps stored by the coordinators. Urls matching a trap are not scheduled.
+    pub crawl_traps_path: Option<String>,
+
+    /// Paths to the host relevance recorded by each of the crawlers during a focused crawl.
+    /// When set, the budget of each host is scaled by its relevance to the topic.
+    #[serde(default)]
+    pub host_relevance_paths: Vec<String>,
+
+    /// Topic of a focused crawl. It is part of every job in the plan, so the workers
+    /// score the fetched pages against it and prioritise the outlinks of on-topic pages.
+    pub topic: Option<TopicConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -493,3 +684,48 @@ impl Default for CorrectionConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRAWLER: &str = r#"
+        num_worker_threads = 1
+        router_hosts = ["0.0.0.0:8181"]
+        timeout_seconds = 30
+
+        [user_agent]
+        full = "StractBot"
+        token = "StractBot"
+    "#;
+
+    #[test]
+    fn legacy_s3_output() {
+        let legacy = format!(
+            r#"{CRAWLER}
+            [s3]
+            access_key = "access"
+            bucket = "crawl"
+            endpoint = "http://localhost"
+            folder = "test"
+            secret_key = "secret"
+            "#
+        );
+
+        let config: CrawlerConfig = toml::from_str(&legacy).unwrap();
+        assert!(matches!(config.output, WarcOutputConfig::S3(s3) if s3.bucket == "crawl"));
+
+        let tagged = format!(
+            r#"{CRAWLER}
+            [output]
+            type = "Local"
+            folder = "data/warc_files"
+            "#
+        );
+
+        let config: CrawlerConfig = toml::from_str(&tagged).unwrap();
+        assert!(
+            matches!(config.output, WarcOutputConfig::Local(local) if local.folder == "data/warc_files")
+        );
+    }
+}
@@ -0,0 +1,194 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HTTP admin API of the crawl coordinator.
+//!
+//! * `GET /status` - status of the crawl, queues, jobs in flight and workers.
+//! * `GET /domains?limit=N` - progress of the most recently crawled domains.
+//! * `GET /domains/:domain` - progress of a single domain.
+//! * `POST /pause`, `POST /resume` and `POST /drain` - change the status of the crawl.
+//!   Draining is terminal, so pausing or resuming a drained crawl responds with `409 Conflict`.
+//! * `POST /seeds` - add ad-hoc jobs for a list of urls.
+//!
+//! When a token is configured, the `POST` endpoints require an `Authorization: Bearer <token>` header.
+use std::sync::Arc;
+
+use axum::{
+    extract, middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use http::StatusCode;
+use url::Url;
+
+use super::{
+    coordinator::{CoordinatorStatus, CrawlCoordinator, CrawlStatus, DomainProgress},
+    Domain,
+};
+
+const DEFAULT_DOMAINS_LIMIT: usize = 100;
+
+pub fn router(coordinator: Arc<CrawlCoordinator>, token: Option<String>) -> Router {
+    Router::new()
+        .route("/status", get(status))
+        .route("/domains", get(domains))
+        .route("/domains/:domain", get(domain))
+        .merge(
+            Router::new()
+                .route("/pause", post(pause))
+                .route("/resume", post(resume))
+                .route("/drain", post(drain))
+                .route("/seeds", post(seeds))
+                .route_layer(middleware::from_fn_with_state(
+                    token.map(Arc::new),
+                    authorize,
+                )),
+        )
+        .with_state(coordinator)
+}
+
+async fn authorize(
+    extract::State(token): extract::State<Option<Arc<String>>>,
+    request: extract::Request,
+    next: middleware::Next,
+) -> Response {
+    if let Some(token) = token {
+        let authorized = request
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|value| value == token.as_str());
+
+        if !authorized {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+async fn status(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+) -> impl IntoResponse {
+    Json(coordinator.status())
+}
+
+#[derive(serde::Deserialize)]
+struct DomainsParams {
+    limit: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+struct DomainWithProgress {
+    domain: Domain,
+    #[serde(flatten)]
+    progress: DomainProgress,
+}
+
+async fn domains(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+    extract::Query(params): extract::Query<DomainsParams>,
+) -> impl IntoResponse {
+    let domains: Vec<_> = coordinator
+        .recently_crawled(params.limit.unwrap_or(DEFAULT_DOMAINS_LIMIT))
+        .into_iter()
+        .map(|(domain, progress)| DomainWithProgress { domain, progress })
+        .collect();
+
+    Json(domains)
+}
+
+async fn domain(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+    extract::Path(domain): extract::Path<String>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    coordinator
+        .domain_progress(&Domain::from(domain))
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn set_status(
+    coordinator: &CrawlCoordinator,
+    status: CrawlStatus,
+) -> std::result::Result<Json<CoordinatorStatus>, StatusCode> {
+    coordinator
+        .set_status(status)
+        .map_err(|_| StatusCode::CONFLICT)?;
+
+    Ok(Json(coordinator.status()))
+}
+
+async fn pause(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    set_status(&coordinator, CrawlStatus::Paused)
+}
+
+async fn resume(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    set_status(&coordinator, CrawlStatus::Running)
+}
+
+async fn drain(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    set_status(&coordinator, CrawlStatus::Draining)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SeedsParams {
+    urls: Vec<String>,
+    #[serde(default)]
+    wandering_urls: u64,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SeedsResponse {
+    jobs_added: usize,
+    invalid_urls: Vec<String>,
+}
+
+async fn seeds(
+    extract::State(coordinator): extract::State<Arc<CrawlCoordinator>>,
+    extract::Json(params): extract::Json<SeedsParams>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    let mut urls = Vec::new();
+    let mut invalid_urls = Vec::new();
+
+    for url in params.urls {
+        match Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => urls.push(parsed),
+            _ => invalid_urls.push(url),
+        }
+    }
+
+    // the seeds are persisted to disk
+    let jobs_added =
+        tokio::task::spawn_blocking(move || coordinator.inject_seeds(urls, params.wandering_urls))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(SeedsResponse {
+        jobs_added,
+        invalid_urls,
+    }))
+}
@@ -14,21 +14,546 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use super::{file_queue::FileQueue, Job, Result};
-use std::{path::Path, sync::Mutex};
+//! The coordinator hands out the jobs from a crawl plan to the workers (through the routers)
+//! and keeps track of the progress of the crawl.
+use super::{
+    crawl_trap::{CrawlTrapStore, TrapPattern},
+    file_queue::FileQueue,
+    Domain, DomainCrawled, Error, Job, JobResponse, Result, WeightedUrl, WorkerId,
+};
+use hashbrown::HashMap;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// Workers that have not been heard from for this long are considered dead.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrawlStatus {
+    Running,
+    /// No new jobs are handed out, but the workers wait for the crawl to be resumed.
+    Paused,
+    /// No new jobs are handed out and the workers stop once their current job is done.
+    /// Draining is terminal: the routers stop asking a coordinator for jobs once it
+    /// has told them it is done, so the crawl can't be resumed afterwards.
+    Draining,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueueStatus {
+    pub name: String,
+    pub remaining: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct InFlightJob {
+    pub domain: Domain,
+    pub worker: WorkerId,
+    pub running_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct DomainProgress {
+    pub jobs_completed: u64,
+    /// Number of urls crawled.
+    pub budget_used: f64,
+    /// unix timestamp (seconds) of when the last job for the domain finished.
+    pub last_crawled: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WorkerStatus {
+    pub worker: WorkerId,
+    pub alive: bool,
+    pub last_seen_secs: u64,
+    pub current_job: Option<Domain>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CoordinatorStatus {
+    pub status: CrawlStatus,
+    pub queues: Vec<QueueStatus>,
+    pub jobs_in_flight: Vec<InFlightJob>,
+    pub workers: Vec<WorkerStatus>,
+    pub domains_crawled: usize,
+}
+
+struct InFlight {
+    domain: Domain,
+    started: Instant,
+}
+
+struct State {
+    status: CrawlStatus,
+    seeds: VecDeque<Job>,
+    workers: HashMap<WorkerId, Instant>,
+    in_flight: HashMap<WorkerId, InFlight>,
+    progress: HashMap<Domain, DomainProgress>,
+}
+
+impl State {
+    /// Take the first seed job for a domain that is not currently being crawled.
+    fn pop_seed(&mut self) -> Option<Job> {
+        let idx = self.seeds.iter().position(|job| {
+            !self
+                .in_flight
+                .values()
+                .any(|in_flight| in_flight.domain == job.domain)
+        })?;
+
+        self.seeds.remove(idx)
+    }
+}
 
 pub struct CrawlCoordinator {
     jobs: Mutex<FileQueue<Job>>,
+    state: Mutex<State>,
+    traps: Option<CrawlTrapStore>,
+    seeds_path: Option<PathBuf>,
 }
 
 impl CrawlCoordinator {
     pub fn new<P: AsRef<Path>>(jobs_queue: P) -> Result<Self> {
         Ok(Self {
             jobs: Mutex::new(FileQueue::new(jobs_queue)?),
+            state: Mutex::new(State {
+                status: CrawlStatus::Running,
+                seeds: VecDeque::new(),
+                workers: HashMap::new(),
+                in_flight: HashMap::new(),
+                progress: HashMap::new(),
+            }),
+            traps: None,
+            seeds_path: None,
         })
     }
 
-    pub fn sample_job(&self) -> Result<Option<Job>> {
-        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).pop()
+    /// Store the crawl traps reported by the workers.
+    pub fn with_trap_store(mut self, traps: CrawlTrapStore) -> Self {
+        self.traps = Some(traps);
+        self
+    }
+
+    /// Persist the injected seed jobs that have not been handed out yet to the file,
+    /// so they survive a restart of the coordinator. Seeds already in the file are loaded.
+    pub fn with_seeds_path<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        if path.exists() {
+            let seeds: VecDeque<Job> = bincode::deserialize(&std::fs::read(&path)?)?;
+            tracing::info!("loaded {} seed jobs", seeds.len());
+            self.state
+                .get_mut()
+                .unwrap_or_else(|e| e.into_inner())
+                .seeds = seeds;
+        }
+
+        self.seeds_path = Some(path);
+        Ok(self)
+    }
+
+    fn save_seeds(&self, seeds: &VecDeque<Job>) {
+        let Some(path) = &self.seeds_path else {
+            return;
+        };
+
+        let res = bincode::serialize(seeds)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| {
+                let tmp_path = path.with_extension("tmp");
+                std::fs::write(&tmp_path, bytes)?;
+                std::fs::rename(tmp_path, path)?;
+                Ok(())
+            });
+
+        if let Err(err) = res {
+            tracing::error!("failed to save seed jobs: {:?}", err);
+        }
+    }
+
+    pub fn sample_job(&self, worker: WorkerId) -> Result<JobResponse> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.workers.insert(worker.clone(), Instant::now());
+        state.in_flight.remove(&worker);
+
+        match state.status {
+            CrawlStatus::Running => {}
+            CrawlStatus::Paused => return Ok(JobResponse::Wait),
+            CrawlStatus::Draining => return Ok(JobResponse::Done),
+        }
+
+        let job = match state.pop_seed() {
+            Some(job) => {
+                self.save_seeds(&state.seeds);
+                Some(job)
+            }
+            None => self.jobs.lock().unwrap_or_else(|e| e.into_inner()).pop()?,
+        };
+
+        match job {
+            Some(job) => {
+                state.in_flight.insert(
+                    worker,
+                    InFlight {
+                        domain: job.domain.clone(),
+                        started: Instant::now(),
+                    },
+                );
+
+                Ok(JobResponse::Job(job))
+            }
+            // the seeds for domains that are being crawled are handed out when the domain
+            // is done, so the crawl is only done when nothing is queued or in flight
+            None if !state.seeds.is_empty() || !state.in_flight.is_empty() => Ok(JobResponse::Wait),
+            None => Ok(JobResponse::Done),
+        }
+    }
+
+    pub fn heartbeat(&self, worker: WorkerId) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .workers
+            .insert(worker, Instant::now());
+    }
+
+    pub fn domain_crawled(&self, worker: WorkerId, domain_crawled: DomainCrawled) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.workers.insert(worker.clone(), Instant::now());
+        state.in_flight.remove(&worker);
+
+        let progress = state.progress.entry(domain_crawled.domain).or_default();
+        progress.jobs_completed += 1;
+        progress.budget_used += domain_crawled.budget_used;
+        progress.last_crawled = chrono::Utc::now().timestamp().max(0) as u64;
+    }
+
+    pub fn report_traps(&self, traps: Vec<TrapPattern>) {
+        match &self.traps {
+            Some(store) => {
+                tracing::info!("storing {} crawl traps", traps.len());
+                store.insert(traps);
+            }
+            None => tracing::debug!("ignoring {} crawl traps", traps.len()),
+        }
+    }
+
+    /// Change the status of the crawl. Fails if the crawl is draining, since a drained
+    /// crawl can't be resumed (see [`CrawlStatus::Draining`]).
+    pub fn set_status(&self, status: CrawlStatus) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if state.status == CrawlStatus::Draining && status != CrawlStatus::Draining {
+            return Err(Error::CrawlDrained.into());
+        }
+
+        tracing::info!("crawl status set to {:?}", status);
+        state.status = status;
+
+        Ok(())
+    }
+
+    /// Add a job for each domain in the urls. Seed jobs are handed out before the jobs
+    /// from the crawl plan. Seed jobs are not focused on a topic.
+    /// Returns the number of jobs that were added.
+    pub fn inject_seeds(&self, urls: Vec<Url>, wandering_urls: u64) -> usize {
+        let mut jobs: HashMap<Domain, Job> = HashMap::new();
+
+        for url in urls {
+            let domain = Domain::from(&url);
+
+            if domain.as_str().is_empty() {
+                continue;
+            }
+
+            jobs.entry(domain.clone())
+                .or_insert_with(|| Job {
+                    domain,
+                    urls: VecDeque::new(),
+                    wandering_urls,
+                    topic: None,
+                })
+                .urls
+                .push_back(WeightedUrl { url, weight: 1.0 });
+        }
+
+        let num_jobs = jobs.len();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.seeds.extend(jobs.into_values());
+        self.save_seeds(&state.seeds);
+
+        num_jobs
+    }
+
+    pub fn status(&self) -> CoordinatorStatus {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let remaining_jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner()).len();
+
+        let mut jobs_in_flight: Vec<_> = state
+            .in_flight
+            .iter()
+            .map(|(worker, in_flight)| InFlightJob {
+                domain: in_flight.domain.clone(),
+                worker: worker.clone(),
+                running_secs: in_flight.started.elapsed().as_secs(),
+            })
+            .collect();
+        jobs_in_flight.sort_by_key(|job| std::cmp::Reverse(job.running_secs));
+
+        let mut workers: Vec<_> = state
+            .workers
+            .iter()
+            .map(|(worker, last_seen)| WorkerStatus {
+                worker: worker.clone(),
+                alive: last_seen.elapsed() < WORKER_TIMEOUT,
+                last_seen_secs: last_seen.elapsed().as_secs(),
+                current_job: state
+                    .in_flight
+                    .get(worker)
+                    .map(|in_flight| in_flight.domain.clone()),
+            })
+            .collect();
+        workers.sort_by(|a, b| a.worker.cmp(&b.worker));
+
+        CoordinatorStatus {
+            status: state.status,
+            queues: vec![
+                QueueStatus {
+                    name: "seeds".to_string(),
+                    remaining: state.seeds.len(),
+                },
+                QueueStatus {
+                    name: "plan".to_string(),
+                    remaining: remaining_jobs,
+                },
+            ],
+            jobs_in_flight,
+            workers,
+            domains_crawled: state.progress.len(),
+        }
+    }
+
+    pub fn domain_progress(&self, domain: &Domain) -> Option<DomainProgress> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .progress
+            .get(domain)
+            .cloned()
+    }
+
+    /// The most recently crawled domains.
+    pub fn recently_crawled(&self, limit: usize) -> Vec<(Domain, DomainProgress)> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut domains: Vec<_> = state
+            .progress
+            .iter()
+            .map(|(domain, progress)| (domain.clone(), progress.clone()))
+            .collect();
+        domains.sort_by_key(|(_, progress)| std::cmp::Reverse(progress.last_crawled));
+        domains.truncate(limit);
+
+        domains
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::file_queue::FileQueueWriter;
+
+    use super::*;
+
+    fn job(domain: &str) -> Job {
+        Job {
+            domain: Domain::from(domain.to_string()),
+            urls: VecDeque::new(),
+            wandering_urls: 0,
+            topic: None,
+        }
+    }
+
+    fn coordinator(jobs: Vec<Job>) -> CrawlCoordinator {
+        let path = crate::gen_temp_path();
+        let mut writer = FileQueueWriter::new(&path).unwrap();
+
+        for job in jobs {
+            writer.push(job).unwrap();
+        }
+
+        writer.finalize().unwrap();
+
+        CrawlCoordinator::new(&path).unwrap()
+    }
+
+    fn domain(res: JobResponse) -> Option<String> {
+        match res {
+            JobResponse::Job(job) => Some(job.domain.as_str().to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn pause_resume_drain() {
+        let coordinator = coordinator(vec![job("a.com"), job("b.com"), job("c.com")]);
+        let worker = WorkerId::new(0);
+
+        assert_eq!(
+            domain(coordinator.sample_job(worker.clone()).unwrap()),
+            Some("a.com".to_string())
+        );
+
+        coordinator.set_status(CrawlStatus::Paused).unwrap();
+        assert!(matches!(
+            coordinator.sample_job(worker.clone()).unwrap(),
+            JobResponse::Wait
+        ));
+
+        coordinator.set_status(CrawlStatus::Running).unwrap();
+        assert_eq!(
+            domain(coordinator.sample_job(worker.clone()).unwrap()),
+            Some("b.com".to_string())
+        );
+
+        coordinator.set_status(CrawlStatus::Draining).unwrap();
+        assert!(matches!(
+            coordinator.sample_job(worker.clone()).unwrap(),
+            JobResponse::Done
+        ));
+
+        // draining is terminal
+        assert!(coordinator.set_status(CrawlStatus::Running).is_err());
+        assert!(coordinator.set_status(CrawlStatus::Paused).is_err());
+
+        let status = coordinator.status();
+        assert_eq!(status.status, CrawlStatus::Draining);
+        assert_eq!(status.queues[1].remaining, 1);
+    }
+
+    #[test]
+    fn seeds_and_progress() {
+        let coordinator = coordinator(vec![job("a.com")]);
+        let first = WorkerId::new(0);
+        let second = WorkerId::new(1);
+
+        let num_jobs = coordinator.inject_seeds(
+            vec![
+                Url::parse("https://a.com/new").unwrap(),
+                Url::parse("https://b.com/").unwrap(),
+                Url::parse("https://www.b.com/other").unwrap(),
+            ],
+            10,
+        );
+        assert_eq!(num_jobs, 2);
+
+        let res = coordinator.sample_job(first.clone()).unwrap();
+        let first_domain = domain(res).unwrap();
+
+        // only one job per domain at a time
+        let second_domain = domain(coordinator.sample_job(second.clone()).unwrap()).unwrap();
+        assert_ne!(first_domain, second_domain);
+
+        let status = coordinator.status();
+        assert_eq!(status.jobs_in_flight.len(), 2);
+        assert_eq!(status.workers.len(), 2);
+        assert!(status.workers.iter().all(|w| w.alive));
+
+        coordinator.domain_crawled(
+            first.clone(),
+            DomainCrawled {
+                domain: Domain::from(first_domain.clone()),
+                budget_used: 3.0,
+            },
+        );
+
+        let status = coordinator.status();
+        assert_eq!(status.jobs_in_flight.len(), 1);
+        assert_eq!(status.domains_crawled, 1);
+
+        let progress = coordinator
+            .domain_progress(&Domain::from(first_domain))
+            .unwrap();
+        assert_eq!(progress.jobs_completed, 1);
+        assert_eq!(progress.budget_used, 3.0);
+        assert_eq!(coordinator.recently_crawled(10).len(), 1);
+    }
+
+    #[test]
+    fn wait_for_domains_in_flight() {
+        let coordinator = coordinator(vec![]);
+        let first = WorkerId::new(0);
+        let second = WorkerId::new(1);
+
+        coordinator.inject_seeds(vec![Url::parse("https://a.com/").unwrap()], 0);
+        assert_eq!(
+            domain(coordinator.sample_job(first.clone()).unwrap()),
+            Some("a.com".to_string())
+        );
+
+        // the seed can't be handed out while its domain is being crawled
+        coordinator.inject_seeds(vec![Url::parse("https://a.com/other").unwrap()], 0);
+        assert!(matches!(
+            coordinator.sample_job(second.clone()).unwrap(),
+            JobResponse::Wait
+        ));
+
+        coordinator.domain_crawled(
+            first.clone(),
+            DomainCrawled {
+                domain: Domain::from("a.com".to_string()),
+                budget_used: 1.0,
+            },
+        );
+        assert_eq!(
+            domain(coordinator.sample_job(second.clone()).unwrap()),
+            Some("a.com".to_string())
+        );
+
+        // nothing is queued, but a domain is still in flight
+        assert!(matches!(
+            coordinator.sample_job(first.clone()).unwrap(),
+            JobResponse::Wait
+        ));
+        assert!(matches!(
+            coordinator.sample_job(second).unwrap(),
+            JobResponse::Wait
+        ));
+        assert!(matches!(
+            coordinator.sample_job(first).unwrap(),
+            JobResponse::Done
+        ));
+    }
+
+    #[test]
+    fn persisted_seeds() {
+        let seeds_path = crate::gen_temp_path().join("seeds.bin");
+        std::fs::create_dir_all(seeds_path.parent().unwrap()).unwrap();
+
+        let first = coordinator(vec![]).with_seeds_path(&seeds_path).unwrap();
+        first.inject_seeds(
+            vec![
+                Url::parse("https://a.com/").unwrap(),
+                Url::parse("https://b.com/").unwrap(),
+            ],
+            0,
+        );
+        let first_domain = domain(first.sample_job(WorkerId::new(0)).unwrap()).unwrap();
+
+        // a restarted coordinator picks up the seeds that were not handed out
+        let restarted = coordinator(vec![]).with_seeds_path(&seeds_path).unwrap();
+        assert_eq!(restarted.status().queues[0].remaining, 1);
+
+        let second_domain = domain(restarted.sample_job(WorkerId::new(0)).unwrap()).unwrap();
+        assert_ne!(first_domain, second_domain);
+        assert!(matches!(
+            restarted.sample_job(WorkerId::new(0)).unwrap(),
+            JobResponse::Done
+        ));
     }
 }
@@ -0,0 +1,488 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-url state from previous crawls.
+//!
+//! The workers record the validators (`ETag` and `Last-Modified`) and a simhash
+//! of the content every time a url is fetched. The validators are sent
+//! with the next fetch so the server can answer with `304 Not Modified`,
+//! and the number of observed changes is used by the planner to estimate
+//! how often a url changes.
+//!
+//! The state is keyed by the canonical key of the url, so the state recorded for
+//! `https://www.example.com/` is found when the planner looks up `http://example.com`.
+//! Each crawler records the urls it fetches in its own store, and the planner
+//! reads the stores of all the crawlers as one.
+use std::path::Path;
+
+use url::Url;
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, KeyLocks, Kv},
+    simhash,
+    webpage::url_canonicalizer,
+};
+
+/// Two simhashes that are more than this many bits apart are considered a change of content.
+const SIMHASH_CHANGE_THRESHOLD: u32 = 3;
+
+/// Change rate (per second) assumed for urls that have not been observed long enough
+/// to estimate their change rate.
+const DEFAULT_CHANGE_RATE: f64 = 1.0 / (7.0 * 24.0 * 60.0 * 60.0);
+
+/// Lower bound on the estimated change rate (per second), so urls that have never
+/// been observed to change are still re-crawled eventually.
+const MIN_CHANGE_RATE: f64 = 1.0 / (365.0 * 24.0 * 60.0 * 60.0);
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UrlCrawlState {
+    /// unix timestamp (seconds) of the first fetch.
+    pub first_fetch: u64,
+    /// unix timestamp (seconds) of the last fetch.
+    pub last_fetch: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub simhash: simhash::HashType,
+    pub num_fetches: u32,
+    pub num_changes: u32,
+    /// unix timestamp (seconds) of the latest `lastmod` seen in a sitemap.
+    pub lastmod: Option<u64>,
+}
+
+impl UrlCrawlState {
+    fn new(now: u64) -> Self {
+        Self {
+            first_fetch: now,
+            last_fetch: now,
+            etag: None,
+            last_modified: None,
+            simhash: 0,
+            num_fetches: 0,
+            num_changes: 0,
+            lastmod: None,
+        }
+    }
+
+    /// Record a successful fetch of the url. Returns whether the content
+    /// has changed since the previous fetch.
+    pub fn record_fetch(
+        &mut self,
+        now: u64,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        simhash: simhash::HashType,
+    ) -> bool {
+        let has_changed = self.num_fetches > 0
+            && simhash::hamming_distance(self.simhash, simhash) > SIMHASH_CHANGE_THRESHOLD;
+
+        if has_changed {
+            self.num_changes += 1;
+        }
+
+        self.num_fetches += 1;
+        self.last_fetch = now;
+        self.etag = etag;
+        self.last_modified = last_modified;
+        self.simhash = simhash;
+
+        has_changed
+    }
+
+    /// Record a fetch where the server responded with `304 Not Modified`.
+    pub fn record_not_modified(&mut self, now: u64) {
+        self.num_fetches += 1;
+        self.last_fetch = now;
+    }
+
+    /// Combine the states of the same url recorded by different crawlers.
+    /// The validators and the simhash are taken from the latest fetch.
+    fn merge(self, other: Self) -> Self {
+        let (older, newer) = if self.last_fetch <= other.last_fetch {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        Self {
+            first_fetch: older.first_fetch.min(newer.first_fetch),
+            num_fetches: older.num_fetches + newer.num_fetches,
+            num_changes: older.num_changes + newer.num_changes,
+            lastmod: older.lastmod.max(newer.lastmod),
+            ..newer
+        }
+    }
+
+    /// Whether a sitemap has reported the url as modified after it was last fetched.
+    pub fn is_modified_since_fetch(&self) -> bool {
+        self.lastmod
+            .map(|lastmod| lastmod > self.last_fetch)
+            .unwrap_or(false)
+    }
+
+    /// Estimated number of changes per second.
+    ///
+    /// Uses the estimator from Cho & Garcia-Molina, "Estimating frequency of change" (2003),
+    /// which corrects for changes that are missed when a url changes more than once
+    /// between two fetches.
+    pub fn change_rate(&self) -> f64 {
+        let revisits = self.num_fetches.saturating_sub(1) as f64;
+        let observed = self.last_fetch.saturating_sub(self.first_fetch) as f64;
+
+        if revisits == 0.0 || observed == 0.0 {
+            return DEFAULT_CHANGE_RATE;
+        }
+
+        let interval = observed / revisits;
+        let changes = (self.num_changes as f64).min(revisits);
+
+        let rate = -((revisits - changes + 0.5) / (revisits + 0.5)).ln() / interval;
+
+        rate.max(MIN_CHANGE_RATE)
+    }
+
+    /// Probability that the url has changed since it was last fetched,
+    /// assuming that changes follow a poisson process. Urls that a sitemap
+    /// reports as modified after the last fetch are certain to have changed.
+    pub fn change_probability(&self, now: u64) -> f64 {
+        if self.is_modified_since_fetch() {
+            return 1.0;
+        }
+
+        let elapsed = now.saturating_sub(self.last_fetch) as f64;
+
+        1.0 - (-self.change_rate() * elapsed).exp()
+    }
+}
+
+pub struct CrawlStateStore {
+    store: RocksDbStore<String, UrlCrawlState>,
+    /// The state of a url is read, modified and written back,
+    /// so concurrent updates of the same url must be serialized.
+    locks: KeyLocks,
+}
+
+impl CrawlStateStore {
+    fn new(store: RocksDbStore<String, UrlCrawlState>) -> Self {
+        Self {
+            store,
+            locks: KeyLocks::default(),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(RocksDbStore::open(path))
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(RocksDbStore::open_read_only(path))
+    }
+
+    pub fn get(&self, url: &Url) -> Option<UrlCrawlState> {
+        self.store.get(&url_canonicalizer::canonical_key(url))
+    }
+
+    pub fn record_fetch(
+        &self,
+        url: &Url,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        simhash: simhash::HashType,
+    ) {
+        let key = url_canonicalizer::canonical_key(url);
+        let _guard = self.locks.lock(&key);
+
+        let now = now();
+        let mut state = self
+            .store
+            .get(&key)
+            .unwrap_or_else(|| UrlCrawlState::new(now));
+
+        if state.record_fetch(now, etag, last_modified, simhash) {
+            tracing::debug!("content of {} has changed", url);
+        }
+
+        self.store.insert(key, state);
+    }
+
+    pub fn record_not_modified(&self, url: &Url) {
+        let key = url_canonicalizer::canonical_key(url);
+        let _guard = self.locks.lock(&key);
+
+        let now = now();
+        let mut state = self
+            .store
+            .get(&key)
+            .unwrap_or_else(|| UrlCrawlState::new(now));
+        state.record_not_modified(now);
+
+        self.store.insert(key, state);
+    }
+
+    /// Record the `lastmod` of the url from a sitemap. Returns whether the url has been
+    /// modified since it was last crawled. Urls that have never been crawled are always modified.
+    pub fn record_lastmod(&self, url: &Url, lastmod: u64) -> bool {
+        let key = url_canonicalizer::canonical_key(url);
+        let _guard = self.locks.lock(&key);
+
+        let mut state = match self.store.get(&key) {
+            Some(state) => state,
+            None => return true,
+        };
+
+        if lastmod <= state.last_fetch {
+            return false;
+        }
+
+        if state.lastmod != Some(lastmod) {
+            state.lastmod = Some(lastmod);
+            self.store.insert(key, state);
+        }
+
+        true
+    }
+
+    /// Probability that the url has changed since it was last crawled.
+    /// Urls that have never been crawled are certain to have changed.
+    pub fn change_probability(&self, url: &Url) -> f64 {
+        self.get(url)
+            .map(|state| state.change_probability(now()))
+            .unwrap_or(1.0)
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+/// The crawl states recorded by several crawlers, read as if they were a single store.
+pub struct MergedCrawlState {
+    stores: Vec<CrawlStateStore>,
+}
+
+impl MergedCrawlState {
+    pub fn open_read_only<P: AsRef<Path>>(paths: &[P]) -> Self {
+        Self {
+            stores: paths.iter().map(CrawlStateStore::open_read_only).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stores.is_empty()
+    }
+
+    pub fn get(&self, url: &Url) -> Option<UrlCrawlState> {
+        self.stores
+            .iter()
+            .filter_map(|store| store.get(url))
+            .reduce(UrlCrawlState::merge)
+    }
+
+    /// Probability that the url has changed since any of the crawlers last crawled it.
+    /// Urls that have never been crawled are certain to have changed.
+    pub fn change_probability(&self, url: &Url) -> f64 {
+        self.get(url)
+            .map(|state| state.change_probability(now()))
+            .unwrap_or(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn change_rate() {
+        let mut rarely = UrlCrawlState::new(0);
+        let mut often = UrlCrawlState::new(0);
+
+        for i in 0..10 {
+            rarely.record_fetch(i * DAY, None, None, u64::MAX);
+
+            let simhash = if i % 2 == 0 { u64::MAX } else { 0 };
+            assert_eq!(
+                often.record_fetch(i * DAY, None, None, simhash),
+                i > 0,
+                "every fetch after the first should be a change"
+            );
+        }
+
+        assert_eq!(rarely.num_changes, 0);
+        assert_eq!(often.num_changes, 9);
+
+        assert!(often.change_rate() > rarely.change_rate());
+        assert_eq!(rarely.change_rate(), MIN_CHANGE_RATE);
+
+        let now = 10 * DAY;
+        assert!(often.change_probability(now) > 0.5);
+        assert!(rarely.change_probability(now) < 0.01);
+        assert!(often.change_probability(now) > often.change_probability(9 * DAY));
+    }
+
+    #[test]
+    fn not_modified_is_not_a_change() {
+        let mut state = UrlCrawlState::new(0);
+        state.record_fetch(0, Some("\"abc\"".to_string()), None, 42);
+        state.record_not_modified(DAY);
+
+        assert_eq!(state.num_fetches, 2);
+        assert_eq!(state.num_changes, 0);
+        assert_eq!(state.last_fetch, DAY);
+        assert_eq!(state.etag.as_deref(), Some("\"abc\""));
+    }
+
+    #[test]
+    fn store() {
+        let store = CrawlStateStore::open(crate::gen_temp_path());
+        let url = Url::parse("https://example.com/").unwrap();
+
+        assert!(store.get(&url).is_none());
+        assert_eq!(store.change_probability(&url), 1.0);
+
+        store.record_fetch(
+            &url,
+            Some("\"abc\"".to_string()),
+            Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+            u64::MAX,
+        );
+        store.record_not_modified(&url);
+        store.record_fetch(&url, None, None, 0);
+
+        let state = store.get(&url).unwrap();
+        assert_eq!(state.num_fetches, 3);
+        assert_eq!(state.num_changes, 1);
+        assert_eq!(state.etag, None);
+        assert!(store.change_probability(&url) < 1.0);
+    }
+
+    #[test]
+    fn sitemap_lastmod() {
+        let store = CrawlStateStore::open(crate::gen_temp_path());
+        let url = Url::parse("https://example.com/").unwrap();
+
+        assert!(store.record_lastmod(&url, 0));
+
+        store.record_fetch(&url, None, None, 42);
+        let last_fetch = store.get(&url).unwrap().last_fetch;
+
+        assert!(!store.record_lastmod(&url, last_fetch - DAY));
+        assert!(store.change_probability(&url) < 1.0);
+
+        assert!(store.record_lastmod(&url, last_fetch + 1));
+        assert_eq!(store.change_probability(&url), 1.0);
+
+        // the next fetch catches up with the sitemap
+        let mut state = store.get(&url).unwrap();
+        state.record_fetch(last_fetch + 2, None, None, 42);
+        assert!(!state.is_modified_since_fetch());
+    }
+
+    #[test]
+    fn canonical_keys() {
+        let store = CrawlStateStore::open(crate::gen_temp_path());
+        let fetched = Url::parse("https://www.example.com/page/").unwrap();
+        let planned = Url::parse("http://example.com/page").unwrap();
+
+        store.record_fetch(&fetched, Some("\"abc\"".to_string()), None, 42);
+
+        let state = store.get(&planned).unwrap();
+        assert_eq!(state.num_fetches, 1);
+        assert_eq!(state.etag.as_deref(), Some("\"abc\""));
+    }
+
+    #[test]
+    fn merge_states() {
+        let mut older = UrlCrawlState::new(0);
+        older.record_fetch(0, Some("\"old\"".to_string()), None, 0);
+        older.record_fetch(DAY, None, None, u64::MAX);
+
+        let mut newer = UrlCrawlState::new(DAY);
+        newer.record_fetch(2 * DAY, Some("\"new\"".to_string()), None, 42);
+        newer.lastmod = Some(DAY);
+
+        let merged = newer.clone().merge(older.clone());
+
+        assert_eq!(merged, older.merge(newer));
+        assert_eq!(merged.first_fetch, 0);
+        assert_eq!(merged.last_fetch, 2 * DAY);
+        assert_eq!(merged.num_fetches, 3);
+        assert_eq!(merged.num_changes, 1);
+        assert_eq!(merged.etag.as_deref(), Some("\"new\""));
+        assert_eq!(merged.simhash, 42);
+        assert_eq!(merged.lastmod, Some(DAY));
+    }
+
+    #[test]
+    fn merge_crawlers() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let paths = [crate::gen_temp_path(), crate::gen_temp_path()];
+
+        {
+            let first = CrawlStateStore::open(&paths[0]);
+            let second = CrawlStateStore::open(&paths[1]);
+
+            first.record_fetch(&url, None, None, 0);
+            second.record_fetch(&url, None, None, u64::MAX);
+            second.record_not_modified(&url);
+
+            first.flush();
+            second.flush();
+        }
+
+        let merged = MergedCrawlState::open_read_only(&paths);
+        let state = merged.get(&url).unwrap();
+
+        assert_eq!(state.num_fetches, 3);
+
+        assert!(merged
+            .get(&Url::parse("https://example.com/other").unwrap())
+            .is_none());
+        assert_eq!(
+            merged.change_probability(&Url::parse("https://example.com/other").unwrap()),
+            1.0
+        );
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        let store = std::sync::Arc::new(CrawlStateStore::open(crate::gen_temp_path()));
+        let url = Url::parse("https://example.com/").unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = std::sync::Arc::clone(&store);
+                let url = url.clone();
+
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.record_not_modified(&url);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(store.get(&url).unwrap().num_fetches, 800);
+    }
+}
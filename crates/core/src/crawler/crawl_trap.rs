@@ -0,0 +1,509 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detection of crawl traps.
+//!
+//! Some sites expose an unbounded number of urls, like calendars with a page for
+//! every day, faceted navigation with a page for every combination of filters or
+//! session ids in the urls. The worker uses three heuristics to detect them:
+//! * paths where the same segment is repeated many times (e.g. `/a/b/a/b/a/b/a/b`).
+//! * path templates that are linked with an exploding number of different queries.
+//! * query parameters that don't change the content of the page (e.g. session ids),
+//!   detected when urls that only differ in those parameters have near-duplicate content.
+//!
+//! The trapped path templates are reported to the coordinator, which stores them
+//! so the planner can exclude them from future crawl plans. Traps that are not
+//! reported again expire after [`TRAP_TTL_SECS`].
+use std::{collections::BTreeSet, path::Path};
+
+use hashbrown::{HashMap, HashSet};
+use url::Url;
+
+use crate::{
+    kv::{rocksdb_store::RocksDbStore, Kv},
+    simhash,
+};
+
+/// A path segment repeated more than this many times is considered a trap.
+const MAX_SEGMENT_REPETITIONS: usize = 3;
+
+/// Paths with more segments than this are considered a trap.
+const MAX_PATH_SEGMENTS: usize = 32;
+
+/// Number of different queries that can be linked for a path template before it is considered a trap.
+const MAX_QUERY_VARIANTS: usize = 128;
+
+/// Number of near-duplicate pages that can be fetched for a path template before it is considered a trap.
+const MAX_NEAR_DUPLICATES: usize = 8;
+
+/// Number of simhashes kept per path template to compare new pages against.
+const MAX_SIMHASHES_PER_TEMPLATE: usize = 64;
+
+/// Two pages whose simhashes are at most this many bits apart are near-duplicates.
+const NEAR_DUPLICATE_THRESHOLD: u32 = 3;
+
+/// Stored traps expire if they have not been reported for this many seconds (30 days),
+/// so sites that fix their urls are eventually crawled again.
+pub const TRAP_TTL_SECS: u64 = 60 * 60 * 24 * 30;
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+/// Segments with digits are usually ids, dates or page numbers.
+fn is_variable_segment(segment: &str) -> bool {
+    segment.chars().any(|c| c.is_ascii_digit())
+}
+
+/// The host and path of a url where segments that look like ids,
+/// dates or page numbers are replaced by a wildcard.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct PathTemplate(String);
+
+impl From<&Url> for PathTemplate {
+    fn from(url: &Url) -> Self {
+        let mut template = url.host_str().unwrap_or_default().to_string();
+
+        for segment in url.path_segments().into_iter().flatten() {
+            template.push('/');
+
+            if is_variable_segment(segment) {
+                template.push('*');
+            } else {
+                template.push_str(segment);
+            }
+        }
+
+        Self(template)
+    }
+}
+
+impl PathTemplate {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Whether the path of the url is suspiciously deep or repeats the same segment
+/// many times, which usually happens when relative links are resolved against
+/// the wrong base.
+pub fn has_repeated_path(url: &Url) -> bool {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut num_segments = 0;
+
+    for segment in url.path_segments().into_iter().flatten() {
+        if segment.is_empty() {
+            continue;
+        }
+
+        num_segments += 1;
+        let count = counts.entry(segment).or_default();
+        *count += 1;
+
+        if *count > MAX_SEGMENT_REPETITIONS || num_segments > MAX_PATH_SEGMENTS {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum TrapReason {
+    QueryExplosion,
+    DuplicateContent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct TrapPattern {
+    pub template: PathTemplate,
+    /// Only match urls with a query, so the page itself can still be crawled
+    /// when the trap is in its query parameters (faceted navigation, session ids etc.).
+    pub query_only: bool,
+    /// Only match urls with at least one of these query parameters. Matches all
+    /// urls of the template if empty.
+    pub params: Vec<String>,
+    pub reason: TrapReason,
+}
+
+impl TrapPattern {
+    pub fn matches(&self, url: &Url) -> bool {
+        if self.query_only && url.query().is_none() {
+            return false;
+        }
+
+        if !self.params.is_empty()
+            && !url
+                .query_pairs()
+                .any(|(key, _)| self.params.iter().any(|param| *param == key))
+        {
+            return false;
+        }
+
+        self.template == PathTemplate::from(url)
+    }
+}
+
+/// A set of trap patterns that urls can be checked against.
+#[derive(Debug, Default)]
+pub struct CrawlTraps {
+    patterns: HashMap<PathTemplate, Vec<TrapPattern>>,
+}
+
+impl CrawlTraps {
+    pub fn insert(&mut self, pattern: TrapPattern) {
+        let patterns = self.patterns.entry(pattern.template.clone()).or_default();
+
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.values().map(|p| p.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_trapped(&self, url: &Url) -> bool {
+        if has_repeated_path(url) {
+            return true;
+        }
+
+        match self.patterns.get(&PathTemplate::from(url)) {
+            Some(patterns) => patterns.iter().any(|pattern| pattern.matches(url)),
+            None => false,
+        }
+    }
+}
+
+impl FromIterator<TrapPattern> for CrawlTraps {
+    fn from_iter<T: IntoIterator<Item = TrapPattern>>(iter: T) -> Self {
+        let mut traps = Self::default();
+
+        for pattern in iter {
+            traps.insert(pattern);
+        }
+
+        traps
+    }
+}
+
+type QueryPairs = BTreeSet<(String, String)>;
+
+#[derive(Default)]
+struct TemplateStats {
+    queries: HashSet<String>,
+    pages: Vec<(simhash::HashType, QueryPairs)>,
+    near_duplicates: usize,
+    /// Query parameters that differed between near-duplicate pages.
+    differing_params: BTreeSet<String>,
+}
+
+/// Detects crawl traps within a single job.
+#[derive(Default)]
+pub struct CrawlTrapDetector {
+    templates: HashMap<(PathTemplate, bool), TemplateStats>,
+    traps: CrawlTraps,
+    detected: Vec<TrapPattern>,
+}
+
+impl CrawlTrapDetector {
+    pub fn is_trapped(&self, url: &Url) -> bool {
+        self.traps.is_trapped(url)
+    }
+
+    fn trap(&mut self, pattern: TrapPattern) {
+        tracing::debug!("detected crawl trap: {:?}", pattern);
+        self.traps.insert(pattern.clone());
+        self.detected.push(pattern);
+    }
+
+    /// Record a url that has been linked from a crawled page.
+    pub fn observe_link(&mut self, url: &Url) {
+        let query = match url.query() {
+            Some(query) => query,
+            None => return,
+        };
+
+        let template = PathTemplate::from(url);
+        let stats = self.templates.entry((template.clone(), true)).or_default();
+
+        if stats.queries.len() > MAX_QUERY_VARIANTS {
+            return;
+        }
+
+        stats.queries.insert(query.to_string());
+
+        if stats.queries.len() > MAX_QUERY_VARIANTS {
+            self.trap(TrapPattern {
+                template,
+                query_only: true,
+                params: Vec::new(),
+                reason: TrapReason::QueryExplosion,
+            });
+        }
+    }
+
+    /// Record the simhash of the content of a fetched url.
+    ///
+    /// Only urls with a query are considered. When urls of the same path template
+    /// keep having near-duplicate content, the query parameters they differ in are
+    /// trapped while the urls without those parameters can still be crawled.
+    pub fn observe_content(&mut self, url: &Url, simhash: simhash::HashType) {
+        if simhash == 0 || url.query().is_none() {
+            return;
+        }
+
+        let template = PathTemplate::from(url);
+        let stats = self.templates.entry((template.clone(), true)).or_default();
+
+        if stats.near_duplicates >= MAX_NEAR_DUPLICATES {
+            return;
+        }
+
+        let pairs: QueryPairs = url
+            .query_pairs()
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+
+        let near_duplicate = stats.pages.iter().find(|(other, _)| {
+            simhash::hamming_distance(*other, simhash) <= NEAR_DUPLICATE_THRESHOLD
+        });
+
+        match near_duplicate {
+            Some((_, other_pairs)) => {
+                let differing: Vec<_> = pairs
+                    .symmetric_difference(other_pairs)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+
+                if differing.is_empty() {
+                    // the same page fetched twice
+                    return;
+                }
+
+                stats.differing_params.extend(differing);
+                stats.near_duplicates += 1;
+
+                if stats.near_duplicates >= MAX_NEAR_DUPLICATES {
+                    let params = stats.differing_params.iter().cloned().collect();
+
+                    self.trap(TrapPattern {
+                        template,
+                        query_only: true,
+                        params,
+                        reason: TrapReason::DuplicateContent,
+                    });
+                }
+            }
+            None => {
+                if stats.pages.len() < MAX_SIMHASHES_PER_TEMPLATE {
+                    stats.pages.push((simhash, pairs));
+                }
+            }
+        }
+    }
+
+    /// The traps detected so far.
+    pub fn into_detected(self) -> Vec<TrapPattern> {
+        self.detected
+    }
+}
+
+/// Trap patterns reported by the workers, stored by the coordinator.
+pub struct CrawlTrapStore {
+    /// pattern -> unix timestamp (seconds) of the latest report.
+    store: RocksDbStore<TrapPattern, u64>,
+}
+
+impl CrawlTrapStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+        }
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open_read_only(path),
+        }
+    }
+
+    pub fn insert(&self, patterns: impl IntoIterator<Item = TrapPattern>) {
+        self.insert_at(patterns, now());
+    }
+
+    fn insert_at(&self, patterns: impl IntoIterator<Item = TrapPattern>, timestamp: u64) {
+        for pattern in patterns {
+            self.store.insert(pattern, timestamp);
+        }
+
+        self.store.flush();
+    }
+
+    /// The traps that have been reported within the last [`TRAP_TTL_SECS`].
+    pub fn traps(&self) -> CrawlTraps {
+        let now = now();
+
+        self.store
+            .iter()
+            .filter(|(_, reported)| reported.saturating_add(TRAP_TTL_SECS) >= now)
+            .map(|(pattern, _)| pattern)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_template() {
+        let url = Url::parse("https://example.com/calendar/2024/01/05?view=day").unwrap();
+        assert_eq!(
+            PathTemplate::from(&url).as_str(),
+            "example.com/calendar/*/*/*"
+        );
+
+        let url = Url::parse("https://example.com/about").unwrap();
+        assert_eq!(PathTemplate::from(&url).as_str(), "example.com/about");
+    }
+
+    #[test]
+    fn repeated_path() {
+        assert!(!has_repeated_path(
+            &Url::parse("https://example.com/a/b/c/d").unwrap()
+        ));
+        assert!(!has_repeated_path(
+            &Url::parse("https://example.com/a/b/a/b/a/b").unwrap()
+        ));
+        assert!(has_repeated_path(
+            &Url::parse("https://example.com/a/b/a/b/a/b/a/b").unwrap()
+        ));
+
+        let deep = format!(
+            "https://example.com/{}",
+            (0..40).map(|i| format!("s{i}/")).collect::<String>()
+        );
+        assert!(has_repeated_path(&Url::parse(&deep).unwrap()));
+    }
+
+    #[test]
+    fn query_explosion() {
+        let mut detector = CrawlTrapDetector::default();
+        let page = Url::parse("https://example.com/shop").unwrap();
+
+        for i in 0..=MAX_QUERY_VARIANTS {
+            let url = Url::parse(&format!("https://example.com/shop?color={i}&size={i}")).unwrap();
+            assert!(!detector.is_trapped(&url));
+            detector.observe_link(&url);
+        }
+
+        let url = Url::parse("https://example.com/shop?color=red").unwrap();
+        assert!(detector.is_trapped(&url));
+        assert!(!detector.is_trapped(&page));
+        assert!(!detector.is_trapped(&Url::parse("https://example.com/other?a=b").unwrap()));
+
+        let detected = detector.into_detected();
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].reason, TrapReason::QueryExplosion);
+    }
+
+    #[test]
+    fn duplicate_content() {
+        let mut detector = CrawlTrapDetector::default();
+
+        for session in 0..=MAX_NEAR_DUPLICATES {
+            let url = Url::parse(&format!(
+                "https://example.com/article?id=1&session={session}"
+            ))
+            .unwrap();
+            detector.observe_content(&url, u64::MAX);
+        }
+
+        // pages without a query are never trapped, even if they look alike
+        for day in 1..=MAX_NEAR_DUPLICATES + 1 {
+            let url = Url::parse(&format!("https://example.com/calendar/2024/01/{day}")).unwrap();
+            detector.observe_content(&url, u64::MAX);
+        }
+
+        // pages with different content are not traps
+        for i in 0..MAX_NEAR_DUPLICATES * 2 {
+            let url = Url::parse(&format!("https://example.com/post?id={i}")).unwrap();
+            detector.observe_content(&url, (i as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        }
+
+        assert!(detector
+            .is_trapped(&Url::parse("https://example.com/article?id=2&session=abc").unwrap()));
+        assert!(!detector.is_trapped(&Url::parse("https://example.com/article?id=2").unwrap()));
+        assert!(!detector.is_trapped(&Url::parse("https://example.com/article").unwrap()));
+        assert!(
+            !detector.is_trapped(&Url::parse("https://example.com/calendar/2025/12/24").unwrap())
+        );
+        assert!(!detector.is_trapped(&Url::parse("https://example.com/post?id=1000").unwrap()));
+
+        let detected = detector.into_detected();
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].reason, TrapReason::DuplicateContent);
+        assert!(detected[0].query_only);
+        assert_eq!(detected[0].params, vec!["session".to_string()]);
+    }
+
+    #[test]
+    fn store() {
+        let store = CrawlTrapStore::open(crate::gen_temp_path());
+        let pattern = TrapPattern {
+            template: PathTemplate::from(&Url::parse("https://example.com/search").unwrap()),
+            query_only: true,
+            params: Vec::new(),
+            reason: TrapReason::QueryExplosion,
+        };
+
+        store.insert(vec![pattern.clone(), pattern]);
+
+        let traps = store.traps();
+        assert_eq!(traps.len(), 1);
+        assert!(traps.is_trapped(&Url::parse("https://example.com/search?q=1").unwrap()));
+        assert!(!traps.is_trapped(&Url::parse("https://example.com/search").unwrap()));
+    }
+
+    #[test]
+    fn expired_traps() {
+        let store = CrawlTrapStore::open(crate::gen_temp_path());
+        let pattern = |path: &str| TrapPattern {
+            template: PathTemplate::from(&Url::parse(path).unwrap()),
+            query_only: true,
+            params: Vec::new(),
+            reason: TrapReason::QueryExplosion,
+        };
+
+        store.insert_at(
+            vec![pattern("https://example.com/old")],
+            now() - TRAP_TTL_SECS - 1,
+        );
+        store.insert(vec![pattern("https://example.com/new")]);
+
+        let traps = store.traps();
+        assert_eq!(traps.len(), 1);
+        assert!(traps.is_trapped(&Url::parse("https://example.com/new?q=1").unwrap()));
+        assert!(!traps.is_trapped(&Url::parse("https://example.com/old?q=1").unwrap()));
+    }
+}
@@ -0,0 +1,135 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fetches pages for the worker, either from the web or (in replay mode) from a local WARC corpus.
+use std::{sync::Arc, time::Duration};
+
+use reqwest::{header::HeaderMap, StatusCode};
+use tokio_stream::StreamExt;
+use url::Url;
+
+use crate::config::CrawlerConfig;
+
+use super::{
+    replay::{ReplayCorpus, ReplayResponse},
+    reqwest_client, Error, Result,
+};
+
+#[derive(Clone)]
+pub enum Fetcher {
+    Http(reqwest::Client),
+    Replay(Arc<ReplayCorpus>),
+}
+
+impl Fetcher {
+    pub fn new(config: &CrawlerConfig) -> Result<Self> {
+        match &config.replay {
+            Some(replay) => Ok(Self::Replay(Arc::new(ReplayCorpus::open(replay)?))),
+            None => Ok(Self::Http(reqwest_client(config)?)),
+        }
+    }
+
+    /// Send a `GET` request for the url. The timeout overrides the timeout of the client.
+    pub async fn get(
+        &self,
+        url: &Url,
+        headers: HeaderMap,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        match self {
+            Fetcher::Http(client) => {
+                let mut req = client.get(url.as_str()).headers(headers);
+
+                if let Some(timeout) = timeout {
+                    req = req.timeout(timeout);
+                }
+
+                Ok(Response::Http(req.send().await?))
+            }
+            Fetcher::Replay(corpus) => Ok(Response::Replay(corpus.get(url, &headers).await)),
+        }
+    }
+}
+
+pub enum Response {
+    Http(reqwest::Response),
+    Replay(ReplayResponse),
+}
+
+impl Response {
+    /// The final url of the response (after redirects).
+    pub fn url(&self) -> &Url {
+        match self {
+            Response::Http(res) => res.url(),
+            Response::Replay(res) => &res.url,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Response::Http(res) => res.status(),
+            Response::Replay(res) => res.status,
+        }
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        match self {
+            Response::Http(res) => res.headers(),
+            Response::Replay(res) => &res.headers,
+        }
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            Response::Http(res) => res.content_length(),
+            Response::Replay(res) => Some(res.body.len() as u64),
+        }
+    }
+
+    /// Read the body. Fails if the body is larger than `limit` bytes.
+    pub async fn bytes(self, limit: usize) -> Result<Vec<u8>> {
+        match self {
+            Response::Http(res) => {
+                let mut bytes = Vec::new();
+                let mut stream = res.bytes_stream();
+
+                while let Some(b) = stream.next().await {
+                    bytes.extend_from_slice(&b?);
+
+                    if bytes.len() > limit {
+                        return Err(Error::ContentTooLarge.into());
+                    }
+                }
+
+                Ok(bytes)
+            }
+            Response::Replay(res) => {
+                if res.body.len() > limit {
+                    return Err(Error::ContentTooLarge.into());
+                }
+
+                Ok(res.body)
+            }
+        }
+    }
+
+    pub async fn text(self) -> Result<String> {
+        match self {
+            Response::Http(res) => Ok(res.text().await?),
+            Response::Replay(res) => Ok(String::from_utf8_lossy(&res.body).to_string()),
+        }
+    }
+}
@@ -83,10 +83,14 @@ where
         self.flush()?;
 
         let file = self.writer.into_inner()?;
+        let mut pointer = FilePointer::new(self.path)?;
+        let file = unsafe { Mmap::map(&file)? };
+        let len = num_items(&file, pointer.get())?;
 
         Ok(FileQueue {
-            pointer: FilePointer::new(self.path)?,
-            file: unsafe { Mmap::map(&file)? },
+            pointer,
+            file,
+            len,
             _marker: std::marker::PhantomData,
         })
     }
@@ -132,9 +136,24 @@ impl FilePointer {
     }
 }
 
+/// Number of items in the file after the pointer.
+fn num_items(file: &[u8], mut pointer: usize) -> Result<usize> {
+    let header_size = std::mem::size_of::<Header>();
+    let mut num_items = 0;
+
+    while pointer + header_size <= file.len() {
+        let header: Header = bincode::deserialize(&file[pointer..pointer + header_size])?;
+        pointer += header_size + header.body_size;
+        num_items += 1;
+    }
+
+    Ok(num_items)
+}
+
 pub struct FileQueue<T> {
     pointer: FilePointer,
     file: Mmap,
+    len: usize,
     _marker: std::marker::PhantomData<T>,
 }
 
@@ -149,14 +168,26 @@ where
 
         let file = File::open(path.as_ref().join(DATA_KEY))?;
         let file = unsafe { Mmap::map(&file)? };
+        let mut pointer = FilePointer::new(path)?;
+        let len = num_items(&file, pointer.get())?;
 
         Ok(Self {
-            pointer: FilePointer::new(path)?,
+            pointer,
             file,
+            len,
             _marker: std::marker::PhantomData,
         })
     }
 
+    /// Number of items left in the queue.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn pop(&mut self) -> Result<Option<T>> {
         let cur_pointer = self.pointer.get();
 
@@ -174,6 +205,7 @@ where
 
         self.pointer
             .set(cur_pointer + header_size + header.body_size)?;
+        self.len = self.len.saturating_sub(1);
 
         Ok(Some(item))
     }
@@ -193,9 +225,30 @@ mod tests {
 
         let mut queue = writer.finalize().unwrap();
 
+        assert_eq!(queue.len(), 2);
         assert_eq!(queue.pop().unwrap().unwrap(), "Hello");
+        assert_eq!(queue.len(), 1);
         assert_eq!(queue.pop().unwrap().unwrap(), "World");
         assert_eq!(queue.pop().unwrap(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn len_after_reopen() {
+        let path = crate::gen_temp_path();
+        let mut writer = FileQueueWriter::new(&path).unwrap();
+
+        for i in 0..10 {
+            writer.push(i.to_string()).unwrap();
+        }
+
+        let mut queue = writer.finalize().unwrap();
+        queue.pop().unwrap();
+        queue.pop().unwrap();
+        drop(queue);
+
+        let queue = FileQueue::<String>::new(&path).unwrap();
+        assert_eq!(queue.len(), 8);
     }
 
     proptest! {
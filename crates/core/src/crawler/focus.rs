@@ -0,0 +1,594 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Focused crawling.
+//!
+//! The worker scores every fetched page against a topic, given either as an optic
+//! or as a naive bayes classifier. The relevance (in `[0, 1]`, where 0.5 is neutral) is used to
+//! boost the outlinks of on-topic pages, and the average relevance of each host is recorded
+//! so the planner can move crawl budget away from off-topic hosts.
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use optics::{Action, MatchLocation, Matching, Optic};
+use tantivy::tokenizer::PreTokenizedString;
+
+use crate::{
+    config::TopicConfig,
+    kv::{rocksdb_store::RocksDbStore, KeyLocks, Kv},
+    query::pattern_query,
+    schema::TextField,
+    webpage::{safety_classifier, url_ext::UrlExt, Html},
+    Result,
+};
+
+/// Relevance of pages that are neither on- nor off-topic.
+pub const NEUTRAL_RELEVANCE: f64 = 0.5;
+
+/// Smallest fraction of its budget that an off-topic host keeps.
+const MIN_BUDGET_FACTOR: f64 = 0.05;
+
+/// Added to the score of pages from hosts that are liked by the optic
+/// (and subtracted for disliked hosts).
+const HOST_PREFERENCE_SCORE: f64 = 2.0;
+
+fn empty_field() -> PreTokenizedString {
+    PreTokenizedString {
+        text: String::new(),
+        tokens: Vec::new(),
+    }
+}
+
+/// The fields of a page that an optic can match, tokenized the same way as when the page
+/// is indexed, so a rule matches the page exactly when it would match the indexed page.
+struct PageFields {
+    host: String,
+    site: PreTokenizedString,
+    url: PreTokenizedString,
+    domain: PreTokenizedString,
+    title: PreTokenizedString,
+    description: PreTokenizedString,
+    content: PreTokenizedString,
+    microformats: PreTokenizedString,
+    schemas: PreTokenizedString,
+}
+
+impl PageFields {
+    fn new(html: &Html) -> Self {
+        Self {
+            host: html.url().normalized_host().unwrap_or_default().to_string(),
+            site: html.pretokenize_url_for_site_operator(),
+            url: html.pretokenize_url(),
+            domain: html.pretokenize_domain(),
+            title: html.pretokenize_title().unwrap_or_else(|_| empty_field()),
+            description: html.pretokenize_description(),
+            content: html.pretokenize_clean_text(),
+            microformats: html.pretokenize_microformats(),
+            schemas: Html::pretokenize_schema_json(html.schema_org()),
+        }
+    }
+
+    fn matches(&self, matching: &Matching) -> bool {
+        let (field, text) = match matching.location {
+            MatchLocation::Site => (TextField::UrlForSiteOperator, &self.site),
+            MatchLocation::Url => (TextField::Url, &self.url),
+            MatchLocation::Domain => (TextField::Domain, &self.domain),
+            MatchLocation::Title => (TextField::Title, &self.title),
+            MatchLocation::Description => (TextField::Description, &self.description),
+            MatchLocation::Content => (TextField::CleanBody, &self.content),
+            MatchLocation::MicroformatTag => (TextField::MicroformatTags, &self.microformats),
+            MatchLocation::Schema => (TextField::FlattenedSchemaOrgJson, &self.schemas),
+            // the spam label and community come from the analysis of the host graph, which is not known while crawling
+            MatchLocation::Spam | MatchLocation::Community => return false,
+        };
+
+        // patterns like `|example.com|` are matched against the whole host or domain
+        let untokenized = match matching.location {
+            MatchLocation::Site => self.host.as_str(),
+            _ => text.text.as_str(),
+        };
+
+        pattern_query::matches_tokens(&matching.pattern, field, &text.tokens, untokenized)
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Score a page by the rules of the optic. Pages that the optic would discard are off-topic,
+/// boosts make a page more relevant and downranks make it less relevant.
+fn optic_relevance(optic: &Optic, html: &Html) -> f64 {
+    let page = PageFields::new(html);
+
+    let host = page.host.as_str();
+    let host_matches = |hosts: &[String]| {
+        hosts
+            .iter()
+            .any(|h| host == h || host.ends_with(&format!(".{h}")))
+    };
+
+    if host_matches(&optic.host_rankings.blocked) {
+        return 0.0;
+    }
+
+    let mut score = 0.0;
+    let mut has_matched = false;
+
+    for rule in &optic.rules {
+        let rule_matches = rule
+            .matches
+            .iter()
+            .any(|matchings| matchings.iter().all(|matching| page.matches(matching)));
+
+        if !rule_matches {
+            continue;
+        }
+
+        has_matched = true;
+
+        match rule.action {
+            Action::Boost(boost) => score += boost as f64,
+            Action::Downrank(downrank) => score -= downrank as f64,
+            Action::Discard => return 0.0,
+        }
+    }
+
+    if optic.discard_non_matching {
+        if !has_matched {
+            return 0.0;
+        }
+
+        // the page is part of the topic the optic selects
+        score += 1.0;
+    }
+
+    if host_matches(&optic.host_rankings.liked) {
+        score += HOST_PREFERENCE_SCORE;
+    }
+
+    if host_matches(&optic.host_rankings.disliked) {
+        score -= HOST_PREFERENCE_SCORE;
+    }
+
+    sigmoid(score)
+}
+
+fn page_text(html: &Html) -> String {
+    html.title().unwrap_or_default()
+        + " "
+        + html.clean_text().map(|s| s.as_str()).unwrap_or_default()
+}
+
+/// Naive bayes classifier that predicts the topic of a page.
+pub type TopicClassifier = safety_classifier::Model<String>;
+
+/// The topic of a focused crawl.
+pub enum Topic {
+    Optic(Optic),
+    Classifier {
+        model: TopicClassifier,
+        /// The label of on-topic pages.
+        label: String,
+    },
+}
+
+impl Topic {
+    pub fn open(config: &TopicConfig) -> Result<Self> {
+        match config {
+            TopicConfig::Optic { path } => {
+                let optic = std::fs::read_to_string(path)?;
+                let optic = Optic::parse(&optic)
+                    .map_err(|err| anyhow::anyhow!("failed to parse topic optic: {err}"))?;
+
+                Ok(Self::Optic(optic))
+            }
+            TopicConfig::Classifier { model_path, label } => Ok(Self::Classifier {
+                model: TopicClassifier::open(model_path)?,
+                label: label.clone(),
+            }),
+        }
+    }
+
+    /// How relevant the page is to the topic, in `[0, 1]`.
+    pub fn relevance(&self, html: &Html) -> f64 {
+        match self {
+            Topic::Optic(optic) => optic_relevance(optic, html),
+            Topic::Classifier { model, label } => {
+                let prediction = model.predict_text(&page_text(html));
+                let confidence = (prediction.confidence as f64).clamp(0.0, 1.0);
+
+                if &prediction.label == label {
+                    confidence
+                } else {
+                    1.0 - confidence
+                }
+            }
+        }
+    }
+}
+
+/// Weight given to the outlinks of a page with the given relevance.
+/// Links from on-topic pages get up to twice their weight plus the relevance itself,
+/// so pages found while wandering (which have no weight of their own) are still prioritised by topic.
+pub fn outlink_weight(weight: f64, relevance: f64) -> f64 {
+    weight * budget_factor(relevance) + relevance
+}
+
+/// How much the crawl budget of a host is scaled by its relevance.
+/// Neutral hosts keep their budget, on-topic hosts get up to twice the budget
+/// and off-topic hosts keep a small part of their budget, so a host that was misjudged
+/// from a few pages can still be re-scored in later crawls.
+pub fn budget_factor(relevance: f64) -> f64 {
+    (relevance / NEUTRAL_RELEVANCE).clamp(MIN_BUDGET_FACTOR, 2.0)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HostRelevance {
+    pub total: f64,
+    pub num_pages: u64,
+}
+
+impl HostRelevance {
+    pub fn mean(&self) -> f64 {
+        if self.num_pages == 0 {
+            return NEUTRAL_RELEVANCE;
+        }
+
+        self.total / self.num_pages as f64
+    }
+
+    pub fn record(&mut self, relevance: f64) {
+        self.total += relevance;
+        self.num_pages += 1;
+    }
+
+    pub fn merge(&mut self, other: &HostRelevance) {
+        self.total += other.total;
+        self.num_pages += other.num_pages;
+    }
+}
+
+/// Average relevance of the pages crawled from each host.
+pub struct HostRelevanceStore {
+    store: RocksDbStore<String, HostRelevance>,
+    /// The relevance of a host is read, modified and written back,
+    /// so concurrent updates of the same host must be serialized.
+    locks: KeyLocks,
+}
+
+impl HostRelevanceStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open(path),
+            locks: KeyLocks::default(),
+        }
+    }
+
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            store: RocksDbStore::open_read_only(path),
+            locks: KeyLocks::default(),
+        }
+    }
+
+    pub fn get(&self, host: &str) -> Option<HostRelevance> {
+        self.store.get(&host.to_string())
+    }
+
+    /// Add the relevance of more pages from the host.
+    pub fn add(&self, host: &str, relevance: &HostRelevance) {
+        let _guard = self.locks.lock(host);
+
+        let mut state = self.get(host).unwrap_or_default();
+        state.merge(relevance);
+
+        self.store.insert(host.to_string(), state);
+    }
+
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+/// The host relevance recorded by several crawlers, read as if it was a single store.
+pub struct MergedHostRelevance {
+    stores: Vec<HostRelevanceStore>,
+}
+
+impl MergedHostRelevance {
+    pub fn open_read_only<P: AsRef<Path>>(paths: &[P]) -> Self {
+        Self {
+            stores: paths
+                .iter()
+                .map(HostRelevanceStore::open_read_only)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stores.is_empty()
+    }
+
+    pub fn get(&self, host: &str) -> Option<HostRelevance> {
+        self.stores
+            .iter()
+            .filter_map(|store| store.get(host))
+            .reduce(|mut total, relevance| {
+                total.merge(&relevance);
+                total
+            })
+    }
+
+    /// How much the crawl budget of the host should be scaled.
+    /// Hosts that have not been crawled keep their budget.
+    pub fn budget_factor(&self, host: &str) -> f64 {
+        let host = host.strip_prefix("www.").unwrap_or(host);
+
+        self.get(host)
+            .map(|relevance| budget_factor(relevance.mean()))
+            .unwrap_or(1.0)
+    }
+}
+
+/// The topics of the crawl plans, opened once and shared by all the jobs of a plan,
+/// and where the relevance of the crawled hosts is recorded.
+pub struct Topics {
+    opened: Mutex<HashMap<TopicConfig, Arc<Topic>>>,
+    host_relevance: Option<Arc<HostRelevanceStore>>,
+}
+
+impl Topics {
+    pub fn new(host_relevance: Option<HostRelevanceStore>) -> Self {
+        Self {
+            opened: Mutex::new(HashMap::new()),
+            host_relevance: host_relevance.map(Arc::new),
+        }
+    }
+
+    fn open(&self, config: &TopicConfig) -> Result<Arc<Topic>> {
+        let mut opened = self.opened.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(topic) = opened.get(config) {
+            return Ok(Arc::clone(topic));
+        }
+
+        let topic = Arc::new(Topic::open(config)?);
+        opened.insert(config.clone(), Arc::clone(&topic));
+
+        Ok(topic)
+    }
+
+    /// The focus of a job that is part of a crawl plan with the topic.
+    pub fn focus(&self, config: &TopicConfig) -> Result<Focus> {
+        Ok(Focus {
+            topic: self.open(config)?,
+            host_relevance: self.host_relevance.clone(),
+            recorded: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn flush(&self) {
+        if let Some(host_relevance) = &self.host_relevance {
+            host_relevance.flush();
+        }
+    }
+}
+
+/// The topic of a focused crawl and where the relevance of the crawled hosts is recorded.
+pub struct Focus {
+    topic: Arc<Topic>,
+    host_relevance: Option<Arc<HostRelevanceStore>>,
+    /// The relevance of the pages scored by the job, written to the store when the job is done.
+    recorded: Mutex<HashMap<String, HostRelevance>>,
+}
+
+impl Focus {
+    /// Score the page against the topic and record the relevance for its host.
+    pub fn relevance(&self, html: &Html) -> f64 {
+        let relevance = self.topic.relevance(html);
+
+        if let (Some(_), Some(host)) = (&self.host_relevance, html.url().normalized_host()) {
+            self.recorded
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(host.to_string())
+                .or_default()
+                .record(relevance);
+        }
+
+        relevance
+    }
+
+    /// Write the relevance recorded by the job to the store. This reads and writes
+    /// the store, so it should not be called from the async runtime.
+    pub fn save(&self) {
+        let Some(host_relevance) = &self.host_relevance else {
+            return;
+        };
+
+        let recorded =
+            std::mem::take(&mut *self.recorded.lock().unwrap_or_else(|e| e.into_inner()));
+
+        for (host, relevance) in recorded {
+            host_relevance.add(&host, &relevance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(url: &str, title: &str, content: &str) -> Html {
+        Html::parse(
+            &format!(
+                "<html><head><title>{title}</title></head><body><p>{content}</p></body></html>"
+            ),
+            url,
+        )
+        .unwrap()
+    }
+
+    fn content_matches(page: &PageFields, pattern: &str) -> bool {
+        let optic =
+            Optic::parse(&format!("Rule {{ Matches {{ Content(\"{pattern}\") }} }};")).unwrap();
+
+        page.matches(&optic.rules[0].matches[0][0])
+    }
+
+    #[test]
+    fn pattern_matching() {
+        let page = PageFields::new(&html(
+            "https://example.com/",
+            "Fox",
+            "The quick brown fox jumps over the lazy dog",
+        ));
+
+        assert!(content_matches(&page, "brown fox"));
+        assert!(!content_matches(&page, "fox brown"));
+        assert!(!content_matches(&page, "quick fox"));
+        assert!(content_matches(&page, "quick * fox"));
+        assert!(content_matches(&page, "|the quick"));
+        assert!(!content_matches(&page, "|quick"));
+        assert!(content_matches(&page, "lazy dog|"));
+        assert!(!content_matches(&page, "lazy|"));
+        assert!(content_matches(&page, "|the * dog|"));
+        assert!(!content_matches(&page, "|dog * the|"));
+    }
+
+    #[test]
+    fn optic_topic() {
+        let topic = Topic::Optic(
+            Optic::parse(
+                r#"
+                DiscardNonMatching;
+                Rule { Matches { Content("rust") }, Action(Boost(2)) };
+                Rule { Matches { Site("|spam.com|") }, Action(Discard) };
+                Rule { Matches { Title("game") }, Action(Downrank(4)) };
+            "#,
+            )
+            .unwrap(),
+        );
+
+        let on_topic = html(
+            "https://blog.example.com/",
+            "Ownership",
+            "rust has a borrow checker",
+        );
+        let off_topic = html("https://example.com/", "Cooking", "how to bake bread");
+        let discarded = html("https://spam.com/", "Rust", "rust rust rust");
+        let game = html("https://example.com/", "Rust the game", "rust is a game");
+
+        assert!(topic.relevance(&on_topic) > 0.9);
+        assert_eq!(topic.relevance(&off_topic), 0.0);
+        assert_eq!(topic.relevance(&discarded), 0.0);
+        assert!(topic.relevance(&game) < NEUTRAL_RELEVANCE);
+    }
+
+    #[test]
+    fn classifier_topic() {
+        let mut model = TopicClassifier::new();
+        let datapoint = |label: &str, text: &str| safety_classifier::Datapoint {
+            label: label.to_string(),
+            text: text.to_string(),
+        };
+
+        let dataset = vec![
+            datapoint("rust", "rust borrow checker ownership lifetimes cargo"),
+            datapoint("rust", "cargo crates rust compiler traits"),
+            datapoint("rust", "ownership and borrowing in rust"),
+            datapoint("cooking", "bake the bread in the oven"),
+            datapoint("cooking", "a recipe with flour sugar and eggs"),
+            datapoint("cooking", "cooking pasta with tomato sauce"),
+        ];
+        model.fit(&dataset);
+
+        let topic = Topic::Classifier {
+            model,
+            label: "rust".to_string(),
+        };
+
+        let on_topic = html(
+            "https://example.com/",
+            "Rust",
+            "cargo and the borrow checker",
+        );
+        let off_topic = html("https://example.com/", "Bread", "bake bread with flour");
+
+        assert!(topic.relevance(&on_topic) > topic.relevance(&off_topic));
+    }
+
+    #[test]
+    fn host_relevance() {
+        let path = crate::gen_temp_path();
+        let store = HostRelevanceStore::open(&path);
+
+        let mut on_topic = HostRelevance::default();
+        on_topic.record(1.0);
+        on_topic.record(0.8);
+
+        let mut off_topic = HostRelevance::default();
+        off_topic.record(0.0);
+
+        store.add("example.com", &on_topic);
+        store.add("spam.com", &off_topic);
+        store.flush();
+        drop(store);
+
+        let other_path = crate::gen_temp_path();
+        let other = HostRelevanceStore::open(&other_path);
+        other.add("example.com", &on_topic);
+        other.flush();
+        drop(other);
+
+        let merged = MergedHostRelevance::open_read_only(&[path, other_path]);
+
+        assert_eq!(merged.budget_factor("other.com"), 1.0);
+        assert_eq!(merged.get("example.com").unwrap().num_pages, 4);
+        assert!(merged.budget_factor("www.example.com") > 1.5);
+        assert_eq!(merged.budget_factor("spam.com"), MIN_BUDGET_FACTOR);
+
+        assert!(outlink_weight(1.0, 1.0) > outlink_weight(1.0, NEUTRAL_RELEVANCE));
+        assert!(outlink_weight(0.0, 1.0) > outlink_weight(0.0, 0.0));
+    }
+
+    #[test]
+    fn focus_records_after_job() {
+        let store = Arc::new(HostRelevanceStore::open(crate::gen_temp_path()));
+        let focus = Focus {
+            topic: Arc::new(Topic::Optic(
+                Optic::parse(r#"Rule { Matches { Content("rust") }, Action(Boost(2)) };"#).unwrap(),
+            )),
+            host_relevance: Some(Arc::clone(&store)),
+            recorded: Mutex::new(HashMap::new()),
+        };
+
+        focus.relevance(&html("https://www.example.com/", "Rust", "rust"));
+        focus.relevance(&html("https://example.com/a", "Bread", "bread"));
+
+        assert!(store.get("example.com").is_none());
+
+        focus.save();
+
+        let relevance = store.get("example.com").unwrap();
+        assert_eq!(relevance.num_pages, 2);
+        assert!(relevance.mean() > NEUTRAL_RELEVANCE);
+    }
+}
@@ -20,19 +20,39 @@ use hashbrown::HashMap;
 
 use url::Url;
 
-use crate::{config::CrawlerConfig, warc, webpage::url_ext::UrlExt};
-
-use self::{warc_writer::WarcWriter, worker::WorkerThread};
-pub use worker::JobExecutor;
-
+use crate::{
+    config::{CrawlerConfig, TopicConfig},
+    metrics::{GaugeFamily, PrometheusRegistry},
+    warc,
+    webpage::url_ext::UrlExt,
+};
+
+use self::{
+    crawl_state::CrawlStateStore,
+    fetcher::Fetcher,
+    focus::{HostRelevanceStore, Topics},
+    politeness::PolitenessMetrics,
+    warc_writer::WarcWriter,
+    worker::WorkerThread,
+};
+pub use worker::{JobExecutor, JobReport};
+
+pub mod admin;
 pub mod coordinator;
+pub mod crawl_state;
+pub mod crawl_trap;
+pub mod fetcher;
+pub mod focus;
 mod robots_txt;
 pub mod router;
 pub use router::Router;
 mod file_queue;
 pub mod planner;
+pub mod politeness;
+pub mod replay;
+pub mod sitemap;
 mod wander_prirotiser;
-mod warc_writer;
+pub mod warc_writer;
 mod worker;
 
 pub use coordinator::CrawlCoordinator;
@@ -60,6 +80,9 @@ pub enum Error {
 
     #[error("invalid redirect")]
     InvalidRedirect,
+
+    #[error("the crawl is draining and can't be resumed")]
+    CrawlDrained,
 }
 
 type Result<T, E = anyhow::Error> = std::result::Result<T, E>;
@@ -109,6 +132,33 @@ pub struct Job {
     pub domain: Domain,
     pub urls: VecDeque<WeightedUrl>,
     pub wandering_urls: u64,
+    /// Topic of the focused crawl the job is part of.
+    pub topic: Option<TopicConfig>,
+}
+
+/// Response to a worker that asks for a new job.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub enum JobResponse {
+    Job(Job),
+    /// The crawl is paused. The worker should ask again later.
+    Wait,
+    /// There are no more jobs.
+    Done,
+}
+
+/// Identifies a worker thread across the crawl.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub struct WorkerId(String);
+
+impl WorkerId {
+    /// A new id for worker thread number `thread` in this process.
+    pub fn new(thread: usize) -> Self {
+        static PROCESS_ID: once_cell::sync::Lazy<u64> = once_cell::sync::Lazy::new(rand::random);
+
+        Self(format!("{:016x}-{}", *PROCESS_ID, thread))
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -116,6 +166,7 @@ pub enum UrlResponse {
     Success { url: Url },
     Failed { url: Url, status_code: Option<u16> },
     Redirected { url: Url, new_url: Url },
+    NotModified { url: Url },
 }
 
 #[derive(
@@ -200,18 +251,34 @@ pub struct CrawlDatum {
     pub url: Url,
     pub status_code: u16,
     pub payload_type: warc::PayloadType,
-    pub body: String,
+    pub body: Vec<u8>,
     pub fetch_time_ms: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
 }
 
 pub struct Crawler {
     writer: Arc<WarcWriter>,
+    crawl_state: Option<Arc<CrawlStateStore>>,
+    topics: Arc<Topics>,
     handles: Vec<tokio::task::JoinHandle<()>>,
 }
 
 impl Crawler {
-    pub async fn new(config: CrawlerConfig) -> Result<Self> {
-        let writer = Arc::new(WarcWriter::new(config.s3.clone()));
+    /// Create the worker threads. Their metrics are registered in the registry.
+    pub async fn new(config: CrawlerConfig, registry: &mut PrometheusRegistry) -> Result<Self> {
+        let writer = Arc::new(WarcWriter::from_config(config.output.clone()));
+        let crawl_state = config
+            .crawl_state_path
+            .as_ref()
+            .map(|path| Arc::new(CrawlStateStore::open(path)));
+        let topics = Arc::new(Topics::new(
+            config
+                .host_relevance_path
+                .as_ref()
+                .map(HostRelevanceStore::open),
+        ));
+        let fetcher = Fetcher::new(&config)?;
         let mut handles = Vec::new();
         let mut router_hosts = Vec::new();
 
@@ -219,16 +286,35 @@ impl Crawler {
             router_hosts.push(host.parse()?);
         }
 
-        for _ in 0..config.num_worker_threads {
-            let worker =
-                WorkerThread::new(Arc::clone(&writer), config.clone(), router_hosts.clone())?;
+        let delays = GaugeFamily::new("host");
+        let metrics: Vec<_> = (0..config.num_worker_threads)
+            .map(|_| PolitenessMetrics::new(delays.clone()))
+            .collect();
+        politeness::register_metrics(registry, &metrics)?;
+
+        for (i, metrics) in metrics.into_iter().enumerate() {
+            let worker = WorkerThread::new(
+                WorkerId::new(i),
+                Arc::clone(&writer),
+                crawl_state.clone(),
+                Arc::clone(&topics),
+                fetcher.clone(),
+                config.clone(),
+                router_hosts.clone(),
+                metrics,
+            )?;
 
             handles.push(tokio::spawn(async move {
                 worker.run().await;
             }));
         }
 
-        Ok(Self { writer, handles })
+        Ok(Self {
+            writer,
+            crawl_state,
+            topics,
+            handles,
+        })
     }
 
     pub async fn run(self) {
@@ -237,6 +323,12 @@ impl Crawler {
         }
 
         self.writer.finish().await.unwrap();
+
+        if let Some(crawl_state) = self.crawl_state {
+            crawl_state.flush();
+        }
+
+        self.topics.flush();
     }
 }
 
@@ -251,7 +343,9 @@ pub fn reqwest_client(config: &CrawlerConfig) -> Result<reqwest::Client> {
     let mut headers = reqwest::header::HeaderMap::default();
     headers.insert(
         reqwest::header::ACCEPT,
-        reqwest::header::HeaderValue::from_static("text/html"),
+        reqwest::header::HeaderValue::from_static(
+            "text/html,application/pdf;q=0.9,text/plain;q=0.8,text/markdown;q=0.8",
+        ),
     );
     headers.insert(
         reqwest::header::ACCEPT_LANGUAGE,
@@ -27,6 +27,9 @@ use std::{
 };
 use url::Url;
 
+use crate::crawler::crawl_state::MergedCrawlState;
+use crate::crawler::crawl_trap::CrawlTraps;
+use crate::crawler::focus::MergedHostRelevance;
 use crate::crawler::WeightedUrl;
 use crate::webgraph::centrality::{top_hosts, TopHosts};
 use crate::{
@@ -82,16 +85,55 @@ fn check_config(config: &CrawlPlannerConfig) -> Result<()> {
     Ok(())
 }
 
+/// Order the urls of a host by how likely they are to have changed since they were last crawled,
+/// weighted by their centrality. Urls that have never been crawled are ordered by centrality alone.
+fn prioritise_by_change_rate(
+    urls: impl Iterator<Item = WeightedUrl>,
+    crawl_state: &MergedCrawlState,
+) -> Vec<WeightedUrl> {
+    let mut prioritised: Vec<_> = urls
+        .map(|url| (url.weight * crawl_state.change_probability(&url.url), url))
+        .collect();
+
+    prioritised.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    prioritised.into_iter().map(|(_, url)| url).collect()
+}
+
+/// What is known from previous crawls.
+#[derive(Default)]
+pub struct CrawlHistory {
+    /// State of the previously crawled urls, recorded by all the crawlers.
+    pub crawl_state: Option<MergedCrawlState>,
+    /// Crawl traps reported by the coordinators.
+    pub crawl_traps: CrawlTraps,
+    /// Relevance of the crawled hosts to the topic of a focused crawl.
+    pub host_relevance: Option<MergedHostRelevance>,
+}
+
 pub fn make_crawl_plan<P: AsRef<Path>>(
     host_centrality: RocksDbStore<NodeID, f64>,
     page_centrality: RocksDbStore<NodeID, f64>,
     host_graph: Webgraph,
     page_graph: Webgraph,
+    history: CrawlHistory,
     config: CrawlPlannerConfig,
     output: P,
 ) -> Result<()> {
     check_config(&config)?;
 
+    let CrawlHistory {
+        crawl_state,
+        crawl_traps,
+        host_relevance,
+    } = history;
+
+    tracing::info!("excluding {} crawl traps", crawl_traps.len());
+
+    if host_relevance.is_some() {
+        tracing::info!("scaling host budgets by their relevance to the topic");
+    }
+
     if output.as_ref().exists() {
         return Err(anyhow!("output path already exists"));
     }
@@ -106,10 +148,19 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
     let num_hosts = hosts.len();
     tracing::info!("found {} hosts", num_hosts);
 
-    let mut total_host_centrality = hosts
-        .iter()
-        .filter_map(|v| host_centrality.get(v))
-        .sum::<f64>();
+    // the centrality of a host, scaled by its relevance if this is a focused crawl
+    let host_weight = |host: &NodeID| {
+        let centrality = host_centrality.get(host).unwrap_or_default();
+
+        match (&host_relevance, host_graph.id2node(host)) {
+            (Some(host_relevance), Some(node)) => {
+                centrality * host_relevance.budget_factor(&node.name)
+            }
+            _ => centrality,
+        }
+    };
+
+    let mut total_host_centrality = hosts.iter().map(host_weight).sum::<f64>();
     let grouped = group_domain(&hosts, &host_graph);
     let num_groups = grouped.len();
 
@@ -147,7 +198,7 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
             .par_iter()
             .progress_count(num_hosts as u64)
             .map(|host| {
-                let host_centrality = host_centrality.get(host).unwrap_or_default();
+                let host_centrality = host_weight(host);
 
                 let host_budget = ((config.crawl_budget as f64 * host_centrality)
                     / total_host_centrality)
@@ -185,7 +236,7 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                 let host_budget = host_budgets.get_mut(host).unwrap();
 
                 if num_pages > *host_budget {
-                    let centrality = host_centrality.get(host).unwrap_or_default();
+                    let centrality = host_weight(host);
                     new_total_host_centrality += centrality;
 
                     let part = (centrality * surplus_budget as f64 / total_host_centrality)
@@ -253,18 +304,25 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                     total_wander_budget += wander_budget;
                     total_schedule_budget += schedule_budget;
 
+                    let pages = pages
+                        .into_iter()
+                        .filter_map(|(id, score)| page_graph.id2node(&id).map(|n| (n, score)))
+                        .map(|(n, score)| (n.name, score))
+                        .filter_map(|(n, score)| {
+                            Url::parse(&format!("http://{n}")).ok().map(|u| (u, score))
+                        })
+                        .filter(|(url, _)| !crawl_traps.is_trapped(url))
+                        .map(|(url, score)| WeightedUrl { url, weight: score });
+
                     let before = urls.len();
-                    urls.extend(
-                        pages
-                            .into_iter()
-                            .filter_map(|(id, score)| page_graph.id2node(&id).map(|n| (n, score)))
-                            .map(|(n, score)| (n.name, score))
-                            .filter_map(|(n, score)| {
-                                Url::parse(&format!("http://{n}")).ok().map(|u| (u, score))
-                            })
-                            .map(|(url, score)| WeightedUrl { url, weight: score })
-                            .take(schedule_budget as usize),
-                    );
+                    match &crawl_state {
+                        Some(crawl_state) => urls.extend(
+                            prioritise_by_change_rate(pages, crawl_state)
+                                .into_iter()
+                                .take(schedule_budget as usize),
+                        ),
+                        None => urls.extend(pages.take(schedule_budget as usize)),
+                    }
 
                     total_scheduled_urls += urls.len() as u64 - before as u64;
                 }
@@ -280,6 +338,7 @@ pub fn make_crawl_plan<P: AsRef<Path>>(
                     domain: domain.clone(),
                     urls,
                     wandering_urls: total_wander_budget,
+                    topic: config.topic.clone(),
                 };
 
                 let domain_stats = DomainStats {
@@ -0,0 +1,441 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Adaptive per-host politeness.
+//!
+//! The delay between two fetches from the same host is based on a rolling average of the
+//! fetch latency, scaled by a backoff multiplier. The multiplier doubles every time the host
+//! responds with a server error (or does not respond at all) and slowly decays again while
+//! the host is healthy. The adaptive delay is kept within `min_crawl_delay_ms` and `max_crawl_delay_ms`.
+//!
+//! `Retry-After` headers and the robots.txt `Crawl-delay` are honoured as lower bounds and never
+//! shortened. A host that asks to wait longer than `max_crawl_delay_ms` is deferred instead:
+//! its remaining urls are skipped for the rest of the job, so it cannot stall the worker.
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+
+use crate::{
+    config::CrawlerConfig,
+    metrics::{self, Counter, GaugeFamily, Label, PrometheusRegistry},
+};
+
+/// Weight of the newest observation in the rolling averages.
+const EWMA_ALPHA: f64 = 0.2;
+
+/// Every unit of error rate adds this many times the base delay.
+const ERROR_RATE_PENALTY: f64 = 4.0;
+
+/// The backoff multiplier is multiplied by this after each healthy fetch.
+const RECOVERY_FACTOR: f64 = 0.9;
+
+/// Hosts are considered healthy when their error rate is below this.
+const HEALTHY_ERROR_RATE: f64 = 0.05;
+
+/// Lowest backoff multiplier. Healthy hosts are crawled up to this much faster
+/// than the configured politeness factor would suggest.
+const MIN_BACKOFF: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Healthy,
+    Error,
+}
+
+impl Outcome {
+    fn from_status(status_code: Option<u16>) -> Self {
+        match status_code {
+            Some(429) => Self::Error,
+            Some(status_code) if status_code >= 500 => Self::Error,
+            Some(_) => Self::Healthy,
+            None => Self::Error,
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, which is either a number of seconds or an http date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let secs = (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).num_seconds();
+
+    Some(Duration::from_secs(secs.max(0) as u64))
+}
+
+#[derive(Debug, Clone)]
+struct HostPoliteness {
+    latency_ms: f64,
+    error_rate: f64,
+    backoff: f64,
+    crawl_delay: Option<Duration>,
+    retry_after: Option<Instant>,
+}
+
+impl Default for HostPoliteness {
+    fn default() -> Self {
+        Self {
+            latency_ms: 0.0,
+            error_rate: 0.0,
+            backoff: 1.0,
+            crawl_delay: None,
+            retry_after: None,
+        }
+    }
+}
+
+impl HostPoliteness {
+    fn record(&mut self, latency: Duration, outcome: Outcome, max_backoff: f64) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = if self.latency_ms == 0.0 {
+            latency_ms
+        } else {
+            EWMA_ALPHA * latency_ms + (1.0 - EWMA_ALPHA) * self.latency_ms
+        };
+
+        let error = match outcome {
+            Outcome::Healthy => 0.0,
+            Outcome::Error => 1.0,
+        };
+        self.error_rate = EWMA_ALPHA * error + (1.0 - EWMA_ALPHA) * self.error_rate;
+
+        match outcome {
+            Outcome::Error => {
+                self.backoff = (self.backoff.max(1.0) * 2.0).min(max_backoff);
+            }
+            Outcome::Healthy if self.error_rate < HEALTHY_ERROR_RATE => {
+                self.backoff = (self.backoff * RECOVERY_FACTOR).max(MIN_BACKOFF);
+            }
+            Outcome::Healthy => {}
+        }
+    }
+
+    /// The delay the host asked for through `Retry-After` or `Crawl-delay`.
+    fn server_delay(&self) -> Duration {
+        let crawl_delay = self.crawl_delay.unwrap_or_default();
+        let retry_after = self
+            .retry_after
+            .map(|retry_after| retry_after.saturating_duration_since(Instant::now()))
+            .unwrap_or_default();
+
+        crawl_delay.max(retry_after)
+    }
+
+    /// The adaptive delay within `min_delay` and `max_delay`, or the delay asked for by the
+    /// server if that is longer.
+    fn delay(&self, min_delay: Duration, max_delay: Duration, politeness_factor: f64) -> Duration {
+        let base = self.latency_ms.max(min_delay.as_secs_f64() * 1000.0);
+        let delay_ms =
+            base * politeness_factor * self.backoff * (1.0 + self.error_rate * ERROR_RATE_PENALTY);
+
+        let delay = Duration::from_secs_f64(delay_ms.min(u64::MAX as f64) / 1000.0);
+
+        delay.clamp(min_delay, max_delay).max(self.server_delay())
+    }
+}
+
+/// Politeness metrics of a worker.
+#[derive(Clone)]
+pub struct PolitenessMetrics {
+    /// The most recent delay of each host that is being crawled. Shared by all workers.
+    pub delay_ms: GaugeFamily,
+    /// Total time spent waiting between fetches.
+    pub total_delay_ms: Counter,
+    /// Number of fetches that made a host back off.
+    pub backoffs: Counter,
+}
+
+impl Default for PolitenessMetrics {
+    fn default() -> Self {
+        Self::new(GaugeFamily::new("host"))
+    }
+}
+
+impl PolitenessMetrics {
+    pub fn new(delay_ms: GaugeFamily) -> Self {
+        Self {
+            delay_ms,
+            total_delay_ms: Counter::default(),
+            backoffs: Counter::default(),
+        }
+    }
+}
+
+/// Register the metrics of all workers in the registry. The delays are shared
+/// by the workers, so they are registered once.
+pub fn register_metrics(
+    registry: &mut PrometheusRegistry,
+    workers: &[PolitenessMetrics],
+) -> Result<(), metrics::Error> {
+    let worker_label = |i: usize| {
+        vec![Label {
+            key: "worker".to_string(),
+            val: i.to_string(),
+        }]
+    };
+
+    let group = registry.new_group(
+        "stract_crawler_delay_ms".to_string(),
+        Some("Most recent delay between two fetches from the same host.".to_string()),
+    )?;
+    if let Some(worker) = workers.first() {
+        group.register(worker.delay_ms.clone(), vec![]);
+    }
+
+    let group = registry.new_group(
+        "stract_crawler_delay_ms_total".to_string(),
+        Some("Total time spent waiting between fetches.".to_string()),
+    )?;
+    for (i, worker) in workers.iter().enumerate() {
+        group.register(worker.total_delay_ms.clone(), worker_label(i));
+    }
+
+    let group = registry.new_group(
+        "stract_crawler_backoffs".to_string(),
+        Some("Number of fetches that made a host back off.".to_string()),
+    )?;
+    for (i, worker) in workers.iter().enumerate() {
+        group.register(worker.backoffs.clone(), worker_label(i));
+    }
+
+    Ok(())
+}
+
+pub struct Politeness {
+    hosts: HashMap<String, HostPoliteness>,
+    min_delay: Duration,
+    max_delay: Duration,
+    politeness_factor: f64,
+    max_backoff: f64,
+    metrics: PolitenessMetrics,
+}
+
+impl Politeness {
+    pub fn new(config: &CrawlerConfig) -> Self {
+        let politeness_factor = config.politeness_factor.max(f32::EPSILON) as f64;
+
+        Self {
+            hosts: HashMap::new(),
+            min_delay: Duration::from_millis(config.min_crawl_delay_ms),
+            max_delay: Duration::from_millis(
+                config.max_crawl_delay_ms.max(config.min_crawl_delay_ms),
+            ),
+            politeness_factor,
+            max_backoff: (config.max_politeness_factor as f64 / politeness_factor).max(1.0),
+            metrics: PolitenessMetrics::default(),
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: PolitenessMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Set the `Crawl-delay` from the robots.txt of the host.
+    pub fn set_crawl_delay(&mut self, host: &str, crawl_delay: Option<Duration>) {
+        self.hosts.entry(host.to_string()).or_default().crawl_delay = crawl_delay;
+    }
+
+    /// Record a fetch from the host. The status code is `None` if the host did not respond.
+    pub fn record(
+        &mut self,
+        host: &str,
+        latency: Duration,
+        status_code: Option<u16>,
+        retry_after: Option<Duration>,
+    ) {
+        let outcome = Outcome::from_status(status_code);
+        let host = self.hosts.entry(host.to_string()).or_default();
+
+        host.record(latency, outcome, self.max_backoff);
+        host.retry_after = retry_after.map(|retry_after| Instant::now() + retry_after);
+
+        if outcome == Outcome::Error {
+            self.metrics.backoffs.inc();
+        }
+    }
+
+    /// The delay before the next fetch from the host, or `None` if the host is deferred
+    /// because it asked to wait longer than the maximum crawl delay.
+    pub fn delay(&self, host_name: &str) -> Option<Duration> {
+        let delay = match self.hosts.get(host_name) {
+            Some(host) => host.delay(self.min_delay, self.max_delay, self.politeness_factor),
+            None => HostPoliteness::default().delay(
+                self.min_delay,
+                self.max_delay,
+                self.politeness_factor,
+            ),
+        };
+
+        self.metrics
+            .delay_ms
+            .set(host_name, delay.as_millis().min(u64::MAX as u128) as u64);
+
+        if delay > self.max_delay {
+            None
+        } else {
+            Some(delay)
+        }
+    }
+
+    /// Whether the host asked to wait longer than the maximum crawl delay.
+    pub fn is_deferred(&self, host: &str) -> bool {
+        self.delay(host).is_none()
+    }
+
+    /// Wait the delay before the next fetch from the host. Deferred hosts are not waited for,
+    /// and `false` is returned so their remaining urls can be skipped.
+    pub async fn wait(&self, host: &str) -> bool {
+        match self.delay(host) {
+            Some(delay) => {
+                self.metrics.total_delay_ms.add(delay.as_millis() as u64);
+                tokio::time::sleep(delay).await;
+
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Politeness {
+    fn drop(&mut self) {
+        // the hosts are no longer crawled by this worker
+        for host in self.hosts.keys() {
+            self.metrics.delay_ms.remove(host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(60);
+
+    fn politeness() -> Politeness {
+        Politeness {
+            hosts: HashMap::new(),
+            min_delay: MIN,
+            max_delay: MAX,
+            politeness_factor: 1.0,
+            max_backoff: 64.0,
+            metrics: PolitenessMetrics::default(),
+        }
+    }
+
+    #[test]
+    fn backoff_and_recovery() {
+        let mut politeness = politeness();
+        let latency = Duration::from_millis(200);
+
+        politeness.record("example.com", latency, Some(200), None);
+        let healthy = politeness.delay("example.com").unwrap();
+        assert_eq!(healthy, Duration::from_millis(180));
+
+        politeness.record("example.com", latency, Some(500), None);
+        let first = politeness.delay("example.com").unwrap();
+        politeness.record("example.com", latency, Some(503), None);
+        let second = politeness.delay("example.com").unwrap();
+
+        assert!(first > healthy);
+        assert!(second > first * 2 - Duration::from_millis(1));
+
+        for _ in 0..100 {
+            politeness.record("example.com", latency, Some(200), None);
+        }
+
+        let recovered = politeness.delay("example.com").unwrap();
+        assert!(recovered < healthy);
+        assert!(recovered < Duration::from_millis(101));
+
+        // other hosts are not affected
+        politeness.record("other.com", latency, Some(500), None);
+        assert_eq!(politeness.delay("example.com"), Some(recovered));
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let mut politeness = politeness();
+
+        for _ in 0..100 {
+            politeness.record("example.com", Duration::from_millis(200), None, None);
+        }
+
+        assert_eq!(politeness.delay("example.com"), Some(MAX));
+        assert_eq!(politeness.hosts["example.com"].backoff, 64.0);
+    }
+
+    #[test]
+    fn retry_after_and_crawl_delay() {
+        let mut politeness = politeness();
+
+        politeness.set_crawl_delay("example.com", Some(Duration::from_secs(10)));
+        politeness.record("example.com", Duration::from_millis(10), Some(200), None);
+        assert_eq!(
+            politeness.delay("example.com"),
+            Some(Duration::from_secs(10))
+        );
+
+        politeness.record(
+            "example.com",
+            Duration::from_millis(10),
+            Some(429),
+            Some(Duration::from_secs(30)),
+        );
+        let delay = politeness.delay("example.com").unwrap();
+        assert!(delay > Duration::from_secs(29) && delay <= Duration::from_secs(30));
+
+        // longer server delays are not shortened, the host is deferred instead
+        politeness.record(
+            "example.com",
+            Duration::from_millis(10),
+            Some(429),
+            Some(Duration::from_secs(3600)),
+        );
+        assert_eq!(politeness.delay("example.com"), None);
+        assert!(politeness.is_deferred("example.com"));
+        assert!(politeness.metrics.delay_ms.get("example.com").unwrap() > 3_599_000);
+
+        politeness.set_crawl_delay("slow.com", Some(MAX * 2));
+        assert!(politeness.is_deferred("slow.com"));
+        assert!(!politeness.is_deferred("other.com"));
+
+        let metrics = politeness.metrics.clone();
+        drop(politeness);
+        assert_eq!(metrics.delay_ms.get("example.com"), None);
+    }
+
+    #[test]
+    fn retry_after_header() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+
+        let future = (chrono::Utc::now() + chrono::Duration::seconds(100)).to_rfc2822();
+        let parsed = parse_retry_after(&future).unwrap();
+        assert!(parsed > Duration::from_secs(95) && parsed <= Duration::from_secs(100));
+
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
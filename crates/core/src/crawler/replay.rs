@@ -0,0 +1,402 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Replay a crawl from a local WARC corpus.
+//!
+//! In replay mode the worker never touches the network. Every fetch is answered from the pages
+//! of a set of WARC files after a fixed, simulated latency, so the coordinator, router and
+//! workers can be exercised end to end with deterministic timing. Urls that are not in the
+//! corpus respond with `404 Not Found`. The robots.txt of a host is served from the corpus
+//! (or from the config) like any other page, so hosts without one allow everything.
+//!
+//! Every page has an `ETag` derived from its body, which makes re-crawls of an unchanged
+//! corpus respond with `304 Not Modified`.
+use std::time::Duration;
+
+use hashbrown::HashMap;
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use url::Url;
+
+use crate::{
+    config::ReplayConfig,
+    warc::{PayloadType, WarcFile, WarcRecord},
+    webpage::url_canonicalizer,
+};
+
+use super::Result;
+
+pub struct ReplayResponse {
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+struct Page {
+    payload_type: PayloadType,
+    body: Vec<u8>,
+    etag: String,
+}
+
+impl Page {
+    fn content_type(&self) -> String {
+        match self.payload_type {
+            PayloadType::Pdf => self.payload_type.to_string(),
+            _ => format!("{}; charset=utf-8", self.payload_type),
+        }
+    }
+}
+
+pub struct ReplayCorpus {
+    /// Pages by the canonical key of their url.
+    pages: HashMap<String, Page>,
+    latency: Duration,
+}
+
+impl ReplayCorpus {
+    pub fn new(latency: Duration) -> Self {
+        Self {
+            pages: HashMap::new(),
+            latency,
+        }
+    }
+
+    pub fn open(config: &ReplayConfig) -> Result<Self> {
+        let mut corpus = Self::new(Duration::from_millis(config.latency_ms));
+
+        for path in config.warc_source.paths()? {
+            let file = WarcFile::download(&config.warc_source, &path)?;
+
+            for record in file.records() {
+                corpus.insert_record(record?);
+            }
+        }
+
+        for (host, robots_txt) in &config.robots_txt {
+            let url = Url::parse(&format!("http://{host}/robots.txt"))?;
+            corpus.insert(url, PayloadType::Text, robots_txt.as_bytes().to_vec());
+        }
+
+        tracing::info!("replaying {} pages", corpus.len());
+
+        Ok(corpus)
+    }
+
+    pub fn insert_record(&mut self, record: WarcRecord) {
+        let url = match Url::parse(&record.request.url) {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+
+        let payload_type = record.response.payload_type.unwrap_or(PayloadType::Html);
+
+        self.insert(url, payload_type, record.response.body);
+    }
+
+    pub fn insert(&mut self, url: Url, payload_type: PayloadType, body: Vec<u8>) {
+        let etag = format!("\"{:x}\"", md5::compute(&body));
+
+        self.pages.insert(
+            url_canonicalizer::canonical_key(&url),
+            Page {
+                payload_type,
+                body,
+                etag,
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Respond to a request for the url after the simulated latency.
+    pub async fn get(&self, url: &Url, headers: &HeaderMap) -> ReplayResponse {
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+
+        self.response(url, headers)
+    }
+
+    fn response(&self, url: &Url, headers: &HeaderMap) -> ReplayResponse {
+        let page = match self.pages.get(&url_canonicalizer::canonical_key(url)) {
+            Some(page) => page,
+            None => {
+                return ReplayResponse {
+                    url: url.clone(),
+                    status: StatusCode::NOT_FOUND,
+                    headers: HeaderMap::new(),
+                    body: Vec::new(),
+                }
+            }
+        };
+
+        let mut res_headers = HeaderMap::new();
+        if let Ok(etag) = HeaderValue::from_str(&page.etag) {
+            res_headers.insert(header::ETAG, etag);
+        }
+
+        let not_modified = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag == page.etag)
+            .unwrap_or(false);
+
+        if not_modified {
+            return ReplayResponse {
+                url: url.clone(),
+                status: StatusCode::NOT_MODIFIED,
+                headers: res_headers,
+                body: Vec::new(),
+            };
+        }
+
+        if let Ok(content_type) = HeaderValue::from_str(&page.content_type()) {
+            res_headers.insert(header::CONTENT_TYPE, content_type);
+        }
+        res_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(page.body.len()));
+
+        ReplayResponse {
+            url: url.clone(),
+            status: StatusCode::OK,
+            headers: res_headers,
+            body: page.body.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use crate::{
+        config::{
+            CrawlerConfig, LocalConfig, LocalWarcOutputConfig, UserAgent, WarcOutputConfig,
+            WarcSource,
+        },
+        crawler::{
+            file_queue::FileQueueWriter,
+            warc_writer::{LocalSink, WarcWriter},
+            CrawlCoordinator, CrawlDatum, Crawler, DatumStream, Domain, Job, Router,
+        },
+        distributed::sonic::service::Service,
+        entrypoint::crawler::{coordinator::CoordinatorService, router::RouterService},
+        metrics::PrometheusRegistry,
+    };
+
+    use super::*;
+
+    fn html(links: &[&str]) -> String {
+        let links: String = links
+            .iter()
+            .map(|link| format!("<a href=\"{link}\">{link}</a>"))
+            .collect();
+
+        format!("<html><head><title>Test</title></head><body><p>A page with some text.</p>{links}</body></html>")
+    }
+
+    #[test]
+    fn responses() {
+        let mut corpus = ReplayCorpus::new(Duration::ZERO);
+        corpus.insert(
+            Url::parse("https://www.example.com/page").unwrap(),
+            PayloadType::Html,
+            html(&[]).into_bytes(),
+        );
+
+        let url = Url::parse("http://example.com/page/").unwrap();
+        let res = corpus.response(&url, &HeaderMap::new());
+
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(res.url, url);
+        assert_eq!(res.body, html(&[]).into_bytes());
+        assert!(res.headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, res.headers[header::ETAG].clone());
+        let res = corpus.response(&url, &headers);
+        assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+        assert!(res.body.is_empty());
+
+        let res = corpus.response(
+            &Url::parse("https://example.com/other").unwrap(),
+            &HeaderMap::new(),
+        );
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    async fn serve<S: Service>(server: crate::distributed::sonic::service::Server<S>) {
+        loop {
+            let _ = server.accept().await;
+        }
+    }
+
+    fn crawled_urls(folder: &str) -> Vec<String> {
+        let source = WarcSource::Local(LocalConfig {
+            folder: folder.to_string(),
+            names: Vec::new(),
+        });
+
+        let mut urls: Vec<_> = source
+            .paths()
+            .unwrap()
+            .iter()
+            .flat_map(|path| {
+                WarcFile::download(&source, path)
+                    .unwrap()
+                    .records()
+                    .map(|record| record.unwrap().request.url)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        urls.sort();
+
+        urls
+    }
+
+    #[test]
+    fn crawl_end_to_end() {
+        let corpus_folder = crate::gen_temp_path().to_str().unwrap().to_string();
+        let output_folder = crate::gen_temp_path().to_str().unwrap().to_string();
+
+        let pages = [
+            (
+                "https://a.com/",
+                html(&["/b", "/private", "https://b.com/"]),
+            ),
+            ("https://a.com/b", html(&["/c"])),
+            ("https://a.com/c", html(&[])),
+            ("https://a.com/private", html(&[])),
+            ("https://b.com/", html(&[])),
+        ];
+
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let corpus = WarcWriter::new(LocalSink::new(LocalWarcOutputConfig {
+                    folder: corpus_folder.clone(),
+                    max_file_bytes: usize::MAX,
+                }));
+
+                for (url, body) in &pages {
+                    corpus
+                        .write(CrawlDatum {
+                            url: Url::parse(url).unwrap(),
+                            status_code: 200,
+                            payload_type: PayloadType::Html,
+                            body: body.clone().into_bytes(),
+                            fetch_time_ms: 0,
+                            etag: None,
+                            last_modified: None,
+                        })
+                        .await
+                        .unwrap();
+                }
+                corpus.finish().await.unwrap();
+
+                // a coordinator with an empty crawl plan and a single seed
+                let plan = crate::gen_temp_path();
+                FileQueueWriter::<Job>::new(&plan)
+                    .unwrap()
+                    .finalize()
+                    .unwrap();
+                let coordinator = Arc::new(CrawlCoordinator::new(&plan).unwrap());
+                coordinator.inject_seeds(vec![Url::parse("https://a.com/").unwrap()], 10);
+
+                let server = CoordinatorService {
+                    coordinator: Arc::clone(&coordinator),
+                }
+                .bind("127.0.0.1:0")
+                .await
+                .unwrap();
+                let coordinator_addr: SocketAddr = server.local_addr().unwrap();
+                tokio::spawn(serve(server));
+
+                let router = Router::new(vec![coordinator_addr]).await.unwrap();
+                let server = RouterService { router }.bind("127.0.0.1:0").await.unwrap();
+                let router_addr: SocketAddr = server.local_addr().unwrap();
+                tokio::spawn(serve(server));
+
+                let config = CrawlerConfig {
+                    num_worker_threads: 1,
+                    user_agent: UserAgent {
+                        full: "StractTest".to_string(),
+                        token: "StractTest".to_string(),
+                    },
+                    robots_txt_cache_sec: 60,
+                    politeness_factor: 1.0,
+                    min_crawl_delay_ms: 0,
+                    max_crawl_delay_ms: 0,
+                    max_politeness_factor: 1.0,
+                    max_url_slowdown_retry: 0,
+                    max_redirects: 0,
+                    dry_run: false,
+                    timeout_seconds: 10,
+                    output: WarcOutputConfig::Local(LocalWarcOutputConfig {
+                        folder: output_folder.clone(),
+                        max_file_bytes: usize::MAX,
+                    }),
+                    router_hosts: vec![router_addr.to_string()],
+                    crawl_state_path: None,
+                    prometheus_host: None,
+                    host_relevance_path: None,
+                    replay: Some(ReplayConfig {
+                        warc_source: WarcSource::Local(LocalConfig {
+                            folder: corpus_folder.clone(),
+                            names: Vec::new(),
+                        }),
+                        latency_ms: 0,
+                        robots_txt: [(
+                            "a.com".to_string(),
+                            "User-agent: *\nDisallow: /private".to_string(),
+                        )]
+                        .into_iter()
+                        .collect(),
+                    }),
+                };
+
+                let crawler = Crawler::new(config, &mut PrometheusRegistry::default())
+                    .await
+                    .unwrap();
+
+                // the worker stops when the coordinator runs out of jobs
+                crawler.run().await;
+
+                let domain = Domain::from(&Url::parse("https://a.com/").unwrap());
+                let progress = coordinator.domain_progress(&domain).unwrap();
+                assert_eq!(progress.jobs_completed, 1);
+                assert_eq!(progress.budget_used, 3.0);
+            });
+
+        assert_eq!(
+            crawled_urls(&output_folder),
+            vec!["https://a.com/", "https://a.com/b", "https://a.com/c"]
+        );
+    }
+}
@@ -14,15 +14,20 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::BTreeMap, panic, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    panic,
+    time::Duration,
+};
 
 use robotstxt_with_cache::matcher::{
     CachingRobotsMatcher, LongestMatchRobotsMatchStrategy, RobotsMatcher,
 };
 
+use reqwest::header::HeaderMap;
 use url::Url;
 
-use super::{Error, Result, Site};
+use super::{fetcher::Fetcher, Error, Result, Site};
 
 enum Lookup<T> {
     Found(T),
@@ -32,14 +37,14 @@ enum Lookup<T> {
 pub struct RobotsTxtManager {
     cache: BTreeMap<Site, Lookup<RobotsTxt>>,
     last_prune: std::time::Instant,
-    client: reqwest::Client,
+    fetcher: Fetcher,
     cache_expiration: Duration,
 }
 
 impl RobotsTxtManager {
-    pub fn new(client: reqwest::Client, cache_expiration: Duration) -> Self {
+    pub fn new(fetcher: Fetcher, cache_expiration: Duration) -> Self {
         Self {
-            client,
+            fetcher,
             cache_expiration,
             last_prune: std::time::Instant::now(),
             cache: BTreeMap::new(),
@@ -56,20 +61,13 @@ impl RobotsTxtManager {
     }
 
     async fn fetch_robots_txt(&self, site: &Site) -> Result<RobotsTxt> {
-        let mut res = self
-            .client
-            .get(&format!("http://{}/robots.txt", site.0))
-            .timeout(Duration::from_secs(60))
-            .send()
-            .await;
+        let timeout = Some(Duration::from_secs(60));
+        let url = Url::parse(&format!("http://{}/robots.txt", site.0))?;
+        let mut res = self.fetcher.get(&url, HeaderMap::new(), timeout).await;
 
         if res.is_err() {
-            res = self
-                .client
-                .get(&format!("https://{}/robots.txt", site.0))
-                .timeout(Duration::from_secs(60))
-                .send()
-                .await;
+            let url = Url::parse(&format!("https://{}/robots.txt", site.0))?;
+            res = self.fetcher.get(&url, HeaderMap::new(), timeout).await;
         }
 
         let res = res?;
@@ -140,25 +138,37 @@ impl RobotsTxtManager {
         self.cache.get_mut(&site).unwrap()
     }
 
-    pub async fn sitemap(&mut self, url: &Url) -> Option<Url> {
+    /// The `Crawl-delay` for the user agent on the site of the url.
+    pub async fn crawl_delay(&mut self, url: &Url, user_agent: &str) -> Option<Duration> {
         match self.get_mut(url).await {
-            Lookup::Found(robotstxt) => robotstxt.sitemap.clone(),
+            Lookup::Found(robots_txt) => robots_txt.crawl_delay(user_agent),
             Lookup::NotFound => None,
         }
     }
+
+    /// All the sitemaps listed in the robots.txt for the site of the url.
+    pub async fn sitemaps(&mut self, url: &Url) -> Vec<Url> {
+        match self.get_mut(url).await {
+            Lookup::Found(robotstxt) => robotstxt.sitemaps.clone(),
+            Lookup::NotFound => Vec::new(),
+        }
+    }
 }
 
 struct RobotsTxt {
     download_time: std::time::Instant,
     matcher: CachingRobotsMatcher<LongestMatchRobotsMatchStrategy>,
-    sitemap: Option<Url>,
+    sitemaps: Vec<Url>,
+    /// lowercase user agent -> crawl delay.
+    crawl_delays: HashMap<String, Duration>,
 }
 
 impl RobotsTxt {
     fn new(body: String) -> Self {
         let mut s = Self {
             matcher: CachingRobotsMatcher::new(RobotsMatcher::default()),
-            sitemap: None,
+            sitemaps: Vec::new(),
+            crawl_delays: HashMap::new(),
             download_time: std::time::Instant::now(),
         };
 
@@ -174,15 +184,66 @@ impl RobotsTxt {
     fn update(&mut self, body: String) {
         self.matcher.parse(&body.to_lowercase());
 
-        self.sitemap = body
-            .to_ascii_lowercase()
+        self.sitemaps = body
             .lines()
-            .find(|line| line.starts_with("sitemap:"))
-            .map(|line| line.split(':').nth(1).unwrap().trim())
-            .and_then(|s| Url::parse(s).ok());
+            .filter_map(|line| line.trim().split_once(':'))
+            .filter(|(key, _)| key.trim().eq_ignore_ascii_case("sitemap"))
+            .filter_map(|(_, url)| Url::parse(url.trim()).ok())
+            .collect();
+        self.sitemaps.dedup();
+
+        self.crawl_delays = parse_crawl_delays(&body);
 
         self.download_time = std::time::Instant::now();
     }
+
+    fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.crawl_delays
+            .get(&user_agent.to_ascii_lowercase())
+            .or_else(|| self.crawl_delays.get("*"))
+            .copied()
+    }
+}
+
+/// The `Crawl-delay` of each user agent group in the robots.txt.
+fn parse_crawl_delays(body: &str) -> HashMap<String, Duration> {
+    let mut delays = HashMap::new();
+    let mut group = Vec::new();
+    let mut in_agents = false;
+
+    for line in body.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+            None => continue,
+        };
+
+        match key.as_str() {
+            "user-agent" => {
+                if !in_agents {
+                    group.clear();
+                    in_agents = true;
+                }
+
+                group.push(value.to_ascii_lowercase());
+            }
+            "crawl-delay" => {
+                in_agents = false;
+
+                if let Ok(secs) = value.parse::<f64>() {
+                    if secs.is_finite() && secs >= 0.0 {
+                        for agent in &group {
+                            delays.insert(agent.clone(), Duration::from_secs_f64(secs));
+                        }
+                    }
+                }
+            }
+            _ => in_agents = false,
+        }
+    }
+
+    delays
 }
 
 #[cfg(test)]
@@ -222,4 +283,50 @@ mod tests {
             .matcher
             .one_agent_allowed_by_robots(ua_token, "http://example.com/example"));
     }
+
+    #[test]
+    fn crawl_delay() {
+        let robots_txt = RobotsTxt::new(
+            r#"User-agent: *
+            Crawl-delay: 10
+
+            User-agent: googlebot
+            User-agent: StractBot
+            Disallow: /private
+            Crawl-delay: 0.5 # half a second"#
+                .to_string(),
+        );
+
+        assert_eq!(
+            robots_txt.crawl_delay("StractBot"),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            robots_txt.crawl_delay("OtherBot"),
+            Some(Duration::from_secs(10))
+        );
+
+        let robots_txt = RobotsTxt::new("User-agent: *\nDisallow: /".to_string());
+        assert_eq!(robots_txt.crawl_delay("StractBot"), None);
+    }
+
+    #[test]
+    fn sitemaps() {
+        let robots_txt = RobotsTxt::new(
+            r#"User-agent: *
+            Disallow: /private
+            Sitemap: https://example.com/Sitemap.xml
+            sitemap:https://example.com/news/sitemap.xml.gz
+            Sitemap: not a url"#
+                .to_string(),
+        );
+
+        assert_eq!(
+            robots_txt.sitemaps,
+            vec![
+                Url::parse("https://example.com/Sitemap.xml").unwrap(),
+                Url::parse("https://example.com/news/sitemap.xml.gz").unwrap(),
+            ]
+        );
+    }
 }
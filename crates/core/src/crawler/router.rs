@@ -1,14 +1,17 @@
 use anyhow::Result;
+use hashbrown::HashMap;
 use rand::Rng;
 use std::{net::SocketAddr, time::Duration};
 use tokio::sync::Mutex;
 
 use crate::{
     distributed::{retry_strategy::ExponentialBackoff, sonic},
-    entrypoint::crawler::coordinator::{CoordinatorService, GetJob},
+    entrypoint::crawler::coordinator::{
+        CoordinatorService, GetJob, Heartbeat, ReportDomainCrawled, ReportTraps,
+    },
 };
 
-use super::Job;
+use super::{crawl_trap::TrapPattern, DomainCrawled, JobResponse, WorkerId};
 
 struct RemoteCoordinator {
     addr: SocketAddr,
@@ -26,19 +29,58 @@ impl RemoteCoordinator {
         .await?)
     }
 
-    async fn sample_job(&self) -> Result<Option<Job>> {
+    async fn sample_job(&self, worker: WorkerId) -> Result<JobResponse> {
         let conn = self.conn().await?;
 
         let response = conn
-            .send_with_timeout(&GetJob {}, Duration::from_secs(90))
+            .send_with_timeout(&GetJob { worker }, Duration::from_secs(90))
             .await?;
 
         Ok(response)
     }
+
+    async fn report_traps(&self, traps: Vec<TrapPattern>) -> Result<()> {
+        let conn = self.conn().await?;
+
+        conn.send_with_timeout(&ReportTraps { traps }, Duration::from_secs(90))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn report_domain_crawled(
+        &self,
+        worker: WorkerId,
+        domain_crawled: DomainCrawled,
+    ) -> Result<()> {
+        let conn = self.conn().await?;
+
+        conn.send_with_timeout(
+            &ReportDomainCrawled {
+                worker,
+                domain_crawled,
+            },
+            Duration::from_secs(90),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn heartbeat(&self, worker: WorkerId) -> Result<()> {
+        let conn = self.conn().await?;
+
+        conn.send_with_timeout(&Heartbeat { worker }, Duration::from_secs(90))
+            .await?;
+
+        Ok(())
+    }
 }
 
 struct InnerRouter {
     coordinators: Vec<RemoteCoordinator>,
+    /// The coordinator that handed out the current job of each worker.
+    origins: HashMap<WorkerId, SocketAddr>,
 }
 
 impl InnerRouter {
@@ -48,37 +90,99 @@ impl InnerRouter {
                 .into_iter()
                 .map(|addr| RemoteCoordinator { addr })
                 .collect(),
+            origins: HashMap::new(),
         })
     }
 
-    async fn sample_job(&mut self) -> Result<Option<Job>> {
-        while !self.coordinators.is_empty() {
-            let idx = rand::thread_rng().gen_range(0..self.coordinators.len());
-            let res = self.coordinators[idx].sample_job().await?;
-
-            if res.is_some() {
-                return Ok(res);
+    /// Sample a job from a random coordinator. If none of the coordinators has a job and
+    /// some of them are paused or waiting for domains in flight, the worker is told to wait.
+    /// Coordinators that are done are still asked, since seeds can be injected into them later.
+    async fn sample_job(&mut self, worker: WorkerId) -> Result<JobResponse> {
+        let mut candidates: Vec<usize> = (0..self.coordinators.len()).collect();
+        let mut res = JobResponse::Done;
+
+        while !candidates.is_empty() {
+            let idx = candidates.swap_remove(rand::thread_rng().gen_range(0..candidates.len()));
+            let coordinator = &self.coordinators[idx];
+
+            match coordinator.sample_job(worker.clone()).await? {
+                JobResponse::Job(job) => {
+                    self.origins.insert(worker, coordinator.addr);
+                    res = JobResponse::Job(job);
+                    break;
+                }
+                JobResponse::Wait => res = JobResponse::Wait,
+                JobResponse::Done => {}
             }
-
-            self.coordinators.remove(idx);
         }
 
-        Ok(None)
+        Ok(res)
     }
 }
 
 pub struct Router {
     inner: Mutex<InnerRouter>,
+    coordinators: Vec<RemoteCoordinator>,
 }
 
 impl Router {
     pub async fn new(coordinator_addrs: Vec<SocketAddr>) -> Result<Self> {
         Ok(Self {
+            coordinators: coordinator_addrs
+                .iter()
+                .map(|addr| RemoteCoordinator { addr: *addr })
+                .collect(),
             inner: Mutex::new(InnerRouter::new(coordinator_addrs).await?),
         })
     }
 
-    pub async fn sample_job(&self) -> Result<Option<Job>> {
-        self.inner.lock().await.sample_job().await
+    pub async fn sample_job(&self, worker: WorkerId) -> Result<JobResponse> {
+        self.inner.lock().await.sample_job(worker).await
+    }
+
+    /// Report crawl traps to all coordinators, including the ones that have run out of jobs,
+    /// since the traps should be excluded from all future crawl plans.
+    pub async fn report_traps(&self, traps: Vec<TrapPattern>) -> Result<()> {
+        for coordinator in &self.coordinators {
+            coordinator.report_traps(traps.clone()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn origin(&self, worker: &WorkerId) -> Option<RemoteCoordinator> {
+        self.inner
+            .lock()
+            .await
+            .origins
+            .get(worker)
+            .map(|addr| RemoteCoordinator { addr: *addr })
+    }
+
+    /// Report a finished job to the coordinator that handed it out.
+    pub async fn report_domain_crawled(
+        &self,
+        worker: WorkerId,
+        domain_crawled: DomainCrawled,
+    ) -> Result<()> {
+        match self.origin(&worker).await {
+            Some(coordinator) => {
+                coordinator
+                    .report_domain_crawled(worker, domain_crawled)
+                    .await
+            }
+            None => {
+                tracing::warn!("no coordinator known for worker {:?}", worker);
+                Ok(())
+            }
+        }
+    }
+
+    /// Forward the heartbeat of the worker to the coordinator of its current job.
+    pub async fn heartbeat(&self, worker: WorkerId) -> Result<()> {
+        match self.origin(&worker).await {
+            Some(coordinator) => coordinator.heartbeat(worker).await,
+            None => Ok(()),
+        }
     }
 }
@@ -0,0 +1,470 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Parsing of sitemaps (<https://www.sitemaps.org/protocol.html>).
+//!
+//! Supports xml sitemaps, sitemap indexes and plain text sitemaps,
+//! all of which can be gzipped.
+use std::io::Read;
+
+use chrono::TimeZone;
+use quick_xml::events::Event;
+use url::Url;
+
+/// Sitemaps are not allowed to be larger than this (uncompressed).
+pub const MAX_SITEMAP_BYTES: usize = 50 * 1024 * 1024;
+
+/// Sitemaps are not allowed to contain more entries than this.
+const MAX_ENTRIES: usize = 50_000;
+
+/// Priority of urls that do not specify one.
+const DEFAULT_PRIORITY: f64 = 0.5;
+
+/// Freshness of urls that specify neither `lastmod` nor `changefreq`.
+const DEFAULT_FRESHNESS: f64 = 0.25;
+
+/// Number of days after `lastmod` at which the freshness of a url is halved.
+const FRESHNESS_HALF_LIFE_DAYS: f64 = 30.0;
+
+const DAY: f64 = 24.0 * 60.0 * 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFreq {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "hourly" => Some(Self::Hourly),
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            "monthly" => Some(Self::Monthly),
+            "yearly" => Some(Self::Yearly),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+
+    /// Freshness of a url with this change frequency when `lastmod` is unknown.
+    fn freshness(&self) -> f64 {
+        match self {
+            Self::Always | Self::Hourly => 1.0,
+            Self::Daily => 0.8,
+            Self::Weekly => 0.6,
+            Self::Monthly => 0.4,
+            Self::Yearly => 0.2,
+            Self::Never => 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapUrl {
+    pub url: Url,
+    /// unix timestamp (seconds) of the last modification.
+    pub lastmod: Option<u64>,
+    pub priority: Option<f64>,
+    pub changefreq: Option<ChangeFreq>,
+}
+
+impl SitemapUrl {
+    fn new(url: Url) -> Self {
+        Self {
+            url,
+            lastmod: None,
+            priority: None,
+            changefreq: None,
+        }
+    }
+
+    /// Weight of the url when prioritising which urls to crawl.
+    /// Recently modified urls with a high priority get the highest weight.
+    pub fn weight(&self, now: u64) -> f64 {
+        let priority = self.priority.unwrap_or(DEFAULT_PRIORITY);
+
+        let freshness = match (self.lastmod, self.changefreq) {
+            (Some(lastmod), _) => {
+                let age_days = now.saturating_sub(lastmod) as f64 / DAY;
+                0.5f64.powf(age_days / FRESHNESS_HALF_LIFE_DAYS)
+            }
+            (None, Some(changefreq)) => changefreq.freshness(),
+            (None, None) => DEFAULT_FRESHNESS,
+        };
+
+        priority * freshness
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SitemapEntry {
+    Url(SitemapUrl),
+    Sitemap { url: Url, lastmod: Option<u64> },
+}
+
+/// Parse a `lastmod` in the W3C datetime format into a unix timestamp (seconds).
+fn parse_lastmod(s: &str) -> Option<u64> {
+    let s = s.trim();
+
+    let timestamp = if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(s) {
+        datetime.timestamp()
+    } else if let Ok(datetime) = chrono::DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M%:z") {
+        datetime.timestamp()
+    } else {
+        let date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .or_else(|_| chrono::NaiveDate::parse_from_str(&format!("{s}-01"), "%Y-%m-%d"))
+            .ok()?;
+
+        chrono::Utc
+            .from_utc_datetime(&date.and_hms_opt(0, 0, 0)?)
+            .timestamp()
+    };
+
+    Some(timestamp.max(0) as u64)
+}
+
+fn decompress(body: &[u8]) -> Vec<u8> {
+    // gzip magic bytes
+    if !body.starts_with(&[0x1f, 0x8b]) {
+        return body.to_vec();
+    }
+
+    let mut res = Vec::new();
+    let decoder = flate2::read::GzDecoder::new(body);
+
+    if let Err(err) = decoder.take(MAX_SITEMAP_BYTES as u64).read_to_end(&mut res) {
+        tracing::debug!("failed to decompress sitemap: {}", err);
+    }
+
+    res
+}
+
+/// Parse a (possibly gzipped) sitemap.
+pub fn parse(body: &[u8]) -> Vec<SitemapEntry> {
+    let body = decompress(body);
+    let body = String::from_utf8_lossy(&body);
+
+    if body.trim_start().starts_with('<') {
+        parse_xml(&body)
+    } else {
+        parse_text(&body)
+    }
+}
+
+/// A text sitemap has one url per line.
+fn parse_text(s: &str) -> Vec<SitemapEntry> {
+    s.lines()
+        .filter_map(|line| Url::parse(line.trim()).ok())
+        .take(MAX_ENTRIES)
+        .map(|url| SitemapEntry::Url(SitemapUrl::new(url)))
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Element {
+    Url,
+    Sitemap,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Loc,
+    Lastmod,
+    Priority,
+    Changefreq,
+}
+
+#[derive(Default)]
+struct PartialEntry {
+    loc: String,
+    lastmod: String,
+    priority: String,
+    changefreq: String,
+}
+
+impl PartialEntry {
+    fn field_mut(&mut self, field: Field) -> &mut String {
+        match field {
+            Field::Loc => &mut self.loc,
+            Field::Lastmod => &mut self.lastmod,
+            Field::Priority => &mut self.priority,
+            Field::Changefreq => &mut self.changefreq,
+        }
+    }
+
+    fn into_entry(self, element: Element) -> Option<SitemapEntry> {
+        let url = Url::parse(self.loc.trim()).ok()?;
+        let lastmod = parse_lastmod(&self.lastmod);
+
+        match element {
+            Element::Sitemap => Some(SitemapEntry::Sitemap { url, lastmod }),
+            Element::Url => Some(SitemapEntry::Url(SitemapUrl {
+                url,
+                lastmod,
+                priority: self
+                    .priority
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|p| p.is_finite())
+                    .map(|p| p.clamp(0.0, 1.0)),
+                changefreq: ChangeFreq::parse(&self.changefreq),
+            })),
+        }
+    }
+}
+
+fn parse_xml(s: &str) -> Vec<SitemapEntry> {
+    let mut reader = quick_xml::Reader::from_str(s);
+
+    let mut res = vec![];
+
+    let mut element = None;
+    let mut field = None;
+    let mut entry = PartialEntry::default();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) => match e.local_name().as_ref() {
+                b"url" => {
+                    element = Some(Element::Url);
+                    entry = PartialEntry::default();
+                }
+                b"sitemap" => {
+                    element = Some(Element::Sitemap);
+                    entry = PartialEntry::default();
+                }
+                b"loc" => field = Some(Field::Loc),
+                b"lastmod" => field = Some(Field::Lastmod),
+                b"priority" => field = Some(Field::Priority),
+                b"changefreq" => field = Some(Field::Changefreq),
+                _ => {}
+            },
+            Ok(Event::End(ref e)) => match e.local_name().as_ref() {
+                b"url" | b"sitemap" => {
+                    if let Some(element) = element.take() {
+                        if let Some(entry) = std::mem::take(&mut entry).into_entry(element) {
+                            res.push(entry);
+
+                            if res.len() >= MAX_ENTRIES {
+                                break;
+                            }
+                        }
+                    }
+                }
+                b"loc" | b"lastmod" | b"priority" | b"changefreq" => field = None,
+                _ => {}
+            },
+            Ok(Event::Text(e)) => {
+                if let (Some(_), Some(field)) = (element, field) {
+                    if let Ok(text) = e.unescape() {
+                        entry.field_mut(field).push_str(&text);
+                    }
+                }
+            }
+            Ok(Event::CData(e)) => {
+                if let (Some(_), Some(field)) = (element, field) {
+                    entry
+                        .field_mut(field)
+                        .push_str(&String::from_utf8_lossy(&e.into_inner()));
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                tracing::debug!("failed to parse sitemap: {}", e);
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn parse_sitemap() {
+        let dr = r#"<sitemapindex>
+        <sitemap>
+        <loc>https://www.dr.dk/drtv/sitemap.xml</loc>
+        </sitemap>
+        <sitemap>
+        <loc>https://www.dr.dk/sitemap.tvguide.xml</loc>
+        </sitemap>
+        <sitemap>
+        <loc>
+        https://www.dr.dk/sitemap.kommunalvalg.resultater.xml
+        </loc>
+        </sitemap>
+        <sitemap>
+        <loc>https://www.dr.dk/sitemap.folketingsvalg2022.xml</loc>
+        <lastmod>2022-11-01</lastmod>
+        </sitemap>
+        </sitemapindex>"#;
+
+        let entries = parse(dr.as_bytes());
+        assert_eq!(
+            entries,
+            vec![
+                SitemapEntry::Sitemap {
+                    url: "https://www.dr.dk/drtv/sitemap.xml".parse().unwrap(),
+                    lastmod: None
+                },
+                SitemapEntry::Sitemap {
+                    url: "https://www.dr.dk/sitemap.tvguide.xml".parse().unwrap(),
+                    lastmod: None
+                },
+                SitemapEntry::Sitemap {
+                    url: "https://www.dr.dk/sitemap.kommunalvalg.resultater.xml"
+                        .parse()
+                        .unwrap(),
+                    lastmod: None
+                },
+                SitemapEntry::Sitemap {
+                    url: "https://www.dr.dk/sitemap.folketingsvalg2022.xml"
+                        .parse()
+                        .unwrap(),
+                    lastmod: Some(1667260800)
+                },
+            ]
+        );
+
+        let dr = r#"<urlset>
+        <url>
+        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
+        <loc>https://www.dr.dk/drtv/serie/sleepover_6382</loc>
+        </url>
+        <url>
+        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
+        <loc>https://www.dr.dk/drtv/saeson/sleepover_9673</loc>
+        <priority>0.8</priority>
+        </url>
+        <url>
+        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
+        <loc>
+        https://www.dr.dk/drtv/episode/sleepover_-zoologisk-museum_52239
+        </loc>
+        <changefreq>weekly</changefreq>
+        </url>
+        <url>
+        <loc>
+        https://www.dr.dk/drtv/episode/sleepover_-koebenhavns-raadhus_52252
+        </loc>
+        </url>
+        </urlset>"#;
+
+        let entries = parse(dr.as_bytes());
+        let urls: Vec<_> = entries
+            .into_iter()
+            .map(|entry| match entry {
+                SitemapEntry::Url(url) => url,
+                SitemapEntry::Sitemap { .. } => panic!("expected url"),
+            })
+            .collect();
+
+        assert_eq!(
+            urls.iter().map(|u| u.url.as_str()).collect::<Vec<_>>(),
+            vec![
+                "https://www.dr.dk/drtv/serie/sleepover_6382",
+                "https://www.dr.dk/drtv/saeson/sleepover_9673",
+                "https://www.dr.dk/drtv/episode/sleepover_-zoologisk-museum_52239",
+                "https://www.dr.dk/drtv/episode/sleepover_-koebenhavns-raadhus_52252",
+            ]
+        );
+
+        assert_eq!(urls[0].lastmod, Some(1697607604));
+        assert_eq!(urls[1].priority, Some(0.8));
+        assert_eq!(urls[2].changefreq, Some(ChangeFreq::Weekly));
+        assert_eq!(urls[3].lastmod, None);
+    }
+
+    #[test]
+    fn namespaced_and_cdata() {
+        let sitemap = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <ns:urlset xmlns:ns="http://www.sitemaps.org/schemas/sitemap/0.9">
+        <ns:url><ns:loc><![CDATA[https://example.com/a?b=1&c=2]]></ns:loc></ns:url>
+        </ns:urlset>"#;
+
+        assert_eq!(
+            parse(sitemap.as_bytes()),
+            vec![SitemapEntry::Url(SitemapUrl::new(
+                "https://example.com/a?b=1&c=2".parse().unwrap()
+            ))]
+        );
+    }
+
+    #[test]
+    fn gzipped_text_sitemap() {
+        let text = "https://example.com/a\n\nnot a url\nhttps://example.com/b\n";
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        assert_eq!(
+            parse(&gzipped),
+            vec![
+                SitemapEntry::Url(SitemapUrl::new("https://example.com/a".parse().unwrap())),
+                SitemapEntry::Url(SitemapUrl::new("https://example.com/b".parse().unwrap())),
+            ]
+        );
+    }
+
+    #[test]
+    fn lastmod_formats() {
+        assert_eq!(parse_lastmod("2023-10-18"), Some(1697587200));
+        assert_eq!(parse_lastmod("2023-10"), Some(1696118400));
+        assert_eq!(parse_lastmod("2023-10-18T05:40+00:00"), Some(1697607600));
+        assert_eq!(parse_lastmod("2023-10-18T07:40:04+02:00"), Some(1697607604));
+        assert_eq!(parse_lastmod("yesterday"), None);
+    }
+
+    #[test]
+    fn weight() {
+        let now = 1697587200;
+        let url: Url = "https://example.com".parse().unwrap();
+
+        let fresh = SitemapUrl {
+            lastmod: Some(now),
+            ..SitemapUrl::new(url.clone())
+        };
+        let stale = SitemapUrl {
+            lastmod: Some(now - 365 * 24 * 60 * 60),
+            ..SitemapUrl::new(url.clone())
+        };
+        let important = SitemapUrl {
+            priority: Some(1.0),
+            ..stale.clone()
+        };
+        let unknown = SitemapUrl::new(url);
+
+        assert!(fresh.weight(now) > unknown.weight(now));
+        assert!(unknown.weight(now) > stale.weight(now));
+        assert!(important.weight(now) > stale.weight(now));
+    }
+}
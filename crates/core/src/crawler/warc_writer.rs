@@ -14,17 +14,24 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::time::Duration;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use crate::{
-    config::{self, S3Config},
+    config::{LocalWarcOutputConfig, S3Config, WarcOutputConfig},
     warc,
 };
 
 use super::{CrawlDatum, DatumStream, Result};
 
+/// WARC files uploaded to S3 are rotated when they exceed this size.
+const S3_MAX_FILE_BYTES: usize = 1_000_000_000;
+
 /// The WarcWriter is responsible for storing the crawl datums
-/// as WARC files on S3.
+/// as WARC files in a [`WarcSink`].
 pub struct WarcWriter {
     tx: tokio::sync::mpsc::Sender<WarcWriterMessage>,
 }
@@ -50,7 +57,99 @@ pub enum WarcWriterMessage {
     Finish,
 }
 
-async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
+/// Destination of the finished (gzipped) WARC files.
+pub trait WarcSink: Send + Sync + 'static {
+    /// The current file is finished and stored once it exceeds this size.
+    fn max_file_bytes(&self) -> usize;
+
+    fn store(&self, filename: String, data: Vec<u8>) -> impl Future<Output = Result<()>> + Send;
+}
+
+pub struct S3Sink {
+    config: S3Config,
+}
+
+impl S3Sink {
+    pub fn new(config: S3Config) -> Self {
+        Self { config }
+    }
+}
+
+impl WarcSink for S3Sink {
+    fn max_file_bytes(&self) -> usize {
+        S3_MAX_FILE_BYTES
+    }
+
+    async fn store(&self, filename: String, data: Vec<u8>) -> Result<()> {
+        let s3 = &self.config;
+
+        let bucket = s3::Bucket::new(
+            &s3.bucket,
+            s3::Region::Custom {
+                region: "".to_string(),
+                endpoint: s3.endpoint.clone(),
+            },
+            s3::creds::Credentials {
+                access_key: Some(s3.access_key.clone()),
+                secret_key: Some(s3.secret_key.clone()),
+                security_token: None,
+                session_token: None,
+                expiration: None,
+            },
+        )?
+        .with_path_style()
+        .with_request_timeout(Duration::from_secs(30 * 60));
+
+        bucket
+            .put_object_with_content_type(
+                &format!("{}/{}", &s3.folder, filename),
+                &data,
+                "application/warc",
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Stores the WARC files in a local folder. The folder can be indexed
+/// directly with a `WarcSource::Local` pointing to the same folder.
+pub struct LocalSink {
+    folder: PathBuf,
+    max_file_bytes: usize,
+}
+
+impl LocalSink {
+    pub fn new(config: LocalWarcOutputConfig) -> Self {
+        Self {
+            folder: PathBuf::from(config.folder),
+            max_file_bytes: config.max_file_bytes,
+        }
+    }
+
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+}
+
+impl WarcSink for LocalSink {
+    fn max_file_bytes(&self) -> usize {
+        self.max_file_bytes
+    }
+
+    async fn store(&self, filename: String, data: Vec<u8>) -> Result<()> {
+        tokio::fs::create_dir_all(&self.folder).await?;
+
+        // write to a temporary file first so readers never see a partially written warc file
+        let tmp = self.folder.join(format!("{filename}.tmp"));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, self.folder.join(filename)).await?;
+
+        Ok(())
+    }
+}
+
+async fn commit<S: WarcSink>(writer: warc::DeduplicatedWarcWriter, sink: &S) {
     let filename = format!(
         "{}_{}.warc.gz",
         chrono::Utc::now().to_rfc3339(),
@@ -58,41 +157,12 @@ async fn commit(writer: warc::DeduplicatedWarcWriter, s3: config::S3Config) {
     );
     let data = writer.finish().unwrap();
 
-    match s3::Bucket::new(
-        &s3.bucket,
-        s3::Region::Custom {
-            region: "".to_string(),
-            endpoint: s3.endpoint.clone(),
-        },
-        s3::creds::Credentials {
-            access_key: Some(s3.access_key.clone()),
-            secret_key: Some(s3.secret_key.clone()),
-            security_token: None,
-            session_token: None,
-            expiration: None,
-        },
-    ) {
-        Ok(bucket) => {
-            let bucket = bucket
-                .with_path_style()
-                .with_request_timeout(Duration::from_secs(30 * 60));
-
-            if let Err(err) = bucket
-                .put_object_with_content_type(
-                    &format!("{}/{}", &s3.folder, filename),
-                    &data,
-                    "application/warc",
-                )
-                .await
-            {
-                tracing::error!("failed to upload to bucket: {:?}", err);
-            }
-        }
-        Err(err) => tracing::error!("failed to connect to bucket: {:?}", err),
+    if let Err(err) = sink.store(filename, data).await {
+        tracing::error!("failed to store warc file: {:?}", err);
     }
 }
 
-async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3: S3Config) {
+async fn writer_task<S: WarcSink>(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, sink: S) {
     let mut writer = warc::DeduplicatedWarcWriter::new();
 
     while let Some(message) = rx.recv().await {
@@ -103,6 +173,16 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
 
                 rayon::scope(move |s| {
                     s.spawn(move |_| {
+                        let metadata = warc::Metadata {
+                            fetch_time_ms: datum.fetch_time_ms,
+                        };
+
+                        if datum.status_code == 304 {
+                            w.write_revisit(datum.url.as_str(), &metadata).unwrap();
+                            send.send(()).unwrap();
+                            return;
+                        }
+
                         let warc_record = warc::WarcRecord {
                             request: warc::Request {
                                 url: datum.url.to_string(),
@@ -111,9 +191,7 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
                                 body: datum.body,
                                 payload_type: Some(datum.payload_type),
                             },
-                            metadata: warc::Metadata {
-                                fetch_time_ms: datum.fetch_time_ms,
-                            },
+                            metadata,
                         };
 
                         w.write(&warc_record).unwrap();
@@ -123,14 +201,14 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
 
                 recv.await.unwrap();
 
-                if writer.num_bytes() > 1_000_000_000 {
-                    commit(writer, s3.clone()).await;
+                if writer.num_bytes() > sink.max_file_bytes() {
+                    commit(writer, &sink).await;
                     writer = warc::DeduplicatedWarcWriter::new();
                 }
             }
             WarcWriterMessage::Finish => {
                 if writer.num_writes() > 0 {
-                    commit(writer, s3.clone()).await;
+                    commit(writer, &sink).await;
                 }
                 break;
             }
@@ -139,11 +217,82 @@ async fn writer_task(mut rx: tokio::sync::mpsc::Receiver<WarcWriterMessage>, s3:
 }
 
 impl WarcWriter {
-    pub fn new(s3: S3Config) -> Self {
+    pub fn new<S: WarcSink>(sink: S) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(1);
 
-        tokio::spawn(writer_task(rx, s3));
+        tokio::spawn(writer_task(rx, sink));
 
         Self { tx }
     }
+
+    pub fn from_config(config: WarcOutputConfig) -> Self {
+        match config {
+            WarcOutputConfig::S3(config) => Self::new(S3Sink::new(config)),
+            WarcOutputConfig::Local(config) => Self::new(LocalSink::new(config)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use crate::{
+        config::{LocalConfig, WarcSource},
+        warc::WarcFile,
+    };
+
+    use super::*;
+
+    fn datum(url: &str) -> CrawlDatum {
+        CrawlDatum {
+            url: Url::parse(url).unwrap(),
+            status_code: 200,
+            payload_type: warc::PayloadType::Html,
+            body: format!("<html><body>{url}</body></html>").into_bytes(),
+            fetch_time_ms: 42,
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn local_sink_rotates_files() {
+        let folder = crate::gen_temp_path();
+
+        let sink = LocalSink::new(LocalWarcOutputConfig {
+            folder: folder.to_str().unwrap().to_string(),
+            max_file_bytes: 1,
+        });
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let writer = WarcWriter::new(sink);
+
+            writer.write(datum("https://a.com/")).await.unwrap();
+            writer.write(datum("https://b.com/")).await.unwrap();
+            writer.finish().await.unwrap();
+        });
+
+        let source = WarcSource::Local(LocalConfig {
+            folder: folder.to_str().unwrap().to_string(),
+            names: Vec::new(),
+        });
+
+        let paths = source.paths().unwrap();
+        assert_eq!(paths.len(), 2);
+
+        let mut urls: Vec<_> = paths
+            .iter()
+            .flat_map(|path| {
+                WarcFile::download(&source, path)
+                    .unwrap()
+                    .records()
+                    .map(|record| record.unwrap().request.url)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        urls.sort();
+
+        assert_eq!(urls, vec!["https://a.com/", "https://b.com/"]);
+    }
 }
@@ -15,12 +15,9 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 use anyhow::anyhow;
 use encoding_rs::{Encoding, UTF_8};
-use futures::{future::BoxFuture, FutureExt};
 use hashbrown::{HashMap, HashSet};
 use mime::Mime;
-use quick_xml::events::Event;
 use rand::seq::SliceRandom;
-use tokio_stream::StreamExt;
 
 use std::{
     collections::VecDeque,
@@ -32,47 +29,81 @@ use std::{
 use url::Url;
 
 use crate::{
-    config::CrawlerConfig,
+    config::{CrawlerConfig, TopicConfig},
     crawler::MAX_URL_LEN_BYTES,
     distributed::{retry_strategy::ExponentialBackoff, sonic},
-    entrypoint::crawler::router::{NewJob, RouterService},
-    warc,
-    webpage::{url_ext::UrlExt, Html},
+    entrypoint::crawler::router::{
+        Heartbeat, NewJob, ReportDomainCrawled, ReportTraps, RouterService,
+    },
+    simhash, warc,
+    webpage::{document, url_canonicalizer, url_ext::UrlExt, Html},
 };
 
 use super::{
-    reqwest_client, robots_txt::RobotsTxtManager, wander_prirotiser::WanderPrioritiser, CrawlDatum,
-    DatumStream, Domain, Error, Result, RetrieableUrl, Site, UrlResponse, WarcWriter, WeightedUrl,
-    WorkerJob,
+    crawl_state::{CrawlStateStore, UrlCrawlState},
+    crawl_trap::{CrawlTrapDetector, TrapPattern},
+    fetcher::{Fetcher, Response},
+    focus::{self, Focus, Topics},
+    politeness::{self, Politeness, PolitenessMetrics},
+    robots_txt::RobotsTxtManager,
+    sitemap::{self, SitemapEntry, SitemapUrl},
+    wander_prirotiser::WanderPrioritiser,
+    CrawlDatum, DatumStream, Domain, DomainCrawled, Error, JobResponse, Result, RetrieableUrl,
+    Site, UrlResponse, WarcWriter, WeightedUrl, WorkerId, WorkerJob,
 };
 
 const MAX_CONTENT_LENGTH: usize = 32 * 1024 * 1024; // 32 MB
 
+/// How often a worker tells the coordinator that it is still alive while running a job.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of sitemaps (including nested sitemaps) fetched for each site.
+const MAX_SITEMAPS_PER_SITE: usize = 32;
+
 struct ProcessedUrl {
     new_urls: Vec<Url>,
     response: UrlResponse,
+    /// Relevance of the page to the topic of a focused crawl.
+    relevance: Option<f64>,
+}
+
+/// What the worker learned from a job.
+pub struct JobReport {
+    pub domain_crawled: DomainCrawled,
+    pub traps: Vec<TrapPattern>,
 }
 
 pub struct WorkerThread {
+    id: WorkerId,
     writer: Arc<WarcWriter>,
-    client: reqwest::Client,
+    crawl_state: Option<Arc<CrawlStateStore>>,
+    topics: Arc<Topics>,
+    fetcher: Fetcher,
     config: Arc<CrawlerConfig>,
     router_hosts: Vec<SocketAddr>,
+    metrics: PolitenessMetrics,
 }
 
 impl WorkerThread {
     pub fn new(
+        id: WorkerId,
         writer: Arc<WarcWriter>,
+        crawl_state: Option<Arc<CrawlStateStore>>,
+        topics: Arc<Topics>,
+        fetcher: Fetcher,
         config: CrawlerConfig,
         router_hosts: Vec<SocketAddr>,
+        metrics: PolitenessMetrics,
     ) -> Result<Self> {
-        let client = reqwest_client(&config)?;
-
         Ok(Self {
+            id,
             writer,
-            client,
+            crawl_state,
+            topics,
+            fetcher,
             config: Arc::new(config),
             router_hosts,
+            metrics,
         })
     }
 
@@ -89,24 +120,128 @@ impl WorkerThread {
         .await?)
     }
 
+    /// Open the topic of a job. Jobs whose topic can't be opened are crawled without focus.
+    fn focus(&self, topic: &TopicConfig) -> Option<Focus> {
+        match self.topics.focus(topic) {
+            Ok(focus) => Some(focus),
+            Err(err) => {
+                tracing::error!("failed to open topic {:?}: {:?}", topic, err);
+                None
+            }
+        }
+    }
+
+    async fn report_traps(&self, traps: Vec<TrapPattern>) {
+        let res = match self.router_conn().await {
+            Ok(conn) => conn
+                .send_with_timeout(&ReportTraps { traps }, Duration::from_secs(90))
+                .await
+                .map_err(anyhow::Error::from),
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            tracing::warn!("failed to report crawl traps: {}", err);
+        }
+    }
+
+    async fn report_domain_crawled(&self, domain_crawled: DomainCrawled) {
+        let req = ReportDomainCrawled {
+            worker: self.id.clone(),
+            domain_crawled,
+        };
+
+        let res = match self.router_conn().await {
+            Ok(conn) => conn
+                .send_with_timeout(&req, Duration::from_secs(90))
+                .await
+                .map_err(anyhow::Error::from),
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            tracing::warn!("failed to report crawled domain: {}", err);
+        }
+    }
+
+    /// Send a heartbeat to the coordinator every `HEARTBEAT_INTERVAL`. Never returns.
+    async fn heartbeats(&self) {
+        loop {
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+
+            let req = Heartbeat {
+                worker: self.id.clone(),
+            };
+
+            let res = match self.router_conn().await {
+                Ok(conn) => conn
+                    .send_with_timeout(&req, Duration::from_secs(90))
+                    .await
+                    .map_err(anyhow::Error::from),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = res {
+                tracing::debug!("failed to send heartbeat: {}", err);
+            }
+        }
+    }
+
     pub async fn run(self) {
         loop {
             let conn = self.router_conn().await.unwrap();
             let res = conn
-                .send_with_timeout(&NewJob {}, Duration::from_secs(90))
+                .send_with_timeout(
+                    &NewJob {
+                        worker: self.id.clone(),
+                    },
+                    Duration::from_secs(90),
+                )
                 .await;
 
             match res {
-                Ok(Some(job)) => {
-                    let executor = JobExecutor::new(
+                Ok(JobResponse::Job(job)) => {
+                    let focus = job
+                        .topic
+                        .as_ref()
+                        .and_then(|topic| self.focus(topic))
+                        .map(Arc::new);
+
+                    let mut executor = JobExecutor::new(
                         job.into(),
-                        self.client.clone(),
+                        self.fetcher.clone(),
                         self.config.clone(),
                         self.writer.clone(),
-                    );
-                    executor.run().await;
+                    )
+                    .with_politeness_metrics(self.metrics.clone());
+
+                    if let Some(crawl_state) = &self.crawl_state {
+                        executor = executor.with_crawl_state(Arc::clone(crawl_state));
+                    }
+
+                    if let Some(focus) = &focus {
+                        executor = executor.with_focus(Arc::clone(focus));
+                    }
+
+                    let report = tokio::select! {
+                        report = executor.run() => report,
+                        () = self.heartbeats() => unreachable!("heartbeats never finish"),
+                    };
+
+                    if let Some(focus) = focus {
+                        tokio::task::spawn_blocking(move || focus.save()).await.ok();
+                    }
+
+                    self.report_domain_crawled(report.domain_crawled).await;
+
+                    if !report.traps.is_empty() {
+                        self.report_traps(report.traps).await;
+                    }
                 }
-                Ok(None) => {
+                Ok(JobResponse::Wait) => {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                Ok(JobResponse::Done) => {
                     return;
                 }
                 _ => {
@@ -120,42 +255,70 @@ impl WorkerThread {
 
 pub struct JobExecutor<S: DatumStream> {
     writer: Arc<S>,
-    client: reqwest::Client,
-    politeness_factor: f32,
+    fetcher: Fetcher,
+    politeness: Politeness,
     robotstxt: RobotsTxtManager,
-    crawled_urls: HashSet<Url>,
+    /// Canonical keys of the urls that have been crawled.
+    crawled_urls: HashSet<String>,
     crawled_sitemaps: HashSet<Site>,
-    sitemap_urls: HashSet<Url>,
     config: Arc<CrawlerConfig>,
     wander_prioritiser: WanderPrioritiser,
+    crawl_state: Option<Arc<CrawlStateStore>>,
+    focus: Option<Arc<Focus>>,
+    traps: CrawlTrapDetector,
+    num_fetched: u64,
     job: WorkerJob,
 }
 
 impl<S: DatumStream> JobExecutor<S> {
     pub fn new(
         job: WorkerJob,
-        client: reqwest::Client,
+        fetcher: Fetcher,
         config: Arc<CrawlerConfig>,
         writer: Arc<S>,
     ) -> Self {
         Self {
             writer,
-            politeness_factor: config.politeness_factor,
+            politeness: Politeness::new(&config),
             robotstxt: RobotsTxtManager::new(
-                client.clone(),
+                fetcher.clone(),
                 Duration::from_secs(config.robots_txt_cache_sec),
             ),
-            client,
+            fetcher,
             crawled_urls: HashSet::new(),
             crawled_sitemaps: HashSet::new(),
-            sitemap_urls: HashSet::new(),
             config,
             wander_prioritiser: WanderPrioritiser::new(),
+            crawl_state: None,
+            focus: None,
+            traps: CrawlTrapDetector::default(),
+            num_fetched: 0,
             job,
         }
     }
 
-    pub async fn run(mut self) {
+    /// Record the state of the crawled urls and make re-crawls conditional
+    /// on the previously recorded `ETag` and `Last-Modified` headers.
+    pub fn with_crawl_state(mut self, crawl_state: Arc<CrawlStateStore>) -> Self {
+        self.crawl_state = Some(crawl_state);
+        self
+    }
+
+    /// Score the fetched pages against the topic of a focused crawl and
+    /// prioritise the outlinks of on-topic pages.
+    pub fn with_focus(mut self, focus: Arc<Focus>) -> Self {
+        self.focus = Some(focus);
+        self
+    }
+
+    /// Expose the delays between fetches through the metrics.
+    pub fn with_politeness_metrics(mut self, metrics: PolitenessMetrics) -> Self {
+        self.politeness = self.politeness.with_metrics(metrics);
+        self
+    }
+
+    /// Crawl the job and report what was crawled and the crawl traps that were detected.
+    pub async fn run(mut self) -> JobReport {
         tracing::info!("Processing job: {:?}", self.job.domain);
 
         self.scheduled_urls().await;
@@ -163,6 +326,14 @@ impl<S: DatumStream> JobExecutor<S> {
         if self.job.wandering_urls > 0 {
             self.wander().await;
         }
+
+        JobReport {
+            domain_crawled: DomainCrawled {
+                domain: self.job.domain,
+                budget_used: self.num_fetched as f64,
+            },
+            traps: self.traps.into_detected(),
+        }
     }
 
     async fn scheduled_urls(&mut self) {
@@ -175,8 +346,11 @@ impl<S: DatumStream> JobExecutor<S> {
             .wander_prioritiser
             .top_and_clear(self.job.wandering_urls as usize)
             .into_iter()
-            .chain(self.sitemap_urls.drain().map(|url| (url.clone(), 0.0)))
-            .filter(|(url, _)| !self.crawled_urls.contains(url))
+            .filter(|(url, _)| {
+                !self
+                    .crawled_urls
+                    .contains(&url_canonicalizer::canonical_key(url))
+            })
             .filter(|(url, _)| self.job.domain == Domain::from(url))
             .filter(|(_, score)| score.is_finite())
             .collect();
@@ -203,7 +377,13 @@ impl<S: DatumStream> JobExecutor<S> {
                 continue;
             }
 
-            if self.crawled_urls.contains(retryable_url.url()) {
+            let key = url_canonicalizer::canonical_key(retryable_url.url());
+
+            if self.crawled_urls.contains(&key) {
+                continue;
+            }
+
+            if self.traps.is_trapped(retryable_url.url()) {
                 continue;
             }
 
@@ -217,6 +397,14 @@ impl<S: DatumStream> JobExecutor<S> {
                 continue;
             }
 
+            // hosts that asked to wait longer than the maximum crawl delay are left for a later job
+            if self
+                .politeness
+                .is_deferred(retryable_url.url().host_str().unwrap_or_default())
+            {
+                continue;
+            }
+
             if !self.config.dry_run
                 && !self
                     .robotstxt
@@ -233,20 +421,42 @@ impl<S: DatumStream> JobExecutor<S> {
                     .unwrap_or_default()
                     .to_string(),
             );
+
+            if !self.config.dry_run {
+                let crawl_delay = self
+                    .robotstxt
+                    .crawl_delay(retryable_url.url(), &self.config.user_agent.token)
+                    .await;
+                self.politeness.set_crawl_delay(&site.0, crawl_delay);
+            }
+
             if !self.config.dry_run && fetch_sitemap && !self.crawled_sitemaps.contains(&site) {
                 self.crawled_sitemaps.insert(site.clone());
 
-                if let Some(sitemap) = self.robotstxt.sitemap(retryable_url.url()).await {
-                    self.sitemap_urls
-                        .extend(self.urls_from_sitemap(sitemap, 0, 5).await);
+                let mut sitemaps = self.robotstxt.sitemaps(retryable_url.url()).await;
+
+                if sitemaps.is_empty() {
+                    if let Ok(default_sitemap) = retryable_url.url().join("/sitemap.xml") {
+                        sitemaps.push(default_sitemap);
+                    }
                 }
+
+                let sitemap_urls = self.urls_from_sitemaps(sitemaps).await;
+                self.prioritise_sitemap_urls(sitemap_urls);
             }
 
+            self.crawled_urls.insert(key.clone());
+            self.num_fetched += 1;
             let res = self.process_url(retryable_url.url().clone()).await;
 
             match res.response {
                 UrlResponse::Success { url: _ } => {
-                    let weight = retryable_url.weighted_url.weight;
+                    let weight = match res.relevance {
+                        Some(relevance) => {
+                            focus::outlink_weight(retryable_url.weighted_url.weight, relevance)
+                        }
+                        None => retryable_url.weighted_url.weight,
+                    };
 
                     for new_url in res.new_urls {
                         if new_url.host_str().is_none() {
@@ -257,6 +467,12 @@ impl<S: DatumStream> JobExecutor<S> {
                             continue;
                         }
 
+                        self.traps.observe_link(&new_url);
+
+                        if self.traps.is_trapped(&new_url) {
+                            continue;
+                        }
+
                         self.wander_prioritiser.inc(new_url, weight);
                     }
                 }
@@ -265,6 +481,7 @@ impl<S: DatumStream> JobExecutor<S> {
                     status_code,
                 } => {
                     if matches!(status_code, Some(429)) {
+                        self.crawled_urls.remove(&key);
                         let mut retryable_url = retryable_url;
                         retryable_url.retries += 1;
                         urls.push_back(retryable_url);
@@ -272,6 +489,7 @@ impl<S: DatumStream> JobExecutor<S> {
                     }
                 }
                 UrlResponse::Redirected { url: _, new_url: _ } => {}
+                UrlResponse::NotModified { url: _ } => {}
             }
         }
     }
@@ -285,16 +503,28 @@ impl<S: DatumStream> JobExecutor<S> {
                     if datum.status_code == 200 {
                         self.save_datum(datum.clone()).await;
 
-                        match Html::parse(&datum.body, datum.url.as_str()) {
+                        let html = document::to_html_blocking(
+                            datum.body.clone(),
+                            datum.payload_type.clone(),
+                        )
+                        .await
+                        .and_then(|html| Html::parse(&html, datum.url.as_str()));
+
+                        match html {
                             Ok(html) => {
+                                self.record_fetch(&url, &datum, &html);
+
+                                // the page might declare a canonical url different from the one we fetched
+                                self.crawled_urls
+                                    .insert(url_canonicalizer::canonical_key(html.canonical()));
+
                                 let new_urls = html
                                     .all_links()
                                     .into_iter()
-                                    .map(|link| link.destination)
+                                    .map(|link| url_canonicalizer::canonicalize(&link.destination))
                                     .filter(|url| url.as_str().len() <= MAX_URL_LEN_BYTES)
                                     .filter(|url| {
-                                        !url.path().ends_with(".pdf")
-                                            && !url.path().ends_with(".jpg")
+                                        !url.path().ends_with(".jpg")
                                             && !url.path().ends_with(".zip")
                                             && !url.path().ends_with(".png")
                                             && !url.path().ends_with(".css")
@@ -323,11 +553,15 @@ impl<S: DatumStream> JobExecutor<S> {
                                     })
                                     .collect();
 
+                                let relevance =
+                                    self.focus.as_ref().map(|focus| focus.relevance(&html));
+
                                 let url_res = UrlResponse::Success { url: datum.url };
 
                                 ProcessedUrl {
                                     new_urls,
                                     response: url_res,
+                                    relevance,
                                 }
                             }
                             Err(_) => ProcessedUrl {
@@ -336,6 +570,7 @@ impl<S: DatumStream> JobExecutor<S> {
                                     url,
                                     status_code: None,
                                 },
+                                relevance: None,
                             },
                         }
                     } else {
@@ -347,23 +582,22 @@ impl<S: DatumStream> JobExecutor<S> {
                         ProcessedUrl {
                             new_urls: Vec::new(),
                             response: url_res,
+                            relevance: None,
                         }
                     }
-                } else {
-                    if datum.status_code == 429 {
-                        self.politeness_factor *= 2.0;
+                } else if datum.status_code == 304 {
+                    if let Some(crawl_state) = &self.crawl_state {
+                        crawl_state.record_not_modified(&url);
+                    }
 
-                        if self.politeness_factor > self.config.max_politeness_factor {
-                            self.politeness_factor = self.config.max_politeness_factor;
-                        }
+                    self.save_datum(datum).await;
 
-                        tracing::warn!(
-                            "politeness factor increased to {} for {}",
-                            self.politeness_factor,
-                            &url
-                        );
+                    ProcessedUrl {
+                        new_urls: Vec::new(),
+                        response: UrlResponse::NotModified { url },
+                        relevance: None,
                     }
-
+                } else {
                     tracing::debug!("failed to fetch url ({}): {}", &url, datum.status_code);
                     ProcessedUrl {
                         new_urls: Vec::new(),
@@ -371,6 +605,7 @@ impl<S: DatumStream> JobExecutor<S> {
                             url,
                             status_code: Some(datum.status_code),
                         },
+                        relevance: None,
                     }
                 }
             }
@@ -383,67 +618,115 @@ impl<S: DatumStream> JobExecutor<S> {
                         url,
                         status_code: None,
                     },
+                    relevance: None,
                 }
             }
         }
     }
 
+    fn record_fetch(&mut self, url: &Url, datum: &CrawlDatum, html: &Html) {
+        let simhash = match html.clean_text() {
+            Some(text) if !text.is_empty() => simhash::hash(text),
+            _ => 0,
+        };
+
+        self.traps.observe_content(&datum.url, simhash);
+
+        if let Some(crawl_state) = &self.crawl_state {
+            crawl_state.record_fetch(
+                url,
+                datum.etag.clone(),
+                datum.last_modified.clone(),
+                simhash,
+            );
+        }
+    }
+
+    /// Store the fetched page. `304 Not Modified` responses are stored as revisit records.
     async fn save_datum(&self, datum: CrawlDatum) {
-        if datum.status_code != 200 {
+        if !matches!(datum.status_code, 200 | 304) {
             return;
         }
 
         self.writer.write(datum).await.ok();
     }
 
-    async fn fetch(&self, url: Url) -> Result<reqwest::Response> {
+    async fn fetch(&self, url: Url) -> Result<Response> {
+        self.fetch_conditional(url, None).await
+    }
+
+    /// Fetch the url. If the url has been crawled before, the request is made conditional
+    /// on the recorded validators so the server can respond with `304 Not Modified`.
+    async fn fetch_conditional(&self, url: Url, state: Option<&UrlCrawlState>) -> Result<Response> {
         if self.config.dry_run {
             tracing::debug!("dry run: {}", url);
             return Err(Error::FetchFailed(reqwest::StatusCode::IM_A_TEAPOT).into());
         }
 
-        self.client
-            .get(url.to_string())
-            .send()
-            .await
-            .map_err(|e| e.into())
+        let mut headers = reqwest::header::HeaderMap::new();
+
+        if let Some(state) = state {
+            if let Some(etag) = state.etag.as_ref().and_then(|etag| etag.parse().ok()) {
+                headers.insert(reqwest::header::IF_NONE_MATCH, etag);
+            }
+
+            if let Some(last_modified) = state
+                .last_modified
+                .as_ref()
+                .and_then(|last_modified| last_modified.parse().ok())
+            {
+                headers.insert(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        self.fetcher.get(&url, headers, None).await
     }
 
-    async fn crawl_url(&self, url: Url) -> Result<CrawlDatum> {
+    async fn crawl_url(&mut self, url: Url) -> Result<CrawlDatum> {
         let start = Instant::now();
 
+        let state = self
+            .crawl_state
+            .as_ref()
+            .and_then(|crawl_state| crawl_state.get(&url));
+
         let res = if url.scheme() == "http" {
             let mut https = url.clone();
             https
                 .set_scheme("https")
                 .map_err(|_| anyhow!("set scheme on url failed"))?;
 
-            match self.fetch(https).await {
+            match self.fetch_conditional(https, state.as_ref()).await {
                 Ok(res) => Ok(res),
                 Err(_) => {
                     tokio::time::sleep(Duration::from_millis(self.config.min_crawl_delay_ms)).await;
-                    self.fetch(url.clone()).await
+                    self.fetch_conditional(url.clone(), state.as_ref()).await
                 }
             }
         } else {
-            self.fetch(url.clone()).await
+            self.fetch_conditional(url.clone(), state.as_ref()).await
         };
 
         let fetch_time = start.elapsed();
+        let host = url.host_str().unwrap_or_default().to_string();
+
+        if !self.config.dry_run {
+            let (status_code, retry_after) = match &res {
+                Ok(res) => (
+                    Some(res.status().as_u16()),
+                    res.headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(politeness::parse_retry_after),
+                ),
+                Err(_) => (None, None),
+            };
 
-        let mut delay = fetch_time;
-
-        if delay < Duration::from_millis(self.config.min_crawl_delay_ms) {
-            delay = Duration::from_millis(self.config.min_crawl_delay_ms);
-        }
-
-        delay = delay.mul_f32(self.politeness_factor);
-
-        if delay > Duration::from_millis(self.config.max_crawl_delay_ms) {
-            delay = Duration::from_millis(self.config.max_crawl_delay_ms);
+            self.politeness
+                .record(&host, fetch_time, status_code, retry_after);
         }
 
-        tokio::time::sleep(delay).await;
+        self.politeness.wait(&host).await;
 
         let res = res?;
 
@@ -460,6 +743,23 @@ impl<S: DatumStream> JobExecutor<S> {
             })
             .collect();
 
+        let etag = headers.get("etag").cloned();
+        let last_modified = headers.get("last-modified").cloned();
+        let status_code = res.status().as_u16();
+
+        // a not modified response has no body, so the content type is not checked
+        if status_code == 304 {
+            return Ok(CrawlDatum {
+                url,
+                status_code,
+                payload_type: warc::PayloadType::Html,
+                body: Vec::new(),
+                fetch_time_ms: fetch_time.as_millis() as u64,
+                etag,
+                last_modified,
+            });
+        }
+
         // check if content length is too large
         if let Some(content_length) = headers.get("content-length") {
             if content_length.parse::<usize>().unwrap_or(0) > MAX_CONTENT_LENGTH {
@@ -467,16 +767,19 @@ impl<S: DatumStream> JobExecutor<S> {
             }
         }
 
-        // check if content type is html
+        // check if content type is supported
         let payload_type = match headers.get("content-type") {
             Some(ct) if ct.contains("text/html") => warc::PayloadType::Html,
+            Some(ct) if ct.contains("application/pdf") => warc::PayloadType::Pdf,
+            Some(ct) if ct.contains("text/plain") => warc::PayloadType::Text,
+            Some(ct) if ct.contains("text/markdown") || ct.contains("text/x-markdown") => {
+                warc::PayloadType::Markdown
+            }
             Some(ct) if ct.contains("application/rss") => warc::PayloadType::Rss,
             Some(ct) if ct.contains("application/atom") => warc::PayloadType::Atom,
             ct => return Err(Error::InvalidContentType(format!("{ct:?}")).into()),
         };
 
-        let status_code = res.status().as_u16();
-
         if status_code == 301 || status_code == 302 {
             let location = res
                 .headers()
@@ -493,8 +796,10 @@ impl<S: DatumStream> JobExecutor<S> {
                 url,
                 status_code,
                 payload_type,
-                body: String::new(),
+                body: Vec::new(),
                 fetch_time_ms: fetch_time.as_millis() as u64,
+                etag,
+                last_modified,
             });
         }
 
@@ -511,25 +816,17 @@ impl<S: DatumStream> JobExecutor<S> {
             .unwrap_or("utf-8");
         let encoding = Encoding::for_label(encoding_name.as_bytes()).unwrap_or(UTF_8);
 
-        let mut bytes = Vec::new();
-
-        let mut stream = res.bytes_stream();
-        while let Some(b) = stream.next().await {
-            if b.is_err() {
-                return Err(Error::ContentTooLarge.into());
-            }
-
-            let b = b.unwrap();
-
-            bytes.extend_from_slice(&b);
-
-            if bytes.len() > MAX_CONTENT_LENGTH {
-                return Err(Error::ContentTooLarge.into());
-            }
-        }
+        let bytes = res
+            .bytes(MAX_CONTENT_LENGTH)
+            .await
+            .map_err(|_| Error::ContentTooLarge)?;
 
-        let (text, _, _) = encoding.decode(&bytes);
-        let body = text.to_string();
+        let body = if payload_type.is_binary() {
+            bytes
+        } else {
+            let (text, _, _) = encoding.decode(&bytes);
+            text.into_owned().into_bytes()
+        };
 
         Ok(CrawlDatum {
             url: res_url,
@@ -537,212 +834,99 @@ impl<S: DatumStream> JobExecutor<S> {
             body,
             payload_type,
             fetch_time_ms: fetch_time.as_millis() as u64,
+            etag,
+            last_modified,
         })
     }
 
-    fn urls_from_sitemap(
-        &self,
-        sitemap: Url,
-        depth: usize,
-        max_depth: usize,
-    ) -> BoxFuture<'_, Vec<Url>> {
-        async move {
-            if depth == max_depth {
-                return vec![];
-            }
-
-            // fetch url
-            let res = self.fetch(sitemap).await;
+    /// Add the urls from the sitemaps to the wander prioritiser. Urls that have not been
+    /// modified since they were last crawled are skipped.
+    fn prioritise_sitemap_urls(&mut self, sitemap_urls: Vec<SitemapUrl>) {
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
 
-            if res.is_err() {
-                return vec![];
+        for sitemap_url in sitemap_urls {
+            if Domain::from(&sitemap_url.url) != self.job.domain {
+                continue;
             }
-            let res = res.unwrap();
 
-            if res.status() != reqwest::StatusCode::OK {
-                return vec![];
+            if let (Some(crawl_state), Some(lastmod)) = (&self.crawl_state, sitemap_url.lastmod) {
+                if !crawl_state.record_lastmod(&sitemap_url.url, lastmod) {
+                    continue;
+                }
             }
 
-            let body = res.text().await;
+            let weight = sitemap_url.weight(now);
+            self.wander_prioritiser
+                .inc(url_canonicalizer::canonicalize(&sitemap_url.url), weight);
+        }
+    }
 
-            if body.is_err() {
-                return vec![];
-            }
+    async fn fetch_sitemap(&self, url: Url) -> Result<Vec<SitemapEntry>> {
+        let res = self.fetch(url).await?;
 
-            let body = body.unwrap();
+        if res.status() != reqwest::StatusCode::OK {
+            return Err(Error::FetchFailed(res.status()).into());
+        }
 
-            // parse xml
-            let entries = parse_sitemap(&body);
+        if res
+            .content_length()
+            .map(|len| len as usize > sitemap::MAX_SITEMAP_BYTES)
+            .unwrap_or(false)
+        {
+            return Err(Error::ContentTooLarge.into());
+        }
 
-            let mut urls = vec![];
+        let body = res.bytes(sitemap::MAX_SITEMAP_BYTES).await?;
 
-            for entry in entries {
-                match entry {
-                    SitemapEntry::Url(url) => {
-                        urls.push(url);
-                    }
-                    SitemapEntry::Sitemap(url) => {
-                        tokio::time::sleep(Duration::from_millis(self.config.min_crawl_delay_ms))
-                            .await;
-                        urls.append(&mut self.urls_from_sitemap(url, depth + 1, max_depth).await);
-                    }
-                }
-            }
-
-            urls
-        }
-        .boxed()
+        Ok(sitemap::parse(&body))
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum SitemapEntry {
-    Url(Url),
-    Sitemap(Url),
-}
+    /// Fetch the sitemaps and the sitemaps they reference. Nested sitemaps are fetched
+    /// in order of their `lastmod`, newest first, until `MAX_SITEMAPS_PER_SITE` sitemaps have been fetched.
+    async fn urls_from_sitemaps(&self, sitemaps: Vec<Url>) -> Vec<SitemapUrl> {
+        let mut queue: VecDeque<Url> = sitemaps.into_iter().collect();
+        let mut fetched = HashSet::new();
+        let mut urls = vec![];
 
-fn parse_sitemap(s: &str) -> Vec<SitemapEntry> {
-    let mut reader = quick_xml::Reader::from_str(s);
+        while let Some(sitemap) = queue.pop_front() {
+            if fetched.len() >= MAX_SITEMAPS_PER_SITE {
+                break;
+            }
 
-    let mut res = vec![];
+            if !fetched.insert(sitemap.clone()) {
+                continue;
+            }
 
-    let mut in_sitemap = false;
-    let mut in_url = false;
-    let mut in_loc = false;
+            let host = sitemap.host_str().unwrap_or_default().to_string();
 
-    loop {
-        match reader.read_event() {
-            Ok(Event::Start(ref e)) => {
-                if e.name().as_ref() == b"sitemap" {
-                    in_sitemap = true;
-                } else if e.name().as_ref() == b"url" {
-                    in_url = true;
-                } else if e.name().as_ref() == b"loc" {
-                    in_loc = true;
-                }
+            if self.politeness.is_deferred(&host) {
+                continue;
             }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"sitemap" {
-                    in_sitemap = false;
-                } else if e.name().as_ref() == b"url" {
-                    in_url = false;
-                } else if e.name().as_ref() == b"loc" {
-                    in_loc = false;
+
+            let res = self.fetch_sitemap(sitemap.clone()).await;
+            self.politeness.wait(&host).await;
+
+            let entries = match res {
+                Ok(entries) => entries,
+                Err(err) => {
+                    tracing::debug!("failed to fetch sitemap ({}): {}", sitemap, err);
+                    continue;
                 }
-            }
-            Ok(Event::Text(e)) => {
-                if in_sitemap && in_loc {
-                    if let Ok(url) = Url::parse(&e.unescape().unwrap()) {
-                        res.push(SitemapEntry::Sitemap(url));
-                    }
-                } else if in_url && in_loc {
-                    if let Ok(url) = Url::parse(&e.unescape().unwrap()) {
-                        res.push(SitemapEntry::Url(url));
-                    }
+            };
+
+            let mut nested = vec![];
+
+            for entry in entries {
+                match entry {
+                    SitemapEntry::Url(url) => urls.push(url),
+                    SitemapEntry::Sitemap { url, lastmod } => nested.push((lastmod, url)),
                 }
             }
-            Ok(Event::Eof) => break,
-            Err(e) => {
-                tracing::debug!("failed to parse sitemap: {}", e);
-                break;
-            }
-            _ => (),
-        }
-    }
 
-    res
-}
+            nested.sort_by_key(|(lastmod, _)| std::cmp::Reverse(*lastmod));
+            queue.extend(nested.into_iter().map(|(_, url)| url));
+        }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn parse_sitemap() {
-        let dr = r#"<sitemapindex>
-        <sitemap>
-        <loc>https://www.dr.dk/drtv/sitemap.xml</loc>
-        </sitemap>
-        <sitemap>
-        <loc>https://www.dr.dk/sitemap.tvguide.xml</loc>
-        </sitemap>
-        <sitemap>
-        <loc>
-        https://www.dr.dk/sitemap.kommunalvalg.resultater.xml
-        </loc>
-        </sitemap>
-        <sitemap>
-        <loc>https://www.dr.dk/sitemap.folketingsvalg2022.xml</loc>
-        </sitemap>
-        </sitemapindex>"#;
-
-        let entries = super::parse_sitemap(dr);
-        assert_eq!(
-            entries,
-            vec![
-                super::SitemapEntry::Sitemap("https://www.dr.dk/drtv/sitemap.xml".parse().unwrap()),
-                super::SitemapEntry::Sitemap(
-                    "https://www.dr.dk/sitemap.tvguide.xml".parse().unwrap()
-                ),
-                super::SitemapEntry::Sitemap(
-                    "https://www.dr.dk/sitemap.kommunalvalg.resultater.xml"
-                        .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Sitemap(
-                    "https://www.dr.dk/sitemap.folketingsvalg2022.xml"
-                        .parse()
-                        .unwrap()
-                ),
-            ]
-        );
-
-        let dr = r#"<urlset>
-        <url>
-        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
-        <loc>https://www.dr.dk/drtv/serie/sleepover_6382</loc>
-        </url>
-        <url>
-        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
-        <loc>https://www.dr.dk/drtv/saeson/sleepover_9673</loc>
-        </url>
-        <url>
-        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
-        <loc>
-        https://www.dr.dk/drtv/episode/sleepover_-zoologisk-museum_52239
-        </loc>
-        </url>
-        <url>
-        <lastmod>2023-10-18T05:40:04.7435930+00:00</lastmod>
-        <loc>
-        https://www.dr.dk/drtv/episode/sleepover_-koebenhavns-raadhus_52252
-        </loc>
-        </url>
-        </urlset>"#;
-
-        let entries = super::parse_sitemap(dr);
-        assert_eq!(
-            entries,
-            vec![
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/serie/sleepover_6382"
-                        .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/saeson/sleepover_9673"
-                        .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/episode/sleepover_-zoologisk-museum_52239"
-                        .parse()
-                        .unwrap()
-                ),
-                super::SitemapEntry::Url(
-                    "https://www.dr.dk/drtv/episode/sleepover_-koebenhavns-raadhus_52252"
-                        .parse()
-                        .unwrap()
-                ),
-            ]
-        );
+        urls
     }
 }
@@ -0,0 +1,739 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The dense index stores a passage embedding for each document and
+//! supports approximate nearest neighbour search over them.
+//!
+//! It is implemented as an inverted file index (IVF). The embeddings are
+//! clustered with k-means and each centroid owns a list of the embeddings
+//! assigned to it. During search, only the lists of the centroids closest
+//! to the query embedding are scanned. The embeddings are expected to be
+//! l2 normalized, so the inner product is used as similarity.
+//!
+//! The raw embeddings are stored in a flat file that is memory mapped. The url and
+//! insertion time of each embedding are appended to two other memory mapped files, so
+//! only a hash of each live url is kept in memory. The centroids and the lists are
+//! stored in a separate metadata file.
+//!
+//! Each url has at most one live embedding. Inserting a url again replaces its
+//! previous embedding, and deleted embeddings are only marked as such until more
+//! than half of the stored embeddings are dead, at which point the files
+//! are compacted.
+//!
+//! The centroids are only retrained once the index has doubled in size since they were
+//! last trained. Otherwise a commit only assigns the new embeddings to their closest centroid.
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::prehashed::{self, Prehashed};
+use crate::Result;
+
+const VECTORS_FILE_NAME: &str = "vectors.bin";
+const URLS_FILE_NAME: &str = "urls.bin";
+const RECORDS_FILE_NAME: &str = "records.bin";
+const META_FILE_NAME: &str = "meta.bin";
+
+const KMEANS_ITERATIONS: usize = 10;
+const NUM_PROBES: usize = 8;
+/// The centroids are trained on a sample of at most this many embeddings per centroid.
+const TRAINING_SAMPLES_PER_CENTROID: usize = 256;
+
+/// The end offset of the url in the urls file and the insertion time (seconds since the
+/// unix epoch) of an embedding.
+type Record = [u64; 2];
+
+#[derive(Default, Serialize, Deserialize)]
+struct Meta {
+    dim: usize,
+    /// number of committed embeddings, including the deleted ones.
+    len: usize,
+    /// embeddings that have been deleted or replaced by a newer embedding of the same url.
+    deleted: HashSet<u32>,
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<u32>>,
+    /// number of embeddings the centroids were trained on.
+    /// The centroids are retrained once the index has doubled in size.
+    trained_on: usize,
+}
+
+enum Operation {
+    Insert {
+        url: String,
+        embedding: Vec<f32>,
+        inserted: u64,
+    },
+    Delete(String),
+    DeleteBefore(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenseHit {
+    pub url: String,
+    pub similarity: f32,
+    pub embedding: Vec<f32>,
+}
+
+pub struct DenseIndex {
+    path: PathBuf,
+    meta: Meta,
+    /// the id of the live embedding of each url, keyed by the hash of the url.
+    live: HashMap<Prehashed, u32>,
+    vectors: Option<Mmap>,
+    urls: Option<Mmap>,
+    records: Option<Mmap>,
+    pending: Mutex<Vec<Operation>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+fn closest_centroid(centroids: &[Vec<f32>], vector: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| dot(a, vector).total_cmp(&dot(b, vector)))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+fn kmeans(vectors: &[&[f32]], dim: usize, k: usize) -> Vec<Vec<f32>> {
+    if vectors.is_empty() || k == 0 {
+        return Vec::new();
+    }
+
+    let k = k.min(vectors.len());
+    let step = vectors.len() / k;
+
+    let mut centroids: Vec<Vec<f32>> = (0..k).map(|i| vectors[i * step].to_vec()).collect();
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![vec![0.0; dim]; k];
+        let mut counts = vec![0usize; k];
+
+        for vector in vectors {
+            let c = closest_centroid(&centroids, vector);
+            counts[c] += 1;
+
+            for (s, v) in sums[c].iter_mut().zip(vector.iter()) {
+                *s += v;
+            }
+        }
+
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count == 0 {
+                continue;
+            }
+
+            let norm = sum.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+            if norm > 0.0 {
+                *centroid = sum.into_iter().map(|v| v / norm).collect();
+            }
+        }
+    }
+
+    centroids
+}
+
+fn map(path: &Path) -> Result<Option<Mmap>> {
+    if path.exists() && fs::metadata(path)?.len() > 0 {
+        let file = File::open(path)?;
+        Ok(Some(unsafe { Mmap::map(&file)? }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn records(records: &Option<Mmap>) -> &[Record] {
+    match records {
+        Some(mmap) => bytemuck::cast_slice(mmap),
+        None => &[],
+    }
+}
+
+/// Truncate the file to `len` bytes and open it for appending.
+fn truncate_and_append(path: &Path, len: u64) -> Result<BufWriter<File>> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.set_len(len)?;
+
+    Ok(BufWriter::new(File::options().append(true).open(path)?))
+}
+
+impl DenseIndex {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if !path.as_ref().exists() {
+            fs::create_dir_all(path.as_ref())?;
+        }
+
+        let meta_path = path.as_ref().join(META_FILE_NAME);
+
+        let meta: Meta = if meta_path.exists() {
+            let mut reader = BufReader::new(File::open(meta_path)?);
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+
+            bincode::deserialize(&bytes)?
+        } else {
+            Meta::default()
+        };
+
+        let mut index = Self {
+            path: path.as_ref().to_path_buf(),
+            meta,
+            live: HashMap::new(),
+            vectors: None,
+            urls: None,
+            records: None,
+            pending: Mutex::new(Vec::new()),
+        };
+
+        index.remap()?;
+        index.live = (0..index.meta.len as u32)
+            .filter(|id| !index.meta.deleted.contains(id))
+            .map(|id| (prehashed::hash(index.url(id as usize)), id))
+            .collect();
+
+        Ok(index)
+    }
+
+    fn remap(&mut self) -> Result<()> {
+        self.vectors = None;
+        self.urls = None;
+        self.records = None;
+
+        self.vectors = map(&self.path.join(VECTORS_FILE_NAME))?;
+        self.urls = map(&self.path.join(URLS_FILE_NAME))?;
+        self.records = map(&self.path.join(RECORDS_FILE_NAME))?;
+
+        Ok(())
+    }
+
+    /// The number of live embeddings in the index.
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn vector(&self, id: usize) -> &[f32] {
+        let vectors: &[f32] = bytemuck::cast_slice(self.vectors.as_ref().unwrap());
+        &vectors[id * self.meta.dim..(id + 1) * self.meta.dim]
+    }
+
+    fn url_range(&self, id: usize) -> std::ops::Range<usize> {
+        let records = records(&self.records);
+        let start = if id == 0 { 0 } else { records[id - 1][0] };
+
+        start as usize..records[id][0] as usize
+    }
+
+    fn url(&self, id: usize) -> &[u8] {
+        &self.urls.as_ref().unwrap()[self.url_range(id)]
+    }
+
+    fn inserted(&self, id: usize) -> u64 {
+        records(&self.records)[id][1]
+    }
+
+    fn live_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<_> = self.live.values().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn push(&self, operation: Operation) {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(operation);
+    }
+
+    /// Insert the embedding of the url. Any previous embedding of the url is replaced
+    /// when the index is committed.
+    pub fn insert(&self, url: String, embedding: Vec<f32>) {
+        self.push(Operation::Insert {
+            url,
+            embedding,
+            inserted: now(),
+        });
+    }
+
+    pub fn delete(&self, url: String) {
+        self.push(Operation::Delete(url));
+    }
+
+    pub fn delete_all_before(&self, timestamp: SystemTime) {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        self.push(Operation::DeleteBefore(timestamp));
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        let pending = std::mem::take(self.pending.get_mut().unwrap_or_else(|e| e.into_inner()));
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        // data written by a commit that failed before its metadata was saved
+        // is not referenced by any embedding and is dropped.
+        let committed = self.meta.len;
+        let urls_len = match committed {
+            0 => 0,
+            n => records(&self.records)[n - 1][0],
+        };
+
+        self.vectors = None;
+        self.urls = None;
+        self.records = None;
+
+        let mut vectors = truncate_and_append(
+            &self.path.join(VECTORS_FILE_NAME),
+            (committed * self.meta.dim * std::mem::size_of::<f32>()) as u64,
+        )?;
+        let mut urls = truncate_and_append(&self.path.join(URLS_FILE_NAME), urls_len)?;
+        let mut records_writer = truncate_and_append(
+            &self.path.join(RECORDS_FILE_NAME),
+            (committed * std::mem::size_of::<Record>()) as u64,
+        )?;
+
+        self.remap()?;
+
+        // the records of the embeddings inserted by this commit
+        let mut new_records: Vec<Record> = Vec::new();
+        let mut url_end = urls_len;
+        let mut removed = HashSet::new();
+
+        for operation in pending {
+            match operation {
+                Operation::Insert {
+                    url,
+                    embedding,
+                    inserted,
+                } => {
+                    if self.meta.dim == 0 {
+                        self.meta.dim = embedding.len();
+                    }
+
+                    if embedding.len() != self.meta.dim {
+                        tracing::warn!(
+                            "skipping embedding for {url} with dimension {} (expected {})",
+                            embedding.len(),
+                            self.meta.dim
+                        );
+                        continue;
+                    }
+
+                    let key = prehashed::hash(&url);
+
+                    if let Some(old) = self.live.get(&key).copied() {
+                        let old = old as usize;
+                        let old_inserted = match old.checked_sub(committed) {
+                            Some(new) => new_records[new][1],
+                            None => self.inserted(old),
+                        };
+
+                        if old_inserted > inserted {
+                            continue;
+                        }
+
+                        self.meta.deleted.insert(old as u32);
+                        removed.insert(old as u32);
+                    }
+
+                    vectors.write_all(bytemuck::cast_slice(&embedding))?;
+                    urls.write_all(url.as_bytes())?;
+                    url_end += url.len() as u64;
+
+                    let record = [url_end, inserted];
+                    records_writer.write_all(bytemuck::cast_slice(&record))?;
+
+                    self.live
+                        .insert(key, (committed + new_records.len()) as u32);
+                    new_records.push(record);
+                }
+                Operation::Delete(url) => {
+                    if let Some(id) = self.live.remove(&prehashed::hash(&url)) {
+                        self.meta.deleted.insert(id);
+                        removed.insert(id);
+                    }
+                }
+                Operation::DeleteBefore(timestamp) => {
+                    let committed_records = records(&self.records);
+                    let deleted = &mut self.meta.deleted;
+
+                    self.live.retain(|_, id| {
+                        let id = *id as usize;
+                        let inserted = match id.checked_sub(committed) {
+                            Some(new) => new_records[new][1],
+                            None => committed_records[id][1],
+                        };
+                        let keep = inserted >= timestamp;
+
+                        if !keep {
+                            deleted.insert(id as u32);
+                            removed.insert(id as u32);
+                        }
+
+                        keep
+                    });
+                }
+            }
+        }
+
+        vectors.flush()?;
+        urls.flush()?;
+        records_writer.flush()?;
+        drop((vectors, urls, records_writer));
+
+        self.meta.len = committed + new_records.len();
+        self.remap()?;
+
+        let added: Vec<_> = (committed as u32..self.meta.len as u32)
+            .filter(|id| !self.meta.deleted.contains(id))
+            .collect();
+
+        if self.meta.deleted.len() * 2 > self.meta.len {
+            self.compact()?;
+            self.build_lists();
+        } else if self.meta.centroids.is_empty() || self.len() > self.meta.trained_on * 2 {
+            self.build_lists();
+        } else {
+            self.update_lists(&removed, added);
+        }
+
+        self.save_meta()
+    }
+
+    /// Rewrite the files without the deleted embeddings.
+    fn compact(&mut self) -> Result<()> {
+        let ids = self.live_ids();
+
+        let vectors_tmp = self.path.join(format!("{VECTORS_FILE_NAME}.tmp"));
+        let urls_tmp = self.path.join(format!("{URLS_FILE_NAME}.tmp"));
+        let records_tmp = self.path.join(format!("{RECORDS_FILE_NAME}.tmp"));
+
+        let mut vectors = BufWriter::new(File::create(&vectors_tmp)?);
+        let mut urls = BufWriter::new(File::create(&urls_tmp)?);
+        let mut records_writer = BufWriter::new(File::create(&records_tmp)?);
+        let mut url_end = 0;
+
+        for id in &ids {
+            let id = *id as usize;
+            let url = self.url(id);
+            url_end += url.len() as u64;
+
+            vectors.write_all(bytemuck::cast_slice(self.vector(id)))?;
+            urls.write_all(url)?;
+            records_writer.write_all(bytemuck::cast_slice(&[url_end, self.inserted(id)]))?;
+        }
+
+        vectors.flush()?;
+        urls.flush()?;
+        records_writer.flush()?;
+        drop((vectors, urls, records_writer));
+
+        self.live = ids
+            .iter()
+            .enumerate()
+            .map(|(new, id)| (prehashed::hash(self.url(*id as usize)), new as u32))
+            .collect();
+        self.meta.len = ids.len();
+        self.meta.deleted.clear();
+
+        self.vectors = None;
+        self.urls = None;
+        self.records = None;
+
+        fs::rename(vectors_tmp, self.path.join(VECTORS_FILE_NAME))?;
+        fs::rename(urls_tmp, self.path.join(URLS_FILE_NAME))?;
+        fs::rename(records_tmp, self.path.join(RECORDS_FILE_NAME))?;
+
+        self.remap()
+    }
+
+    /// Assign all live embeddings to their closest centroid. The centroids are
+    /// (re)trained if the index has grown to more than twice the number
+    /// of embeddings they were trained on.
+    fn build_lists(&mut self) {
+        let ids = self.live_ids();
+        let n = ids.len();
+
+        if self.meta.centroids.is_empty() || n > self.meta.trained_on * 2 {
+            let num_lists = ((n as f64).sqrt().ceil() as usize).max(1);
+            let step = (n / (num_lists * TRAINING_SAMPLES_PER_CENTROID)).max(1);
+
+            let centroids = {
+                let vectors: Vec<_> = ids
+                    .iter()
+                    .step_by(step)
+                    .map(|id| self.vector(*id as usize))
+                    .collect();
+                kmeans(&vectors, self.meta.dim, num_lists)
+            };
+
+            self.meta.centroids = centroids;
+            self.meta.trained_on = n;
+        }
+
+        let mut lists = vec![Vec::new(); self.meta.centroids.len()];
+
+        for id in ids {
+            let c = closest_centroid(&self.meta.centroids, self.vector(id as usize));
+            lists[c].push(id);
+        }
+
+        self.meta.lists = lists;
+    }
+
+    /// Remove the embeddings deleted by a commit from the lists and assign the
+    /// embeddings it added to their closest centroid.
+    fn update_lists(&mut self, removed: &HashSet<u32>, added: Vec<u32>) {
+        if !removed.is_empty() {
+            for list in &mut self.meta.lists {
+                list.retain(|id| !removed.contains(id));
+            }
+        }
+
+        for id in added {
+            let c = closest_centroid(&self.meta.centroids, self.vector(id as usize));
+            self.meta.lists[c].push(id);
+        }
+    }
+
+    /// The metadata is written to a temporary file that replaces the previous
+    /// metadata, so a failed write leaves the last committed state intact.
+    fn save_meta(&self) -> Result<()> {
+        let tmp_path = self.path.join(format!("{META_FILE_NAME}.tmp"));
+
+        let mut file = File::options()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+
+        let bytes = bincode::serialize(&self.meta)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+
+        fs::rename(tmp_path, self.path.join(META_FILE_NAME))?;
+
+        Ok(())
+    }
+
+    /// Find the `top_n` embeddings with the highest inner product to the query.
+    pub fn search(&self, query: &[f32], top_n: usize) -> Vec<DenseHit> {
+        if self.is_empty() || query.len() != self.meta.dim {
+            return Vec::new();
+        }
+
+        let mut centroids: Vec<_> = self
+            .meta
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(i, centroid)| (i, dot(centroid, query)))
+            .collect();
+
+        centroids.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut hits: Vec<_> = centroids
+            .into_iter()
+            .take(NUM_PROBES)
+            .flat_map(|(c, _)| self.meta.lists[c].iter())
+            .map(|id| (*id as usize, dot(self.vector(*id as usize), query)))
+            .collect();
+
+        hits.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        hits.into_iter()
+            .take(top_n)
+            .map(|(id, similarity)| DenseHit {
+                url: String::from_utf8_lossy(self.url(id)).into_owned(),
+                similarity,
+                embedding: self.vector(id).to_vec(),
+            })
+            .collect()
+    }
+
+    /// Merge the embeddings of `other` into this index and retrain the centroids.
+    /// Urls found in both indexes keep the most recently inserted embedding.
+    pub fn merge(&mut self, other: DenseIndex) -> Result<()> {
+        self.commit()?;
+
+        if other.is_empty() {
+            return Ok(());
+        }
+
+        for id in other.live_ids() {
+            let id = id as usize;
+
+            self.push(Operation::Insert {
+                url: String::from_utf8_lossy(other.url(id)).into_owned(),
+                embedding: other.vector(id).to_vec(),
+                inserted: other.inserted(id),
+            });
+        }
+
+        self.meta.centroids.clear();
+        self.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(v: Vec<f32>) -> Vec<f32> {
+        let norm = v.iter().map(|v| v * v).sum::<f32>().sqrt();
+        v.into_iter().map(|v| v / norm).collect()
+    }
+
+    #[test]
+    fn nearest_neighbour() {
+        let mut index = DenseIndex::open(crate::gen_temp_path()).unwrap();
+
+        for i in 0..100 {
+            let angle = i as f32 / 100.0 * std::f32::consts::FRAC_PI_2;
+            index.insert(
+                format!("https://www.{i}.com/"),
+                normalize(vec![angle.cos(), angle.sin(), 0.0]),
+            );
+        }
+
+        index.commit().unwrap();
+        assert_eq!(index.len(), 100);
+
+        let angle = 42.0 / 100.0 * std::f32::consts::FRAC_PI_2;
+        let res = index.search(&[angle.cos(), angle.sin(), 0.0], 3);
+
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].url, "https://www.42.com/");
+    }
+
+    #[test]
+    fn commit_assigns_new_embeddings() {
+        let path = crate::gen_temp_path();
+        let mut index = DenseIndex::open(&path).unwrap();
+
+        for i in 0..100 {
+            let angle = i as f32 / 100.0 * std::f32::consts::FRAC_PI_2;
+            index.insert(
+                format!("https://www.{i}.com/"),
+                vec![angle.cos(), angle.sin()],
+            );
+        }
+        index.commit().unwrap();
+
+        let centroids = index.meta.centroids.clone();
+        assert_eq!(index.meta.trained_on, 100);
+
+        index.insert("https://new.com/".to_string(), vec![0.0, 1.0]);
+        index.delete("https://www.0.com/".to_string());
+        index.commit().unwrap();
+
+        assert_eq!(index.meta.centroids, centroids);
+        assert_eq!(index.meta.lists.iter().map(Vec::len).sum::<usize>(), 100);
+
+        let index = DenseIndex::open(&path).unwrap();
+        assert_eq!(index.len(), 100);
+
+        let res = index.search(&[0.0, 1.0], 1);
+        assert_eq!(res[0].url, "https://new.com/");
+        assert!(index
+            .search(&[1.0, 0.0], 100)
+            .iter()
+            .all(|hit| hit.url != "https://www.0.com/"));
+    }
+
+    #[test]
+    fn reopen_and_merge() {
+        let path = crate::gen_temp_path();
+        let mut a = DenseIndex::open(&path).unwrap();
+        a.insert("https://a.com/".to_string(), vec![1.0, 0.0]);
+        a.commit().unwrap();
+        drop(a);
+
+        let mut b = DenseIndex::open(crate::gen_temp_path()).unwrap();
+        b.insert("https://b.com/".to_string(), vec![0.0, 1.0]);
+        b.commit().unwrap();
+
+        let mut a = DenseIndex::open(&path).unwrap();
+        assert_eq!(a.len(), 1);
+
+        a.merge(b).unwrap();
+        assert_eq!(a.len(), 2);
+
+        let res = a.search(&[0.0, 1.0], 1);
+        assert_eq!(res[0].url, "https://b.com/");
+    }
+    #[test]
+    fn replace_and_delete() {
+        let mut index = DenseIndex::open(crate::gen_temp_path()).unwrap();
+
+        index.insert("https://a.com/".to_string(), vec![1.0, 0.0]);
+        index.insert("https://b.com/".to_string(), vec![0.0, 1.0]);
+        index.commit().unwrap();
+
+        index.insert("https://a.com/".to_string(), vec![0.0, 1.0]);
+        index.commit().unwrap();
+
+        assert_eq!(index.len(), 2);
+
+        let res = index.search(&[0.0, 1.0], 10);
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|hit| hit.similarity == 1.0));
+
+        index.delete("https://b.com/".to_string());
+        index.commit().unwrap();
+
+        let res = index.search(&[0.0, 1.0], 10);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].url, "https://a.com/");
+
+        index.delete_all_before(SystemTime::now() + std::time::Duration::from_secs(1));
+        index.commit().unwrap();
+
+        assert!(index.is_empty());
+        assert!(index.search(&[0.0, 1.0], 10).is_empty());
+    }
+}
@@ -18,7 +18,7 @@ use std::net::SocketAddr;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{config::WebgraphGranularity, searcher::ShardId};
+use crate::{config::WebgraphGranularity, searcher::ShardId, webgraph::shard::WebgraphShard};
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub enum Service {
@@ -39,6 +39,7 @@ pub enum Service {
     Webgraph {
         host: SocketAddr,
         granularity: WebgraphGranularity,
+        shard: WebgraphShard,
     },
 }
 
@@ -181,6 +181,10 @@ where
     pub fn new(id: Id, replicas: ReplicatedClient<S>) -> Self {
         Self { replicas, id }
     }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
 }
 
 pub struct ShardedClient<S: sonic::service::Service, Id: ShardIdentifier> {
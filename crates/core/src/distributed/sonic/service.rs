@@ -52,6 +52,12 @@ impl<S: Service> Server<S> {
             service: Arc::new(service),
         })
     }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.inner.listener.local_addr()?)
+    }
+
     pub async fn accept(&self) -> Result<()> {
         let mut req = self.inner.accept().await?;
 
@@ -14,17 +14,23 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::{cmp::Reverse, fs::File, path::Path};
+use std::{cmp::Reverse, collections::BTreeSet, fs::File, path::Path};
 
 use crate::{
     external_sort::ExternalSorter,
     kv::{rocksdb_store::RocksDbStore, Kv},
     ranking::inbound_similarity::InboundSimilarity,
     webgraph::{
-        centrality::{approx_harmonic::ApproxHarmonic, harmonic::HarmonicCentrality},
-        Node, WebgraphBuilder,
+        anchor_text::AnchorTextStore,
+        centrality::{
+            approx_harmonic::ApproxHarmonic, harmonic::HarmonicCentrality, pagerank::PageRank,
+        },
+        community::Communities,
+        shard,
+        spam::SpamScores,
+        Node, NodeID, Webgraph, WebgraphBuilder,
     },
 };
 
@@ -46,6 +52,55 @@ fn store_csv<P: AsRef<Path>>(data: Vec<(Node, f64)>, output: P) {
     wtr.flush().unwrap();
 }
 
+fn store_pagerank<P: AsRef<Path>>(
+    graph: &Webgraph,
+    pagerank: &PageRank,
+    base_output: P,
+    name: &str,
+) {
+    let store = RocksDbStore::open(base_output.as_ref().join(name));
+
+    for (node_id, rank) in pagerank.iter() {
+        store.insert(*node_id, rank);
+    }
+    store.flush();
+
+    let mut top_nodes: Vec<_> = pagerank
+        .iter()
+        .map(|(node_id, rank)| (*node_id, rank))
+        .collect();
+    top_nodes.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    top_nodes.truncate(1_000_000);
+
+    let top_nodes = top_nodes
+        .into_iter()
+        .filter_map(|(node_id, rank)| graph.id2node(&node_id).map(|node| (node, rank)))
+        .collect();
+
+    store_csv(top_nodes, base_output.as_ref().join(format!("{name}.csv")));
+}
+
+/// Open the graph at `path`. The page metrics need the whole graph, so a page graph that has
+/// been split into shards has to be merged into one graph first.
+fn open_whole_graph<P: AsRef<Path>>(path: P) -> Result<Webgraph> {
+    let shards = shard::shard_paths(&path)?;
+
+    if shards.len() > 1 {
+        return Err(anyhow!(
+            "the graph at {} is split into {} shards, merge them with `stract webgraph merge {}` first",
+            path.as_ref().display(),
+            shards.len(),
+            shards
+                .iter()
+                .map(|shard| shard.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+    }
+
+    Ok(WebgraphBuilder::new(path).single_threaded().open())
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 struct SortableFloat(f64);
 
@@ -130,7 +185,7 @@ impl Centrality {
             webgraph_path.as_ref().to_str().unwrap()
         );
 
-        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+        let graph = open_whole_graph(webgraph_path)?;
 
         let approx = ApproxHarmonic::build(&graph, base_output.as_ref().join("approx_harmonic"));
         let approx_rank = RocksDbStore::open(base_output.as_ref().join("approx_harmonic_rank"));
@@ -159,4 +214,164 @@ impl Centrality {
 
         Ok(())
     }
+
+    pub fn build_pagerank<P: AsRef<Path>>(webgraph_path: P, base_output: P) -> Result<()> {
+        tracing::info!(
+            "Building pagerank for {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+
+        let graph = open_whole_graph(webgraph_path)?;
+        let pagerank = PageRank::calculate(&graph);
+
+        store_pagerank(&graph, &pagerank, base_output, "pagerank");
+
+        Ok(())
+    }
+
+    /// Build the personalised pagerank of the host graph, where the random surfer
+    /// teleports to the seed hosts.
+    pub fn build_personalized_pagerank<P: AsRef<Path>>(
+        webgraph_path: P,
+        base_output: P,
+        seed_hosts: &[String],
+    ) {
+        tracing::info!(
+            "Building personalized pagerank for {} from {} seed hosts",
+            webgraph_path.as_ref().to_str().unwrap(),
+            seed_hosts.len()
+        );
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+
+        let seeds: Vec<_> = seed_hosts
+            .iter()
+            .map(|host| Node { name: host.clone() }.into_host())
+            .filter(|node| !node.name.is_empty())
+            .map(|node| node.id())
+            .collect();
+
+        let pagerank = PageRank::personalized(&graph, &seeds);
+
+        store_pagerank(&graph, &pagerank, base_output, "personalized_pagerank");
+    }
+
+    /// Build the spam scores of the host graph from lists of trusted and known spam hosts.
+    pub fn build_spam<P: AsRef<Path>>(
+        webgraph_path: P,
+        base_output: P,
+        trusted_hosts: &[String],
+        spam_hosts: &[String],
+    ) -> Result<()> {
+        tracing::info!(
+            "Building spam scores for {} from {} trusted and {} spam hosts",
+            webgraph_path.as_ref().to_str().unwrap(),
+            trusted_hosts.len(),
+            spam_hosts.len()
+        );
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+
+        let host_ids = |hosts: &[String]| -> BTreeSet<NodeID> {
+            hosts
+                .iter()
+                .map(|host| Node { name: host.clone() }.into_host())
+                .filter(|node| !node.name.is_empty())
+                .map(|node| node.id())
+                .collect()
+        };
+
+        let scores = SpamScores::build(
+            &graph,
+            &host_ids(trusted_hosts),
+            &host_ids(spam_hosts),
+            base_output.as_ref().join("spam"),
+        )?;
+
+        let mut top_nodes: Vec<_> = scores.iter().collect();
+        top_nodes.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        top_nodes.truncate(1_000_000);
+
+        let top_nodes = top_nodes
+            .into_iter()
+            .filter_map(|(node_id, score)| graph.id2node(&node_id).map(|node| (node, score)))
+            .collect();
+
+        store_csv(top_nodes, base_output.as_ref().join("spam.csv"));
+
+        Ok(())
+    }
+
+    /// Detect the communities of the host graph.
+    pub fn build_communities<P: AsRef<Path>>(webgraph_path: P, base_output: P) -> Result<()> {
+        tracing::info!(
+            "Building communities for {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+
+        let graph = WebgraphBuilder::new(webgraph_path).single_threaded().open();
+
+        Communities::build(&graph, base_output.as_ref().join("community"))?;
+
+        Ok(())
+    }
+
+    /// Aggregate the anchor texts of the links to each page in the page graph. Links from the
+    /// hosts that are labelled as spam by the spam scores at `host_spam_path` are skipped.
+    pub fn build_anchor_text<P: AsRef<Path>>(
+        webgraph_path: P,
+        base_output: P,
+        host_spam_path: Option<P>,
+    ) -> Result<()> {
+        tracing::info!(
+            "Building anchor texts for {}",
+            webgraph_path.as_ref().to_str().unwrap()
+        );
+
+        let graph = open_whole_graph(webgraph_path)?;
+        let spam = host_spam_path.map(SpamScores::open);
+
+        AnchorTextStore::build(
+            &graph,
+            spam.as_ref(),
+            base_output.as_ref().join("anchor_text"),
+        )?;
+
+        Ok(())
+    }
+
+    /// The hosts in a file with one host per line.
+    pub fn hosts_from_file(path: Option<String>) -> Result<Vec<String>> {
+        let mut hosts = Vec::new();
+
+        if let Some(path) = path {
+            hosts.extend(
+                std::fs::read_to_string(path)?
+                    .lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .map(|line| line.to_string()),
+            );
+        }
+
+        Ok(hosts)
+    }
+
+    /// The seed hosts for the personalised pagerank. The hosts are read from a file with one host
+    /// per line and from the liked hosts of an optic.
+    pub fn seed_hosts(
+        seed_hosts_path: Option<String>,
+        seed_optic_path: Option<String>,
+    ) -> Result<Vec<String>> {
+        let mut hosts = Self::hosts_from_file(seed_hosts_path)?;
+
+        if let Some(path) = seed_optic_path {
+            let optic = optics::Optic::parse(&std::fs::read_to_string(path)?)
+                .map_err(|err| anyhow::anyhow!("failed to parse seed optic: {err}"))?;
+
+            hosts.extend(optic.host_rankings.liked);
+        }
+
+        Ok(hosts)
+    }
 }
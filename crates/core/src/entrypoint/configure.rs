@@ -121,7 +121,8 @@ fn create_webgraph() -> Result<()> {
 
     let mut worker = webgraph::WebgraphWorker {
         host_graph: webgraph::open_host_graph_writer(&out_path_host),
-        page_graph: webgraph::open_page_graph_writer(&out_path_page),
+        page_graph: webgraph::open_sharded_page_graph_writer(&out_path_page, 1),
+        page_tombstones: None,
     };
 
     worker.process_job(&job);
@@ -140,13 +141,15 @@ fn calculate_centrality() {
     if !out_path.exists() {
         Centrality::build_harmonic(&webgraph_path, &out_path);
         Centrality::build_similarity(&webgraph_path, &out_path);
+        Centrality::build_pagerank(&webgraph_path, &out_path).unwrap();
     }
 
     let webgraph_page = Path::new(DATA_PATH).join("webgraph_page");
     let out_path_page = Path::new(DATA_PATH).join("centrality_page");
 
     if !out_path_page.exists() {
-        Centrality::build_approx_harmonic(webgraph_page, out_path_page).unwrap();
+        Centrality::build_approx_harmonic(&webgraph_page, &out_path_page).unwrap();
+        Centrality::build_pagerank(&webgraph_page, &out_path_page).unwrap();
     }
 }
 
@@ -193,6 +196,7 @@ fn create_inverted_index() -> Result<()> {
                 .to_string(),
         ),
         None,
+        None,
     );
 
     let index = indexer::process_job(&job, &worker);
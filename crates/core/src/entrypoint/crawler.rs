@@ -14,22 +14,51 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::Path, sync::Arc};
+
+use rand::seq::SliceRandom;
 
 use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
 
 use crate::{
+    api::metrics_router,
     config,
-    crawler::{self, planner::make_crawl_plan, CrawlCoordinator, Crawler},
+    crawler::{
+        self,
+        crawl_state::MergedCrawlState,
+        crawl_trap::CrawlTrapStore,
+        focus::{MergedHostRelevance, TopicClassifier},
+        planner::{make_crawl_plan, CrawlHistory},
+        CrawlCoordinator, Crawler,
+    },
     distributed::sonic::{self, service::Message},
     kv::rocksdb_store::RocksDbStore,
+    metrics::PrometheusRegistry,
     sonic_service,
     webgraph::WebgraphBuilder,
+    webpage::safety_classifier,
     Result,
 };
 
+/// Fraction of the dataset used to evaluate the topic classifier.
+const TOPIC_TEST_SIZE: f64 = 0.2;
+
 pub async fn worker(config: config::CrawlerConfig) -> Result<()> {
-    let crawler = Crawler::new(config).await?;
+    let mut registry = PrometheusRegistry::default();
+    let crawler = Crawler::new(config.clone(), &mut registry).await?;
+
+    if let Some(addr) = config.prometheus_host {
+        tracing::info!("prometheus exporter listening on {}", addr);
+        let listener = TcpListener::bind(&addr).await?;
+        let metrics_app = metrics_router(registry);
+
+        tokio::spawn(async move {
+            axum::serve(listener, metrics_app.into_make_service())
+                .await
+                .ok();
+        });
+    }
 
     crawler.run().await;
 
@@ -37,7 +66,35 @@ pub async fn worker(config: config::CrawlerConfig) -> Result<()> {
 }
 
 pub async fn coordinator(config: config::CrawlCoordinatorConfig) -> Result<()> {
-    let coordinator = Arc::new(CrawlCoordinator::new(config.job_queue)?);
+    let mut coordinator = CrawlCoordinator::new(config.job_queue)?;
+
+    if let Some(path) = &config.crawl_traps_path {
+        coordinator = coordinator.with_trap_store(CrawlTrapStore::open(path));
+    }
+
+    if let Some(path) = &config.seeds_path {
+        coordinator = coordinator.with_seeds_path(path)?;
+    }
+
+    let coordinator = Arc::new(coordinator);
+
+    if let Some(addr) = config.admin_host {
+        if !addr.ip().is_loopback() && config.admin_token.is_none() {
+            return Err(anyhow::anyhow!(
+                "admin_token must be set when the admin api is not bound to a loopback address"
+            ));
+        }
+
+        tracing::info!("Crawl coordinator admin api listening on {}", addr);
+        let listener = TcpListener::bind(&addr).await?;
+        let admin_app = crawler::admin::router(Arc::clone(&coordinator), config.admin_token);
+
+        tokio::spawn(async move {
+            axum::serve(listener, admin_app.into_make_service())
+                .await
+                .ok();
+        });
+    }
 
     let addr: SocketAddr = config.host;
     let server = coordinator::CoordinatorService { coordinator }
@@ -73,6 +130,17 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
         .single_threaded()
         .open();
     let host_graph = WebgraphBuilder::new(&config.host_graph_path).open();
+    let crawl_state = Some(MergedCrawlState::open_read_only(&config.crawl_state_paths))
+        .filter(|crawl_state| !crawl_state.is_empty());
+    let crawl_traps = config
+        .crawl_traps_path
+        .as_ref()
+        .map(|path| CrawlTrapStore::open_read_only(path).traps())
+        .unwrap_or_default();
+    let host_relevance = Some(MergedHostRelevance::open_read_only(
+        &config.host_relevance_paths,
+    ))
+    .filter(|host_relevance| !host_relevance.is_empty());
     let output_path = config.output_path.clone();
 
     make_crawl_plan(
@@ -80,6 +148,11 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
         page_centrality,
         host_graph,
         page_graph,
+        CrawlHistory {
+            crawl_state,
+            crawl_traps,
+            host_relevance,
+        },
         config,
         output_path,
     )?;
@@ -87,30 +160,109 @@ pub fn planner(config: config::CrawlPlannerConfig) -> Result<()> {
     Ok(())
 }
 
+/// Train the classifier that scores pages during a focused crawl. The dataset is a csv
+/// file with a `label` and a `text` column.
+pub fn train_topic_classifier<P: AsRef<Path>>(dataset: P, output: P) -> Result<()> {
+    if !dataset.as_ref().exists() {
+        return Err(anyhow::anyhow!(
+            "dataset path {:?} does not exist",
+            dataset.as_ref()
+        ));
+    }
+
+    let mut dataset = safety_classifier::load_dataset(dataset)?;
+
+    if dataset.is_empty() {
+        return Err(anyhow::anyhow!("dataset is empty"));
+    }
+
+    dataset.shuffle(&mut rand::thread_rng());
+
+    let test_size = (dataset.len() as f64 * TOPIC_TEST_SIZE) as usize;
+    let test_set = dataset.split_off(dataset.len() - test_size);
+
+    let mut model = TopicClassifier::new();
+    model.fit(&dataset);
+
+    tracing::info!("accuracy: {}", model.accuracy(&test_set));
+
+    model.save(output)?;
+
+    Ok(())
+}
+
 pub mod router {
-    use crate::crawler::Job;
+    use crate::crawler::{crawl_trap::TrapPattern, DomainCrawled, JobResponse, WorkerId};
 
     use super::*;
     pub struct RouterService {
         pub router: crawler::Router,
     }
 
-    sonic_service!(RouterService, [NewJob]);
+    sonic_service!(
+        RouterService,
+        [NewJob, ReportTraps, ReportDomainCrawled, Heartbeat]
+    );
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct NewJob {}
+    pub struct NewJob {
+        pub worker: WorkerId,
+    }
 
     impl Message<RouterService> for NewJob {
-        type Response = Option<Job>;
+        type Response = JobResponse;
+
+        async fn handle(self, server: &RouterService) -> sonic::Result<Self::Response> {
+            Ok(server.router.sample_job(self.worker).await?)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ReportDomainCrawled {
+        pub worker: WorkerId,
+        pub domain_crawled: DomainCrawled,
+    }
+
+    impl Message<RouterService> for ReportDomainCrawled {
+        type Response = ();
 
         async fn handle(self, server: &RouterService) -> sonic::Result<Self::Response> {
-            Ok(server.router.sample_job().await?)
+            Ok(server
+                .router
+                .report_domain_crawled(self.worker, self.domain_crawled)
+                .await?)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Heartbeat {
+        pub worker: WorkerId,
+    }
+
+    impl Message<RouterService> for Heartbeat {
+        type Response = ();
+
+        async fn handle(self, server: &RouterService) -> sonic::Result<Self::Response> {
+            Ok(server.router.heartbeat(self.worker).await?)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ReportTraps {
+        pub traps: Vec<TrapPattern>,
+    }
+
+    impl Message<RouterService> for ReportTraps {
+        type Response = ();
+
+        async fn handle(self, server: &RouterService) -> sonic::Result<Self::Response> {
+            Ok(server.router.report_traps(self.traps).await?)
         }
     }
 }
 
 pub mod coordinator {
-    use crate::crawler::Job;
+    use crate::crawler::{crawl_trap::TrapPattern, DomainCrawled, JobResponse, WorkerId};
 
     use super::*;
 
@@ -118,17 +270,71 @@ pub mod coordinator {
         pub coordinator: Arc<CrawlCoordinator>,
     }
 
-    sonic_service!(CoordinatorService, [GetJob]);
+    sonic_service!(
+        CoordinatorService,
+        [GetJob, ReportTraps, ReportDomainCrawled, Heartbeat]
+    );
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct GetJob {}
+    pub struct GetJob {
+        pub worker: WorkerId,
+    }
 
     impl Message<CoordinatorService> for GetJob {
-        type Response = Option<Job>;
+        type Response = JobResponse;
 
         async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
-            let job = server.coordinator.sample_job()?;
+            let job = server.coordinator.sample_job(self.worker)?;
             Ok(job)
         }
     }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ReportDomainCrawled {
+        pub worker: WorkerId,
+        pub domain_crawled: DomainCrawled,
+    }
+
+    impl Message<CoordinatorService> for ReportDomainCrawled {
+        type Response = ();
+
+        async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
+            server
+                .coordinator
+                .domain_crawled(self.worker, self.domain_crawled);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Heartbeat {
+        pub worker: WorkerId,
+    }
+
+    impl Message<CoordinatorService> for Heartbeat {
+        type Response = ();
+
+        async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
+            server.coordinator.heartbeat(self.worker);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ReportTraps {
+        pub traps: Vec<TrapPattern>,
+    }
+
+    impl Message<CoordinatorService> for ReportTraps {
+        type Response = ();
+
+        async fn handle(self, server: &CoordinatorService) -> sonic::Result<Self::Response> {
+            let coordinator = Arc::clone(&server.coordinator);
+            tokio::task::spawn_blocking(move || coordinator.report_traps(self.traps))
+                .await
+                .map_err(anyhow::Error::from)?;
+
+            Ok(())
+        }
+    }
 }
@@ -31,9 +31,12 @@ use crate::kv::rocksdb_store::RocksDbStore;
 use crate::kv::Kv;
 use crate::mapreduce::{Map, Reduce, Worker};
 use crate::ranking::SignalAggregator;
+use crate::summarizer::DualEncoder;
 use crate::warc::PayloadType;
-use crate::webgraph::{Node, NodeID, Webgraph, WebgraphBuilder};
-use crate::webpage::{safety_classifier, Html, Webpage};
+use crate::webgraph::anchor_text::{self, AnchorTextStore};
+use crate::webgraph::community::Communities;
+use crate::webgraph::{shard, Node, NodeID, Webgraph, WebgraphBuilder};
+use crate::webpage::{document, safety_classifier, Webpage};
 use crate::{human_website_annotations, Result};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,9 +58,18 @@ pub struct IndexingWorker {
     host_centrality_rank_store: RocksDbStore<NodeID, f64>,
     page_centrality_store: Option<RocksDbStore<NodeID, f64>>,
     page_centrality_rank_store: Option<RocksDbStore<NodeID, f64>>,
-    page_webgraph: Option<Webgraph>,
+    host_pagerank_store: Option<RocksDbStore<NodeID, f64>>,
+    host_personalized_pagerank_store: Option<RocksDbStore<NodeID, f64>>,
+    page_pagerank_store: Option<RocksDbStore<NodeID, f64>>,
+    host_spam_store: Option<RocksDbStore<NodeID, f64>>,
+    host_communities: Option<Communities>,
+    page_anchor_text: Option<AnchorTextStore>,
+    /// The shards of the page graph. Each shard has the links from a part of the hosts,
+    /// so the backlinks of a page are collected from all of them.
+    page_webgraph_shards: Vec<Webgraph>,
     topics: Option<human_website_annotations::Mapper>,
     safety_classifier: Option<safety_classifier::Model>,
+    dual_encoder: Option<DualEncoder>,
     job_settings: Option<JobSettings>,
 }
 
@@ -68,6 +80,7 @@ impl IndexingWorker {
         page_webgraph_path: Option<String>,
         topics_path: Option<String>,
         safety_classifier_path: Option<String>,
+        dual_encoder_path: Option<String>,
     ) -> Self {
         Self {
             host_centrality_store: RocksDbStore::open(
@@ -82,11 +95,45 @@ impl IndexingWorker {
             page_centrality_rank_store: page_centrality_store_path
                 .as_ref()
                 .map(|p| RocksDbStore::open(Path::new(&p).join("approx_harmonic_rank"))),
-            page_webgraph: page_webgraph_path
-                .map(|path| WebgraphBuilder::new(path).single_threaded().open()),
+            // only built when the pagerank centrality job has been run
+            host_pagerank_store: Some(Path::new(&host_centrality_store_path).join("pagerank"))
+                .filter(|p| p.exists())
+                .map(RocksDbStore::open),
+            // only built when the centrality job was given seed hosts
+            host_personalized_pagerank_store: Some(
+                Path::new(&host_centrality_store_path).join("personalized_pagerank"),
+            )
+            .filter(|p| p.exists())
+            .map(RocksDbStore::open),
+            page_pagerank_store: page_centrality_store_path
+                .as_ref()
+                .map(|p| Path::new(&p).join("pagerank"))
+                .filter(|p| p.exists())
+                .map(RocksDbStore::open),
+            // only built when the spam centrality job has been run
+            host_spam_store: Some(Path::new(&host_centrality_store_path).join("spam"))
+                .filter(|p| p.exists())
+                .map(RocksDbStore::open),
+            // only built when the community centrality job has been run
+            host_communities: Some(Path::new(&host_centrality_store_path).join("community"))
+                .filter(|p| p.exists())
+                .map(Communities::open),
+            // only built when the anchor text job has been run
+            page_anchor_text: page_centrality_store_path
+                .as_ref()
+                .map(|p| Path::new(&p).join("anchor_text"))
+                .filter(|p| p.exists())
+                .map(AnchorTextStore::open),
+            page_webgraph_shards: page_webgraph_path
+                .map(|path| shard::shard_paths(path).unwrap())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|path| WebgraphBuilder::new(path).single_threaded().open())
+                .collect(),
             topics: topics_path.map(|path| human_website_annotations::Mapper::open(path).unwrap()),
             safety_classifier: safety_classifier_path
                 .map(|path| safety_classifier::Model::open(path).unwrap()),
+            dual_encoder: dual_encoder_path.map(|path| DualEncoder::open(path).unwrap()),
             job_settings: None,
         }
     }
@@ -95,12 +142,18 @@ impl IndexingWorker {
         self.job_settings = Some(job_settings);
     }
 
-    pub fn prepare_webpage(&self, body: &str, url: &str, fetch_time_ms: u64) -> Result<Webpage> {
-        let mut html = match Html::parse_without_text(body, url) {
+    pub fn prepare_webpage(
+        &self,
+        body: &[u8],
+        url: &str,
+        payload_type: &PayloadType,
+        fetch_time_ms: u64,
+    ) -> Result<Webpage> {
+        let mut html = match document::parse_without_text(body, url, payload_type) {
             Ok(html) => html,
             Err(err) => {
-                debug!("error parsing html: {:?}", err);
-                return Err(anyhow!("error parsing html: {:?}", err));
+                debug!("error parsing {}: {:?}", payload_type, err);
+                return Err(anyhow!("error parsing {}: {:?}", payload_type, err));
             }
         };
 
@@ -113,7 +166,7 @@ impl IndexingWorker {
             return Err(anyhow!("empty title"));
         }
 
-        let node = Node::from(html.url());
+        let node = Node::from(html.canonical());
         let host_node_id = node.clone().into_host().id();
 
         let mut host_centrality = self
@@ -152,41 +205,27 @@ impl IndexingWorker {
             }
         }
 
-        let backlink_labels: Vec<String> = self
-            .page_webgraph
-            .as_ref()
-            .map(|webgraph| {
-                webgraph
-                    .raw_ingoing_edges_with_labels(&Node::from(html.url()).id())
-                    .into_iter()
-                    .map(|edge| edge.label)
-                    .filter(|label| !label.is_empty())
-                    .filter(|label| {
-                        let label = label.to_lowercase();
-                        let stopwords = [
-                            "click",
-                            "click here",
-                            "here",
-                            "link",
-                            "website",
-                            "webpage",
-                            "page",
-                            "site",
-                            "url",
-                            "web",
-                            "visit",
-                            "more",
-                            "info",
-                            "information",
-                            "read",
-                            "read more",
-                        ];
-
-                        !stopwords.contains(&label.as_str())
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
+        // the aggregated anchor texts are preferred, so the graph is only queried
+        // when the anchor text job has not been run. Texts used by more hosts are
+        // repeated so they weigh more in the backlink text.
+        let backlink_labels: Vec<String> = match &self.page_anchor_text {
+            Some(store) => store
+                .get(&node.id())
+                .into_iter()
+                .flat_map(|anchor| {
+                    let repetitions = anchor.repetitions();
+                    std::iter::repeat(anchor.text).take(repetitions)
+                })
+                .collect(),
+            None => self
+                .page_webgraph_shards
+                .iter()
+                .flat_map(|webgraph| webgraph.raw_ingoing_edges_with_labels(&node.id()))
+                .filter(|edge| !edge.label.rel.is_qualified())
+                .map(|edge| edge.label.text)
+                .filter(|label| anchor_text::is_descriptive(label))
+                .collect(),
+        };
 
         let mut page_centrality = 0.0;
 
@@ -204,6 +243,39 @@ impl IndexingWorker {
             page_centrality_rank = store.get(&node_id).unwrap_or(u64::MAX as f64);
         }
 
+        let page_pagerank = self
+            .page_pagerank_store
+            .as_ref()
+            .and_then(|store| store.get(&node.id()))
+            .filter(|rank| rank.is_finite())
+            .unwrap_or_default();
+
+        let host_pagerank = self
+            .host_pagerank_store
+            .as_ref()
+            .and_then(|store| store.get(&host_node_id))
+            .filter(|rank| rank.is_finite())
+            .unwrap_or_default();
+
+        let host_personalized_pagerank = self
+            .host_personalized_pagerank_store
+            .as_ref()
+            .and_then(|store| store.get(&host_node_id))
+            .filter(|rank| rank.is_finite())
+            .unwrap_or_default();
+
+        let host_spam_score = self
+            .host_spam_store
+            .as_ref()
+            .and_then(|store| store.get(&host_node_id))
+            .filter(|score| score.is_finite())
+            .unwrap_or_default();
+
+        let host_community = self
+            .host_communities
+            .as_ref()
+            .and_then(|communities| communities.get(&host_node_id));
+
         if !page_centrality.is_finite() {
             page_centrality = 0.0;
         }
@@ -235,18 +307,37 @@ impl IndexingWorker {
             page_centrality_rank,
             host_centrality,
             host_centrality_rank,
+            host_pagerank,
+            host_personalized_pagerank,
+            page_pagerank,
+            host_spam_score,
+            host_community,
             fetch_time_ms,
             pre_computed_score: 0.0,
             node_id: Some(host_node_id),
             dmoz_description,
             safety_classification: None,
             inserted_at: Utc::now(),
+            dense_embedding: None,
         };
 
         if let Some(model) = self.safety_classifier.as_ref() {
             webpage.safety_classification = Some(model.predict(&webpage).label);
         }
 
+        if let Some(model) = self.dual_encoder.as_ref() {
+            let passage = format!(
+                "{}\n{}",
+                webpage.html.title().unwrap_or_default(),
+                webpage.html.clean_text().cloned().unwrap_or_default()
+            );
+
+            webpage.dense_embedding = model
+                .embed_vectors(&[passage.as_str()])
+                .ok()
+                .and_then(|embeddings| embeddings.into_iter().next());
+        }
+
         let mut signal_aggregator = SignalAggregator::new(None);
         signal_aggregator.set_current_timestamp(Utc::now().timestamp().max(0) as usize);
 
@@ -276,13 +367,20 @@ pub fn process_job(job: &Job, worker: &IndexingWorker) -> Index {
             file.records()
                 .flatten()
                 .filter(|record| match &record.response.payload_type {
-                    Some(payload_type) => matches!(payload_type, PayloadType::Html),
+                    Some(payload_type) => {
+                        !matches!(payload_type, PayloadType::Rss | PayloadType::Atom)
+                    }
                     None => true,
                 })
         {
             if let Ok(webpage) = worker.prepare_webpage(
                 &record.response.body,
                 &record.request.url,
+                record
+                    .response
+                    .payload_type
+                    .as_ref()
+                    .unwrap_or(&PayloadType::Html),
                 record.metadata.fetch_time_ms,
             ) {
                 if webpage.host_centrality > 0.0 {
@@ -296,7 +394,7 @@ pub fn process_job(job: &Job, worker: &IndexingWorker) -> Index {
                 if !webpage.backlink_labels.is_empty() {
                     has_backlinks = true;
                 }
-                trace!("inserting webpage: {:?}", webpage.html.url());
+                trace!("inserting webpage: {:?}", webpage.html.canonical());
                 trace!("title = {:?}", webpage.html.title());
                 trace!("text = {:?}", webpage.html.clean_text());
 
@@ -318,7 +416,9 @@ pub fn process_job(job: &Job, worker: &IndexingWorker) -> Index {
         warn!("no page centrality values found in {}", name);
     }
 
-    if !has_backlinks && worker.page_webgraph.is_some() {
+    if !has_backlinks
+        && (worker.page_anchor_text.is_some() || !worker.page_webgraph_shards.is_empty())
+    {
         warn!("no backlinks found in {}", name);
     }
 
@@ -373,6 +473,7 @@ impl Indexer {
             config.page_webgraph_path.clone(),
             config.topics_path.clone(),
             config.safety_classifier_path.clone(),
+            config.dual_encoder_model_path.clone(),
         );
 
         let indexes = warc_paths
@@ -17,7 +17,8 @@
 use rand::seq::SliceRandom;
 use tracing::info;
 
-use crate::{webpage, Result};
+use crate::webpage::safety_classifier::{self, Datapoint, Model};
+use crate::Result;
 use std::path::Path;
 
 const TEST_SIZE: f64 = 0.2;
@@ -30,8 +31,8 @@ pub fn train<P: AsRef<Path>>(dataset: P, output: P) -> Result<()> {
         ));
     }
 
-    let mut model = webpage::safety_classifier::Model::new();
-    let mut dataset = webpage::safety_classifier::load_dataset(dataset)?;
+    let mut model: Model = Model::new();
+    let mut dataset: Vec<Datapoint> = safety_classifier::load_dataset(dataset)?;
 
     if dataset.is_empty() {
         return Err(anyhow::anyhow!("dataset is empty"));
@@ -56,7 +57,7 @@ pub fn train<P: AsRef<Path>>(dataset: P, output: P) -> Result<()> {
 }
 
 pub fn predict<P: AsRef<Path>>(model: P, text: &str) -> Result<()> {
-    let model = webpage::safety_classifier::Model::open(model)?;
+    let model: Model = Model::open(model)?;
     let pred = model.predict_text(text);
 
     info!("{:#?}", pred);
@@ -30,11 +30,15 @@ use crate::{
     index::Index,
     inverted_index::{self, RetrievedWebpage},
     ranking::{
+        coefficient_profiles::CoefficientProfileStore,
         inbound_similarity::InboundSimilarity,
         models::{lambdamart::LambdaMART, linear::LinearRegression},
+        signal_provider::{configure_custom_signals, register_fast_field_signals},
     },
     searcher::{InitialWebsiteResult, LocalSearcher, SearchQuery},
-    sonic_service, Result,
+    sonic_service,
+    summarizer::DualEncoder,
+    Result,
 };
 
 sonic_service!(
@@ -56,6 +60,9 @@ pub struct SearchService {
 
 impl SearchService {
     async fn new(config: config::SearchServerConfig) -> Result<Self> {
+        configure_custom_signals(&config.custom_signals)?;
+        register_fast_field_signals(&config.fast_field_signals)?;
+
         let centrality_store = config
             .host_centrality_store_path
             .map(|p| InboundSimilarity::open(Path::new(&p).join("inbound_similarity")).unwrap());
@@ -75,6 +82,14 @@ impl SearchService {
             local_searcher.set_lambda_model(LambdaMART::open(model_path)?);
         }
 
+        if let Some(model_path) = config.dual_encoder_model_path {
+            local_searcher.set_dual_encoder(DualEncoder::open(model_path)?);
+        }
+
+        if let Some(path) = config.signal_coefficient_profiles_path {
+            local_searcher.set_coefficient_profiles(CoefficientProfileStore::open(path)?);
+        }
+
         local_searcher.set_collector_config(config.collector);
         local_searcher.set_snippet_config(config.snippet);
 
@@ -18,10 +18,13 @@ use crate::{
     config::{self, WebgraphConstructConfig},
     entrypoint::download_all_warc_files,
     mapreduce::Worker,
-    webgraph::{self, Node, WebgraphWriter},
+    webgraph::{
+        self, shard::ShardedWebgraphWriter, EdgeLabel, Node, WebgraphBuilder, WebgraphWriter,
+    },
     webpage::{url_ext::UrlExt, Html},
     Result,
 };
+use anyhow::anyhow;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::{fs, path::Path};
@@ -82,9 +85,20 @@ pub fn open_page_graph_writer<P: AsRef<Path>>(path: P) -> webgraph::WebgraphWrit
     )
 }
 
+pub fn open_sharded_page_graph_writer<P: AsRef<Path>>(
+    path: P,
+    num_shards: u64,
+) -> ShardedWebgraphWriter {
+    ShardedWebgraphWriter::new(path, num_shards, open_page_graph_writer)
+}
+
 pub struct WebgraphWorker {
     pub host_graph: webgraph::WebgraphWriter,
-    pub page_graph: webgraph::WebgraphWriter,
+    pub page_graph: ShardedWebgraphWriter,
+    /// Tombstones for the outgoing links of the crawled pages, which are only written when an
+    /// existing page graph is updated. They are kept apart from `page_graph`, so they only
+    /// delete the links from the existing graph and never the links found by this update.
+    pub page_tombstones: Option<ShardedWebgraphWriter>,
 }
 
 impl WebgraphWorker {
@@ -101,7 +115,7 @@ impl WebgraphWorker {
         for file in warc_files.by_ref() {
             for record in file.records().flatten() {
                 let webpage =
-                    match Html::parse_without_text(&record.response.body, &record.request.url) {
+                    match Html::parse_without_text(&record.response.text(), &record.request.url) {
                         Ok(webpage) => webpage,
                         Err(err) => {
                             tracing::error!("error parsing webpage: {}", err);
@@ -109,25 +123,28 @@ impl WebgraphWorker {
                         }
                     };
 
+                let page = Node::from(webpage.canonical());
+
+                if let Some(tombstones) = &mut self.page_tombstones {
+                    tombstones.remove_outgoing(&page);
+                }
+
                 for mut link in webpage
                     .anchor_links()
                     .into_iter()
                     .filter(|link| matches!(link.destination.scheme(), "http" | "https"))
                 {
-                    let source = link.source.clone();
-                    let destination = link.destination.clone();
                     link.text = link.text.chars().take(128).collect();
 
                     trace!("inserting link {:?}", link);
-                    let mut source = Node::from(source);
-
-                    let mut destination = Node::from(destination);
+                    let destination = Node::from(link.destination.clone());
+                    let label = EdgeLabel::from(&link);
 
                     self.page_graph
-                        .insert(source.clone(), destination.clone(), link.text.clone());
+                        .insert(page.clone(), destination.clone(), label.clone());
 
-                    source = source.into_host();
-                    destination = destination.into_host();
+                    let source = Node::from(link.source.clone()).into_host();
+                    let destination = destination.into_host();
 
                     let dest_domain = link.destination.root_domain();
                     let source_domain = link.source.root_domain();
@@ -135,13 +152,17 @@ impl WebgraphWorker {
                         && source_domain.is_some()
                         && dest_domain != source_domain
                     {
-                        self.host_graph.insert(source, destination, link.text);
+                        self.host_graph.insert(source, destination, label);
                     }
                 }
             }
 
             self.host_graph.commit();
             self.page_graph.commit();
+
+            if let Some(tombstones) = &mut self.page_tombstones {
+                tombstones.commit();
+            }
         }
 
         info!("{} done", name);
@@ -175,6 +196,11 @@ impl Webgraph {
         let mut handlers = Vec::new();
         let host_path = &config.host_graph_base_path;
         let page_path = &config.page_graph_base_path;
+        let num_page_shards = config.page_graph_shards.unwrap_or(1);
+
+        if num_page_shards == 0 {
+            return Err(anyhow!("the number of page graph shards must be positive"));
+        }
 
         const MAX_FINALIZE_CONCURRENT: usize = 8;
         let (s, r) = crossbeam_channel::bounded(MAX_FINALIZE_CONCURRENT);
@@ -190,11 +216,16 @@ impl Webgraph {
 
             let page_path = page_path.clone();
             let page_path = Path::new(&page_path);
+            let tombstones_path = page_path.join(format!("tombstones_{i}"));
             let page_path = page_path.join(format!("worker_{i}"));
 
             let mut worker = WebgraphWorker {
                 host_graph: open_host_graph_writer(host_path),
-                page_graph: open_page_graph_writer(page_path),
+                page_graph: open_sharded_page_graph_writer(page_path, num_page_shards),
+                page_tombstones: config
+                    .page_graph_update_path
+                    .as_ref()
+                    .map(|_| open_sharded_page_graph_writer(tombstones_path, num_page_shards)),
             };
 
             let jobs = jobs.clone();
@@ -205,7 +236,13 @@ impl Webgraph {
                 }
 
                 r.recv().unwrap();
-                let res = (worker.host_graph.finalize(), worker.page_graph.finalize());
+                let res = (
+                    worker.host_graph.finalize(),
+                    worker.page_graph.finalize(),
+                    worker
+                        .page_tombstones
+                        .map(|tombstones| tombstones.finalize()),
+                );
                 s.send(()).unwrap();
                 res
             }));
@@ -216,19 +253,58 @@ impl Webgraph {
             graphs.push(handler.join().unwrap());
         }
 
-        let (mut host_graph, mut page_graph) = graphs.pop().unwrap();
+        let (mut host_graph, mut page_shards, mut tombstone_shards) = graphs.pop().unwrap();
 
-        for (other_host, other_page) in graphs {
+        for (other_host, other_page_shards, other_tombstone_shards) in graphs {
             let other_host_path = other_host.path.clone();
-            let other_page_path = other_page.path.clone();
-
             host_graph.merge(other_host);
-            page_graph.merge(other_page);
-
             fs::remove_dir_all(other_host_path)?;
-            fs::remove_dir_all(other_page_path)?;
+
+            merge_shards(&mut page_shards, other_page_shards)?;
+
+            if let (Some(tombstone_shards), Some(other_tombstone_shards)) =
+                (&mut tombstone_shards, other_tombstone_shards)
+            {
+                merge_shards(tombstone_shards, other_tombstone_shards)?;
+            }
+        }
+
+        if let (Some(update_path), Some(tombstone_shards)) =
+            (&config.page_graph_update_path, tombstone_shards)
+        {
+            // the tombstones are merged before the new links, so they only delete
+            // the links that the existing graph has for the crawled pages
+            for (id, (tombstones, page_shard)) in
+                tombstone_shards.into_iter().zip_eq(page_shards).enumerate()
+            {
+                let mut graph = WebgraphBuilder::new(ShardedWebgraphWriter::shard_path(
+                    update_path,
+                    num_page_shards,
+                    id as u64,
+                ))
+                .compression(webgraph::Compression::Lz4)
+                .open();
+
+                for update in [tombstones, page_shard] {
+                    let merged_path = update.path.clone();
+                    graph.merge(update);
+                    fs::remove_dir_all(merged_path)?;
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+/// Merge each of the graphs in `other` into the graph with the same position in `shards`
+/// and remove the merged graphs.
+fn merge_shards(shards: &mut [webgraph::Webgraph], other: Vec<webgraph::Webgraph>) -> Result<()> {
+    for (shard, other) in shards.iter_mut().zip_eq(other) {
+        let other_path = other.path.clone();
+        shard.merge(other);
+        fs::remove_dir_all(other_path)?;
+    }
+
+    Ok(())
+}
@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use itertools::Itertools;
@@ -31,14 +34,22 @@ use crate::distributed::member::Member;
 use crate::distributed::member::Service;
 use crate::distributed::sonic;
 use crate::distributed::sonic::service::Message;
+use crate::kv::rocksdb_store::RocksDbStore;
+use crate::kv::Kv;
 use crate::ranking::inbound_similarity::InboundSimilarity;
 use crate::searcher::DistributedSearcher;
 use crate::searcher::SearchClient;
 use crate::similar_hosts::SimilarHostsFinder;
 use crate::sonic_service;
+use crate::webgraph::community::Communities;
 use crate::webgraph::Compression;
+use crate::webgraph::Edge;
+use crate::webgraph::EdgeFilter;
+use crate::webgraph::EdgeLabel;
 use crate::webgraph::FullEdge;
 use crate::webgraph::Node;
+use crate::webgraph::NodeID;
+use crate::webgraph::ShortestPaths;
 use crate::webgraph::Webgraph;
 use crate::webgraph::WebgraphBuilder;
 use crate::Result;
@@ -51,20 +62,364 @@ pub struct ScoredHost {
     pub description: Option<String>,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Community {
+    /// Only stable as long as the graph does not change.
+    pub id: String,
+    /// The most linked host of the community, which is more stable than the id.
+    pub name: String,
+    pub size: usize,
+    pub hosts: Vec<String>,
+}
+
+/// A node in the neighbourhood of a host together with its link distance from the host.
+#[derive(serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NeighbourhoodNode {
+    pub node: Node,
+    pub distance: u8,
+}
+
+/// The hosts that can be reached from a host within a number of links
+/// and the links between them.
+#[derive(serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Neighbourhood {
+    pub nodes: Vec<NeighbourhoodNode>,
+    pub edges: Vec<FullEdge>,
+}
+
+/// A page of links ordered by the centrality of the host at the other end of the links.
+#[derive(serde::Serialize, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinksPage {
+    pub links: Vec<FullEdge>,
+    /// Pass this as the cursor to get the next page. Missing on the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl LinksPage {
+    /// Merge the links returned by the shards of the graph into a single page.
+    /// Each shard returns its links ordered by their cursors and one more link than
+    /// the page size if there are more links after the page.
+    pub fn merge<I>(shards: I, limit: usize) -> Self
+    where
+        I: IntoIterator<Item = Vec<RankedLink>>,
+    {
+        let limit = page_size(limit);
+
+        let mut links: Vec<_> = shards.into_iter().flatten().collect();
+        links.sort_by(|a, b| a.cursor.compare(&b.cursor));
+        links.dedup_by(|a, b| a.edge == b.edge);
+
+        let next_cursor = if links.len() > limit {
+            Some(links[limit - 1].cursor.to_string())
+        } else {
+            None
+        };
+
+        Self {
+            links: links
+                .into_iter()
+                .take(limit)
+                .map(|link| link.edge)
+                .collect(),
+            next_cursor,
+        }
+    }
+}
+
+/// The position of a link in the ordering of a [`LinksPage`]: highest centrality first,
+/// ties are broken by the id of the node at the other end of the link.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LinkCursor {
+    centrality: f64,
+    node: NodeID,
+}
+
+impl LinkCursor {
+    fn compare(&self, other: &Self) -> Ordering {
+        other
+            .centrality
+            .total_cmp(&self.centrality)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl std::fmt::Display for LinkCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:016x}{:016x}",
+            self.centrality.to_bits(),
+            self.node.as_u64()
+        )
+    }
+}
+
+impl FromStr for LinkCursor {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || crate::Error::ParsingError(format!("invalid cursor: {s}"));
+
+        if s.len() != 32 || !s.is_ascii() {
+            return Err(invalid());
+        }
+
+        let (centrality, node) = s.split_at(16);
+
+        let centrality = u64::from_str_radix(centrality, 16).map_err(|_| invalid())?;
+        let node = u64::from_str_radix(node, 16).map_err(|_| invalid())?;
+
+        Ok(Self {
+            centrality: f64::from_bits(centrality),
+            node: NodeID::from(node),
+        })
+    }
+}
+
+/// A link together with its position in the ordering of a [`LinksPage`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedLink {
+    pub cursor: LinkCursor,
+    pub edge: FullEdge,
+}
+
+fn page_size(limit: usize) -> usize {
+    limit.clamp(1, MAX_LINKS_PER_PAGE)
+}
+
 const MAX_HOSTS: usize = 20;
+const MAX_LINKS_PER_PAGE: usize = 1_000;
+const MAX_COMMUNITY_HOSTS: usize = 1_000;
+const MAX_PATH_LENGTH: u8 = 8;
+/// The search for a path between two hosts gives up after visiting this many hosts.
+const MAX_PATH_VISITED_HOSTS: usize = 100_000;
+const MAX_NEIGHBOURHOOD_DISTANCE: u8 = 3;
+const MAX_NEIGHBOURHOOD_HOSTS: usize = 1_000;
 
 pub struct WebGraphService {
     granularity: WebgraphGranularity,
     searcher: DistributedSearcher,
     similar_hosts_finder: Option<SimilarHostsFinder>,
+    communities: Option<Communities>,
+    host_centrality: Option<Arc<RocksDbStore<NodeID, f64>>>,
+    graph: Arc<Webgraph>,
+}
+
+impl WebGraphService {
+    fn link_ranker(&self) -> LinkRanker {
+        LinkRanker {
+            granularity: self.granularity,
+            host_centrality: self.host_centrality.clone(),
+            graph: Arc::clone(&self.graph),
+        }
+    }
+}
+
+/// Ranks the links of a node by the host centrality of the node at the other end of each link.
+/// The ranking reads the graph, so it should run on the blocking thread pool.
+struct LinkRanker {
+    granularity: WebgraphGranularity,
+    host_centrality: Option<Arc<RocksDbStore<NodeID, f64>>>,
     graph: Arc<Webgraph>,
 }
 
+impl LinkRanker {
+    /// The host centrality of the node at the other end of a link. At page granularity
+    /// the host of the page is only looked up if there is a centrality store.
+    fn link_centrality(&self, node: &NodeID) -> f64 {
+        let Some(store) = self.host_centrality.as_ref() else {
+            return 0.0;
+        };
+
+        match self.granularity {
+            WebgraphGranularity::Host => store.get(node),
+            WebgraphGranularity::Page => self.graph.host_id(node).and_then(|host| store.get(&host)),
+        }
+        .unwrap_or_default()
+    }
+
+    /// Keep the edges after `cursor` with the best positions in a bounded heap while they are
+    /// streamed from the graph, and return them in page order. One edge more than the page size
+    /// is returned if there are more links after the page.
+    fn top_edges<F>(
+        &self,
+        edges: impl Iterator<Item = Edge<()>>,
+        other: F,
+        cursor: Option<LinkCursor>,
+        limit: usize,
+    ) -> Vec<RankedEdge>
+    where
+        F: Fn(&Edge<()>) -> NodeID,
+    {
+        let num_links = page_size(limit) + 1;
+
+        // the last link of the page is at the top of the heap
+        let mut heap = BinaryHeap::with_capacity(num_links + 1);
+
+        // an edge can be stored in more than one segment
+        let mut in_heap = HashSet::with_capacity(num_links + 1);
+
+        for edge in edges {
+            let node = other(&edge);
+
+            if in_heap.contains(&node) {
+                continue;
+            }
+
+            let link_cursor = LinkCursor {
+                centrality: self.link_centrality(&node),
+                node,
+            };
+
+            if let Some(after) = &cursor {
+                if link_cursor.compare(after) != Ordering::Greater {
+                    continue;
+                }
+            }
+
+            heap.push(RankedEdge {
+                cursor: link_cursor,
+                edge,
+            });
+            in_heap.insert(node);
+
+            if heap.len() > num_links {
+                if let Some(last) = heap.pop() {
+                    in_heap.remove(&last.cursor.node);
+                }
+            }
+        }
+
+        heap.into_sorted_vec()
+    }
+
+    /// Look up the names of the nodes of the ranked edges and add their labels.
+    fn links(
+        &self,
+        ranked: Vec<RankedEdge>,
+        mut labels: HashMap<NodeID, EdgeLabel>,
+    ) -> Vec<RankedLink> {
+        ranked
+            .into_iter()
+            .filter_map(|ranked| {
+                Some(RankedLink {
+                    cursor: ranked.cursor,
+                    edge: FullEdge {
+                        from: self.graph.id2node(&ranked.edge.from)?,
+                        to: self.graph.id2node(&ranked.edge.to)?,
+                        label: labels.remove(&ranked.cursor.node)?,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn ingoing(
+        &self,
+        node: Node,
+        filter: EdgeFilter,
+        cursor: Option<LinkCursor>,
+        limit: usize,
+    ) -> Vec<RankedLink> {
+        let ranked = self.top_edges(
+            self.graph.stream_ingoing_edges_filtered(&node, &filter),
+            |edge| edge.from,
+            cursor,
+            limit,
+        );
+
+        let sources: HashSet<_> = ranked.iter().map(|ranked| ranked.cursor.node).collect();
+        let labels = self
+            .graph
+            .raw_ingoing_edges_from(&node.id(), &sources)
+            .into_iter()
+            .map(|edge| (edge.from, edge.label))
+            .collect();
+
+        self.links(ranked, labels)
+    }
+
+    fn outgoing(
+        &self,
+        node: Node,
+        filter: EdgeFilter,
+        cursor: Option<LinkCursor>,
+        limit: usize,
+    ) -> Vec<RankedLink> {
+        let ranked = self.top_edges(
+            self.graph.stream_outgoing_edges_filtered(&node, &filter),
+            |edge| edge.to,
+            cursor,
+            limit,
+        );
+
+        let targets: HashSet<_> = ranked.iter().map(|ranked| ranked.cursor.node).collect();
+        let labels = self
+            .graph
+            .raw_outgoing_edges_to(&node.id(), &targets)
+            .into_iter()
+            .map(|edge| (edge.to, edge.label))
+            .collect();
+
+        self.links(ranked, labels)
+    }
+}
+
+/// An edge ordered by its position in a [`LinksPage`].
+struct RankedEdge {
+    cursor: LinkCursor,
+    edge: Edge<()>,
+}
+
+impl PartialEq for RankedEdge {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedEdge {}
+
+impl PartialOrd for RankedEdge {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedEdge {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cursor.compare(&other.cursor)
+    }
+}
+
 sonic_service!(
     WebGraphService,
-    [SimilarHosts, Knows, IngoingLinks, OutgoingLinks]
+    [
+        SimilarHosts,
+        Knows,
+        IngoingLinks,
+        OutgoingLinks,
+        HostCommunity,
+        HostPath,
+        HostNeighbourhood,
+        IngoingLinksByHost
+    ]
 );
 
+fn host_node(host: &str) -> sonic::Result<Node> {
+    let host = host
+        .strip_prefix("http://")
+        .or_else(|| host.strip_prefix("https://"))
+        .unwrap_or(host);
+
+    let url = Url::parse(&("http://".to_string() + host)).map_err(|_| sonic::Error::BadRequest)?;
+
+    Ok(Node::from(url).into_host())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimilarHosts {
     pub hosts: Vec<String>,
@@ -120,19 +475,8 @@ impl Message<WebGraphService> for SimilarHosts {
 impl Message<WebGraphService> for Knows {
     type Response = Option<Node>;
 
-    async fn handle(mut self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        if let Some(suf) = self.host.strip_prefix("http://") {
-            self.host = suf.to_string();
-        }
-
-        if let Some(suf) = self.host.strip_prefix("https://") {
-            self.host = suf.to_string();
-        }
-
-        let url = Url::parse(&("http://".to_string() + self.host.as_str()))
-            .map_err(|_| sonic::Error::BadRequest)?;
-
-        let node = Node::from(url).into_host();
+    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+        let node = host_node(&self.host)?;
 
         if server
             .similar_hosts_finder
@@ -147,47 +491,210 @@ impl Message<WebGraphService> for Knows {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostCommunity {
+    pub host: String,
+}
+
+impl Message<WebGraphService> for HostCommunity {
+    type Response = Option<Community>;
+
+    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+        let Some(communities) = server.communities.as_ref() else {
+            return Ok(None);
+        };
+
+        let node = host_node(&self.host)?;
+
+        let Some(id) = communities.get(&node.id()) else {
+            return Ok(None);
+        };
+
+        let Some(members) = communities.members(&id) else {
+            return Ok(None);
+        };
+
+        let hosts: Vec<String> = members
+            .hosts
+            .iter()
+            .filter_map(|host| server.graph.id2node(host))
+            .map(|host| host.name)
+            .take(MAX_COMMUNITY_HOSTS)
+            .collect();
+
+        Ok(Some(Community {
+            id: id.to_string(),
+            name: hosts.first().cloned().unwrap_or_default(),
+            size: members.size,
+            hosts,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostPath {
+    pub from: String,
+    pub to: String,
+}
+
+impl Message<WebGraphService> for HostPath {
+    type Response = Vec<Node>;
+
+    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+        let from = host_node(&self.from)?;
+        let to = host_node(&self.to)?;
+        let graph = Arc::clone(&server.graph);
+
+        let path = tokio::task::spawn_blocking(move || {
+            graph.bounded_shortest_path(from, to, MAX_PATH_LENGTH, MAX_PATH_VISITED_HOSTS)
+        })
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(path.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostNeighbourhood {
+    pub host: String,
+    pub max_distance: u8,
+}
+
+impl Message<WebGraphService> for HostNeighbourhood {
+    type Response = Neighbourhood;
+
+    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+        let node = host_node(&self.host)?;
+        let max_distance = self.max_distance.min(MAX_NEIGHBOURHOOD_DISTANCE);
+        let graph = Arc::clone(&server.graph);
+
+        let (nodes, edges) = tokio::task::spawn_blocking(move || {
+            let mut distances: Vec<_> = graph
+                .raw_bounded_distances(node.id(), max_distance, MAX_NEIGHBOURHOOD_HOSTS)
+                .into_iter()
+                .collect();
+
+            distances.sort_by_key(|(id, distance)| (*distance, *id));
+
+            let ids: BTreeSet<_> = distances.iter().map(|(id, _)| *id).collect();
+
+            let edges: Vec<_> = distances
+                .iter()
+                .flat_map(|(id, _)| graph.raw_outgoing_edges_with_labels(id))
+                .filter(|edge| ids.contains(&edge.to))
+                .filter_map(|edge| {
+                    Some(FullEdge {
+                        from: graph.id2node(&edge.from)?,
+                        to: graph.id2node(&edge.to)?,
+                        label: edge.label,
+                    })
+                })
+                .collect();
+
+            let nodes: Vec<_> = distances
+                .into_iter()
+                .filter_map(|(id, distance)| {
+                    graph
+                        .id2node(&id)
+                        .map(|node| NeighbourhoodNode { node, distance })
+                })
+                .collect();
+
+            (nodes, edges)
+        })
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(Neighbourhood { nodes, edges })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IngoingLinks {
     pub node: Node,
+    pub filter: EdgeFilter,
+    pub cursor: Option<LinkCursor>,
+    pub limit: usize,
 }
 
 impl Message<WebGraphService> for IngoingLinks {
-    type Response = Vec<FullEdge>;
+    type Response = Vec<RankedLink>;
 
     async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        match server.granularity {
-            WebgraphGranularity::Host => {
-                let node = self.node.into_host();
-                Ok(server.graph.ingoing_edges(node))
-            }
-            WebgraphGranularity::Page => Ok(server.graph.ingoing_edges(self.node)),
-        }
+        let node = match server.granularity {
+            WebgraphGranularity::Host => self.node.into_host(),
+            WebgraphGranularity::Page => self.node,
+        };
+
+        let ranker = server.link_ranker();
+        let (filter, cursor, limit) = (self.filter, self.cursor, self.limit);
+
+        let links =
+            tokio::task::spawn_blocking(move || ranker.ingoing(node, filter, cursor, limit))
+                .await
+                .map_err(anyhow::Error::from)?;
+
+        Ok(links)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutgoingLinks {
     pub node: Node,
+    pub filter: EdgeFilter,
+    pub cursor: Option<LinkCursor>,
+    pub limit: usize,
 }
 
 impl Message<WebGraphService> for OutgoingLinks {
+    type Response = Vec<RankedLink>;
+
+    async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
+        let node = match server.granularity {
+            WebgraphGranularity::Host => self.node.into_host(),
+            WebgraphGranularity::Page => self.node,
+        };
+
+        let ranker = server.link_ranker();
+        let (filter, cursor, limit) = (self.filter, self.cursor, self.limit);
+
+        let links =
+            tokio::task::spawn_blocking(move || ranker.outgoing(node, filter, cursor, limit))
+                .await
+                .map_err(anyhow::Error::from)?;
+
+        Ok(links)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngoingLinksByHost {
+    pub host: Node,
+    pub limit: usize,
+}
+
+impl Message<WebGraphService> for IngoingLinksByHost {
     type Response = Vec<FullEdge>;
 
     async fn handle(self, server: &WebGraphService) -> sonic::Result<Self::Response> {
-        match server.granularity {
-            WebgraphGranularity::Host => {
-                let node = self.node.into_host();
-                Ok(server.graph.outgoing_edges(node))
-            }
-            WebgraphGranularity::Page => Ok(server.graph.outgoing_edges(self.node)),
-        }
+        Ok(server
+            .graph
+            .ingoing_edges_by_host(self.host, page_size(self.limit)))
     }
 }
 
 pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
     let addr: SocketAddr = config.host;
 
+    if config.shard.id >= config.shard.num_shards {
+        return Err(anyhow::anyhow!(
+            "shard {} is not one of the {} shards of the graph",
+            config.shard.id,
+            config.shard.num_shards
+        ));
+    }
+
     // dropping the handle leaves the cluster
     let cluster = Arc::new(
         Cluster::join(
@@ -196,6 +703,7 @@ pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
                 service: Service::Webgraph {
                     host: addr,
                     granularity: config.granularity,
+                    shard: config.shard,
                 },
             },
             config.gossip_addr,
@@ -220,10 +728,17 @@ pub async fn run(config: config::WebgraphServerConfig) -> Result<()> {
         )
     });
 
+    let communities = config.community_path.map(Communities::open);
+    let host_centrality = config
+        .host_centrality_path
+        .map(|path| Arc::new(RocksDbStore::open_read_only(path)));
+
     let server = WebGraphService {
         graph,
         searcher,
         similar_hosts_finder,
+        communities,
+        host_centrality,
         granularity: config.granularity,
     }
     .bind(addr)
@@ -23,6 +23,7 @@ use std::time::SystemTime;
 use tantivy::tokenizer::TokenizerManager;
 
 use crate::collector::MainCollector;
+use crate::dense_index::DenseIndex;
 use crate::inverted_index::{self, InvertedIndex};
 use crate::query::Query;
 use crate::search_ctx::Ctx;
@@ -32,10 +33,12 @@ use crate::webpage::Webpage;
 use crate::Result;
 
 const INVERTED_INDEX_SUBFOLDER_NAME: &str = "inverted_index";
+const DENSE_INDEX_SUBFOLDER_NAME: &str = "dense_index";
 const REGION_COUNT_FILE_NAME: &str = "region_count.json";
 
 pub struct Index {
     pub inverted_index: InvertedIndex,
+    pub dense_index: DenseIndex,
     pub region_count: Mutex<RegionCount>,
     pub path: String,
 }
@@ -49,10 +52,13 @@ impl Index {
         let inverted_index =
             InvertedIndex::open(path.as_ref().join(INVERTED_INDEX_SUBFOLDER_NAME))?;
 
+        let dense_index = DenseIndex::open(path.as_ref().join(DENSE_INDEX_SUBFOLDER_NAME))?;
+
         let region_count = RegionCount::open(path.as_ref().join(REGION_COUNT_FILE_NAME));
 
         Ok(Self {
             inverted_index,
+            dense_index,
             region_count: Mutex::new(region_count),
             path: path.as_ref().to_str().unwrap().to_string(),
         })
@@ -76,22 +82,30 @@ impl Index {
         Ok(s)
     }
 
-    pub fn insert(&self, webpage: Webpage) -> Result<()> {
+    pub fn insert(&self, mut webpage: Webpage) -> Result<()> {
         if let Ok(region) = Region::guess_from(&webpage) {
             let mut reg = self.region_count.lock().unwrap_or_else(|e| e.into_inner());
             reg.increment(&region);
         }
 
+        if let Some(embedding) = webpage.dense_embedding.take() {
+            self.dense_index
+                .insert(webpage.html.canonical().to_string(), embedding);
+        }
+
         self.inverted_index.insert(webpage)
     }
 
     pub fn delete_all_before(&self, timestamp: SystemTime) -> Result<()> {
+        self.dense_index.delete_all_before(timestamp);
+
         self.inverted_index
             .delete_all_before(tantivy::DateTime::from_utc(timestamp.into()))
     }
 
     pub fn commit(&mut self) -> Result<()> {
         self.inverted_index.commit()?;
+        self.dense_index.commit()?;
 
         let mut reg = self.region_count.lock().unwrap_or_else(|e| e.into_inner());
         reg.commit();
@@ -130,6 +144,14 @@ impl Index {
     pub fn merge(self, other: Self) -> Self {
         self.inverted_index.merge(other.inverted_index);
 
+        // the dense index only adds recall on top of the inverted index, so a failed merge
+        // should not take the whole index down. The last committed dense index is kept.
+        let mut dense_index = self.dense_index;
+        if let Err(err) = dense_index.merge(other.dense_index) {
+            tracing::error!("failed to merge dense index: {err}");
+        }
+        drop(dense_index);
+
         let mut self_region_count = self
             .region_count
             .into_inner()
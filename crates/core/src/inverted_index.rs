@@ -33,12 +33,14 @@ use tantivy::directory::MmapDirectory;
 use tantivy::merge_policy::NoMergePolicy;
 use tantivy::schema::{Schema, Value};
 use tantivy::tokenizer::TokenizerManager;
-use tantivy::{IndexReader, IndexWriter, SegmentMeta, TantivyDocument};
+use tantivy::{DocSet, IndexReader, IndexWriter, SegmentMeta, TantivyDocument};
 use url::Url;
 
 use crate::collector::{Hashes, MainCollector};
+use crate::combine_u64s;
 use crate::config::SnippetConfig;
 use crate::fastfield_reader::FastFieldReader;
+use crate::prehashed::Prehashed;
 use crate::query::shortcircuit::ShortCircuitQuery;
 use crate::query::Query;
 use crate::rake::RakeModel;
@@ -432,6 +434,90 @@ impl InvertedIndex {
         }
     }
 
+    /// Find the pointer to the document with the given url.
+    pub fn website_pointer_by_url(&self, ctx: &Ctx, url: &str) -> Result<Option<WebsitePointer>> {
+        let field = ctx
+            .tv_searcher
+            .schema()
+            .get_field(Field::Text(TextField::UrlNoTokenizer).name())
+            .unwrap();
+
+        let term = tantivy::Term::from_field_text(field, url);
+        let query = tantivy::query::TermQuery::new(term, tantivy::schema::IndexRecordOption::Basic);
+
+        let address = match ctx
+            .tv_searcher
+            .search(&query, &tantivy::collector::TopDocs::with_limit(1))?
+            .pop()
+        {
+            Some((_, address)) => address,
+            None => return Ok(None),
+        };
+
+        let segment_id = ctx
+            .tv_searcher
+            .segment_reader(address.segment_ord)
+            .segment_id();
+        let segment_reader = ctx.fastfield_reader.get_segment(&segment_id);
+        let field_reader = segment_reader.get_field_reader(&address.doc_id);
+
+        let hash = |field1: FastField, field2: FastField| -> Prehashed {
+            combine_u64s([field_reader.get(&field1), field_reader.get(&field2)]).into()
+        };
+
+        Ok(Some(WebsitePointer {
+            score: Score { total: 0.0 },
+            hashes: Hashes {
+                site: hash(FastField::SiteHash1, FastField::SiteHash2),
+                title: hash(FastField::TitleHash1, FastField::TitleHash2),
+                url: hash(FastField::UrlHash1, FastField::UrlHash2),
+                url_without_tld: hash(FastField::UrlWithoutTldHash1, FastField::UrlWithoutTldHash2),
+                simhash: field_reader.get(&FastField::SimHash),
+            },
+            address: address.into(),
+        }))
+    }
+
+    /// Keep the pointers to the documents that match the query.
+    pub fn retain_matching(
+        &self,
+        ctx: &Ctx,
+        query: &dyn tantivy::query::Query,
+        mut pointers: Vec<WebsitePointer>,
+    ) -> Result<Vec<WebsitePointer>> {
+        let weight = query.weight(tantivy::query::EnableScoring::Enabled {
+            searcher: &ctx.tv_searcher,
+            statistics_provider: &ctx.tv_searcher,
+        })?;
+
+        // the scorers can only move forward, so the documents of each segment are visited in order
+        let mut order: Vec<_> = (0..pointers.len()).collect();
+        order.sort_by_key(|i| (pointers[*i].address.segment, pointers[*i].address.doc_id));
+
+        let mut matches = vec![false; pointers.len()];
+        let mut scorer: Option<(u32, Box<dyn tantivy::query::Scorer>)> = None;
+
+        for i in order {
+            let address = pointers[i].address;
+
+            if !matches!(&scorer, Some((segment, _)) if *segment == address.segment) {
+                let segment_reader = ctx.tv_searcher.segment_reader(address.segment);
+                scorer = Some((address.segment, weight.scorer(segment_reader, 1.0)?));
+            }
+
+            let (_, scorer) = scorer.as_mut().unwrap();
+            let doc = scorer.doc();
+
+            matches[i] = doc == address.doc_id
+                || (doc < address.doc_id && scorer.seek(address.doc_id) == address.doc_id);
+        }
+
+        let mut matches = matches.into_iter();
+        pointers.retain(|_| matches.next().unwrap());
+
+        Ok(pointers)
+    }
+
     pub fn retrieve_websites(
         &self,
         websites: &[WebsitePointer],
@@ -14,10 +14,49 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Mutex, MutexGuard},
+};
+
 use serde::{de::DeserializeOwned, Serialize};
 
 pub mod rocksdb_store;
 
+/// Number of locks the keys are spread over by default.
+const DEFAULT_NUM_KEY_LOCKS: usize = 64;
+
+/// Serializes read-modify-write updates of the same key in a store. The keys are
+/// spread over a fixed number of locks, so updates of different keys rarely wait for each other.
+pub struct KeyLocks {
+    locks: Vec<Mutex<()>>,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self::new(DEFAULT_NUM_KEY_LOCKS)
+    }
+}
+
+impl KeyLocks {
+    pub fn new(num_locks: usize) -> Self {
+        Self {
+            locks: (0..num_locks.max(1)).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    /// Lock the key. The lock is held until the guard is dropped.
+    pub fn lock<K: Hash + ?Sized>(&self, key: &K) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        self.locks[hasher.finish() as usize % self.locks.len()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
 pub trait Kv<K, V>
 where
     K: Serialize + DeserializeOwned,
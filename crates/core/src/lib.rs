@@ -38,6 +38,7 @@ mod bloom;
 mod collector;
 pub mod config;
 pub mod crawler;
+mod dense_index;
 mod distributed;
 pub mod entity_index;
 mod enum_map;
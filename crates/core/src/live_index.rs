@@ -27,13 +27,16 @@ use url::Url;
 use crate::{
     config::{CrawlerConfig, LiveIndexConfig},
     crawler::{
-        reqwest_client, CrawlDatum, DatumStream, JobExecutor, RetrieableUrl, WeightedUrl, WorkerJob,
+        fetcher::Fetcher, reqwest_client, CrawlDatum, DatumStream, JobExecutor, RetrieableUrl,
+        WeightedUrl, WorkerJob,
     },
     entrypoint::indexer::IndexingWorker,
     feed::{
         self,
         scheduler::{Domain, DomainFeeds, Split},
     },
+    warc::PayloadType,
+    webpage::document,
 };
 
 const TTL: Duration = Duration::from_secs(60 * 60 * 24 * 60); // 60 days
@@ -159,7 +162,7 @@ impl Crawler {
 
         let executor = JobExecutor::new(
             job,
-            self.client.clone(),
+            Fetcher::Http(self.client.clone()),
             self.config.clone(),
             self.indexer.clone(),
         );
@@ -267,9 +270,17 @@ impl Index {
 
 impl DatumStream for Indexer {
     async fn write(&self, crawl_datum: CrawlDatum) -> Result<()> {
+        // revisits of unchanged pages are already in the index
+        if crawl_datum.status_code != 200 {
+            return Ok(());
+        }
+
+        let html = document::to_html_blocking(crawl_datum.body, crawl_datum.payload_type).await?;
+
         let webpage = self.worker.prepare_webpage(
-            &crawl_datum.body,
+            html.as_bytes(),
             crawl_datum.url.as_str(),
+            &PayloadType::Html,
             crawl_datum.fetch_time_ms,
         )?;
 
@@ -306,14 +317,18 @@ impl From<&LiveIndexConfig> for CrawlerConfig {
             dry_run: false,
             timeout_seconds: live.timeout_seconds,
             // no impact
-            s3: crate::config::S3Config {
+            output: crate::config::WarcOutputConfig::S3(crate::config::S3Config {
                 bucket: String::new(),
                 folder: String::new(),
                 access_key: String::new(),
                 secret_key: String::new(),
                 endpoint: String::new(),
-            },
+            }),
             router_hosts: Vec::new(),
+            crawl_state_path: None,
+            prometheus_host: None,
+            host_relevance_path: None,
+            replay: None,
         }
     }
 }
@@ -334,6 +349,7 @@ impl IndexManager {
                 config.page_webgraph_path.clone(),
                 None,
                 config.safety_classifier_path.clone(),
+                None,
             ),
         });
 
@@ -27,7 +27,8 @@ use stract::entrypoint::configure;
 use stract::entrypoint::{
     self, api, entity_search_server, safety_classifier, search_server, webgraph_server,
 };
-use stract::webgraph::WebgraphBuilder;
+use stract::webgraph::export::{Exporter, Format};
+use stract::webgraph::{import, WebgraphBuilder};
 use tracing_subscriber::prelude::*;
 
 #[cfg(not(target_env = "msvc"))]
@@ -152,6 +153,12 @@ enum Crawler {
 
     /// Create a crawl plan.
     Plan { config_path: String },
+
+    /// Train the classifier used to score pages against the topic of a focused crawl.
+    TrainTopic {
+        dataset_path: String,
+        output_path: String,
+    },
 }
 
 /// Commands to train or run inference on the classifier that predicts if a webpage is NSFW or SFW.
@@ -173,12 +180,55 @@ enum CentralityMode {
     Host {
         webgraph_path: String,
         output_path: String,
+
+        /// File with one host per line. When set (or when `seed_optic` is set), a
+        /// personalised pagerank is calculated that teleports to these hosts.
+        #[clap(long)]
+        seed_hosts: Option<String>,
+
+        /// Optic whose liked hosts are used as seeds for the personalised pagerank.
+        #[clap(long)]
+        seed_optic: Option<String>,
+    },
+    /// Calculate spam scores for the host webgraph. The scores are stored next to the
+    /// other host metrics, so `output_path` should be the same as for `host`.
+    Spam {
+        webgraph_path: String,
+        output_path: String,
+
+        /// File with one trusted host per line (TrustRank seeds).
+        #[clap(long)]
+        trusted_hosts: Option<String>,
+
+        /// Optic whose liked hosts are used as trusted hosts.
+        #[clap(long)]
+        trusted_optic: Option<String>,
+
+        /// File with one known spam host per line (anti-TrustRank seeds).
+        #[clap(long)]
+        spam_hosts: Option<String>,
+    },
+    /// Detect communities of hosts in the host webgraph. The communities are stored next to the
+    /// other host metrics, so `output_path` should be the same as for `host`.
+    Community {
+        webgraph_path: String,
+        output_path: String,
     },
     /// Calculate metrics for the page webgraph.
     Page {
         webgraph_path: String,
         output_path: String,
     },
+    /// Aggregate the anchor texts of the links to each page in the page webgraph. The texts are
+    /// stored next to the other page metrics, so `output_path` should be the same as for `page`.
+    AnchorText {
+        webgraph_path: String,
+        output_path: String,
+
+        /// Spam scores of the hosts (e.g. `<host_centrality>/spam`). Links from spam hosts are skipped.
+        #[clap(long)]
+        host_spam_path: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -192,6 +242,39 @@ enum WebgraphOptions {
         paths: Vec<String>,
     },
 
+    /// Rewrite the segments of a webgraph into a single segment, removing the edges that have been deleted or replaced.
+    Compact { path: String },
+
+    /// Export the webgraph to a format that can be read by other graph tools.
+    Export {
+        path: String,
+
+        /// Base path of the exported files. The file extensions depend on the format.
+        output_path: String,
+
+        /// One of `tsv`, `graphml` or `bvgraph`.
+        #[clap(long, default_value = "tsv")]
+        format: Format,
+
+        /// Include the edge labels. Not supported by `bvgraph`.
+        #[clap(long)]
+        labels: bool,
+
+        /// Centrality store (e.g. `<host_centrality>/harmonic`) to include as a node column. Can be repeated.
+        #[clap(long)]
+        centrality: Vec<String>,
+    },
+
+    /// Build a webgraph from a tab separated edge list with one `from<TAB>to[<TAB>label]` edge per line.
+    Import {
+        edges_path: String,
+        output_path: String,
+
+        /// Convert the nodes to their hosts to build a host graph.
+        #[clap(long)]
+        hosts: bool,
+    },
+
     /// Deploy the webgraph server. The webgraph server is responsible for serving the webgraph to the search servers.
     /// This is e.g. used to find similar sites etc.
     Server { config_path: String },
@@ -266,14 +349,65 @@ fn main() -> Result<()> {
                 CentralityMode::Host {
                     webgraph_path,
                     output_path,
+                    seed_hosts,
+                    seed_optic,
                 } => {
+                    let seed_hosts = entrypoint::Centrality::seed_hosts(seed_hosts, seed_optic)?;
+
                     entrypoint::Centrality::build_harmonic(&webgraph_path, &output_path);
                     entrypoint::Centrality::build_similarity(&webgraph_path, &output_path);
+                    entrypoint::Centrality::build_pagerank(&webgraph_path, &output_path)?;
+
+                    if !seed_hosts.is_empty() {
+                        entrypoint::Centrality::build_personalized_pagerank(
+                            &webgraph_path,
+                            &output_path,
+                            &seed_hosts,
+                        );
+                    }
+                }
+                CentralityMode::Spam {
+                    webgraph_path,
+                    output_path,
+                    trusted_hosts,
+                    trusted_optic,
+                    spam_hosts,
+                } => {
+                    let trusted_hosts =
+                        entrypoint::Centrality::seed_hosts(trusted_hosts, trusted_optic)?;
+                    let spam_hosts = entrypoint::Centrality::hosts_from_file(spam_hosts)?;
+
+                    entrypoint::Centrality::build_spam(
+                        &webgraph_path,
+                        &output_path,
+                        &trusted_hosts,
+                        &spam_hosts,
+                    )?;
+                }
+                CentralityMode::Community {
+                    webgraph_path,
+                    output_path,
+                } => {
+                    entrypoint::Centrality::build_communities(&webgraph_path, &output_path)?;
                 }
                 CentralityMode::Page {
                     webgraph_path,
                     output_path,
-                } => entrypoint::Centrality::build_approx_harmonic(webgraph_path, output_path)?,
+                } => {
+                    entrypoint::Centrality::build_approx_harmonic(&webgraph_path, &output_path)?;
+                    entrypoint::Centrality::build_pagerank(&webgraph_path, &output_path)?;
+                }
+                CentralityMode::AnchorText {
+                    webgraph_path,
+                    output_path,
+                    host_spam_path,
+                } => {
+                    entrypoint::Centrality::build_anchor_text(
+                        webgraph_path,
+                        output_path,
+                        host_spam_path,
+                    )?;
+                }
             }
             tracing::info!("Done");
         }
@@ -293,6 +427,33 @@ fn main() -> Result<()> {
                     std::fs::remove_dir_all(other_path).unwrap();
                 }
             }
+            WebgraphOptions::Compact { path } => {
+                let mut webgraph = WebgraphBuilder::new(path).single_threaded().open();
+                webgraph.compact();
+            }
+            WebgraphOptions::Export {
+                path,
+                output_path,
+                format,
+                labels,
+                centrality,
+            } => {
+                let webgraph = WebgraphBuilder::new(path).single_threaded().open();
+                let mut exporter = Exporter::new(&webgraph).labels(labels);
+
+                for store_path in centrality {
+                    exporter = exporter.centrality(store_path)?;
+                }
+
+                exporter.export(format, output_path)?;
+            }
+            WebgraphOptions::Import {
+                edges_path,
+                output_path,
+                hosts,
+            } => {
+                import::from_edge_list(edges_path, output_path, hosts)?;
+            }
             WebgraphOptions::Server { config_path } => {
                 let config: config::WebgraphServerConfig = load_toml_config(config_path);
 
@@ -372,6 +533,10 @@ fn main() -> Result<()> {
 
                 entrypoint::crawler::planner(config)?;
             }
+            Crawler::TrainTopic {
+                dataset_path,
+                output_path,
+            } => entrypoint::crawler::train_topic_classifier(dataset_path, output_path)?,
         },
         Commands::SafetyClassifier { options } => match options {
             SafetyClassifierOptions::Train {
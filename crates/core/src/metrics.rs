@@ -15,11 +15,11 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::Display,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex, MutexGuard,
     },
     time::{SystemTime, UNIX_EPOCH},
 };
@@ -40,25 +40,96 @@ impl Counter {
         self.0.fetch_add(1, Ordering::SeqCst);
     }
 
+    pub fn add(&self, val: u64) {
+        self.0.fetch_add(val, Ordering::SeqCst);
+    }
+
     pub fn store(&self, val: u64) {
         self.0.store(val, Ordering::SeqCst);
     }
 }
 
+/// A value that can go both up and down.
+#[derive(Default, Clone)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, val: u64) {
+        self.0.store(val, Ordering::SeqCst);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Gauges distinguished by a label whose values are only known at runtime, like the hosts
+/// that are being crawled. Values that are removed are no longer exported.
+#[derive(Clone)]
+pub struct GaugeFamily {
+    key: String,
+    gauges: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl GaugeFamily {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            gauges: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn set(&self, label: &str, val: u64) {
+        self.lock().insert(label.to_string(), val);
+    }
+
+    pub fn get(&self, label: &str) -> Option<u64> {
+        self.lock().get(label).copied()
+    }
+
+    pub fn remove(&self, label: &str) {
+        self.lock().remove(label);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, u64>> {
+        self.gauges.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
 pub enum PrometheusMetric {
     Counter(Counter),
+    Gauge(Gauge),
+    GaugeFamily(GaugeFamily),
 }
 
 impl PrometheusMetric {
     fn prom_type(&self) -> &'static str {
         match self {
             PrometheusMetric::Counter(_) => "counter",
+            PrometheusMetric::Gauge(_) | PrometheusMetric::GaugeFamily(_) => "gauge",
         }
     }
 
-    fn prom_val(&self) -> String {
+    /// The label value (if the metric is a family) and value of each sample.
+    fn prom_vals(&self) -> Vec<(Option<Label>, String)> {
         match self {
-            PrometheusMetric::Counter(counter) => format!("{}", counter.0.load(Ordering::SeqCst)),
+            PrometheusMetric::Counter(counter) => {
+                vec![(None, format!("{}", counter.0.load(Ordering::SeqCst)))]
+            }
+            PrometheusMetric::Gauge(gauge) => vec![(None, format!("{}", gauge.get()))],
+            PrometheusMetric::GaugeFamily(family) => family
+                .lock()
+                .iter()
+                .map(|(label, val)| {
+                    (
+                        Some(Label {
+                            key: family.key.clone(),
+                            val: label.clone(),
+                        }),
+                        format!("{val}"),
+                    )
+                })
+                .collect(),
         }
     }
 }
@@ -69,6 +140,18 @@ impl From<Counter> for PrometheusMetric {
     }
 }
 
+impl From<Gauge> for PrometheusMetric {
+    fn from(gauge: Gauge) -> Self {
+        Self::Gauge(gauge)
+    }
+}
+
+impl From<GaugeFamily> for PrometheusMetric {
+    fn from(family: GaugeFamily) -> Self {
+        Self::GaugeFamily(family)
+    }
+}
+
 type Name = String;
 
 #[derive(Default)]
@@ -124,27 +207,38 @@ struct LabelledMetric {
     metric: PrometheusMetric,
     labels: Vec<Label>,
 }
-impl Display for LabelledMetric {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if !self.labels.is_empty() {
-            f.write_str("{")?;
-
-            let num_labels = self.labels.len();
-            for (i, label) in self.labels.iter().enumerate() {
-                f.write_str(&format!("{}=\"{}\"", label.key, label.val))?;
 
-                if i < num_labels - 1 {
-                    f.write_str(",")?;
+impl LabelledMetric {
+    /// Format the labels and value of each sample of the metric.
+    fn samples(&self) -> Vec<String> {
+        self.metric
+            .prom_vals()
+            .into_iter()
+            .map(|(family_label, val)| {
+                let labels: Vec<_> = self.labels.iter().chain(family_label.as_ref()).collect();
+                let mut sample = String::new();
+
+                if !labels.is_empty() {
+                    sample.push('{');
+
+                    let num_labels = labels.len();
+                    for (i, label) in labels.iter().enumerate() {
+                        sample.push_str(&format!("{}=\"{}\"", label.key, label.val));
+
+                        if i < num_labels - 1 {
+                            sample.push(',');
+                        }
+                    }
+
+                    sample.push('}');
                 }
-            }
-
-            f.write_str("}")?;
-        }
 
-        f.write_str(" ")?;
-        f.write_str(&self.metric.prom_val())?;
+                sample.push(' ');
+                sample.push_str(&val);
 
-        Ok(())
+                sample
+            })
+            .collect()
     }
 }
 
@@ -188,10 +282,10 @@ impl Display for PrometheusGroup {
             f.write_str(first.metric.prom_type())?;
         }
 
-        for m in &self.metrics {
+        for sample in self.metrics.iter().flat_map(LabelledMetric::samples) {
             f.write_str("\n")?;
             f.write_str(&self.name)?;
-            f.write_str(&format!("{m}"))?;
+            f.write_str(&sample)?;
             f.write_str(" ")?;
             f.write_str(&format!("{timestamp}"))?;
         }
@@ -248,6 +342,71 @@ test_counter{{test_label="123"}} 0 {t}
             r##"# HELP test_counter Test counter help.
 # TYPE test_counter counter
 test_counter{{test_label="123"}} 1 {t}
+"##
+        );
+        assert_eq!(format!("{registry}"), expected);
+    }
+
+    #[test]
+    fn gauge() {
+        let gauge = Gauge::default();
+        let mut registry = PrometheusRegistry::default();
+
+        let group = registry.new_group("test_gauge".to_string(), None).unwrap();
+        group.register(gauge.clone(), vec![]);
+
+        let t = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+
+        for group in registry.groups.values_mut() {
+            group.forced_timestamp = Some(t);
+        }
+
+        gauge.set(42);
+        gauge.set(7);
+
+        let expected = format!(
+            r##"# TYPE test_gauge gauge
+test_gauge 7 {t}
+"##
+        );
+        assert_eq!(format!("{registry}"), expected);
+    }
+
+    #[test]
+    fn gauge_family() {
+        let family = GaugeFamily::new("host");
+        let mut registry = PrometheusRegistry::default();
+
+        let group = registry
+            .new_group("test_gauge_family".to_string(), None)
+            .unwrap();
+        group.register(
+            family.clone(),
+            vec![Label {
+                key: "test_label".to_string(),
+                val: "123".to_string(),
+            }],
+        );
+
+        let t = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+
+        for group in registry.groups.values_mut() {
+            group.forced_timestamp = Some(t);
+        }
+
+        family.set("a.com", 1);
+        family.set("b.com", 2);
+        family.remove("a.com");
+
+        let expected = format!(
+            r##"# TYPE test_gauge_family gauge
+test_gauge_family{{test_label="123",host="b.com"}} 2 {t}
 "##
         );
         assert_eq!(format!("{registry}"), expected);
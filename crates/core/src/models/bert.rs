@@ -1,11 +1,13 @@
 //! this file has been copied from the candle-transformers project and modified
-//! to include an optional pooler layer and use an attention mask for padding tokens.
+//! to include an optional pooler layer, use an attention mask for padding tokens
+//! and optionally quantise the weights of the linear layers to int8.
 //!
 //! The original file can be found at:
 //!
 //! https://github.com/huggingface/candle/blob/main/candle-examples/examples/bert/main.rs
 
-use candle_core::{Device, Result, Tensor};
+use candle_core::quantized::{k_quants::BlockQ8_0, QMatMul, QTensor};
+use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::{embedding, Embedding, Module, VarBuilder};
 use candle_transformers::models::with_tracing::{layer_norm, linear, LayerNorm, Linear};
 use serde::Deserialize;
@@ -68,6 +70,10 @@ pub struct Config {
     pub use_cache: bool,
     pub classifier_dropout: Option<f64>,
     pub model_type: Option<String>,
+    /// quantise the weights of the linear layers to int8.
+    /// The model must then be loaded and run in f32.
+    #[serde(default)]
+    pub quantize: bool,
 }
 
 impl Default for Config {
@@ -89,6 +95,7 @@ impl Default for Config {
             use_cache: true,
             classifier_dropout: None,
             model_type: Some("bert".to_string()),
+            quantize: false,
         }
     }
 }
@@ -113,6 +120,48 @@ impl Config {
             use_cache: true,
             classifier_dropout: None,
             model_type: Some("bert".to_string()),
+            quantize: false,
+        }
+    }
+}
+
+/// A linear layer where the weights are either kept in the dtype of the
+/// var builder or quantised to int8 (ggml q8_0).
+enum BertLinear {
+    Full(Linear),
+    Int8 {
+        weight: QMatMul,
+        bias: Tensor,
+        span: tracing::Span,
+    },
+}
+
+impl BertLinear {
+    fn load(in_dim: usize, out_dim: usize, vb: VarBuilder, config: &Config) -> Result<Self> {
+        if !config.quantize {
+            return Ok(Self::Full(linear(in_dim, out_dim, vb)?));
+        }
+
+        let weight = vb.get((out_dim, in_dim), "weight")?.to_dtype(DType::F32)?;
+        let bias = vb.get(out_dim, "bias")?.to_dtype(DType::F32)?;
+        let weight = QMatMul::from_qtensor(QTensor::quantize::<BlockQ8_0>(&weight)?)?;
+
+        Ok(Self::Int8 {
+            weight,
+            bias,
+            span: tracing::span!(tracing::Level::TRACE, "linear-int8"),
+        })
+    }
+}
+
+impl Module for BertLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::Full(linear) => linear.forward(xs),
+            Self::Int8 { weight, bias, span } => {
+                let _enter = span.enter();
+                weight.forward(xs)?.broadcast_add(bias)
+            }
         }
     }
 }
@@ -196,9 +245,9 @@ impl BertEmbeddings {
 }
 
 struct BertSelfAttention {
-    query: Linear,
-    key: Linear,
-    value: Linear,
+    query: BertLinear,
+    key: BertLinear,
+    value: BertLinear,
     dropout: Dropout,
     num_attention_heads: usize,
     attention_head_size: usize,
@@ -212,9 +261,9 @@ impl BertSelfAttention {
         let all_head_size = config.num_attention_heads * attention_head_size;
         let dropout = Dropout::new(config.hidden_dropout_prob);
         let hidden_size = config.hidden_size;
-        let query = linear(hidden_size, all_head_size, vb.pp("query"))?;
-        let value = linear(hidden_size, all_head_size, vb.pp("value"))?;
-        let key = linear(hidden_size, all_head_size, vb.pp("key"))?;
+        let query = BertLinear::load(hidden_size, all_head_size, vb.pp("query"), config)?;
+        let value = BertLinear::load(hidden_size, all_head_size, vb.pp("value"), config)?;
+        let key = BertLinear::load(hidden_size, all_head_size, vb.pp("key"), config)?;
         Ok(Self {
             query,
             key,
@@ -266,7 +315,7 @@ impl BertSelfAttention {
 }
 
 struct BertSelfOutput {
-    dense: Linear,
+    dense: BertLinear,
     layer_norm: LayerNorm,
     dropout: Dropout,
     span: tracing::Span,
@@ -274,7 +323,12 @@ struct BertSelfOutput {
 
 impl BertSelfOutput {
     fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let dense = linear(config.hidden_size, config.hidden_size, vb.pp("dense"))?;
+        let dense = BertLinear::load(
+            config.hidden_size,
+            config.hidden_size,
+            vb.pp("dense"),
+            config,
+        )?;
         let layer_norm = layer_norm(
             config.hidden_size,
             config.layer_norm_eps,
@@ -327,14 +381,19 @@ impl BertAttention {
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L441
 struct BertIntermediate {
-    dense: Linear,
+    dense: BertLinear,
     intermediate_act: HiddenActLayer,
     span: tracing::Span,
 }
 
 impl BertIntermediate {
     fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let dense = linear(config.hidden_size, config.intermediate_size, vb.pp("dense"))?;
+        let dense = BertLinear::load(
+            config.hidden_size,
+            config.intermediate_size,
+            vb.pp("dense"),
+            config,
+        )?;
         Ok(Self {
             dense,
             intermediate_act: HiddenActLayer::new(config.hidden_act),
@@ -354,7 +413,7 @@ impl Module for BertIntermediate {
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L456
 struct BertOutput {
-    dense: Linear,
+    dense: BertLinear,
     layer_norm: LayerNorm,
     dropout: Dropout,
     span: tracing::Span,
@@ -362,7 +421,12 @@ struct BertOutput {
 
 impl BertOutput {
     fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let dense = linear(config.intermediate_size, config.hidden_size, vb.pp("dense"))?;
+        let dense = BertLinear::load(
+            config.intermediate_size,
+            config.hidden_size,
+            vb.pp("dense"),
+            config,
+        )?;
         let layer_norm = layer_norm(
             config.hidden_size,
             config.layer_norm_eps,
@@ -452,12 +516,17 @@ impl BertEncoder {
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L654
 pub struct BertPooler {
-    dense: Linear,
+    dense: BertLinear,
 }
 
 impl BertPooler {
     fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let dense = linear(config.hidden_size, config.hidden_size, vb.pp("dense"))?;
+        let dense = BertLinear::load(
+            config.hidden_size,
+            config.hidden_size,
+            vb.pp("dense"),
+            config,
+        )?;
         Ok(Self { dense })
     }
 }
@@ -26,13 +26,13 @@ use crate::{
 };
 use optics::{HostRankings, Optic};
 use std::collections::HashMap;
-use tantivy::query::{BooleanQuery, Occur, QueryClone, TermQuery};
+use tantivy::query::{AllQuery, BooleanQuery, Occur, QueryClone, TermQuery};
 
 mod const_query;
 pub mod intersection;
 pub mod optic;
 pub mod parser;
-mod pattern_query;
+pub mod pattern_query;
 pub mod shortcircuit;
 pub mod union;
 
@@ -48,6 +48,7 @@ pub struct Query {
     terms: Vec<Box<Term>>,
     simple_terms_text: Vec<String>,
     tantivy_query: Box<BooleanQuery>,
+    filter_query: Box<BooleanQuery>,
     host_rankings: HostRankings,
     offset: usize,
     region: Option<Region>,
@@ -111,20 +112,28 @@ impl Query {
             .map(|term| term.as_tantivy_query(&fields))
             .collect();
 
+        // the operators of the query (e.g. `site:` and `-term`) also restrict the documents
+        // that are found without matching the terms, so they are kept as filters.
+        let mut filters: Vec<(Occur, Box<dyn tantivy::query::Query + 'static>)> = compound_terms
+            .iter()
+            .filter(|term| !matches!(term.term, Term::Simple(_) | Term::PossibleBang(_)))
+            .map(|term| term.as_tantivy_query(&fields))
+            .collect();
+
         if query.safe_search {
             let field = Field::Text(TextField::SafetyClassification);
             let field = schema.get_field(field.name()).unwrap();
 
-            queries.push((
-                Occur::MustNot,
-                Box::new(TermQuery::new(
-                    tantivy::Term::from_field_text(
-                        field,
-                        safety_classifier::Label::NSFW.to_string().as_str(),
-                    ),
-                    tantivy::schema::IndexRecordOption::Basic,
-                )),
-            ));
+            let nsfw = TermQuery::new(
+                tantivy::Term::from_field_text(
+                    field,
+                    safety_classifier::Label::NSFW.to_string().as_str(),
+                ),
+                tantivy::schema::IndexRecordOption::Basic,
+            );
+
+            queries.push((Occur::MustNot, nsfw.box_clone()));
+            filters.push((Occur::MustNot, Box::new(nsfw)));
         }
 
         let mut tantivy_query = Box::new(BooleanQuery::new(queries));
@@ -153,9 +162,21 @@ impl Query {
         for optic in &optics {
             let mut subqueries = vec![(Occur::Must, tantivy_query.box_clone())];
             subqueries.append(&mut optic.as_multiple_tantivy(&schema, &ctx.fastfield_reader));
+
+            filters.extend(
+                subqueries
+                    .iter()
+                    .skip(1)
+                    .filter(|(occur, _)| *occur != Occur::Should)
+                    .map(|(occur, query)| (*occur, query.box_clone())),
+            );
+
             tantivy_query = Box::new(BooleanQuery::new(subqueries));
         }
 
+        filters.push((Occur::Must, Box::new(AllQuery)));
+        let filter_query = Box::new(BooleanQuery::new(filters));
+
         Ok(Query {
             terms,
             host_rankings: optics.iter().fold(HostRankings::default(), |mut acc, el| {
@@ -164,6 +185,7 @@ impl Query {
             }),
             simple_terms_text,
             tantivy_query,
+            filter_query,
             optics,
             offset: query.num_results * query.page,
             region: query.selected_region,
@@ -184,6 +206,12 @@ impl Query {
         &self.terms
     }
 
+    /// Matches the documents that are not excluded by the operators, safe search or optics of
+    /// the query, regardless of whether they contain its terms.
+    pub fn filter_query(&self) -> &BooleanQuery {
+        &self.filter_query
+    }
+
     pub fn optics(&self) -> &[Optic] {
         &self.optics
     }
@@ -925,6 +953,91 @@ mod tests {
         assert_eq!(result.webpages[0].url, "https://www.sfw.com/");
     }
 
+    #[test]
+    fn filter_query() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, label) in [
+            ("https://www.a.com", safety_classifier::Label::SFW),
+            ("https://www.b.com", safety_classifier::Label::SFW),
+            ("https://www.nsfw.a.com", safety_classifier::Label::NSFW),
+        ] {
+            let mut webpage = Webpage::new(
+                &format!(
+                    r#"
+                    <html>
+                        <head>
+                            <title>Test website</title>
+                        </head>
+                        <body>
+                            {}
+                        </body>
+                    </html>
+                "#,
+                    rand_words(100)
+                ),
+                url,
+            )
+            .unwrap();
+
+            webpage.safety_classification = Some(label);
+            index.insert(webpage).expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+
+        let ctx = index.inverted_index.local_search_ctx();
+        let pointers: Vec<_> = [
+            "https://www.a.com/",
+            "https://www.b.com/",
+            "https://www.nsfw.a.com/",
+        ]
+        .into_iter()
+        .map(|url| {
+            index
+                .inverted_index
+                .website_pointer_by_url(&ctx, url)
+                .unwrap()
+                .unwrap()
+        })
+        .collect();
+
+        let query = Query::parse(
+            &ctx,
+            &SearchQuery {
+                query: "unrelated words site:a.com".to_string(),
+                safe_search: true,
+                ..Default::default()
+            },
+            &index.inverted_index,
+        )
+        .unwrap();
+
+        let res = index
+            .inverted_index
+            .retain_matching(&ctx, query.filter_query(), pointers.clone())
+            .unwrap();
+
+        assert_eq!(res, vec![pointers[0].clone()]);
+
+        let query = Query::parse(
+            &ctx,
+            &SearchQuery {
+                query: "unrelated words".to_string(),
+                ..Default::default()
+            },
+            &index.inverted_index,
+        )
+        .unwrap();
+
+        let res = index
+            .inverted_index
+            .retain_matching(&ctx, query.filter_query(), pointers.clone())
+            .unwrap();
+
+        assert_eq!(res, pointers);
+    }
+
     #[test]
     fn suffix_domain_prefix_path_site_operator() {
         let mut index = Index::temporary().expect("Unable to open index");
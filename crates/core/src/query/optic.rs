@@ -15,14 +15,17 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use itertools::Itertools;
-use optics::{Action, MatchLocation, Matching, Optic, Rule};
+use optics::{Action, MatchLocation, Matching, Optic, PatternPart, Rule};
 use std::iter;
 use tantivy::{
-    query::{BooleanQuery, Occur, QueryClone},
-    schema::Schema,
+    query::{BooleanQuery, Occur, QueryClone, TermQuery},
+    schema::{IndexRecordOption, Schema},
 };
 
-use crate::{fastfield_reader::FastFieldReader, schema::TextField};
+use crate::{
+    fastfield_reader::FastFieldReader,
+    schema::{Field, TextField},
+};
 
 use super::{const_query::ConstQuery, pattern_query::PatternQuery, union::UnionQuery};
 
@@ -256,6 +259,32 @@ impl AsTantivyQuery for Matching {
                 )),
                 1.0,
             )),
+            MatchLocation::Spam | MatchLocation::Community => {
+                // the label is indexed as a single term, so there is no need for a pattern query
+                let label = self
+                    .pattern
+                    .iter()
+                    .filter_map(|part| match part {
+                        PatternPart::Raw(s) => Some(s.to_lowercase()),
+                        PatternPart::Wildcard | PatternPart::Anchor => None,
+                    })
+                    .join(" ");
+
+                let field = match self.location {
+                    MatchLocation::Community => TextField::HostCommunity,
+                    _ => TextField::HostSpamLabel,
+                };
+
+                let field = schema.get_field(Field::Text(field).name()).unwrap();
+
+                Box::new(ConstQuery::new(
+                    Box::new(TermQuery::new(
+                        tantivy::Term::from_field_text(field, &label),
+                        IndexRecordOption::Basic,
+                    )),
+                    1.0,
+                ))
+            }
         }
     }
 }
@@ -272,7 +301,7 @@ mod tests {
         index::Index,
         ranking::inbound_similarity::InboundSimilarity,
         searcher::{LocalSearcher, SearchQuery},
-        webgraph::{Node, WebgraphWriter},
+        webgraph::{community::CommunityId, Node, WebgraphWriter},
         webpage::{Html, Webpage},
     };
 
@@ -1913,4 +1942,156 @@ mod tests {
         assert_eq!(res.len(), 1);
         assert_eq!(res[0].url, "https://a-third-example.com/");
     }
+
+    #[test]
+    fn discard_spam_hosts() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for (url, host_spam_score) in [
+            ("https://www.a.com", 0.0),
+            ("https://www.b.com", 0.6),
+            ("https://www.c.com", 0.9),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                        <html>
+                            <head>
+                                <title>Example website</title>
+                            </head>
+                            <body>
+                                {CONTENT} {}
+                            </body>
+                        </html>
+                    "#,
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    host_spam_score,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let res = searcher
+            .search(&SearchQuery {
+                query: "website".to_string(),
+                optic: Some(
+                    Optic::parse(
+                        r#"
+                        Rule {
+                            Matches {
+                                Spam("spam")
+                            },
+                            Action(Discard)
+                        }
+                    "#,
+                    )
+                    .unwrap(),
+                ),
+                ..Default::default()
+            })
+            .unwrap()
+            .webpages;
+
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|page| page.url != "https://www.c.com/"));
+
+        let res = searcher
+            .search(&SearchQuery {
+                query: "website".to_string(),
+                optic: Some(
+                    Optic::parse(
+                        r#"
+                        DiscardNonMatching;
+                        Rule {
+                            Matches {
+                                Spam("clean")
+                            }
+                        }
+                    "#,
+                    )
+                    .unwrap(),
+                ),
+                ..Default::default()
+            })
+            .unwrap()
+            .webpages;
+
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].url, "https://www.a.com/");
+    }
+
+    #[test]
+    fn match_community() {
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        let community = CommunityId::from(Node::from("a.com").id());
+
+        for (url, host_community) in [
+            ("https://www.a.com", Some(community)),
+            ("https://www.b.com", Some(community)),
+            ("https://www.c.com", None),
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                        <html>
+                            <head>
+                                <title>Example website</title>
+                            </head>
+                            <body>
+                                {CONTENT} {}
+                            </body>
+                        </html>
+                    "#,
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    fetch_time_ms: 500,
+                    host_community,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::from(index);
+
+        let res = searcher
+            .search(&SearchQuery {
+                query: "website".to_string(),
+                optic: Some(
+                    Optic::parse(&format!(
+                        r#"
+                        DiscardNonMatching;
+                        Rule {{
+                            Matches {{
+                                Community("{community}")
+                            }}
+                        }}
+                    "#
+                    ))
+                    .unwrap(),
+                ),
+                ..Default::default()
+            })
+            .unwrap()
+            .webpages;
+
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|page| page.url != "https://www.c.com/"));
+    }
 }
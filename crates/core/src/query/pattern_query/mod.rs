@@ -19,14 +19,17 @@ mod weight;
 
 use optics::PatternPart;
 
-use tantivy::tokenizer::Tokenizer;
+use tantivy::tokenizer::{Token, Tokenizer};
 
 use crate::{
     fastfield_reader::FastFieldReader,
     schema::{Field, TextField},
 };
 
-use self::weight::{FastSiteDomainPatternWeight, PatternWeight};
+use self::{
+    scorer::count_pattern_matches,
+    weight::{FastSiteDomainPatternWeight, PatternWeight},
+};
 
 #[derive(Clone)]
 pub struct PatternQuery {
@@ -149,6 +152,92 @@ impl tantivy::query::Query for PatternQuery {
     }
 }
 
+/// Whether the patterns match a field with the given tokens, the same way a [`PatternQuery`]
+/// on the field matches an indexed document with the tokens. `untokenized` is the text of
+/// the field without tokenization, which is what patterns like `|example.com|` on the site
+/// and domain fields are matched against.
+pub fn matches_tokens(
+    patterns: &[PatternPart],
+    field: TextField,
+    tokens: &[Token],
+    untokenized: &str,
+) -> bool {
+    if can_optimize_site_domain(patterns, Field::Text(field)) {
+        let term: String = patterns
+            .iter()
+            .filter_map(|p| match p {
+                PatternPart::Raw(s) => Some(s.as_str()),
+                PatternPart::Wildcard | PatternPart::Anchor => None,
+            })
+            .collect();
+
+        return untokenized == term;
+    }
+
+    let mut terms = Vec::with_capacity(patterns.len());
+    let mut small_patterns = Vec::with_capacity(patterns.len());
+
+    for pattern in patterns {
+        match pattern {
+            PatternPart::Raw(text) => {
+                let mut tokenizer = field.indexing_tokenizer();
+                let mut stream = tokenizer.token_stream(text);
+
+                while let Some(token) = stream.next() {
+                    terms.push(token.text.clone());
+                    small_patterns.push(SmallPatternPart::Term);
+                }
+            }
+            PatternPart::Wildcard => small_patterns.push(SmallPatternPart::Wildcard),
+            PatternPart::Anchor => small_patterns.push(SmallPatternPart::Anchor),
+        }
+    }
+
+    if small_patterns.is_empty() {
+        return false;
+    }
+
+    // "*" matches everything
+    if terms.is_empty()
+        && small_patterns
+            .iter()
+            .any(|p| matches!(p, SmallPatternPart::Wildcard))
+    {
+        return true;
+    }
+
+    // "||" and "|" matches empty string
+    if terms.is_empty() {
+        return tokens.is_empty();
+    }
+
+    let positions: Vec<Vec<u32>> = terms
+        .iter()
+        .map(|term| {
+            tokens
+                .iter()
+                .filter(|token| &token.text == term)
+                .map(|token| token.position as u32)
+                .collect()
+        })
+        .collect();
+
+    if positions.iter().any(|positions| positions.is_empty()) {
+        return false;
+    }
+
+    count_pattern_matches(
+        &small_patterns,
+        tokens.len() as u64,
+        &mut Vec::new(),
+        &mut Vec::new(),
+        |term, out| {
+            out.clear();
+            out.extend_from_slice(&positions[term]);
+        },
+    ) > 0
+}
+
 #[derive(Debug)]
 pub enum SmallPatternPart {
     Term,
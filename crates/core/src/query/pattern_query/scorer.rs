@@ -264,17 +264,6 @@ impl NormalPatternScorer {
             return 0;
         }
 
-        {
-            self.intersection_docset
-                .docset_mut_specialized(0)
-                .positions(&mut self.left);
-        }
-
-        let mut intersection_len = self.left.len();
-        let mut out = Vec::new();
-
-        let mut current_right_term = 0;
-        let mut slop = 1;
         let num_tokens_doc: Option<u64> = self
             .segment_reader
             .get_field_reader(&self.doc())
@@ -282,62 +271,90 @@ impl NormalPatternScorer {
             .into();
         let num_tokens_doc = num_tokens_doc.unwrap();
 
-        for (i, pattern_part) in self.pattern.iter().enumerate() {
-            match pattern_part {
-                SmallPatternPart::Term => {
-                    if current_right_term == 0 {
-                        current_right_term = 1;
-                        continue;
-                    }
+        let docset = &mut self.intersection_docset;
 
-                    {
-                        self.intersection_docset
-                            .docset_mut_specialized(current_right_term)
-                            .positions(&mut self.right);
-                    }
-                    out.resize(self.left.len().max(self.right.len()), 0);
-                    intersection_len =
-                        intersection_with_slop(&self.left[..], &self.right[..], &mut out, slop);
+        count_pattern_matches(
+            &self.pattern,
+            num_tokens_doc,
+            &mut self.left,
+            &mut self.right,
+            |term, out| docset.docset_mut_specialized(term).positions(out),
+        )
+    }
+}
 
-                    slop = 1;
+/// Count the matches of the pattern in a field with `num_tokens` tokens.
+/// `positions(i, out)` writes the positions of the `i`th term of the pattern in the field to `out`.
+/// `left` and `right` are scratch buffers.
+pub(super) fn count_pattern_matches<F>(
+    pattern: &[SmallPatternPart],
+    num_tokens: u64,
+    left: &mut Vec<u32>,
+    right: &mut Vec<u32>,
+    mut positions: F,
+) -> usize
+where
+    F: FnMut(usize, &mut Vec<u32>),
+{
+    let num_query_terms = pattern
+        .iter()
+        .filter(|part| matches!(part, SmallPatternPart::Term))
+        .count();
+
+    positions(0, left);
+
+    let mut intersection_len = left.len();
+    let mut out = Vec::new();
+
+    let mut current_right_term = 0;
+    let mut slop = 1;
+
+    for (i, pattern_part) in pattern.iter().enumerate() {
+        match pattern_part {
+            SmallPatternPart::Term => {
+                if current_right_term == 0 {
+                    current_right_term = 1;
+                    continue;
+                }
 
-                    if intersection_len == 0 {
-                        return 0;
-                    }
+                positions(current_right_term, right);
+                out.resize(left.len().max(right.len()), 0);
+                intersection_len = intersection_with_slop(&left[..], &right[..], &mut out, slop);
 
-                    self.left = out[..intersection_len].to_vec();
-                    out = Vec::new();
-                    current_right_term += 1;
-                }
-                SmallPatternPart::Wildcard => {
-                    slop = u32::MAX;
+                slop = 1;
+
+                if intersection_len == 0 {
+                    return 0;
                 }
-                SmallPatternPart::Anchor if i == 0 => {
-                    if let Some(pos) = self.left.first() {
-                        if *pos != 0 {
-                            return 0;
-                        }
+
+                *left = out[..intersection_len].to_vec();
+                out = Vec::new();
+                current_right_term += 1;
+            }
+            SmallPatternPart::Wildcard => {
+                slop = u32::MAX;
+            }
+            SmallPatternPart::Anchor if i == 0 => {
+                if let Some(pos) = left.first() {
+                    if *pos != 0 {
+                        return 0;
                     }
                 }
-                SmallPatternPart::Anchor if i == self.pattern.len() - 1 => {
-                    {
-                        self.intersection_docset
-                            .docset_mut_specialized(self.num_query_terms - 1)
-                            .positions(&mut self.right);
-                    }
+            }
+            SmallPatternPart::Anchor if i == pattern.len() - 1 => {
+                positions(num_query_terms - 1, right);
 
-                    if let Some(pos) = self.right.last() {
-                        if *pos != (num_tokens_doc - 1) as u32 {
-                            return 0;
-                        }
+                if let Some(pos) = right.last() {
+                    if *pos != (num_tokens - 1) as u32 {
+                        return 0;
                     }
                 }
-                SmallPatternPart::Anchor => {}
             }
+            SmallPatternPart::Anchor => {}
         }
-
-        intersection_len
     }
+
+    intersection_len
 }
 
 impl Scorer for NormalPatternScorer {
@@ -0,0 +1,315 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Named signal coefficient profiles that can be selected per search request.
+//!
+//! The profiles are defined in a TOML file where each table is a profile
+//! mapping signal names to their coefficient:
+//!
+//! ```toml
+//! [news]
+//! update_timestamp = 0.05
+//! page_centrality = 0.1
+//! ```
+//!
+//! The file is watched for changes and the profiles are reloaded without
+//! restarting the server. A profile is applied to a query by prepending its
+//! coefficients to the rankings of the query optic, so coefficients set
+//! explicitly in the optic still take precedence.
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, SystemTime},
+};
+
+use optics::{
+    ast::{RankingCoeff, RankingTarget},
+    Optic,
+};
+use thiserror::Error;
+
+use crate::{searcher::SearchQuery, Result};
+
+use super::{all_signals, Signal, SignalCoefficient};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown signal '{signal}' in coefficient profile '{profile}'")]
+    UnknownSignal { profile: String, signal: String },
+
+    #[error("coefficient for '{signal}' in profile '{profile}' is not a finite number")]
+    InvalidCoefficient { profile: String, signal: String },
+
+    #[error("unknown coefficient profile: {0}")]
+    UnknownProfile(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CoefficientProfiles {
+    profiles: HashMap<String, Vec<RankingCoeff>>,
+}
+
+impl CoefficientProfiles {
+    pub fn parse(s: &str) -> Result<Self> {
+        let raw: HashMap<String, HashMap<String, f64>> = toml::from_str(s)?;
+        let mut profiles = HashMap::with_capacity(raw.len());
+
+        for (profile, coefficients) in raw {
+            let mut rankings = Vec::with_capacity(coefficients.len());
+
+            for (signal, value) in coefficients {
+                let is_known = Signal::from_str(&signal)
+                    .map(|s| all_signals().any(|known| known == s))
+                    .unwrap_or(false);
+
+                if !is_known {
+                    return Err(Error::UnknownSignal { profile, signal }.into());
+                }
+
+                if !value.is_finite() {
+                    return Err(Error::InvalidCoefficient { profile, signal }.into());
+                }
+
+                rankings.push(RankingCoeff {
+                    target: RankingTarget::Signal(signal),
+                    value,
+                });
+            }
+
+            profiles.insert(profile, rankings);
+        }
+
+        Ok(Self { profiles })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<SignalCoefficient> {
+        self.profiles.get(name).map(|rankings| {
+            SignalCoefficient::from_optic(&Optic {
+                rankings: rankings.clone(),
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Resolve the coefficient profile selected by the query (if any)
+    /// and merge it into the query optic.
+    pub fn apply(&self, query: &mut SearchQuery) -> Result<()> {
+        let name = match query.signal_coefficient_profile.take() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        let rankings = match self.profiles.get(&name) {
+            Some(rankings) => rankings,
+            None => return Err(Error::UnknownProfile(name).into()),
+        };
+
+        let optic = query.optic.get_or_insert_with(Optic::default);
+        optic.rankings.splice(0..0, rankings.iter().cloned());
+
+        Ok(())
+    }
+}
+
+struct Watched {
+    path: PathBuf,
+    profiles: RwLock<CoefficientProfiles>,
+    last_seen: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl Watched {
+    fn file_version(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    fn reload_if_changed(&self) {
+        let version = self.file_version();
+        let mut last_seen = self.last_seen.lock().unwrap_or_else(|e| e.into_inner());
+
+        if version.is_none() || version == *last_seen {
+            return;
+        }
+
+        match CoefficientProfiles::open(&self.path) {
+            Ok(profiles) => {
+                tracing::info!(
+                    "reloaded signal coefficient profiles from {}",
+                    self.path.display()
+                );
+                *self.profiles.write().unwrap_or_else(|e| e.into_inner()) = profiles;
+            }
+            Err(err) => {
+                tracing::error!(
+                    "failed to reload signal coefficient profiles from {}: {err}. Keeping the previous profiles",
+                    self.path.display()
+                );
+            }
+        }
+
+        *last_seen = version;
+    }
+}
+
+/// Coefficient profiles backed by a file that is polled for changes
+/// in a background thread. The thread stops when the store is dropped.
+#[derive(Clone)]
+pub struct CoefficientProfileStore {
+    inner: Arc<Watched>,
+}
+
+impl CoefficientProfileStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let profiles = CoefficientProfiles::open(&path)?;
+
+        let inner = Arc::new(Watched {
+            path,
+            profiles: RwLock::new(profiles),
+            last_seen: Mutex::new(None),
+        });
+        *inner.last_seen.lock().unwrap_or_else(|e| e.into_inner()) = inner.file_version();
+
+        let watched: Weak<Watched> = Arc::downgrade(&inner);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(RELOAD_INTERVAL);
+
+            match watched.upgrade() {
+                Some(watched) => watched.reload_if_changed(),
+                None => break,
+            }
+        });
+
+        Ok(Self { inner })
+    }
+
+    pub fn get(&self, name: &str) -> Option<SignalCoefficient> {
+        self.inner
+            .profiles
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+    }
+
+    pub fn apply(&self, query: &mut SearchQuery) -> Result<()> {
+        self.inner
+            .profiles
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .apply(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+[news]
+update_timestamp = 0.05
+page_centrality = 0.1
+
+[academic]
+bm25_title = 0.02
+"#;
+
+    #[test]
+    fn parse_profiles() {
+        let profiles = CoefficientProfiles::parse(PROFILES).unwrap();
+
+        let news = profiles.get("news").unwrap();
+        assert_eq!(news.get(&Signal::UpdateTimestamp), Some(0.05));
+        assert_eq!(news.get(&Signal::PageCentrality), Some(0.1));
+        assert_eq!(news.get(&Signal::Bm25Title), None);
+
+        let academic = profiles.get("academic").unwrap();
+        assert_eq!(academic.get(&Signal::Bm25Title), Some(0.02));
+
+        assert!(profiles.get("sports").is_none());
+    }
+
+    #[test]
+    fn unknown_signal_is_rejected() {
+        let res = CoefficientProfiles::parse("[news]\nnot_a_signal = 1.0\n");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn optic_takes_precedence() {
+        let profiles = CoefficientProfiles::parse(PROFILES).unwrap();
+
+        let mut query = SearchQuery {
+            query: "test".to_string(),
+            optic: Some(Optic::parse("Ranking(Signal(\"update_timestamp\"), 2);").unwrap()),
+            signal_coefficient_profile: Some("news".to_string()),
+            ..Default::default()
+        };
+
+        profiles.apply(&mut query).unwrap();
+        assert!(query.signal_coefficient_profile.is_none());
+
+        let coeffs = SignalCoefficient::from_optic(query.optic.as_ref().unwrap());
+        assert_eq!(coeffs.get(&Signal::UpdateTimestamp), Some(2.0));
+        assert_eq!(coeffs.get(&Signal::PageCentrality), Some(0.1));
+
+        let mut query = SearchQuery {
+            query: "test".to_string(),
+            signal_coefficient_profile: Some("sports".to_string()),
+            ..Default::default()
+        };
+        assert!(profiles.apply(&mut query).is_err());
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let path = crate::gen_temp_path().join("profiles.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, PROFILES).unwrap();
+
+        let store = CoefficientProfileStore::open(&path).unwrap();
+        assert_eq!(
+            store.get("academic").unwrap().get(&Signal::Bm25Title),
+            Some(0.02)
+        );
+
+        fs::write(&path, "[academic]\nbm25_title = 0.5\nbm25_keywords = 0.1\n").unwrap();
+        store.inner.reload_if_changed();
+
+        assert_eq!(
+            store.get("academic").unwrap().get(&Signal::Bm25Title),
+            Some(0.5)
+        );
+        assert!(store.get("news").is_none());
+
+        // invalid profiles keep the previous version
+        fs::write(&path, "[academic]\nnot_a_signal = 1.0\n").unwrap();
+        store.inner.reload_if_changed();
+
+        assert_eq!(
+            store.get("academic").unwrap().get(&Signal::Bm25Title),
+            Some(0.5)
+        );
+    }
+}
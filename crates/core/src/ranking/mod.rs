@@ -16,6 +16,7 @@
 
 pub mod bitvec_similarity;
 pub mod bm25;
+pub mod coefficient_profiles;
 pub mod inbound_similarity;
 pub mod initial;
 pub mod models;
@@ -23,6 +24,7 @@ pub mod optics;
 pub mod pipeline;
 pub mod query_centrality;
 pub mod signal;
+pub mod signal_provider;
 
 use initial::InitialScoreTweaker;
 
@@ -767,4 +769,73 @@ mod tests {
         assert_eq!(result.webpages[1].url, "https://www.second.com/one/two");
         assert_eq!(result.webpages[2].url, "https://www.third.com/one/two123");
     }
+
+    #[test]
+    fn custom_signal_provider() {
+        use crate::fastfield_reader::FieldReader;
+        use crate::ranking::signal_provider::{
+            configure_custom_signals, register_signal_provider, SignalProvider, SignalQuery,
+        };
+        use crate::schema::FastField;
+
+        struct DeepPages;
+
+        impl SignalProvider for DeepPages {
+            fn score(&self, fastfields: &FieldReader<'_>, _: &SignalQuery<'_>) -> Option<f64> {
+                Some(fastfields.get(&FastField::NumPathAndQuerySlashes) as f64)
+            }
+
+            fn default_coefficient(&self) -> f64 {
+                0.0
+            }
+        }
+
+        configure_custom_signals(&["test_deep_pages".to_string()]).unwrap();
+        register_signal_provider("test_deep_pages", DeepPages).unwrap();
+
+        let mut index = Index::temporary().expect("Unable to open index");
+
+        for url in [
+            "https://www.first.com/one",
+            "https://www.second.com/one/two",
+        ] {
+            index
+                .insert(Webpage {
+                    html: Html::parse(
+                        &format!(
+                            r#"
+                        <html>
+                            <head>
+                                <title>Test website</title>
+                            </head>
+                            <body>
+                                {CONTENT} {}
+                            </body>
+                        </html>
+                    "#,
+                            crate::rand_words(100)
+                        ),
+                        url,
+                    )
+                    .unwrap(),
+                    host_centrality: 1.0,
+                    ..Default::default()
+                })
+                .expect("failed to insert webpage");
+        }
+        index.commit().expect("failed to commit index");
+        let searcher = LocalSearcher::new(index);
+
+        let result = searcher
+            .search(&SearchQuery {
+                query: "test".to_string(),
+                optic: Some(Optic::parse("Ranking(Signal(\"test_deep_pages\"), 100000);").unwrap()),
+                ..Default::default()
+            })
+            .expect("Search failed");
+
+        assert_eq!(result.webpages.len(), 2);
+        assert_eq!(result.webpages[0].url, "https://www.second.com/one/two");
+        assert_eq!(result.webpages[1].url, "https://www.first.com/one");
+    }
 }
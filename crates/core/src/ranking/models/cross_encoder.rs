@@ -14,22 +14,34 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+//! Cross-encoder used to re-rank the top results.
+//!
+//! The pairs from concurrent requests are collected into a single batch before
+//! they are scored by the model, and the scores are cached by (query, body hash)
+//! so repeated queries and pagination don't re-score identical pairs.
 use anyhow::anyhow;
 use anyhow::Result;
 use candle_core::Module;
 use candle_core::{Device, Tensor};
 use candle_nn::Linear;
 use candle_nn::VarBuilder;
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokenizers::PaddingParams;
 use tokenizers::TruncationParams;
 
+use crate::config::CrossEncoderConfig;
 use crate::models::bert;
 use crate::models::bert::BertModel;
 
 const TRUNCATE_INPUT: usize = 128;
 
-pub struct CrossEncoderModel {
+struct Inference {
     tokenizer: tokenizers::Tokenizer,
     encoder: BertModel,
     classifier: Linear,
@@ -37,10 +49,16 @@ pub struct CrossEncoderModel {
     dtype: candle_core::DType,
 }
 
-impl CrossEncoderModel {
-    pub fn open<P: AsRef<Path>>(folder: P) -> Result<Self> {
+impl Inference {
+    fn open<P: AsRef<Path>>(folder: P, quantize: bool) -> Result<Self> {
         let device = Device::Cpu;
-        let dtype = candle_core::DType::F16;
+
+        // the quantised matmuls only support f32 activations
+        let dtype = if quantize {
+            candle_core::DType::F32
+        } else {
+            candle_core::DType::F16
+        };
 
         let truncation = TruncationParams {
             max_length: TRUNCATE_INPUT,
@@ -70,6 +88,7 @@ impl CrossEncoderModel {
         let config = std::fs::read_to_string(folder.as_ref().join("config.json"))?;
         let mut config: bert::Config = serde_json::from_str(&config)?;
         config.hidden_act = bert::HiddenAct::GeluApproximate;
+        config.quantize = quantize;
 
         let classifier: Linear = candle_nn::linear(config.hidden_size, 1, vb.pp("classifier"))?;
 
@@ -87,71 +106,237 @@ impl CrossEncoderModel {
         })
     }
 
-    fn scores(&self, query: &str, bodies: &[String]) -> Vec<f64> {
-        if bodies.is_empty() {
-            return Vec::new();
+    fn scores(&self, pairs: &[(String, String)]) -> Result<Vec<f64>> {
+        if pairs.is_empty() {
+            return Ok(Vec::new());
         }
 
-        let input: Vec<_> = bodies
-            .iter()
-            .map(|body| {
-                (
-                    query.to_string(),
-                    body.split_whitespace()
-                        .take(TRUNCATE_INPUT)
-                        .collect::<String>(),
-                )
-            })
-            .collect();
-
-        let encoded = self.tokenizer.encode_batch(input, true).unwrap();
+        let encoded = self
+            .tokenizer
+            .encode_batch(pairs.to_vec(), true)
+            .map_err(|e| anyhow!(e))?;
 
         let ids = encoded
             .iter()
             .map(|enc| Tensor::new(enc.get_ids(), &self.device).map_err(|e| anyhow!(e)))
-            .collect::<Result<Vec<_>>>()
-            .unwrap();
-        let input_ids = Tensor::stack(&ids, 0).unwrap();
+            .collect::<Result<Vec<_>>>()?;
+        let input_ids = Tensor::stack(&ids, 0)?;
 
-        let token_type_ids = input_ids.zeros_like().unwrap();
+        let token_type_ids = input_ids.zeros_like()?;
 
         let attention_mask = encoded
             .iter()
             .map(|enc| Tensor::new(enc.get_attention_mask(), &self.device).map_err(|e| anyhow!(e)))
-            .collect::<Result<Vec<_>>>()
-            .unwrap();
-        let attention_mask = Tensor::stack(&attention_mask, 0)
-            .unwrap()
-            .to_dtype(self.dtype)
-            .unwrap();
+            .collect::<Result<Vec<_>>>()?;
+        let attention_mask = Tensor::stack(&attention_mask, 0)?.to_dtype(self.dtype)?;
 
         let logits = self
             .encoder
-            .forward(&input_ids, &token_type_ids, &attention_mask)
-            .unwrap();
+            .forward(&input_ids, &token_type_ids, &attention_mask)?;
 
         let scores = self
             .classifier
-            .forward(&logits)
-            .unwrap()
-            .squeeze(1)
-            .unwrap()
-            .to_dtype(candle_core::DType::F64)
-            .unwrap();
+            .forward(&logits)?
+            .squeeze(1)?
+            .to_dtype(candle_core::DType::F64)?;
+
+        let scores = candle_nn::ops::sigmoid(&scores)?;
+
+        Ok(scores.to_vec1()?)
+    }
+}
+
+struct BatchRequest {
+    pairs: Vec<(String, String)>,
+    response: crossbeam_channel::Sender<Option<Vec<f64>>>,
+}
+
+/// Collects the pairs of concurrent requests into batches of up to `max_batch_size` pairs.
+/// A batch is scored when it is full or `timeout` has passed since its first request arrived.
+///
+/// If scoring a batch fails or panics, the requests in that batch get no scores and the
+/// batcher keeps serving the following batches.
+struct Batcher {
+    sender: crossbeam_channel::Sender<BatchRequest>,
+}
+
+impl Batcher {
+    fn new<F>(score: F, max_batch_size: usize, timeout: Duration) -> Self
+    where
+        F: Fn(&[(String, String)]) -> Result<Vec<f64>> + Send + 'static,
+    {
+        let (sender, receiver) = crossbeam_channel::unbounded::<BatchRequest>();
+        let max_batch_size = max_batch_size.max(1);
+
+        std::thread::spawn(move || {
+            while let Ok(first) = receiver.recv() {
+                let deadline = Instant::now() + timeout;
+                let mut num_pairs = first.pairs.len();
+                let mut requests = vec![first];
+
+                while num_pairs < max_batch_size {
+                    match receiver.recv_deadline(deadline) {
+                        Ok(request) => {
+                            num_pairs += request.pairs.len();
+                            requests.push(request);
+                        }
+                        Err(_) => break,
+                    }
+                }
+
+                let lens: Vec<_> = requests.iter().map(|request| request.pairs.len()).collect();
+                let pairs: Vec<_> = requests
+                    .iter_mut()
+                    .flat_map(|request| std::mem::take(&mut request.pairs))
+                    .collect();
+
+                let scores = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    pairs
+                        .chunks(max_batch_size)
+                        .map(&score)
+                        .collect::<Result<Vec<_>>>()
+                }));
+
+                let mut scores = match scores {
+                    Ok(Ok(scores)) => Some(scores.into_iter().flatten()),
+                    Ok(Err(err)) => {
+                        tracing::error!("failed to score cross encoder batch: {err}");
+                        None
+                    }
+                    Err(_) => {
+                        tracing::error!("cross encoder panicked while scoring batch");
+                        None
+                    }
+                };
+
+                for (request, len) in requests.into_iter().zip(lens) {
+                    let res = scores
+                        .as_mut()
+                        .map(|scores| scores.by_ref().take(len).collect());
+                    let _ = request.response.send(res);
+                }
+            }
+        });
+
+        Self { sender }
+    }
+
+    /// The scores of the pairs, or `None` if they could not be scored.
+    fn scores(&self, pairs: Vec<(String, String)>) -> Option<Vec<f64>> {
+        if pairs.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let (response, receiver) = crossbeam_channel::bounded(1);
+
+        self.sender.send(BatchRequest { pairs, response }).ok()?;
+
+        receiver.recv().ok().flatten()
+    }
+}
+
+fn hash_body(body: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub struct CrossEncoderModel {
+    batcher: Batcher,
+    cache: Option<Mutex<LruCache<(String, u64), f64>>>,
+}
+
+impl CrossEncoderModel {
+    pub fn open<P: AsRef<Path>>(folder: P) -> Result<Self> {
+        Self::open_with_config(folder, &CrossEncoderConfig::default())
+    }
+
+    pub fn open_with_config<P: AsRef<Path>>(
+        folder: P,
+        config: &CrossEncoderConfig,
+    ) -> Result<Self> {
+        let inference = Inference::open(folder, config.quantize)?;
+
+        let batcher = Batcher::new(
+            move |pairs| inference.scores(pairs),
+            config.max_batch_size,
+            Duration::from_millis(config.batch_timeout_ms),
+        );
+
+        Ok(Self::new(batcher, config.cache_size))
+    }
+
+    fn new(batcher: Batcher, cache_size: usize) -> Self {
+        Self {
+            batcher,
+            cache: NonZeroUsize::new(cache_size).map(|size| Mutex::new(LruCache::new(size))),
+        }
+    }
+
+    fn scores(&self, query: &str, bodies: &[String]) -> Option<Vec<f64>> {
+        if bodies.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let keys: Vec<_> = bodies
+            .iter()
+            .map(|body| (query.to_string(), hash_body(body)))
+            .collect();
+
+        let mut scores: Vec<Option<f64>> = match &self.cache {
+            Some(cache) => {
+                let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+                keys.iter().map(|key| cache.get(key).copied()).collect()
+            }
+            None => vec![None; bodies.len()],
+        };
 
-        let scores = candle_nn::ops::sigmoid(&scores).unwrap();
+        let missing: Vec<_> = scores
+            .iter()
+            .enumerate()
+            .filter(|(_, score)| score.is_none())
+            .map(|(i, _)| i)
+            .collect();
+
+        let pairs: Vec<_> = missing
+            .iter()
+            .map(|i| {
+                (
+                    query.to_string(),
+                    bodies[*i]
+                        .split_whitespace()
+                        .take(TRUNCATE_INPUT)
+                        .collect::<String>(),
+                )
+            })
+            .collect();
+
+        let new_scores = self.batcher.scores(pairs)?;
+
+        if let Some(cache) = &self.cache {
+            let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+
+            for (i, score) in missing.iter().zip(new_scores.iter()) {
+                cache.put(keys[*i].clone(), *score);
+            }
+        }
+
+        for (i, score) in missing.into_iter().zip(new_scores) {
+            scores[i] = Some(score);
+        }
 
-        scores.to_vec1().unwrap()
+        Some(scores.into_iter().map(|s| s.unwrap_or_default()).collect())
     }
 }
 
 impl CrossEncoder for CrossEncoderModel {
     fn run(&self, query: &str, bodies: &[String]) -> Vec<f64> {
-        let mut scores = self
-            .scores(query, bodies)
-            .into_iter()
-            .enumerate()
-            .collect::<Vec<_>>();
+        // without scores the results are left in the order of the previous stage
+        let Some(scores) = self.scores(query, bodies) else {
+            return DummyCrossEncoder.run(query, bodies);
+        };
+
+        let mut scores = scores.into_iter().enumerate().collect::<Vec<_>>();
 
         scores.sort_by(|a, b| b.1.total_cmp(&a.1));
 
@@ -216,4 +401,81 @@ mod tests {
 
         assert!(res[0] > res[1]);
     }
+
+    #[test]
+    fn batching_and_cache() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let num_scored = Arc::new(AtomicUsize::new(0));
+
+        let batcher = {
+            let num_scored = Arc::clone(&num_scored);
+            Batcher::new(
+                move |pairs: &[(String, String)]| {
+                    num_scored.fetch_add(pairs.len(), Ordering::SeqCst);
+                    Ok(pairs.iter().map(|(_, body)| body.len() as f64).collect())
+                },
+                4,
+                Duration::from_millis(1),
+            )
+        };
+        let model = Arc::new(CrossEncoderModel::new(batcher, 64));
+
+        let bodies: Vec<_> = (1..=10).map(|i| "a".repeat(i)).collect();
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let model = Arc::clone(&model);
+                let bodies = bodies.clone();
+                std::thread::spawn(move || model.scores(&format!("query {i}"), &bodies))
+            })
+            .collect();
+
+        for handle in handles {
+            let scores = handle.join().unwrap().unwrap();
+            assert_eq!(scores, (1..=10).map(|i| i as f64).collect::<Vec<_>>());
+        }
+
+        assert_eq!(num_scored.load(Ordering::SeqCst), 40);
+
+        // pagination re-scores the same pairs, which should be served from the cache
+        let scores = model.scores("query 0", &bodies[..5]).unwrap();
+        assert_eq!(scores, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(num_scored.load(Ordering::SeqCst), 40);
+
+        let scores = model.scores("another query", &bodies[..2]).unwrap();
+        assert_eq!(scores, vec![1.0, 2.0]);
+        assert_eq!(num_scored.load(Ordering::SeqCst), 42);
+    }
+
+    #[test]
+    fn recover_from_failed_batch() {
+        let batcher = Batcher::new(
+            |pairs: &[(String, String)]| {
+                if pairs.iter().any(|(_, body)| body == "panic") {
+                    panic!("failed to score");
+                }
+
+                if pairs.iter().any(|(_, body)| body == "error") {
+                    return Err(anyhow!("failed to score"));
+                }
+
+                Ok(vec![1.0; pairs.len()])
+            },
+            4,
+            Duration::from_millis(1),
+        );
+        let model = CrossEncoderModel::new(batcher, 0);
+
+        let bodies = vec!["a".to_string(), "panic".to_string()];
+        assert_eq!(model.scores("query", &bodies), None);
+        assert_eq!(model.run("query", &bodies), vec![1.0, 1.0]);
+
+        let bodies = vec!["error".to_string()];
+        assert_eq!(model.scores("query", &bodies), None);
+
+        let bodies = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(model.scores("query", &bodies), Some(vec![1.0, 1.0]));
+    }
 }
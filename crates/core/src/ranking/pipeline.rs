@@ -24,7 +24,7 @@ use crate::{
     enum_map::EnumMap,
     inverted_index::{RetrievedWebpage, WebsitePointer},
     searcher::SearchQuery,
-    Result,
+    simhash, Result,
 };
 
 use super::{
@@ -108,6 +108,13 @@ pub struct RankingWebsite {
     pub snippet: Option<String>,
     pub optic_boost: Option<f64>,
     pub score: f64,
+    /// whether the website was recalled by the inverted index.
+    pub lexical_match: bool,
+    /// similarity to the query if the website was recalled by the dense index.
+    pub dense_similarity: Option<f64>,
+    /// dual-encoder embedding of the website. Only set when the results
+    /// should be diversified and the website was recalled by the dense index.
+    pub embedding: Option<Vec<f32>>,
 }
 
 impl RankingWebsite {
@@ -118,6 +125,9 @@ impl RankingWebsite {
             score: pointer.score.total,
             optic_boost: None,
             snippet: None,
+            lexical_match: true,
+            dense_similarity: None,
+            embedding: None,
             pointer: pointer.clone(),
         };
 
@@ -283,13 +293,183 @@ impl<T: AsRankingWebsite> Scorer<T> for Initial {
     }
 }
 
+/// Combines the lexical ranking of the websites (the score assigned by the stage)
+/// with the ranking from the dense retrieval using reciprocal rank fusion.
+/// Each ranking contributes `1 / (k + rank)` to the fused score of a website.
+struct ReciprocalRankFusion {
+    k: f64,
+}
+
+impl ReciprocalRankFusion {
+    fn fuse<T: AsRankingWebsite>(&self, websites: &mut [T]) {
+        let mut lexical: Vec<_> = websites
+            .iter()
+            .enumerate()
+            .filter(|(_, website)| website.as_ranking().lexical_match)
+            .map(|(i, website)| (i, website.as_ranking().score))
+            .collect();
+        lexical.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut dense: Vec<_> = websites
+            .iter()
+            .enumerate()
+            .filter_map(|(i, website)| website.as_ranking().dense_similarity.map(|sim| (i, sim)))
+            .collect();
+        dense.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut fused = vec![0.0; websites.len()];
+
+        for ranking in [lexical, dense] {
+            for (rank, (i, _)) in ranking.into_iter().enumerate() {
+                fused[i] += 1.0 / (self.k + (rank + 1) as f64);
+            }
+        }
+
+        for (website, score) in websites.iter_mut().zip(fused) {
+            website.as_mut_ranking().score = score;
+        }
+
+        websites.sort_by(|a, b| b.as_ranking().score.total_cmp(&a.as_ranking().score));
+    }
+}
+
+/// Diversifies the best websites using maximal marginal relevance.
+/// Websites are greedily selected by `lambda * relevance - (1 - lambda) * similarity`,
+/// where relevance is the min-max normalized score of the website and similarity
+/// is the highest similarity to any of the already selected websites.
+struct MaximalMarginalRelevance {
+    lambda: f64,
+}
+
+impl MaximalMarginalRelevance {
+    fn new(lambda: f64) -> Self {
+        Self {
+            lambda: lambda.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Embeddings are compared using cosine similarity when both websites have one,
+    /// otherwise we fall back to the similarity of their simhashes.
+    fn similarity(a: &RankingWebsite, b: &RankingWebsite) -> f64 {
+        if let (Some(a), Some(b)) = (&a.embedding, &b.embedding) {
+            let dot: f32 = a.iter().zip(b.iter()).map(|(a, b)| a * b).sum();
+            let norm_a = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+            let norm_b = b.iter().map(|v| v * v).sum::<f32>().sqrt();
+
+            if norm_a > 0.0 && norm_b > 0.0 {
+                return (dot / (norm_a * norm_b)).max(0.0) as f64;
+            }
+        }
+
+        let a = a.pointer.hashes.simhash;
+        let b = b.pointer.hashes.simhash;
+
+        if a == 0 || b == 0 {
+            return 0.0;
+        }
+
+        simhash::similarity(a, b)
+    }
+
+    /// Reorder the `top_n` first websites. The remaining websites keep their order.
+    fn diversify<T: AsRankingWebsite>(&self, websites: Vec<T>, top_n: usize) -> Vec<T> {
+        if websites.len() <= 1 || self.lambda >= 1.0 {
+            return websites;
+        }
+
+        let (min, max) = websites
+            .iter()
+            .map(|website| website.as_ranking().score)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), score| {
+                (min.min(score), max.max(score))
+            });
+
+        let relevance: Vec<_> = websites
+            .iter()
+            .map(|website| {
+                if max > min {
+                    (website.as_ranking().score - min) / (max - min)
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+
+        let mut max_similarity = vec![0.0; websites.len()];
+        let mut selected = Vec::with_capacity(top_n.min(websites.len()));
+        let mut remaining: Vec<_> = websites.into_iter().map(Some).collect();
+
+        while selected.len() < top_n {
+            let best = remaining
+                .iter()
+                .enumerate()
+                .filter(|(_, website)| website.is_some())
+                .map(|(i, _)| {
+                    (
+                        i,
+                        self.lambda * relevance[i] - (1.0 - self.lambda) * max_similarity[i],
+                    )
+                })
+                .fold(None, |best, (i, mmr)| match best {
+                    Some((_, best_mmr)) if best_mmr >= mmr => best,
+                    _ => Some((i, mmr)),
+                });
+
+            let website = match best.and_then(|(i, _)| remaining[i].take()) {
+                Some(website) => website,
+                None => break,
+            };
+
+            for (i, other) in remaining.iter().enumerate() {
+                if let Some(other) = other {
+                    let sim = Self::similarity(website.as_ranking(), other.as_ranking());
+                    max_similarity[i] = f64::max(max_similarity[i], sim);
+                }
+            }
+
+            selected.push(website);
+        }
+
+        selected.extend(remaining.into_iter().flatten());
+
+        // later stages and the merging of shard results sort the websites by score,
+        // so the diversified order must be reflected in the scores.
+        let mut prev = f64::INFINITY;
+        for website in &mut selected {
+            let score = &mut website.as_mut_ranking().score;
+
+            if *score >= prev {
+                *score = prev - f64::EPSILON * prev.abs().max(1.0);
+            }
+
+            prev = *score;
+        }
+
+        selected
+    }
+}
+
 struct RankingStage<T> {
     scorer: Box<dyn Scorer<T>>,
     stage_top_n: usize,
     derank_similar: bool,
+    fusion: Option<ReciprocalRankFusion>,
+    diversity: Option<MaximalMarginalRelevance>,
 }
 
 impl<T: AsRankingWebsite> RankingStage<T> {
+    fn score(&self, websites: &mut [T]) {
+        self.scorer.score(websites);
+        for website in websites.iter_mut() {
+            let boost = website.as_ranking().optic_boost;
+            if let Some(boost) = boost {
+                if boost != 0.0 {
+                    website.as_mut_ranking().score *= boost;
+                }
+            }
+        }
+    }
+
     fn apply(
         &self,
         websites: Vec<T>,
@@ -297,20 +477,25 @@ impl<T: AsRankingWebsite> RankingStage<T> {
         offset: usize,
         collector_config: CollectorConfig,
     ) -> Vec<T> {
-        let mut websites = websites
-            .into_iter()
-            .skip(offset)
-            .take(self.stage_top_n.max(top_n))
-            .collect::<Vec<_>>();
+        // the dense candidates are not ordered relative to the lexical candidates, and the
+        // diversified order of a page depends on the websites on the previous pages, so in
+        // both cases all websites must be ranked before the offset can be applied.
+        let rank_all = self.fusion.is_some() || self.diversity.is_some();
+
+        let mut websites = if rank_all {
+            websites
+        } else {
+            websites
+                .into_iter()
+                .skip(offset)
+                .take(self.stage_top_n.max(top_n))
+                .collect::<Vec<_>>()
+        };
 
-        self.scorer.score(&mut websites);
-        for website in websites.iter_mut() {
-            let boost = website.as_ranking().optic_boost;
-            if let Some(boost) = boost {
-                if boost != 0.0 {
-                    website.as_mut_ranking().score *= boost;
-                }
-            }
+        self.score(&mut websites);
+
+        if let Some(fusion) = &self.fusion {
+            fusion.fuse(&mut websites);
         }
 
         let mut collector =
@@ -320,11 +505,18 @@ impl<T: AsRankingWebsite> RankingStage<T> {
             collector.insert(website);
         }
 
-        collector
-            .into_sorted_vec(self.derank_similar)
-            .into_iter()
-            .take(top_n)
-            .collect()
+        let websites = collector.into_sorted_vec(self.derank_similar);
+
+        let websites = match &self.diversity {
+            Some(mmr) => mmr.diversify(websites, top_n + offset),
+            None => websites,
+        };
+
+        if rank_all {
+            websites.into_iter().skip(offset).take(top_n).collect()
+        } else {
+            websites.into_iter().take(top_n).collect()
+        }
     }
 
     fn set_query_info(&mut self, query: &SearchQuery) {
@@ -357,6 +549,8 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
             scorer,
             stage_top_n: top_n_considered,
             derank_similar: true,
+            fusion: None,
+            diversity: None,
         };
 
         Ok(Self {
@@ -393,6 +587,8 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
             }),
             stage_top_n,
             derank_similar: true,
+            fusion: None,
+            diversity: None,
         };
 
         Self {
@@ -415,6 +611,28 @@ impl<T: AsRankingWebsite> RankingPipeline<T> {
         pipeline
     }
 
+    /// Fuse the lexical and dense rankings of the websites using reciprocal rank fusion
+    /// with the constant `k`.
+    pub fn with_rank_fusion(mut self, k: f64) -> Self {
+        self.stage.fusion = Some(ReciprocalRankFusion { k });
+        self
+    }
+
+    /// Diversify the top websites using maximal marginal relevance with the given `lambda`.
+    pub fn with_diversity(mut self, lambda: f64) -> Self {
+        self.stage.diversity = Some(MaximalMarginalRelevance::new(lambda));
+        self
+    }
+
+    /// Diversify the top websites if the query asks for it. This must only be done once the
+    /// results of all shards have been merged, since the merge orders the websites by score.
+    pub fn with_query_diversity(self, query: &SearchQuery) -> Self {
+        match query.diversity_lambda() {
+            Some(lambda) => self.with_diversity(lambda),
+            None => self,
+        }
+    }
+
     fn set_query_info(&mut self, query: &mut SearchQuery) {
         self.stage.set_query_info(query);
         self.page = query.page;
@@ -493,6 +711,9 @@ mod tests {
                     title: None,
                     snippet: None,
                     score: 1.0 / i as f64,
+                    lexical_match: true,
+                    dense_similarity: None,
+                    embedding: None,
                 }
             })
             .collect()
@@ -610,4 +831,133 @@ mod tests {
             prev = res;
         }
     }
+
+    #[test]
+    fn reciprocal_rank_fusion() {
+        let mut websites = sample_websites(4);
+
+        // only recalled by the dense index
+        websites[3].lexical_match = false;
+        websites[3].dense_similarity = Some(0.8);
+
+        websites[2].dense_similarity = Some(0.9);
+        websites[1].dense_similarity = Some(0.1);
+
+        let fusion = ReciprocalRankFusion { k: 60.0 };
+        fusion.fuse(&mut websites);
+
+        let res: Vec<_> = websites
+            .into_iter()
+            .map(|w| w.pointer.address.doc_id)
+            .collect();
+
+        // lexical ranking: 0, 1, 2
+        // dense ranking: 2, 3, 1
+        assert_eq!(res, vec![2, 1, 0, 3]);
+    }
+
+    #[test]
+    fn maximal_marginal_relevance() {
+        let diversified_websites = || {
+            let mut websites: Vec<_> = sample_websites(5).into_iter().skip(1).collect();
+
+            websites[0].embedding = Some(vec![1.0, 0.0]);
+            websites[1].embedding = Some(vec![1.0, 0.0]);
+            websites[2].embedding = Some(vec![0.0, 1.0]);
+            websites[3].embedding = Some(vec![0.0, 1.0]);
+
+            websites
+        };
+
+        let ranked = |mut query: SearchQuery| -> Vec<_> {
+            query.num_results = 2;
+
+            RankingPipeline::recall_stage(&mut query, None, CollectorConfig::default(), 100)
+                .with_query_diversity(&query)
+                .apply(diversified_websites())
+                .into_iter()
+                .map(|w| w.pointer.address.doc_id)
+                .collect()
+        };
+
+        assert_eq!(ranked(SearchQuery::default()), vec![1, 2]);
+
+        // the shards leave the diversification to the searcher that merges their results
+        let mut query = SearchQuery {
+            diversity: Some(0.5),
+            num_results: 2,
+            ..Default::default()
+        };
+        let shard_ranked: Vec<_> =
+            RankingPipeline::recall_stage(&mut query, None, CollectorConfig::default(), 100)
+                .apply(diversified_websites())
+                .into_iter()
+                .map(|w| w.pointer.address.doc_id)
+                .collect();
+        assert_eq!(shard_ranked, vec![1, 2]);
+
+        assert_eq!(
+            ranked(SearchQuery {
+                diversity: Some(0.5),
+                ..Default::default()
+            }),
+            vec![1, 3]
+        );
+
+        assert_eq!(
+            ranked(SearchQuery {
+                optic: Some(optics::Optic::parse("Diversity(0.5);").unwrap()),
+                ..Default::default()
+            }),
+            vec![1, 3]
+        );
+
+        // the request takes precedence over the optic
+        assert_eq!(
+            ranked(SearchQuery {
+                optic: Some(optics::Optic::parse("Diversity(0.5);").unwrap()),
+                diversity: Some(1.0),
+                ..Default::default()
+            }),
+            vec![1, 2]
+        );
+
+        // the second page continues the diversified order of the first page
+        let page = |page: usize| -> Vec<_> {
+            let mut query = SearchQuery {
+                diversity: Some(0.5),
+                num_results: 1,
+                page,
+                ..Default::default()
+            };
+
+            RankingPipeline::recall_stage(&mut query, None, CollectorConfig::default(), 100)
+                .with_query_diversity(&query)
+                .apply(diversified_websites())
+                .into_iter()
+                .map(|w| w.pointer.address.doc_id)
+                .collect()
+        };
+
+        assert_eq!(page(0), vec![1]);
+        assert_eq!(page(1), vec![3]);
+
+        // near-duplicate simhashes are used when the websites have no embeddings
+        let mut websites: Vec<_> = sample_websites(5).into_iter().skip(1).collect();
+        websites[0].pointer.hashes.simhash = u64::MAX;
+        websites[1].pointer.hashes.simhash = u64::MAX << 8;
+        websites[2].pointer.hashes.simhash = 0xff;
+        websites[3].pointer.hashes.simhash = 0xff;
+
+        let res = MaximalMarginalRelevance::new(0.5).diversify(websites, 2);
+
+        assert!(res
+            .iter()
+            .tuple_windows()
+            .all(|(a, b)| a.as_ranking().score > b.as_ranking().score));
+
+        let res: Vec<_> = res.into_iter().map(|w| w.pointer.address.doc_id).collect();
+
+        assert_eq!(res, vec![1, 3, 2, 4]);
+    }
 }
@@ -48,15 +48,20 @@ use crate::{
 
 use super::bm25::MultiBm25Weight;
 use super::models::linear::LinearRegression;
+use super::signal_provider::{self, CustomSignal, SignalProvider, SignalQuery};
 use super::{inbound_similarity, query_centrality};
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("unknown signal: {0}")]
-    UnknownSignal(#[from] serde_json::Error),
+    UnknownSignal(String),
 }
 
+/// Signals are (de)serialized as their name in every format, so custom signals are
+/// identified the same way as the builtin ones. The derived implementation is only used
+/// to look up the names of the builtin signals.
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(remote = "Self")]
 pub enum Signal {
     #[serde(rename = "bm25_title")]
     Bm25Title,
@@ -112,6 +117,14 @@ pub enum Signal {
     PageCentrality,
     #[serde(rename = "page_centrality_rank")]
     PageCentralityRank,
+    #[serde(rename = "host_pagerank")]
+    HostPageRank,
+    #[serde(rename = "host_personalized_pagerank")]
+    HostPersonalizedPageRank,
+    #[serde(rename = "page_pagerank")]
+    PagePageRank,
+    #[serde(rename = "host_spam_score")]
+    HostSpamScore,
     #[serde(rename = "is_homepage")]
     IsHomepage,
     #[serde(rename = "fetch_time_ms")]
@@ -134,15 +147,91 @@ pub enum Signal {
     UrlSlashes,
     #[serde(rename = "link_density")]
     LinkDensity,
+    #[serde(skip)]
+    Custom(CustomSignal),
+}
+
+impl Signal {
+    pub fn name(&self) -> String {
+        match self {
+            Signal::Custom(custom) => custom.name(),
+            _ => match Signal::serialize(self, serde_json::value::Serializer) {
+                Ok(serde_json::Value::String(name)) => name,
+                _ => unreachable!("builtin signals are serialized as strings"),
+            },
+        }
+    }
+}
+
+impl Serialize for Signal {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Signal {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Signal::from_str(&name).map_err(serde::de::Error::custom)
+    }
 }
 
 impl From<Signal> for usize {
     fn from(signal: Signal) -> Self {
-        signal as usize
+        match signal {
+            Signal::Bm25Title => 0,
+            Signal::Bm25TitleBigrams => 1,
+            Signal::Bm25TitleTrigrams => 2,
+            Signal::Bm25CleanBody => 3,
+            Signal::Bm25CleanBodyBigrams => 4,
+            Signal::Bm25CleanBodyTrigrams => 5,
+            Signal::Bm25StemmedTitle => 6,
+            Signal::Bm25StemmedCleanBody => 7,
+            Signal::Bm25AllBody => 8,
+            Signal::Bm25Keywords => 9,
+            Signal::Bm25BacklinkText => 10,
+            Signal::IdfSumUrl => 11,
+            Signal::IdfSumSite => 12,
+            Signal::IdfSumDomain => 13,
+            Signal::IdfSumSiteNoTokenizer => 14,
+            Signal::IdfSumDomainNoTokenizer => 15,
+            Signal::IdfSumDomainNameNoTokenizer => 16,
+            Signal::IdfSumDomainIfHomepage => 17,
+            Signal::IdfSumDomainNameIfHomepageNoTokenizer => 18,
+            Signal::IdfSumDomainIfHomepageNoTokenizer => 19,
+            Signal::IdfSumTitleIfHomepage => 20,
+            Signal::CrossEncoderSnippet => 21,
+            Signal::CrossEncoderTitle => 22,
+            Signal::HostCentrality => 23,
+            Signal::HostCentralityRank => 24,
+            Signal::PageCentrality => 25,
+            Signal::PageCentralityRank => 26,
+            Signal::IsHomepage => 27,
+            Signal::FetchTimeMs => 28,
+            Signal::UpdateTimestamp => 29,
+            Signal::TrackerScore => 30,
+            Signal::Region => 31,
+            Signal::QueryCentrality => 32,
+            Signal::InboundSimilarity => 33,
+            Signal::LambdaMART => 34,
+            Signal::UrlDigits => 35,
+            Signal::UrlSlashes => 36,
+            Signal::LinkDensity => 37,
+            Signal::HostPageRank => 38,
+            Signal::HostPersonalizedPageRank => 39,
+            Signal::PagePageRank => 40,
+            Signal::HostSpamScore => 41,
+            Signal::Custom(custom) => ALL_SIGNALS.len() + custom.id(),
+        }
     }
 }
 
-pub const ALL_SIGNALS: [Signal; 38] = [
+pub const ALL_SIGNALS: [Signal; 42] = [
     Signal::Bm25Title,
     Signal::Bm25TitleBigrams,
     Signal::Bm25TitleTrigrams,
@@ -181,8 +270,19 @@ pub const ALL_SIGNALS: [Signal; 38] = [
     Signal::UrlDigits,
     Signal::UrlSlashes,
     Signal::LinkDensity,
+    Signal::HostPageRank,
+    Signal::HostPersonalizedPageRank,
+    Signal::PagePageRank,
+    Signal::HostSpamScore,
 ];
 
+/// All builtin signals followed by the custom signals registered at startup.
+pub fn all_signals() -> impl Iterator<Item = Signal> {
+    ALL_SIGNALS
+        .into_iter()
+        .chain(signal_provider::custom_signals())
+}
+
 fn score_timestamp(timestamp: usize, signal_aggregator: &SignalAggregator) -> f64 {
     if timestamp >= signal_aggregator.current_timestamp.unwrap_or(0) {
         return 0.0;
@@ -208,6 +308,12 @@ fn score_trackers(num_trackers: f64) -> f64 {
     1.0 / (num_trackers + 1.0)
 }
 
+/// Hosts with a lower spam score get a higher signal value.
+#[inline]
+fn score_spam(spam_score: f64) -> f64 {
+    (1.0 - spam_score).clamp(0.0, 1.0)
+}
+
 #[inline]
 fn score_digits(num_digits: f64) -> f64 {
     1.0 / (num_digits + 1.0)
@@ -319,6 +425,10 @@ impl Signal {
             Signal::HostCentralityRank => 0.0,
             Signal::PageCentrality => 0.25,
             Signal::PageCentralityRank => 0.0,
+            Signal::HostPageRank => 0.0,
+            Signal::HostPersonalizedPageRank => 0.0,
+            Signal::PagePageRank => 0.0,
+            Signal::HostSpamScore => 0.0,
             Signal::QueryCentrality => 0.0,
             Signal::IsHomepage => 0.0005,
             Signal::FetchTimeMs => 0.001,
@@ -330,6 +440,10 @@ impl Signal {
             Signal::UrlSlashes => 0.01,
             Signal::UrlDigits => 0.01,
             Signal::LinkDensity => 0.00,
+            Signal::Custom(custom) => custom
+                .provider()
+                .map(|provider| provider.default_coefficient())
+                .unwrap_or(0.0),
         }
     }
 
@@ -354,7 +468,11 @@ impl Signal {
         };
 
         let value: Option<f64> = match self {
-            Signal::HostCentrality | Signal::PageCentrality => {
+            Signal::HostCentrality
+            | Signal::PageCentrality
+            | Signal::HostPageRank
+            | Signal::HostPersonalizedPageRank
+            | Signal::PagePageRank => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(val as f64 / FLOAT_SCALING as f64)
             }
@@ -388,6 +506,10 @@ impl Signal {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(score_trackers(val as f64))
             }
+            Signal::HostSpamScore => {
+                let val = fastfield_reader.get(&self.as_fastfield().unwrap());
+                Some(score_spam(val as f64 / FLOAT_SCALING as f64))
+            }
             Signal::UrlDigits => {
                 let val = fastfield_reader.get(&self.as_fastfield().unwrap());
                 Some(score_digits(val as f64))
@@ -439,6 +561,27 @@ impl Signal {
             Signal::CrossEncoderSnippet => None, // this is calculated in a later step
             Signal::CrossEncoderTitle => None,   // this is calculated in a later step
             Signal::LambdaMART => None,
+            Signal::Custom(custom) => {
+                signal_aggregator
+                    .custom_provider(&custom)
+                    .and_then(|provider| {
+                        let terms = signal_aggregator
+                            .query_data
+                            .as_ref()
+                            .map(|q| q.simple_terms.as_slice())
+                            .unwrap_or_default();
+
+                        let query = SignalQuery {
+                            terms,
+                            selected_region: signal_aggregator
+                                .query_data
+                                .as_ref()
+                                .and_then(|q| q.selected_region),
+                        };
+
+                        provider.score(&fastfield_reader, &query)
+                    })
+            }
         };
 
         value.map(|value| ComputedSignal {
@@ -461,6 +604,9 @@ impl Signal {
             Signal::HostCentralityRank => Some(webpage.host_centrality_rank),
             Signal::PageCentrality => Some(webpage.page_centrality),
             Signal::PageCentralityRank => Some(webpage.page_centrality_rank),
+            Signal::HostPageRank => Some(webpage.host_pagerank),
+            Signal::HostPersonalizedPageRank => Some(webpage.host_personalized_pagerank),
+            Signal::PagePageRank => Some(webpage.page_pagerank),
             Signal::IsHomepage => Some(webpage.html.is_homepage().into()),
             Signal::FetchTimeMs => {
                 let fetch_time_ms = webpage.fetch_time_ms as usize;
@@ -483,6 +629,7 @@ impl Signal {
                 let num_trackers = webpage.html.trackers().len() as f64;
                 Some(score_trackers(num_trackers))
             }
+            Signal::HostSpamScore => Some(score_spam(webpage.host_spam_score)),
             Signal::Region => {
                 let region = Region::guess_from(webpage).unwrap_or(Region::All);
                 Some(score_region(region, signal_aggregator))
@@ -490,14 +637,14 @@ impl Signal {
             Signal::UrlDigits => {
                 let num_digits = (webpage
                     .html
-                    .url()
+                    .canonical()
                     .path()
                     .chars()
                     .filter(|c| c.is_ascii_digit())
                     .count()
                     + webpage
                         .html
-                        .url()
+                        .canonical()
                         .query()
                         .unwrap_or_default()
                         .chars()
@@ -508,7 +655,7 @@ impl Signal {
             Signal::UrlSlashes => {
                 let num_slashes = webpage
                     .html
-                    .url()
+                    .canonical()
                     .path()
                     .chars()
                     .filter(|c| c == &'/')
@@ -544,7 +691,8 @@ impl Signal {
             | Signal::CrossEncoderTitle
             | Signal::InboundSimilarity
             | Signal::LambdaMART
-            | Signal::QueryCentrality => {
+            | Signal::QueryCentrality
+            | Signal::Custom(_) => {
                 tracing::error!("signal {self:?} cannot be precomputed");
                 None
             }
@@ -565,6 +713,10 @@ impl Signal {
             Signal::HostCentralityRank => Some(FastField::HostCentralityRank),
             Signal::PageCentrality => Some(FastField::PageCentrality),
             Signal::PageCentralityRank => Some(FastField::PageCentralityRank),
+            Signal::HostPageRank => Some(FastField::HostPageRank),
+            Signal::HostPersonalizedPageRank => Some(FastField::HostPersonalizedPageRank),
+            Signal::PagePageRank => Some(FastField::PagePageRank),
+            Signal::HostSpamScore => Some(FastField::HostSpamScore),
             Signal::IsHomepage => Some(FastField::IsHomepage),
             Signal::FetchTimeMs => Some(FastField::FetchTimeMs),
             Signal::UpdateTimestamp => Some(FastField::LastUpdated),
@@ -614,8 +766,11 @@ impl FromStr for Signal {
 
     fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
         let s = "\"".to_string() + name + "\"";
-        let signal = serde_json::from_str(&s)?;
-        Ok(signal)
+
+        Signal::deserialize(&mut serde_json::Deserializer::from_str(&s))
+            .ok()
+            .or_else(|| CustomSignal::from_name(name).map(Signal::Custom))
+            .ok_or_else(|| Error::UnknownSignal(name.to_string()))
     }
 }
 
@@ -650,7 +805,7 @@ impl SignalCoefficient {
     }
 
     pub fn merge_into(&mut self, coeffs: SignalCoefficient) {
-        for signal in ALL_SIGNALS {
+        for signal in all_signals() {
             if let Some(coeff) = coeffs.get(&signal) {
                 match self.map.get_mut(signal) {
                     Some(existing_coeff) => *existing_coeff += coeff,
@@ -703,6 +858,8 @@ pub struct SignalAggregator {
     region_count: Option<Arc<RegionCount>>,
     current_timestamp: Option<usize>,
     linear_regression: Option<Arc<LinearRegression>>,
+    /// providers of the custom signals, resolved once for the query.
+    custom_providers: Vec<Option<Arc<dyn SignalProvider>>>,
     order: SignalOrder,
 }
 
@@ -786,6 +943,7 @@ impl SignalAggregator {
             region_count: None,
             current_timestamp: None,
             linear_regression: None,
+            custom_providers: signal_provider::providers(),
             query_data: query,
             order: SignalOrder::empty(),
         };
@@ -1003,7 +1161,25 @@ impl SignalAggregator {
                     .as_ref()
                     .and_then(|model| model.weights.get(*signal).copied())
             })
-            .unwrap_or(signal.default_coefficient())
+            .unwrap_or_else(|| self.default_coefficient(signal))
+    }
+
+    /// Like [`Signal::default_coefficient`], but uses the custom signal providers
+    /// that were resolved for the query.
+    fn default_coefficient(&self, signal: &Signal) -> f64 {
+        match signal {
+            Signal::Custom(custom) => self
+                .custom_provider(custom)
+                .map(|provider| provider.default_coefficient())
+                .unwrap_or(0.0),
+            _ => signal.default_coefficient(),
+        }
+    }
+
+    fn custom_provider(&self, custom: &CustomSignal) -> Option<&Arc<dyn SignalProvider>> {
+        self.custom_providers
+            .get(custom.id())
+            .and_then(Option::as_ref)
     }
 }
 
@@ -1038,7 +1214,7 @@ impl SignalOrder {
         let mut text_signals = EnumMap::new();
         let mut other_signals = Vec::new();
 
-        for signal in ALL_SIGNALS {
+        for signal in all_signals() {
             if signal_aggregator.coefficient(&signal) == 0.0 {
                 continue;
             }
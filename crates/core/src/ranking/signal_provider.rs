@@ -0,0 +1,341 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Custom ranking signals that are computed by providers outside of `signal.rs`.
+//!
+//! A provider is registered by name at startup and the returned
+//! [`Signal::Custom`] behaves like any of the builtin signals: it can be
+//! referenced from optics with `Signal("name")`, used as a feature name
+//! in LambdaMART models and is returned as part of the ranking signals.
+//!
+//! The names of the custom signals are listed in the `custom_signals` option of the
+//! configuration and reserved with [`configure_custom_signals`] before any provider is
+//! registered. The id of a custom signal is its position in that list, so it does not
+//! depend on the order in which the providers are registered. Processes that only need
+//! to parse the signals (e.g. the api) can configure the names without registering the
+//! providers. Signals are always (de)serialized by name.
+//!
+//! The search server registers a [`FastFieldSignal`] for each of the `fast_field_signals`
+//! in its configuration. Searchers resolve the providers once per query with [`providers`],
+//! so scoring a document does not take the lock of the registry.
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use thiserror::Error;
+
+use crate::{
+    config::FastFieldSignalConfig,
+    fastfield_reader::FieldReader,
+    schema::{FastField, Field},
+    webpage::region::Region,
+    Result,
+};
+
+use super::Signal;
+
+static PROVIDERS: RwLock<Vec<ConfiguredSignal>> = RwLock::new(Vec::new());
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("a signal named '{0}' already exists")]
+    DuplicateSignal(String),
+
+    #[error("too many custom signals configured")]
+    TooManySignals,
+
+    #[error("the signal '{0}' is not listed in the configured custom signals")]
+    NotConfigured(String),
+
+    #[error("unknown fast field: {0}")]
+    UnknownFastField(String),
+}
+
+/// The parts of the query that are available to a signal provider.
+pub struct SignalQuery<'a> {
+    pub terms: &'a [String],
+    pub selected_region: Option<Region>,
+}
+
+pub trait SignalProvider: Send + Sync {
+    /// Compute the value of the signal for a document. `None` means
+    /// that the signal is not available for the document.
+    fn score(&self, fastfields: &FieldReader<'_>, query: &SignalQuery<'_>) -> Option<f64>;
+
+    /// The coefficient used when neither the optic nor a model overrides it.
+    /// A coefficient of 0.0 disables the signal by default.
+    fn default_coefficient(&self) -> f64;
+}
+
+/// A signal whose value is the value of a fast field of the document.
+pub struct FastFieldSignal {
+    field: FastField,
+    default_coefficient: f64,
+}
+
+impl FastFieldSignal {
+    pub fn new(field: FastField, default_coefficient: f64) -> Self {
+        Self {
+            field,
+            default_coefficient,
+        }
+    }
+}
+
+impl SignalProvider for FastFieldSignal {
+    fn score(&self, fastfields: &FieldReader<'_>, _: &SignalQuery<'_>) -> Option<f64> {
+        Some(fastfields.get(&self.field) as f64)
+    }
+
+    fn default_coefficient(&self) -> f64 {
+        self.default_coefficient
+    }
+}
+
+struct ConfiguredSignal {
+    name: String,
+    provider: Option<Arc<dyn SignalProvider>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomSignal(u16);
+
+impl CustomSignal {
+    pub fn id(&self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PROVIDERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .position(|registered| registered.name == name)
+            .map(|id| Self(id as u16))
+    }
+
+    pub fn name(&self) -> String {
+        PROVIDERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(self.id())
+            .map(|registered| registered.name.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn provider(&self) -> Option<Arc<dyn SignalProvider>> {
+        PROVIDERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(self.id())
+            .and_then(|registered| registered.provider.clone())
+    }
+}
+
+/// Reserve ids for the custom signals in the order they are listed. Names that are
+/// already configured keep their id.
+pub fn configure_custom_signals(names: &[String]) -> Result<()> {
+    let mut providers = PROVIDERS.write().unwrap_or_else(|e| e.into_inner());
+
+    for name in names {
+        if providers.iter().any(|configured| configured.name == *name) {
+            continue;
+        }
+
+        if Signal::from_str(name).is_ok() {
+            return Err(Error::DuplicateSignal(name.to_string()).into());
+        }
+
+        if providers.len() >= u16::MAX as usize {
+            return Err(Error::TooManySignals.into());
+        }
+
+        providers.push(ConfiguredSignal {
+            name: name.to_string(),
+            provider: None,
+        });
+    }
+
+    Ok(())
+}
+
+/// Register the provider of a configured custom signal.
+pub fn register_signal_provider<P: SignalProvider + 'static>(
+    name: &str,
+    provider: P,
+) -> Result<Signal> {
+    let mut providers = PROVIDERS.write().unwrap_or_else(|e| e.into_inner());
+
+    let id = providers
+        .iter()
+        .position(|configured| configured.name == name)
+        .ok_or_else(|| Error::NotConfigured(name.to_string()))?;
+
+    if providers[id].provider.is_some() {
+        return Err(Error::DuplicateSignal(name.to_string()).into());
+    }
+
+    providers[id].provider = Some(Arc::new(provider));
+
+    tracing::info!("registered custom signal '{name}'");
+
+    Ok(Signal::Custom(CustomSignal(id as u16)))
+}
+
+/// Register a [`FastFieldSignal`] for each of the configured signals.
+pub fn register_fast_field_signals(signals: &[FastFieldSignalConfig]) -> Result<()> {
+    for signal in signals {
+        let field = Field::all()
+            .filter_map(Field::as_fast)
+            .find(|field| field.name() == signal.fast_field)
+            .ok_or_else(|| Error::UnknownFastField(signal.fast_field.clone()))?;
+
+        register_signal_provider(
+            &signal.name,
+            FastFieldSignal::new(field, signal.default_coefficient),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The providers of all configured custom signals, indexed by the id of the signal.
+pub fn providers() -> Vec<Option<Arc<dyn SignalProvider>>> {
+    PROVIDERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|configured| configured.provider.clone())
+        .collect()
+}
+
+/// All custom signals that have a registered provider, ordered by their id.
+pub fn custom_signals() -> impl Iterator<Item = Signal> {
+    PROVIDERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .enumerate()
+        .filter(|(_, configured)| configured.provider.is_some())
+        .map(|(id, _)| Signal::Custom(CustomSignal(id as u16)))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::FastField;
+
+    use super::*;
+
+    struct NumSlashes;
+
+    impl SignalProvider for NumSlashes {
+        fn score(&self, fastfields: &FieldReader<'_>, _: &SignalQuery<'_>) -> Option<f64> {
+            Some(fastfields.get(&FastField::NumPathAndQuerySlashes) as f64)
+        }
+
+        fn default_coefficient(&self) -> f64 {
+            // the registry is shared by all tests, so the signal
+            // must not change the ranking of other tests
+            0.0
+        }
+    }
+
+    #[test]
+    fn register_and_lookup() {
+        configure_custom_signals(&["test_custom_num_slashes".to_string()]).unwrap();
+        let signal = register_signal_provider("test_custom_num_slashes", NumSlashes).unwrap();
+
+        assert_eq!(Signal::from_str("test_custom_num_slashes").unwrap(), signal);
+        assert_eq!(signal.default_coefficient(), 0.0);
+        assert_eq!(
+            serde_json::to_string(&signal).unwrap(),
+            "\"test_custom_num_slashes\""
+        );
+        assert!(custom_signals().any(|s| s == signal));
+
+        assert!(usize::from(signal) >= crate::ranking::ALL_SIGNALS.len());
+        assert!(register_signal_provider("test_custom_num_slashes", NumSlashes).is_err());
+        assert!(register_signal_provider("test_custom_not_configured", NumSlashes).is_err());
+        assert!(configure_custom_signals(&["bm25_title".to_string()]).is_err());
+        assert!(Signal::from_str("test_custom_unknown").is_err());
+    }
+
+    #[test]
+    fn stable_ids() {
+        let names = vec![
+            "test_custom_stable_a".to_string(),
+            "test_custom_stable_b".to_string(),
+        ];
+        configure_custom_signals(&names).unwrap();
+
+        let b = register_signal_provider("test_custom_stable_b", NumSlashes).unwrap();
+        let a = register_signal_provider("test_custom_stable_a", NumSlashes).unwrap();
+
+        assert_eq!(usize::from(b), usize::from(a) + 1);
+
+        // configuring the same names again does not move them
+        configure_custom_signals(&names).unwrap();
+        assert_eq!(Signal::from_str("test_custom_stable_a").unwrap(), a);
+    }
+
+    #[test]
+    fn fast_field_signals_from_config() {
+        configure_custom_signals(&["test_custom_fast_field".to_string()]).unwrap();
+
+        let unknown = FastFieldSignalConfig {
+            name: "test_custom_fast_field".to_string(),
+            fast_field: "not_a_field".to_string(),
+            default_coefficient: 0.0,
+        };
+        assert!(register_fast_field_signals(&[unknown]).is_err());
+
+        let signal = FastFieldSignalConfig {
+            name: "test_custom_fast_field".to_string(),
+            fast_field: "num_path_and_query_slashes".to_string(),
+            default_coefficient: 0.0,
+        };
+        register_fast_field_signals(&[signal]).unwrap();
+
+        let custom = match Signal::from_str("test_custom_fast_field").unwrap() {
+            Signal::Custom(custom) => custom,
+            _ => panic!("expected a custom signal"),
+        };
+        assert!(providers()[custom.id()].is_some());
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        configure_custom_signals(&["test_custom_bincode".to_string()]).unwrap();
+        let custom = register_signal_provider("test_custom_bincode", NumSlashes).unwrap();
+
+        let signals = vec![Signal::Bm25Title, custom, Signal::HostCentrality];
+
+        let bytes = bincode::serialize(&signals).unwrap();
+        let res: Vec<Signal> = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(res, signals);
+
+        let json = serde_json::to_string(&signals).unwrap();
+        assert_eq!(
+            json,
+            "[\"bm25_title\",\"test_custom_bincode\",\"host_centrality\"]"
+        );
+        assert_eq!(serde_json::from_str::<Vec<Signal>>(&json).unwrap(), signals);
+    }
+}
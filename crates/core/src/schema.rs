@@ -62,6 +62,10 @@ pub enum TextField {
     InsertionTimestamp,
     RecipeFirstIngredientTagId,
     Keywords,
+    /// the spam label of the host (see `webgraph::spam::SpamLabel`)
+    HostSpamLabel,
+    /// the community of the host (see `webgraph::community::CommunityId`)
+    HostCommunity,
 }
 
 impl From<TextField> for usize {
@@ -105,6 +109,8 @@ impl TextField {
             TextField::InsertionTimestamp => 1,
             TextField::RecipeFirstIngredientTagId => 1,
             TextField::Keywords => 1,
+            TextField::HostSpamLabel => 1,
+            TextField::HostCommunity => 1,
         }
     }
 
@@ -144,6 +150,8 @@ impl TextField {
             TextField::InsertionTimestamp => TextField::InsertionTimestamp,
             TextField::RecipeFirstIngredientTagId => TextField::RecipeFirstIngredientTagId,
             TextField::Keywords => TextField::Keywords,
+            TextField::HostSpamLabel => TextField::HostSpamLabel,
+            TextField::HostCommunity => TextField::HostCommunity,
         }
     }
 
@@ -191,6 +199,8 @@ impl TextField {
             TextField::InsertionTimestamp => Tokenizer::Identity(Identity {}),
             TextField::RecipeFirstIngredientTagId => Tokenizer::Identity(Identity {}),
             TextField::Keywords => Tokenizer::default(),
+            TextField::HostSpamLabel => Tokenizer::Identity(Identity {}),
+            TextField::HostCommunity => Tokenizer::Identity(Identity {}),
         }
     }
 
@@ -236,6 +246,8 @@ impl TextField {
             TextField::InsertionTimestamp => false,
             TextField::RecipeFirstIngredientTagId => false,
             TextField::Keywords => false,
+            TextField::HostSpamLabel => false,
+            TextField::HostCommunity => false,
         }
     }
 
@@ -273,6 +285,8 @@ impl TextField {
             TextField::InsertionTimestamp => "insertion_timestamp",
             TextField::RecipeFirstIngredientTagId => "recipe_first_ingredient_tag_id",
             TextField::Keywords => "keywords",
+            TextField::HostSpamLabel => "host_spam_label",
+            TextField::HostCommunity => "host_community",
         }
     }
 }
@@ -284,6 +298,10 @@ pub enum FastField {
     HostCentralityRank,
     PageCentrality,
     PageCentralityRank,
+    HostPageRank,
+    HostPersonalizedPageRank,
+    PagePageRank,
+    HostSpamScore,
     FetchTimeMs,
     LastUpdated,
     TrackerScore,
@@ -325,6 +343,10 @@ impl FastField {
             FastField::HostCentralityRank => "host_centrality_rank",
             FastField::PageCentrality => "page_centrality",
             FastField::PageCentralityRank => "page_centrality_rank",
+            FastField::HostPageRank => "host_pagerank",
+            FastField::HostPersonalizedPageRank => "host_personalized_pagerank",
+            FastField::PagePageRank => "page_pagerank",
+            FastField::HostSpamScore => "host_spam_score",
             FastField::IsHomepage => "is_homepage",
             FastField::FetchTimeMs => "fetch_time_ms",
             FastField::LastUpdated => "last_updated",
@@ -374,7 +396,7 @@ pub enum Field {
     Text(TextField),
 }
 
-static ALL_FIELDS: [Field; 67] = [
+static ALL_FIELDS: [Field; 73] = [
     Field::Text(TextField::Title),
     Field::Text(TextField::CleanBody),
     Field::Text(TextField::StemmedTitle),
@@ -406,12 +428,18 @@ static ALL_FIELDS: [Field; 67] = [
     Field::Text(TextField::SafetyClassification),
     Field::Text(TextField::InsertionTimestamp),
     Field::Text(TextField::Keywords),
+    Field::Text(TextField::HostSpamLabel),
+    Field::Text(TextField::HostCommunity),
     // FAST FIELDS
     Field::Fast(FastField::IsHomepage),
     Field::Fast(FastField::HostCentrality),
     Field::Fast(FastField::HostCentralityRank),
     Field::Fast(FastField::PageCentrality),
     Field::Fast(FastField::PageCentralityRank),
+    Field::Fast(FastField::HostPageRank),
+    Field::Fast(FastField::HostPersonalizedPageRank),
+    Field::Fast(FastField::PagePageRank),
+    Field::Fast(FastField::HostSpamScore),
     Field::Fast(FastField::FetchTimeMs),
     Field::Fast(FastField::LastUpdated),
     Field::Fast(FastField::TrackerScore),
@@ -565,6 +593,12 @@ impl Field {
             Field::Text(TextField::Keywords) => {
                 IndexingOption::Text(self.default_text_options().set_stored())
             }
+            Field::Text(TextField::HostSpamLabel) => {
+                IndexingOption::Text(self.default_text_options())
+            }
+            Field::Text(TextField::HostCommunity) => {
+                IndexingOption::Text(self.default_text_options())
+            }
             Field::Fast(FastField::IsHomepage) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
@@ -580,6 +614,18 @@ impl Field {
             Field::Fast(FastField::PageCentralityRank) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
+            Field::Fast(FastField::HostPageRank) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::HostPersonalizedPageRank) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::PagePageRank) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
+            Field::Fast(FastField::HostSpamScore) => {
+                IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
+            }
             Field::Fast(FastField::FetchTimeMs) => {
                 IndexingOption::Integer(NumericOptions::default().set_fast().set_indexed())
             }
@@ -738,6 +784,8 @@ impl Field {
                 | Field::Text(TextField::Domain) // will match url
                 | Field::Text(TextField::InsertionTimestamp)
                 | Field::Text(TextField::RecipeFirstIngredientTagId)
+                | Field::Text(TextField::HostSpamLabel)
+                | Field::Text(TextField::HostCommunity)
         ) && !self.is_fast()
     }
 
@@ -792,6 +840,10 @@ impl FastField {
             FastField::HostCentralityRank => DataType::U64,
             FastField::PageCentrality => DataType::U64,
             FastField::PageCentralityRank => DataType::U64,
+            FastField::HostPageRank => DataType::U64,
+            FastField::HostPersonalizedPageRank => DataType::U64,
+            FastField::PagePageRank => DataType::U64,
+            FastField::HostSpamScore => DataType::U64,
             FastField::FetchTimeMs => DataType::U64,
             FastField::LastUpdated => DataType::U64,
             FastField::TrackerScore => DataType::U64,
@@ -29,9 +29,10 @@ use crate::collector::Doc;
 use crate::config::{ApiConfig, CollectorConfig};
 use crate::image_store::Image;
 use crate::inverted_index::RetrievedWebpage;
+use crate::ranking::coefficient_profiles::CoefficientProfileStore;
 use crate::ranking::models::cross_encoder::CrossEncoderModel;
 use crate::ranking::pipeline::{AsRankingWebsite, RankingWebsite, RetrievedWebpageRanking};
-use crate::ranking::ALL_SIGNALS;
+use crate::ranking::{all_signals, ALL_SIGNALS};
 use crate::search_prettifier::{DisplayedSidebar, DisplayedWebpage, HighlightedSpellCorrection};
 use crate::web_spell::SpellChecker;
 use crate::widgets::{Widget, Widgets};
@@ -126,7 +127,7 @@ pub fn add_ranking_signals(websites: &mut [DisplayedWebpage], pointers: &[Scored
     for (website, pointer) in websites.iter_mut().zip(pointers.iter()) {
         let mut signals = HashMap::with_capacity(ALL_SIGNALS.len());
 
-        for signal in ALL_SIGNALS {
+        for signal in all_signals() {
             if let Some(signal_value) = pointer.as_ranking().signals.get(signal) {
                 signals.insert(signal, *signal_value);
             }
@@ -146,6 +147,7 @@ pub struct ApiSearcher<S, L> {
     collector_config: CollectorConfig,
     widget_manager: WidgetManager,
     spell_checker: Option<SpellChecker>,
+    coefficient_profiles: Option<CoefficientProfileStore>,
 }
 
 impl<S, L> ApiSearcher<S, L>
@@ -181,6 +183,9 @@ where
             spell_checker: config
                 .spell_checker_path
                 .map(|c| SpellChecker::open(c, config.correction_config).unwrap()),
+            coefficient_profiles: config
+                .signal_coefficient_profiles_path
+                .map(|path| CoefficientProfileStore::open(path).unwrap()),
         }
     }
 
@@ -359,17 +364,25 @@ where
             return Err(distributed::Error::EmptyQuery.into());
         }
 
+        let mut query = query.clone();
+        if let Some(profiles) = &self.coefficient_profiles {
+            profiles.apply(&mut query)?;
+        }
+        let query = &query;
+
         let mut search_query = query.clone();
         let top_n = search_query.num_results;
 
         // This pipeline should be created before the first search is performed
-        // so the query knows how many results to fetch from the indices
+        // so the query knows how many results to fetch from the indices.
+        // The results are diversified once the results of all shards have been merged.
         let recall_pipeline: RankingPipeline<ScoredWebsitePointer> = RankingPipeline::recall_stage(
             &mut search_query,
             self.lambda_model.clone(),
             self.collector_config.clone(),
             top_n,
-        );
+        )
+        .with_query_diversity(query);
 
         let (initial_results, live_results) = tokio::join!(
             self.distributed_searcher.search_initial(&search_query),
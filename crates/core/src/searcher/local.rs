@@ -23,19 +23,24 @@ use crate::config::{CollectorConfig, SnippetConfig};
 use crate::index::Index;
 use crate::inverted_index::{InvertedIndex, RetrievedWebpage};
 use crate::query::Query;
+use crate::ranking::coefficient_profiles::CoefficientProfileStore;
 use crate::ranking::inbound_similarity::InboundSimilarity;
 use crate::ranking::models::lambdamart::LambdaMART;
 use crate::ranking::models::linear::LinearRegression;
 use crate::ranking::pipeline::{RankingPipeline, RankingWebsite};
-use crate::ranking::{query_centrality, Ranker, Signal, SignalAggregator, ALL_SIGNALS};
+use crate::ranking::{all_signals, query_centrality, Ranker, Signal, SignalAggregator};
 use crate::search_ctx::Ctx;
 use crate::search_prettifier::DisplayedWebpage;
+use crate::summarizer::DualEncoder;
 use crate::webgraph::Node;
 use crate::{inverted_index, live_index, Error, Result};
 
 use super::WebsitesResult;
 use super::{InitialWebsiteResult, SearchQuery};
 
+const RECALL_TOP_N: usize = 100;
+const RECIPROCAL_RANK_FUSION_K: f64 = 60.0;
+
 pub trait SearchableIndex {
     type SearchGuard<'a>: SearchGuard<'a>
     where
@@ -103,6 +108,8 @@ pub struct LocalSearcher<I: SearchableIndex> {
     inbound_similarity: Option<InboundSimilarity>,
     linear_regression: Option<Arc<LinearRegression>>,
     lambda_model: Option<Arc<LambdaMART>>,
+    dual_encoder: Option<Arc<DualEncoder>>,
+    coefficient_profiles: Option<CoefficientProfileStore>,
     collector_config: CollectorConfig,
 }
 
@@ -131,6 +138,8 @@ where
             inbound_similarity: None,
             linear_regression: None,
             lambda_model: None,
+            dual_encoder: None,
+            coefficient_profiles: None,
             collector_config: CollectorConfig::default(),
         }
     }
@@ -147,6 +156,14 @@ where
         self.lambda_model = Some(Arc::new(model));
     }
 
+    pub fn set_dual_encoder(&mut self, model: DualEncoder) {
+        self.dual_encoder = Some(Arc::new(model));
+    }
+
+    pub fn set_coefficient_profiles(&mut self, profiles: CoefficientProfileStore) {
+        self.coefficient_profiles = Some(profiles);
+    }
+
     pub fn set_collector_config(&mut self, config: CollectorConfig) {
         self.collector_config = config;
     }
@@ -218,6 +235,13 @@ where
             .with_offset(query.offset()))
     }
 
+    fn apply_coefficient_profile(&self, query: &mut SearchQuery) -> Result<()> {
+        match &self.coefficient_profiles {
+            Some(profiles) => profiles.apply(query),
+            None => Ok(()),
+        }
+    }
+
     fn search_inverted_index<'a, G: SearchGuard<'a>>(
         &'a self,
         ctx: &Ctx,
@@ -226,12 +250,22 @@ where
         de_rank_similar: bool,
     ) -> Result<InvertedIndexResult> {
         let mut query = query.clone();
-        let pipeline: RankingPipeline<RankingWebsite> = RankingPipeline::recall_stage(
+        self.apply_coefficient_profile(&mut query)?;
+
+        let mut pipeline: RankingPipeline<RankingWebsite> = RankingPipeline::recall_stage(
             &mut query,
             self.lambda_model.clone(),
             self.collector_config.clone(),
-            100,
+            RECALL_TOP_N,
         );
+
+        let use_dense_retrieval =
+            self.dual_encoder.is_some() && !guard.search_index().dense_index.is_empty();
+
+        if use_dense_retrieval {
+            pipeline = pipeline.with_rank_fusion(RECIPROCAL_RANK_FUSION_K);
+        }
+
         let parsed_query = self.parse_query(ctx, guard, &query)?;
 
         let mut aggregator = SignalAggregator::new(Some(&parsed_query));
@@ -280,13 +314,19 @@ where
 
         let fastfield_reader = guard.inverted_index().fastfield_reader();
 
-        let ranking_websites = guard.inverted_index().retrieve_ranking_websites(
+        let mut ranking_websites = guard.inverted_index().retrieve_ranking_websites(
             ctx,
             res.top_websites,
             ranker.aggregator(),
             &fastfield_reader,
         )?;
 
+        if use_dense_retrieval {
+            let dense_websites =
+                self.search_dense_index(ctx, guard, &query, &parsed_query, &ranker)?;
+            merge_dense_websites(&mut ranking_websites, dense_websites);
+        }
+
         let pipe_top_n = pipeline.top_n;
         let has_more = ranking_websites.len() > pipe_top_n;
 
@@ -299,6 +339,82 @@ where
         })
     }
 
+    fn search_dense_index<'a, G: SearchGuard<'a>>(
+        &'a self,
+        ctx: &Ctx,
+        guard: &G,
+        query: &SearchQuery,
+        parsed_query: &Query,
+        ranker: &Ranker,
+    ) -> Result<Vec<RankingWebsite>> {
+        let model = match self.dual_encoder.as_ref() {
+            Some(model) => model,
+            None => return Ok(Vec::new()),
+        };
+
+        // operators like `site:` are applied as filters below and should not be embedded
+        let text = parsed_query.simple_terms().join(" ");
+
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query_embedding = match model.embed_vectors(&[text.as_str()])?.pop() {
+            Some(embedding) => embedding,
+            None => return Ok(Vec::new()),
+        };
+
+        let inverted_index = guard.inverted_index();
+
+        // the embeddings are only needed when the results should be diversified
+        let keep_embeddings = query.diversity_lambda().is_some();
+
+        let mut pointers = Vec::new();
+        let mut similarities = Vec::new();
+        let mut embeddings = Vec::new();
+
+        for hit in guard
+            .search_index()
+            .dense_index
+            .search(&query_embedding, RECALL_TOP_N)
+        {
+            if let Some(pointer) = inverted_index.website_pointer_by_url(ctx, &hit.url)? {
+                let url = pointer.hashes.url;
+
+                pointers.push(pointer);
+                similarities.push((url, hit.similarity as f64));
+                embeddings.push((url, hit.embedding));
+            }
+        }
+
+        let pointers =
+            inverted_index.retain_matching(ctx, parsed_query.filter_query(), pointers)?;
+
+        let similarities: HashMap<_, _> = similarities.into_iter().collect();
+        let mut embeddings: HashMap<_, _> = if keep_embeddings {
+            embeddings.into_iter().collect()
+        } else {
+            HashMap::new()
+        };
+
+        let mut websites = inverted_index.retrieve_ranking_websites(
+            ctx,
+            pointers,
+            ranker.aggregator(),
+            &ctx.fastfield_reader,
+        )?;
+
+        for website in &mut websites {
+            let url = website.pointer.hashes.url;
+
+            website.lexical_match = false;
+            website.dense_similarity = similarities.get(&url).copied();
+            website.embedding = embeddings.remove(&url);
+        }
+
+        Ok(websites)
+    }
+
     pub fn search_initial(
         &self,
         query: &SearchQuery,
@@ -342,6 +458,7 @@ where
 
         let start = Instant::now();
         let mut search_query = query.clone();
+        self.apply_coefficient_profile(&mut search_query)?;
 
         let pipeline = {
             use crate::ranking::models::cross_encoder::CrossEncoderModel;
@@ -363,6 +480,7 @@ where
             }
         };
 
+        let pipeline = pipeline.with_query_diversity(&search_query);
         let search_result = self.search_initial(&search_query, true)?;
 
         let search_len = search_result.websites.len();
@@ -386,7 +504,7 @@ where
         for (webpage, ranking) in webpages.iter_mut().zip(top_websites) {
             let mut ranking_signals = HashMap::new();
 
-            for signal in ALL_SIGNALS {
+            for signal in all_signals() {
                 if let Some(score) = ranking.signals.get(signal) {
                     ranking_signals.insert(signal, *score);
                 }
@@ -412,6 +530,29 @@ where
     }
 }
 
+/// Add the websites found by the dense index to the websites found by the inverted index.
+/// Websites found by both are only included once.
+fn merge_dense_websites(websites: &mut Vec<RankingWebsite>, dense_websites: Vec<RankingWebsite>) {
+    let mut positions: HashMap<_, _> = websites
+        .iter()
+        .enumerate()
+        .map(|(i, website)| (website.pointer.hashes.url, i))
+        .collect();
+
+    for dense_website in dense_websites {
+        match positions.get(&dense_website.pointer.hashes.url) {
+            Some(i) => {
+                websites[*i].dense_similarity = dense_website.dense_similarity;
+                websites[*i].embedding = dense_website.embedding;
+            }
+            None => {
+                positions.insert(dense_website.pointer.hashes.url, websites.len());
+                websites.push(dense_website);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
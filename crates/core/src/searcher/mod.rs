@@ -58,6 +58,8 @@ pub struct SearchQuery {
     pub return_ranking_signals: bool,
     pub safe_search: bool,
     pub count_results: bool,
+    pub signal_coefficient_profile: Option<String>,
+    pub diversity: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,6 +84,8 @@ impl Default for SearchQuery {
             return_ranking_signals: defaults::SearchQuery::return_ranking_signals(),
             safe_search: defaults::SearchQuery::safe_search(),
             count_results: defaults::SearchQuery::count_results(),
+            signal_coefficient_profile: Default::default(),
+            diversity: Default::default(),
         }
     }
 }
@@ -90,4 +94,11 @@ impl SearchQuery {
     pub fn is_empty(&self) -> bool {
         self.query.is_empty()
     }
+
+    /// The lambda used to diversify the results. The lambda set on the request
+    /// takes precedence over the one set in the optic.
+    pub fn diversity_lambda(&self) -> Option<f64> {
+        self.diversity
+            .or_else(|| self.optic.as_ref().and_then(|optic| optic.diversity))
+    }
 }
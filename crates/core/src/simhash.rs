@@ -59,10 +59,17 @@ pub fn hash(text: &str) -> HashType {
     simhash
 }
 
-fn hamming_distance(x: HashType, y: HashType) -> u32 {
+pub fn hamming_distance(x: HashType, y: HashType) -> u32 {
     (x ^ y).count_ones()
 }
 
+/// Calculate similarity as `f64` of two hashes
+/// 0.0 means no similarity, 1.0 means identical
+pub fn similarity(hash1: HashType, hash2: HashType) -> f64 {
+    let distance: f64 = hamming_distance(hash1, hash2) as f64;
+    1.0 - (distance / (HashType::BITS as f64))
+}
+
 #[derive(PartialEq, Eq, Hash)]
 struct Prefix(HashType);
 
@@ -135,13 +142,6 @@ impl Table {
 mod tests {
     use super::*;
 
-    /// Calculate similarity as `f64` of two hashes
-    /// 0.0 means no similarity, 1.0 means identical
-    pub fn similarity(hash1: HashType, hash2: HashType) -> f64 {
-        let distance: f64 = hamming_distance(hash1, hash2) as f64;
-        1.0 - (distance / (HashType::BITS as f64))
-    }
-
     #[test]
     fn simhash_test() {
         assert_eq!(hash("The cat sat on the mat"), 1696787384511938835);
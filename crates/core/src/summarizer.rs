@@ -373,6 +373,14 @@ impl DualEncoder {
 
         Ok(emb)
     }
+
+    /// Embed the texts and return the embeddings as plain vectors.
+    pub fn embed_vectors(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        Ok(self
+            .embed(texts)?
+            .to_dtype(candle_core::DType::F32)?
+            .to_vec2()?)
+    }
 }
 
 impl PassageScorer for DualEncoder {
@@ -16,6 +16,7 @@
 
 use crate::distributed::retry_strategy::ExponentialBackoff;
 use crate::{config::S3Config, config::WarcSource, Error, Result};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fs::File;
@@ -34,6 +35,10 @@ use proptest_derive::Arbitrary;
 
 use tracing::{debug, trace};
 
+/// Profile of the revisit records written for `304 Not Modified` responses.
+const REVISIT_NOT_MODIFIED_PROFILE: &str =
+    "http://netpreserve.org/warc/1.1/revisit/server-not-modified";
+
 pub struct WarcFile {
     bytes: Vec<u8>,
 }
@@ -241,7 +246,10 @@ impl Request {
 #[cfg_attr(test, derive(Arbitrary))]
 pub enum PayloadType {
     Html,
+    /// The body of pdf responses is stored as the raw bytes of the document.
     Pdf,
+    Text,
+    Markdown,
     Rss,
     Atom,
 }
@@ -254,6 +262,9 @@ impl FromStr for PayloadType {
             "application/html" => Ok(Self::Html),
             "text/html" => Ok(Self::Html),
             "application/pdf" => Ok(Self::Pdf),
+            "text/plain" => Ok(Self::Text),
+            "text/markdown" => Ok(Self::Markdown),
+            "text/x-markdown" => Ok(Self::Markdown),
             "application/rss" => Ok(Self::Rss),
             "application/rss+xml" => Ok(Self::Rss),
             "application/atom" => Ok(Self::Atom),
@@ -263,11 +274,20 @@ impl FromStr for PayloadType {
     }
 }
 
+impl PayloadType {
+    /// Binary payloads are stored as-is. Other payloads are decoded to utf-8 when read.
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Pdf)
+    }
+}
+
 impl Display for PayloadType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
             Self::Html => "text/html",
             Self::Pdf => "application/pdf",
+            Self::Text => "text/plain",
+            Self::Markdown => "text/markdown",
             Self::Rss => "application/rss",
             Self::Atom => "application/atom",
         };
@@ -278,25 +298,42 @@ impl Display for PayloadType {
 #[derive(Debug)]
 #[cfg_attr(test, derive(Clone, Arbitrary, PartialEq))]
 pub struct Response {
-    pub body: String,
+    #[cfg_attr(
+        test,
+        proptest(
+            strategy = "proptest::strategy::Strategy::prop_map(proptest::prelude::any::<String>(), String::into_bytes)"
+        )
+    )]
+    pub body: Vec<u8>,
     pub payload_type: Option<PayloadType>,
 }
 
 impl Response {
     fn from_raw(record: RawWarcRecord) -> Result<Self> {
-        let content = decode(&record.content[..]);
+        let payload_type = record
+            .header
+            .get("WARC-IDENTIFIED-PAYLOAD-TYPE")
+            .and_then(|p| PayloadType::from_str(p).ok());
+
+        let body_start = record
+            .content
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or(Error::WarcParse("Invalid http body".to_string()))?
+            + 4;
+        let content = &record.content[body_start..];
+
+        let body = match &payload_type {
+            Some(payload_type) if payload_type.is_binary() => content.to_vec(),
+            _ => decode(content).into_bytes(),
+        };
 
-        let (_header, content) = content
-            .split_once("\r\n\r\n")
-            .ok_or(Error::WarcParse("Invalid http body".to_string()))?;
+        Ok(Self { body, payload_type })
+    }
 
-        Ok(Self {
-            body: content.to_string(),
-            payload_type: record
-                .header
-                .get("WARC-IDENTIFIED-PAYLOAD-TYPE")
-                .and_then(|p| PayloadType::from_str(p).ok()),
-        })
+    /// The body decoded as utf-8. Binary payloads are lossily converted.
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
     }
 }
 
@@ -441,6 +478,7 @@ impl<R: Read> Iterator for RecordIterator<R> {
         let mut request = None;
         let mut response = None;
         let mut metadata = None;
+        let mut not_modified = false;
 
         while let Some(item) = self.next_raw() {
             if item.is_err() {
@@ -471,6 +509,13 @@ impl<R: Read> Iterator for RecordIterator<R> {
                         }
                     }
 
+                    if item.header.get("WARC-PROFILE").map(String::as_str)
+                        == Some(REVISIT_NOT_MODIFIED_PROFILE)
+                    {
+                        not_modified = true;
+                        continue;
+                    }
+
                     if response.is_some() {
                         return Some(Err(Error::WarcParse(
                             "Already have a response but got another.".to_string(),
@@ -509,6 +554,14 @@ impl<R: Read> Iterator for RecordIterator<R> {
                 }
             }
 
+            if request.is_some() && not_modified && metadata.is_some() {
+                // the record of a `304 Not Modified` response has no payload
+                request = None;
+                metadata = None;
+                not_modified = false;
+                continue;
+            }
+
             if request.is_some() && response.is_some() && metadata.is_some() {
                 break;
             }
@@ -552,6 +605,17 @@ impl DeduplicatedWarcWriter {
         self.writer.write(record)
     }
 
+    pub fn write_revisit(&mut self, url: &str, metadata: &Metadata) -> Result<()> {
+        let url_hash = md5::compute(url);
+        if self.seen_url_hashes.contains(&url_hash) {
+            return Ok(());
+        }
+
+        self.seen_url_hashes.insert(url_hash);
+
+        self.writer.write_revisit(url, metadata)
+    }
+
     pub fn finish(self) -> Result<Vec<u8>> {
         self.writer.finish()
     }
@@ -598,16 +662,69 @@ impl WarcWriter {
         }
     }
 
-    pub fn write(&mut self, record: &WarcRecord) -> Result<()> {
+    fn write_request(&mut self, url: &str) -> Result<()> {
         self.writer.write_all("WARC/1.0\r\n".as_bytes())?;
 
         self.writer.write_all("WARC-Type: request\r\n".as_bytes())?;
         self.writer
-            .write_all(format!("WARC-Target-URI: {}\r\n", record.request.url).as_bytes())?;
+            .write_all(format!("WARC-Target-URI: {}\r\n", url).as_bytes())?;
         self.writer.write_all("Content-Length: 0\r\n".as_bytes())?;
         self.writer.write_all("\r\n".as_bytes())?;
         self.writer.write_all("\r\n\r\n".as_bytes())?;
 
+        Ok(())
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.writer.write_all("WARC/1.0\r\n".as_bytes())?;
+        self.writer
+            .write_all("WARC-Type: metadata\r\n".as_bytes())?;
+
+        let body = format!("fetchTimeMs: {}", metadata.fetch_time_ms);
+        let content_len = body.len();
+
+        self.writer
+            .write_all(format!("Content-Length: {content_len}\r\n").as_bytes())?;
+        self.writer.write_all("\r\n".as_bytes())?;
+        self.writer.write_all(body.as_bytes())?;
+        self.writer.write_all("\r\n\r\n".as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Record that the server responded with `304 Not Modified` to a conditional request
+    /// for the url. Revisit records have no payload and are skipped by the [`RecordIterator`].
+    pub fn write_revisit(&mut self, url: &str, metadata: &Metadata) -> Result<()> {
+        self.write_request(url)?;
+
+        let content = "HTTP/1.1 304 Not Modified\r\n\r\n";
+
+        self.writer.write_all("WARC/1.0\r\n".as_bytes())?;
+        self.writer.write_all("WARC-Type: revisit\r\n".as_bytes())?;
+        self.writer
+            .write_all(format!("WARC-Target-URI: {}\r\n", url).as_bytes())?;
+        self.writer
+            .write_all(format!("WARC-Profile: {REVISIT_NOT_MODIFIED_PROFILE}\r\n").as_bytes())?;
+        self.writer
+            .write_all("Content-Type: application/http; msgtype=response\r\n".as_bytes())?;
+        self.writer
+            .write_all(format!("Content-Length: {}\r\n", content.len()).as_bytes())?;
+        self.writer.write_all("\r\n".as_bytes())?;
+        self.writer.write_all(content.as_bytes())?;
+        self.writer.write_all("\r\n\r\n".as_bytes())?;
+
+        self.write_metadata(metadata)?;
+
+        self.writer.flush().unwrap();
+
+        self.num_writes += 1;
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, record: &WarcRecord) -> Result<()> {
+        self.write_request(&record.request.url)?;
+
         self.writer.write_all("WARC/1.0\r\n".as_bytes())?;
         self.writer
             .write_all("WARC-Type: response\r\n".as_bytes())?;
@@ -618,7 +735,7 @@ impl WarcWriter {
             )?;
         }
 
-        let body = record.response.body.as_bytes();
+        let body = &record.response.body[..];
         let content_len = body.len() + 4; // +4 is for the \r\n\r\n between http header and body
         self.writer
             .write_all(format!("Content-Length: {content_len}\r\n").as_bytes())?;
@@ -630,18 +747,7 @@ impl WarcWriter {
         self.writer.write_all(body)?;
         self.writer.write_all("\r\n\r\n".as_bytes())?;
 
-        self.writer.write_all("WARC/1.0\r\n".as_bytes())?;
-        self.writer
-            .write_all("WARC-Type: metadata\r\n".as_bytes())?;
-
-        let body = format!("fetchTimeMs: {}", record.metadata.fetch_time_ms);
-        let content_len = body.len();
-
-        self.writer
-            .write_all(format!("Content-Length: {content_len}\r\n").as_bytes())?;
-        self.writer.write_all("\r\n".as_bytes())?;
-        self.writer.write_all(body.as_bytes())?;
-        self.writer.write_all("\r\n\r\n".as_bytes())?;
+        self.write_metadata(&record.metadata)?;
 
         self.writer.flush().unwrap();
 
@@ -719,7 +825,7 @@ mod tests {
 
         assert_eq!(records.len(), 1);
         assert_eq!(&records[0].request.url, "http://0575ls.cn/news-52300.htm");
-        assert_eq!(&records[0].response.body, "body of response");
+        assert_eq!(&records[0].response.body, b"body of response");
         assert_eq!(records[0].metadata.fetch_time_ms, 937);
     }
 
@@ -751,7 +857,7 @@ mod tests {
                 url: "https://a.com".to_string(),
             },
             response: Response {
-                body: "body of a".to_string(),
+                body: b"body of a".to_vec(),
                 payload_type: Some(PayloadType::Html),
             },
             metadata: Metadata {
@@ -765,7 +871,7 @@ mod tests {
                 url: "https://b.com".to_string(),
             },
             response: Response {
-                body: "body of b".to_string(),
+                body: b"body of b".to_vec(),
                 payload_type: None,
             },
             metadata: Metadata {
@@ -783,11 +889,11 @@ mod tests {
 
         assert_eq!(records.len(), 2);
         assert_eq!(&records[0].request.url, "https://a.com");
-        assert_eq!(&records[0].response.body, "body of a");
+        assert_eq!(&records[0].response.body, b"body of a");
         assert_eq!(records[0].metadata.fetch_time_ms, 1337);
 
         assert_eq!(&records[1].request.url, "https://b.com");
-        assert_eq!(&records[1].response.body, "body of b");
+        assert_eq!(&records[1].response.body, b"body of b");
         assert_eq!(records[1].metadata.fetch_time_ms, 4242);
     }
 
@@ -801,7 +907,7 @@ mod tests {
                 url: "https://a.com".to_string(),
             },
             response: Response {
-                body: utf8.to_string(),
+                body: utf8.as_bytes().to_vec(),
                 payload_type: Some(PayloadType::Html),
             },
             metadata: Metadata { fetch_time_ms: 0 },
@@ -816,7 +922,7 @@ mod tests {
 
         assert_eq!(records.len(), 1);
         assert_eq!(&records[0].request.url, "https://a.com");
-        assert_eq!(&records[0].response.body, utf8);
+        assert_eq!(records[0].response.text(), utf8);
         assert_eq!(records[0].metadata.fetch_time_ms, 0);
     }
 
@@ -833,7 +939,7 @@ mod tests {
                 url: "https://a.com".to_string(),
             },
             response: Response {
-                body: body.to_string(),
+                body: body.as_bytes().to_vec(),
                 payload_type: Some(PayloadType::Html),
             },
             metadata: Metadata { fetch_time_ms: 0 },
@@ -848,10 +954,77 @@ mod tests {
 
         assert_eq!(records.len(), 1);
         assert_eq!(&records[0].request.url, "https://a.com");
-        assert_eq!(&records[0].response.body, body);
+        assert_eq!(records[0].response.text(), body);
         assert_eq!(records[0].metadata.fetch_time_ms, 0);
     }
 
+    #[test]
+    fn skip_revisits() {
+        let record = |url: &str| WarcRecord {
+            request: Request {
+                url: url.to_string(),
+            },
+            response: Response {
+                body: format!("body of {url}").into_bytes(),
+                payload_type: Some(PayloadType::Html),
+            },
+            metadata: Metadata { fetch_time_ms: 42 },
+        };
+
+        let mut writer = WarcWriter::new();
+        writer
+            .write_revisit("https://a.com", &Metadata { fetch_time_ms: 1 })
+            .unwrap();
+        writer.write(&record("https://b.com")).unwrap();
+        writer
+            .write_revisit("https://c.com", &Metadata { fetch_time_ms: 1 })
+            .unwrap();
+        writer.write(&record("https://d.com")).unwrap();
+
+        assert_eq!(writer.num_writes(), 4);
+
+        let compressed = writer.finish().unwrap();
+        let records: Vec<WarcRecord> = WarcFile::new(compressed)
+            .records()
+            .map(|res| res.unwrap())
+            .collect();
+
+        assert_eq!(
+            records,
+            vec![record("https://b.com"), record("https://d.com")]
+        );
+    }
+
+    #[test]
+    fn writer_binary() {
+        let body = vec![
+            0x25, 0x50, 0x44, 0x46, 0xff, 0xfe, 0x00, 0x0d, 0x0a, 0x0d, 0x0a, 0x80,
+        ];
+
+        let mut writer = WarcWriter::new();
+        let record = WarcRecord {
+            request: Request {
+                url: "https://a.com/doc.pdf".to_string(),
+            },
+            response: Response {
+                body: body.clone(),
+                payload_type: Some(PayloadType::Pdf),
+            },
+            metadata: Metadata { fetch_time_ms: 0 },
+        };
+        writer.write(&record).unwrap();
+
+        let compressed = writer.finish().unwrap();
+        let records: Vec<WarcRecord> = WarcFile::new(compressed)
+            .records()
+            .map(|res| res.unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].response.body, body);
+        assert_eq!(records[0].response.payload_type, Some(PayloadType::Pdf));
+    }
+
     proptest! {
         #[test]
         fn write_read_invariant_prop(records: Vec<WarcRecord>) {
@@ -0,0 +1,379 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The anchor text of the links to each page in the page graph.
+//!
+//! The labels of all the edges are read in a single pass over the graph and sorted by their
+//! target, so the texts of each page can be aggregated one page at a time. A text is counted
+//! once per source host, so a host that links to a page from all of its pages does not decide
+//! the anchor text of the page on its own. Nofollow, ugc and sponsored links, links from spam
+//! hosts and texts that do not describe the target (e.g. "click here") are skipped.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use tracing::info;
+
+use crate::{
+    external_sort::ExternalSorter,
+    kv::{rocksdb_store::RocksDbStore, Kv},
+};
+
+use super::{
+    spam::{SpamLabel, SpamScores},
+    NodeID, Webgraph,
+};
+
+/// A text is repeated at most this many times when the page is indexed.
+const MAX_REPETITIONS: u64 = 8;
+
+/// Texts longer than this are truncated.
+const MAX_TEXT_LENGTH: usize = 128;
+
+/// Only this many characters of anchor text are stored for each page.
+/// The texts used by the most hosts are kept.
+const MAX_TOTAL_LENGTH: usize = 2_048;
+
+/// Anchor texts that say nothing about the page they link to.
+const STOPWORDS: [&str; 16] = [
+    "click",
+    "click here",
+    "here",
+    "link",
+    "website",
+    "webpage",
+    "page",
+    "site",
+    "url",
+    "web",
+    "visit",
+    "more",
+    "info",
+    "information",
+    "read",
+    "read more",
+];
+
+/// Whether the anchor text describes the page it links to.
+pub fn is_descriptive(text: &str) -> bool {
+    !text.is_empty() && !STOPWORDS.contains(&text.to_lowercase().as_str())
+}
+
+/// Collapse the whitespace of the text and truncate it.
+fn normalize(text: &str) -> String {
+    itertools::intersperse(text.split_whitespace(), " ")
+        .collect::<String>()
+        .chars()
+        .take(MAX_TEXT_LENGTH)
+        .collect()
+}
+
+/// An anchor text of the links to a page and the number of hosts that use it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AnchorText {
+    pub text: String,
+    pub num_hosts: u64,
+}
+
+impl AnchorText {
+    /// How many times the text is repeated in the indexed backlink text, so texts used by
+    /// more hosts weigh more. Grows with the logarithm of the number of hosts.
+    pub fn repetitions(&self) -> usize {
+        (1 + u64::from(self.num_hosts.max(1).ilog2())).min(MAX_REPETITIONS) as usize
+    }
+}
+
+/// The texts of a page ordered by the number of hosts that use them, truncated to
+/// [`MAX_TOTAL_LENGTH`] characters in total.
+fn aggregate(mut texts: Vec<AnchorText>) -> Vec<AnchorText> {
+    texts.sort_by(|a, b| b.num_hosts.cmp(&a.num_hosts).then(a.text.cmp(&b.text)));
+
+    let mut total_length = 0;
+
+    texts
+        .into_iter()
+        .take_while(|text| {
+            total_length += text.text.chars().count();
+            total_length <= MAX_TOTAL_LENGTH
+        })
+        .collect()
+}
+
+/// The aggregated anchor text of each page in the page graph.
+pub struct AnchorTextStore {
+    inner: RocksDbStore<NodeID, Vec<AnchorText>>,
+}
+
+impl AnchorTextStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            inner: RocksDbStore::open(path),
+        }
+    }
+
+    /// Aggregate the anchor texts of the page graph. Links from the hosts that are labelled
+    /// as spam by `spam` are skipped.
+    pub fn build<P: AsRef<Path>>(
+        graph: &Webgraph,
+        spam: Option<&SpamScores>,
+        output: P,
+    ) -> Result<Self> {
+        if output.as_ref().exists() {
+            return Err(anyhow!("output path already exists"));
+        }
+
+        // the edges are ordered by their source, so the host of the previous edge can be reused
+        let mut last_source: Option<(NodeID, Option<NodeID>)> = None;
+
+        let mut source_host = |source: &NodeID| -> Option<NodeID> {
+            match last_source {
+                Some((node, host)) if node == *source => host,
+                _ => {
+                    let host = graph
+                        .id2node(source)
+                        .map(|node| node.into_host().id())
+                        .filter(|host| {
+                            spam.map(|spam| {
+                                SpamLabel::from_score(spam.get(host)) != SpamLabel::Spam
+                            })
+                            .unwrap_or(true)
+                        });
+
+                    last_source = Some((*source, host));
+                    host
+                }
+            }
+        };
+
+        // (target, lowercase text, source host, text)
+        let records = graph
+            .edges_with_label()
+            .filter(|edge| !edge.label.rel.is_qualified())
+            .filter_map(|edge| {
+                let text = normalize(&edge.label.text);
+
+                if !is_descriptive(&text) {
+                    return None;
+                }
+
+                let host = source_host(&edge.from)?;
+
+                Some((edge.to, text.to_lowercase(), host, text))
+            });
+
+        let inner = RocksDbStore::open(output.as_ref());
+
+        let mut current: Option<NodeID> = None;
+        let mut texts: Vec<AnchorText> = Vec::new();
+        let mut last: Option<(String, NodeID)> = None;
+        let mut num_pages = 0;
+
+        for (target, key, host, text) in ExternalSorter::new()
+            .with_chunk_size(10_000_000)
+            .sort(records)?
+        {
+            if current != Some(target) {
+                if let Some(page) = current {
+                    inner.insert(page, aggregate(std::mem::take(&mut texts)));
+                    num_pages += 1;
+                }
+
+                current = Some(target);
+                last = None;
+            }
+
+            match &last {
+                Some((last_key, _)) if *last_key != key => {
+                    texts.push(AnchorText { text, num_hosts: 1 });
+                }
+                None => {
+                    texts.push(AnchorText { text, num_hosts: 1 });
+                }
+                Some((_, last_host)) if *last_host != host => {
+                    texts.last_mut().unwrap().num_hosts += 1;
+                }
+                Some(_) => {}
+            }
+
+            last = Some((key, host));
+        }
+
+        if let Some(page) = current {
+            inner.insert(page, aggregate(texts));
+            num_pages += 1;
+        }
+
+        inner.flush();
+
+        info!("Aggregated the anchor text of {num_pages} pages");
+
+        Ok(Self { inner })
+    }
+
+    /// The anchor texts of the page ordered by the number of hosts that use them.
+    pub fn get(&self, node: &NodeID) -> Vec<AnchorText> {
+        self.inner.get(node).unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, Vec<AnchorText>)> + '_ {
+        self.inner.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{EdgeLabel, Node, WebgraphWriter};
+    use crate::webpage::RelFlags;
+
+    fn test_graph() -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to, text) in [
+            ("a.com/1", "c.com", "Example  site"),
+            ("a.com/2", "c.com", "example site"),
+            ("b.com/1", "c.com", "Example site"),
+            ("b.com/1", "c.com/page", "Click here"),
+            ("b.com/2", "c.com", "another text"),
+            ("spam.com/1", "c.com", "cheap pills"),
+        ] {
+            writer.insert(Node::from(from), Node::from(to), text.to_string());
+        }
+
+        for (from, text, rel) in [
+            (
+                "d.com",
+                "sponsored text",
+                RelFlags {
+                    sponsored: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "e.com",
+                "nofollow text",
+                RelFlags {
+                    nofollow: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "f.com",
+                "ugc text",
+                RelFlags {
+                    ugc: true,
+                    ..Default::default()
+                },
+            ),
+        ] {
+            writer.insert(
+                Node::from(from),
+                Node::from("c.com"),
+                EdgeLabel {
+                    text: text.to_string(),
+                    rel,
+                    ..Default::default()
+                },
+            );
+        }
+
+        writer.finalize()
+    }
+
+    #[test]
+    fn dedup_by_host() {
+        let graph = test_graph();
+        let store = AnchorTextStore::build(&graph, None, crate::gen_temp_path()).unwrap();
+
+        let texts = store.get(&Node::from("c.com").id());
+
+        assert_eq!(
+            texts,
+            vec![
+                AnchorText {
+                    text: "Example site".to_string(),
+                    num_hosts: 2,
+                },
+                AnchorText {
+                    text: "another text".to_string(),
+                    num_hosts: 1,
+                },
+                AnchorText {
+                    text: "cheap pills".to_string(),
+                    num_hosts: 1,
+                },
+            ]
+        );
+
+        assert!(store.get(&Node::from("c.com/page").id()).is_empty());
+    }
+
+    #[test]
+    fn skip_spam_hosts() {
+        let graph = test_graph();
+
+        let path = crate::gen_temp_path();
+        {
+            let scores: RocksDbStore<NodeID, f64> = RocksDbStore::open(&path);
+            scores.insert(Node::from("spam.com").id(), 1.0);
+            scores.flush();
+        }
+        let spam = SpamScores::open(&path);
+
+        let store = AnchorTextStore::build(&graph, Some(&spam), crate::gen_temp_path()).unwrap();
+
+        let texts = store.get(&Node::from("c.com").id());
+
+        assert_eq!(texts.len(), 2);
+        assert!(texts.iter().all(|text| text.text != "cheap pills"));
+    }
+
+    #[test]
+    fn repetitions() {
+        let repetitions = |num_hosts| {
+            AnchorText {
+                text: String::new(),
+                num_hosts,
+            }
+            .repetitions()
+        };
+
+        assert_eq!(repetitions(0), 1);
+        assert_eq!(repetitions(1), 1);
+        assert_eq!(repetitions(2), 2);
+        assert_eq!(repetitions(5), 3);
+        assert_eq!(repetitions(u64::MAX), MAX_REPETITIONS as usize);
+    }
+
+    #[test]
+    fn capped_length() {
+        let texts: Vec<_> = (0..100)
+            .map(|i| AnchorText {
+                text: format!("{i:0>100}"),
+                num_hosts: i,
+            })
+            .collect();
+
+        let texts = aggregate(texts);
+
+        assert_eq!(texts.len(), MAX_TOTAL_LENGTH / 100);
+        assert_eq!(texts[0].num_hosts, 99);
+    }
+}
@@ -27,12 +27,34 @@ use tracing::info;
 use crate::{
     hyperloglog::HyperLogLog,
     kahan_sum::KahanSum,
-    webgraph::{NodeID, Webgraph},
+    webgraph::{Edge, NodeID, Webgraph},
 };
 
+use super::LinkWeights;
+
 const HYPERLOGLOG_COUNTERS: usize = 64;
 
-fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
+/// The links with a weight of 0, which are not followed when computing the distances.
+/// They are found in a single pass over the labelled edges, so the iterations only
+/// need to read the raw edges.
+fn ignored_edges(graph: &Webgraph, weights: &LinkWeights) -> BTreeSet<(NodeID, NodeID)> {
+    if *weights == LinkWeights::UNIFORM {
+        return BTreeSet::new();
+    }
+
+    graph
+        .edges_with_label()
+        .filter(|edge| weights.weight(&edge.label) <= 0.0)
+        .map(|edge| (edge.from, edge.to))
+        .collect()
+}
+
+fn is_followed(edge: &Edge<()>, ignored: &BTreeSet<(NodeID, NodeID)>) -> bool {
+    ignored.is_empty() || !ignored.contains(&(edge.from, edge.to))
+}
+
+fn calculate_centrality(graph: &Webgraph, weights: &LinkWeights) -> BTreeMap<NodeID, f64> {
+    let ignored = ignored_edges(graph, weights);
     let mut num_nodes = 0;
 
     let mut counters: BTreeMap<NodeID, HyperLogLog<HYPERLOGLOG_COUNTERS>> = BTreeMap::new();
@@ -81,7 +103,11 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
             let mut new_exact_changed_nodes = BTreeSet::default();
 
             exact_changed_nodes.iter().for_each(|changed_node| {
-                for edge in graph.raw_outgoing_edges(changed_node) {
+                for edge in graph
+                    .raw_outgoing_edges(changed_node)
+                    .into_iter()
+                    .filter(|edge| is_followed(edge, &ignored))
+                {
                     if let (Some(counter_to), Some(counter_from)) =
                         (new_counters.get_mut(&edge.to), counters.get(&edge.from))
                     {
@@ -106,7 +132,7 @@ fn calculate_centrality(graph: &Webgraph) -> BTreeMap<NodeID, f64> {
         } else {
             exact_changed_nodes = BTreeSet::default();
             graph.edges().for_each(|edge| {
-                if changed_nodes.contains(&edge.from.as_u64()) {
+                if changed_nodes.contains(&edge.from.as_u64()) && is_followed(&edge, &ignored) {
                     if let (Some(counter_to), Some(counter_from)) =
                         (new_counters.get_mut(&edge.to), counters.get(&edge.from))
                     {
@@ -177,7 +203,12 @@ pub struct HarmonicCentrality(BTreeMap<NodeID, f64>);
 
 impl HarmonicCentrality {
     pub fn calculate(graph: &Webgraph) -> Self {
-        Self(calculate_centrality(graph))
+        Self::with_weights(graph, &LinkWeights::default())
+    }
+
+    /// Harmonic centrality where the links with a weight of 0 are ignored.
+    pub fn with_weights(graph: &Webgraph, weights: &LinkWeights) -> Self {
+        Self(calculate_centrality(graph, weights))
     }
 
     pub fn get(&self, node: &NodeID) -> Option<f64> {
@@ -193,6 +224,7 @@ impl HarmonicCentrality {
 mod tests {
     use super::*;
     use crate::webgraph::{Node, WebgraphWriter};
+    use crate::webpage::RelFlags;
 
     fn test_edges() -> Vec<(Node, Node, String)> {
         //     ┌────┐
@@ -369,4 +401,39 @@ mod tests {
 
         assert_eq!(centrality.0, centrality_extra.0);
     }
+
+    #[test]
+    fn nofollow_links_ignored() {
+        let graph = test_graph();
+        let centrality = HarmonicCentrality::calculate(&graph);
+
+        let mut other = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to, label) in test_edges() {
+            other.insert(from, to, label);
+        }
+
+        other.insert(
+            Node::from("C"),
+            Node::from("D"),
+            EdgeLabel {
+                rel: RelFlags {
+                    nofollow: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        let other = other.finalize();
+
+        assert_eq!(HarmonicCentrality::calculate(&other).0, centrality.0);
+
+        let uniform = HarmonicCentrality::with_weights(&other, &LinkWeights::UNIFORM);
+        assert!(uniform.get(&Node::from("D").id()).unwrap() > 0.0);
+    }
 }
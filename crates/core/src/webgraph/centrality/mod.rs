@@ -16,12 +16,54 @@
 
 use crate::kv::{rocksdb_store::RocksDbStore, Kv};
 
-use super::NodeID;
+use super::{EdgeLabel, NodeID};
 
 pub mod approx_harmonic;
 pub mod betweenness;
 pub mod derived_harmonic;
 pub mod harmonic;
+pub mod pagerank;
+
+/// How much a link counts in the centrality calculations, depending on its label.
+/// Centralities that cannot weight their edges ignore the links with a weight of 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkWeights {
+    /// Weight of links with `rel="nofollow"`, `rel="sponsored"` or `rel="ugc"`.
+    pub qualified: f64,
+    /// Weight of links in the navigation or footer of the page.
+    pub boilerplate: f64,
+}
+
+impl Default for LinkWeights {
+    fn default() -> Self {
+        Self {
+            qualified: 0.0,
+            boilerplate: 0.5,
+        }
+    }
+}
+
+impl LinkWeights {
+    /// All links count the same, so the labels of the edges are not needed.
+    pub const UNIFORM: Self = Self {
+        qualified: 1.0,
+        boilerplate: 1.0,
+    };
+
+    pub fn weight(&self, label: &EdgeLabel) -> f64 {
+        let mut weight = 1.0;
+
+        if label.rel.is_qualified() {
+            weight *= self.qualified;
+        }
+
+        if label.position.is_boilerplate() {
+            weight *= self.boilerplate;
+        }
+
+        weight
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum TopHosts {
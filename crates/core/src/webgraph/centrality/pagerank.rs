@@ -0,0 +1,431 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! PageRank and personalised PageRank by power iteration.
+//!
+//! Only the scores of the nodes are kept in memory. The outgoing edges of every node
+//! are read from the segments in each iteration, so the edges never have to fit in memory.
+//! Personalised PageRank teleports to a set of seed nodes instead of to all nodes,
+//! which ranks nodes by how close they are to the seeds.
+//! The random surfer follows the links of a node proportionally to their [`LinkWeights`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use tracing::info;
+
+use crate::{
+    kahan_sum::KahanSum,
+    webgraph::{NodeID, Webgraph},
+};
+
+use super::LinkWeights;
+
+/// Probability of following a link instead of teleporting.
+const DAMPING: f64 = 0.85;
+const MAX_ITERATIONS: usize = 50;
+/// The iteration stops when the L1 distance between two iterations is below this.
+const TOLERANCE: f64 = 1e-6;
+
+/// The edges the random surfer follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Outgoing,
+    /// Follow the edges backwards. This propagates scores from a node to the nodes linking to it,
+    /// which is what anti-TrustRank uses to find the hosts that link to known spam.
+    Ingoing,
+}
+
+/// Where the random surfer teleports to.
+#[derive(Debug, Clone)]
+pub enum Teleport {
+    /// Teleport to all nodes with equal probability.
+    Uniform,
+    /// Teleport to the seeds with equal probability. Seeds that are not in the graph are ignored
+    /// and if none of the seeds are in the graph, this is the same as [`Teleport::Uniform`].
+    Seeds(BTreeSet<NodeID>),
+}
+
+/// The neighbours of the node in the direction together with the weight of the edge.
+/// Edges with a weight of 0 are left out.
+pub(crate) fn edges(
+    graph: &Webgraph,
+    node: &NodeID,
+    direction: Direction,
+    weights: &LinkWeights,
+) -> Vec<(NodeID, f64)> {
+    if *weights == LinkWeights::UNIFORM {
+        return match direction {
+            Direction::Outgoing => graph
+                .raw_outgoing_edges(node)
+                .into_iter()
+                .map(|edge| (edge.to, 1.0))
+                .collect(),
+            Direction::Ingoing => graph
+                .raw_ingoing_edges(node)
+                .into_iter()
+                .map(|edge| (edge.from, 1.0))
+                .collect(),
+        };
+    }
+
+    let edges = match direction {
+        Direction::Outgoing => graph
+            .raw_outgoing_edges_with_labels(node)
+            .into_iter()
+            .map(|edge| (edge.to, weights.weight(&edge.label)))
+            .collect::<Vec<_>>(),
+        Direction::Ingoing => graph
+            .raw_ingoing_edges_with_labels(node)
+            .into_iter()
+            .map(|edge| (edge.from, weights.weight(&edge.label)))
+            .collect(),
+    };
+
+    edges
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0.0)
+        .collect()
+}
+
+/// Run the power iteration and return the raw (un-normalised) scores of the nodes.
+pub fn power_iteration(
+    graph: &Webgraph,
+    direction: Direction,
+    teleport: Teleport,
+    weights: &LinkWeights,
+) -> BTreeMap<NodeID, f64> {
+    let mut out_degrees: BTreeMap<NodeID, f64> = BTreeMap::new();
+
+    for node in graph.nodes() {
+        let out_degree = edges(graph, &node, direction, weights)
+            .into_iter()
+            .map(|(_, weight)| weight)
+            .sum();
+
+        out_degrees.insert(node, out_degree);
+    }
+
+    let num_nodes = out_degrees.len();
+    info!("Found {} nodes in the graph", num_nodes);
+
+    if num_nodes == 0 {
+        return BTreeMap::new();
+    }
+
+    let teleport = match teleport {
+        Teleport::Uniform => Teleport::Uniform,
+        Teleport::Seeds(seeds) => {
+            let seeds: BTreeSet<NodeID> = seeds
+                .into_iter()
+                .filter(|node| out_degrees.contains_key(node))
+                .collect();
+
+            if seeds.is_empty() {
+                Teleport::Uniform
+            } else {
+                Teleport::Seeds(seeds)
+            }
+        }
+    };
+
+    let teleport = |node: &NodeID| -> f64 {
+        match &teleport {
+            Teleport::Uniform => 1.0 / num_nodes as f64,
+            Teleport::Seeds(seeds) if seeds.contains(node) => 1.0 / seeds.len() as f64,
+            Teleport::Seeds(_) => 0.0,
+        }
+    };
+
+    let mut ranks: BTreeMap<NodeID, f64> = out_degrees
+        .keys()
+        .map(|node| (*node, teleport(node)))
+        .collect();
+
+    for iteration in 0..MAX_ITERATIONS {
+        let mut dangling = KahanSum::default();
+        let mut contributions: BTreeMap<NodeID, KahanSum> = BTreeMap::new();
+
+        for (node, out_degree) in &out_degrees {
+            let rank = ranks[node];
+
+            if *out_degree == 0.0 {
+                dangling += rank;
+                continue;
+            }
+
+            let contribution = rank / *out_degree;
+
+            for (to, weight) in edges(graph, node, direction, weights) {
+                *contributions.entry(to).or_default() += contribution * weight;
+            }
+        }
+
+        // the rank of nodes without outgoing edges is redistributed as if they teleported
+        let dangling = f64::from(dangling);
+        let mut diff = KahanSum::default();
+
+        let new_ranks: BTreeMap<NodeID, f64> = out_degrees
+            .keys()
+            .map(|node| {
+                let incoming = contributions
+                    .remove(node)
+                    .map(f64::from)
+                    .unwrap_or_default();
+
+                let rank =
+                    (1.0 - DAMPING + DAMPING * dangling) * teleport(node) + DAMPING * incoming;
+
+                diff += (rank - ranks[node]).abs();
+
+                (*node, rank)
+            })
+            .collect();
+
+        ranks = new_ranks;
+
+        let diff = f64::from(diff);
+        info!("PageRank iteration {} changed by {}", iteration + 1, diff);
+
+        if diff < TOLERANCE {
+            break;
+        }
+    }
+
+    ranks
+}
+
+/// Scale the scores such that the highest score is 1 and drop the nodes with a score of 0.
+pub fn normalize(ranks: BTreeMap<NodeID, f64>) -> BTreeMap<NodeID, f64> {
+    let max = ranks.values().copied().fold(0.0, f64::max);
+
+    ranks
+        .into_iter()
+        .filter(|(_, rank)| *rank > 0.0)
+        .map(|(node, rank)| (node, rank / max))
+        .map(|(node, rank)| {
+            if rank.is_finite() {
+                (node, rank)
+            } else {
+                (node, 0.0)
+            }
+        })
+        .collect()
+}
+
+fn calculate_pagerank(
+    graph: &Webgraph,
+    seeds: BTreeSet<NodeID>,
+    weights: &LinkWeights,
+) -> BTreeMap<NodeID, f64> {
+    let res = normalize(power_iteration(
+        graph,
+        Direction::Outgoing,
+        Teleport::Seeds(seeds),
+        weights,
+    ));
+
+    info!("PageRank calculated");
+
+    res
+}
+
+/// The PageRank of the nodes in the graph.
+/// The scores are scaled such that the highest ranked node has a score of 1.
+pub struct PageRank(BTreeMap<NodeID, f64>);
+
+impl PageRank {
+    pub fn calculate(graph: &Webgraph) -> Self {
+        Self::weighted(graph, &[], &LinkWeights::default())
+    }
+
+    /// PageRank where the random surfer only teleports to the seeds.
+    /// Seeds that are not in the graph are ignored. If none of the seeds are in the graph,
+    /// this is the same as the normal PageRank.
+    pub fn personalized(graph: &Webgraph, seeds: &[NodeID]) -> Self {
+        Self::weighted(graph, seeds, &LinkWeights::default())
+    }
+
+    /// (Personalised) PageRank with custom weights for the different kinds of links.
+    pub fn weighted(graph: &Webgraph, seeds: &[NodeID], weights: &LinkWeights) -> Self {
+        Self(calculate_pagerank(
+            graph,
+            seeds.iter().copied().collect(),
+            weights,
+        ))
+    }
+
+    pub fn get(&self, node: &NodeID) -> Option<f64> {
+        self.0.get(node).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeID, f64)> {
+        self.0.iter().map(|(node, rank)| (node, *rank))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{EdgeLabel, Node, WebgraphWriter};
+    use crate::webpage::{LinkPosition, RelFlags};
+
+    fn test_graph() -> Webgraph {
+        //     ┌────┐
+        //     │    │
+        // ┌───A◄─┐ │
+        // │      │ │
+        // ▼      │ │
+        // B─────►C◄┘
+        //        ▲
+        //        │
+        //        │
+        //        D
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        writer.insert(Node::from("B"), Node::from("C"), String::new());
+        writer.insert(Node::from("A"), Node::from("C"), String::new());
+        writer.insert(Node::from("C"), Node::from("A"), String::new());
+        writer.insert(Node::from("D"), Node::from("C"), String::new());
+
+        writer.finalize()
+    }
+
+    #[test]
+    fn pagerank() {
+        let graph = test_graph();
+        let pagerank = PageRank::calculate(&graph);
+
+        let a = pagerank.get(&Node::from("A").id()).unwrap();
+        let b = pagerank.get(&Node::from("B").id()).unwrap();
+        let c = pagerank.get(&Node::from("C").id()).unwrap();
+        let d = pagerank.get(&Node::from("D").id()).unwrap();
+
+        assert_eq!(c, 1.0);
+        assert!(c > a);
+        assert!(a > b);
+        assert!(b > d);
+    }
+
+    #[test]
+    fn duplicate_edges_ignored() {
+        let graph = test_graph();
+        let pagerank = PageRank::calculate(&graph);
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        writer.insert(Node::from("B"), Node::from("C"), String::new());
+        writer.insert(Node::from("A"), Node::from("C"), String::new());
+        writer.insert(Node::from("C"), Node::from("A"), String::new());
+        writer.insert(Node::from("D"), Node::from("C"), String::new());
+        writer.commit();
+        writer.insert(Node::from("A"), Node::from("B"), "1".to_string());
+        writer.commit();
+        writer.insert(Node::from("A"), Node::from("B"), "2".to_string());
+        writer.commit();
+
+        let graph = writer.finalize();
+
+        assert_eq!(pagerank.0, PageRank::calculate(&graph).0);
+    }
+
+    #[test]
+    fn personalized_pagerank() {
+        let graph = test_graph();
+
+        let pagerank = PageRank::calculate(&graph);
+        let personalized = PageRank::personalized(&graph, &[Node::from("D").id()]);
+
+        let d = Node::from("D").id();
+        let b = Node::from("B").id();
+
+        assert!(personalized.get(&d).unwrap() > pagerank.get(&d).unwrap());
+        assert!(personalized.get(&d).unwrap() > personalized.get(&b).unwrap());
+
+        let unknown = PageRank::personalized(&graph, &[Node::from("E").id()]);
+        assert_eq!(unknown.0, pagerank.0);
+    }
+
+    #[test]
+    fn link_weights() {
+        let graph = test_graph();
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        writer.insert(Node::from("B"), Node::from("C"), String::new());
+        writer.insert(Node::from("A"), Node::from("C"), String::new());
+        writer.insert(Node::from("C"), Node::from("A"), String::new());
+        writer.insert(Node::from("D"), Node::from("C"), String::new());
+        writer.insert(
+            Node::from("D"),
+            Node::from("B"),
+            EdgeLabel {
+                rel: RelFlags {
+                    nofollow: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        writer.insert(
+            Node::from("B"),
+            Node::from("D"),
+            EdgeLabel {
+                position: LinkPosition::Footer,
+                ..Default::default()
+            },
+        );
+
+        let weighted = writer.finalize();
+
+        let b = Node::from("B").id();
+        let c = Node::from("C").id();
+        let d = Node::from("D").id();
+
+        // the nofollow link is ignored, but the footer link still counts a bit
+        let pagerank = PageRank::calculate(&weighted);
+        assert!(pagerank.get(&d).unwrap() > PageRank::calculate(&graph).get(&d).unwrap());
+
+        let uniform = PageRank::weighted(&weighted, &[], &LinkWeights::UNIFORM);
+        assert!(uniform.get(&b).unwrap() > pagerank.get(&b).unwrap());
+
+        // the footer link gets half the weight of the body link from B
+        let contributions = power_iteration(
+            &weighted,
+            Direction::Outgoing,
+            Teleport::Uniform,
+            &LinkWeights::default(),
+        );
+        let from_b = contributions[&b] * DAMPING;
+        let teleport = (1.0 - DAMPING) / 4.0;
+        assert!((contributions[&d] - teleport - from_b / 3.0).abs() < 1e-4);
+        assert!(contributions[&c] > contributions[&d]);
+    }
+}
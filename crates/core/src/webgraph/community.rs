@@ -0,0 +1,329 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Community detection on the host graph.
+//!
+//! The communities are found with label propagation: every host starts in its own community
+//! and then repeatedly joins the community that most of its neighbours are in, until no host
+//! changes community. The hosts are visited in a new random order in every iteration and ties
+//! are broken randomly, both from a fixed seed so the same graph always gives the same
+//! communities. The links are treated as undirected and weighted by their [`LinkWeights`],
+//! so hosts that link to each other count twice and pull harder than one-way links.
+//!
+//! The id of a community is the id of one of its hosts. It is only stable as long as the graph
+//! does not change: a rebuild on an updated graph can give the same community another id.
+//! The most linked host of a community (the first of its [`Members`]) is a more stable name.
+
+use std::{collections::BTreeMap, fmt::Display, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use tracing::info;
+
+use crate::kv::{rocksdb_store::RocksDbStore, Kv};
+
+use super::{
+    centrality::{
+        pagerank::{self, Direction},
+        LinkWeights,
+    },
+    NodeID, Webgraph,
+};
+
+/// The propagation stops after this many iterations even if some hosts still change community.
+const MAX_ITERATIONS: usize = 20;
+
+/// Only this many hosts of each community are stored in the member list.
+const MAX_STORED_MEMBERS: usize = 10_000;
+
+/// Seed of the visiting order and the tie breaks of the label propagation.
+const SEED: u64 = 42;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct CommunityId(u64);
+
+impl CommunityId {
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<NodeID> for CommunityId {
+    fn from(node: NodeID) -> Self {
+        Self(node.as_u64())
+    }
+}
+
+impl Display for CommunityId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for CommunityId {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.parse()
+            .map(Self)
+            .map_err(|_| crate::Error::ParsingError(format!("invalid community id: {s}")))
+    }
+}
+
+/// The size of a community and (some of) its hosts.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Members {
+    pub size: usize,
+    /// The most linked hosts first.
+    pub hosts: Vec<NodeID>,
+}
+
+/// The hosts that link to or are linked from the node together with the weight of the link.
+/// Hosts that are both are included twice.
+fn neighbours(graph: &Webgraph, node: &NodeID, weights: &LinkWeights) -> Vec<(NodeID, f64)> {
+    pagerank::edges(graph, node, Direction::Outgoing, weights)
+        .into_iter()
+        .chain(pagerank::edges(graph, node, Direction::Ingoing, weights))
+        .filter(|(neighbour, _)| neighbour != node)
+        .collect()
+}
+
+struct Propagation {
+    communities: BTreeMap<NodeID, CommunityId>,
+    /// The total weight of the links of each host.
+    degrees: BTreeMap<NodeID, f64>,
+}
+
+fn label_propagation(graph: &Webgraph, weights: &LinkWeights) -> Propagation {
+    let mut communities: BTreeMap<NodeID, CommunityId> = graph
+        .nodes()
+        .map(|node| (node, CommunityId::from(node)))
+        .collect();
+    let mut degrees: BTreeMap<NodeID, f64> = BTreeMap::new();
+
+    let mut nodes: Vec<NodeID> = communities.keys().copied().collect();
+    let mut rng = StdRng::seed_from_u64(SEED);
+
+    for iteration in 0..MAX_ITERATIONS {
+        let mut num_changed = 0;
+
+        nodes.shuffle(&mut rng);
+
+        for node in &nodes {
+            let mut counts: BTreeMap<CommunityId, f64> = BTreeMap::new();
+            let mut degree = 0.0;
+
+            for (neighbour, weight) in neighbours(graph, node, weights) {
+                if let Some(community) = communities.get(&neighbour) {
+                    *counts.entry(*community).or_default() += weight;
+                    degree += weight;
+                }
+            }
+
+            if iteration == 0 {
+                degrees.insert(*node, degree);
+            }
+
+            let Some(max) = counts.values().copied().reduce(f64::max) else {
+                continue;
+            };
+
+            let current = communities[node];
+
+            // stay in the current community on ties to make sure the propagation converges
+            if counts.get(&current) == Some(&max) {
+                continue;
+            }
+
+            let best: Vec<CommunityId> = counts
+                .into_iter()
+                .filter(|(_, count)| *count == max)
+                .map(|(community, _)| community)
+                .collect();
+
+            communities.insert(*node, *best.choose(&mut rng).unwrap());
+            num_changed += 1;
+        }
+
+        info!("iteration {iteration}: {num_changed} hosts changed community");
+
+        if num_changed == 0 {
+            break;
+        }
+    }
+
+    Propagation {
+        communities,
+        degrees,
+    }
+}
+
+/// The community of each host in the host graph.
+pub struct Communities {
+    hosts: RocksDbStore<NodeID, CommunityId>,
+    members: RocksDbStore<CommunityId, Members>,
+}
+
+impl Communities {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            hosts: RocksDbStore::open(path.as_ref().join("hosts")),
+            members: RocksDbStore::open(path.as_ref().join("members")),
+        }
+    }
+
+    pub fn build<P: AsRef<Path>>(graph: &Webgraph, output: P) -> Result<Self> {
+        Self::build_weighted(graph, &LinkWeights::default(), output)
+    }
+
+    /// Detect the communities with the links weighted by their labels.
+    pub fn build_weighted<P: AsRef<Path>>(
+        graph: &Webgraph,
+        weights: &LinkWeights,
+        output: P,
+    ) -> Result<Self> {
+        if output.as_ref().exists() {
+            return Err(anyhow!("output path already exists"));
+        }
+
+        let Propagation {
+            communities,
+            degrees,
+        } = label_propagation(graph, weights);
+
+        let res = Self::open(output);
+        let mut members: BTreeMap<CommunityId, Vec<(NodeID, f64)>> = BTreeMap::new();
+
+        for (node, community) in communities {
+            res.hosts.insert(node, community);
+
+            let degree = degrees.get(&node).copied().unwrap_or_default();
+            members.entry(community).or_default().push((node, degree));
+        }
+
+        info!("Found {} communities", members.len());
+
+        for (community, mut hosts) in members {
+            hosts.sort_by(|(a, a_degree), (b, b_degree)| {
+                b_degree.total_cmp(a_degree).then_with(|| a.cmp(b))
+            });
+
+            let size = hosts.len();
+            let hosts = hosts
+                .into_iter()
+                .take(MAX_STORED_MEMBERS)
+                .map(|(node, _)| node)
+                .collect();
+
+            res.members.insert(community, Members { size, hosts });
+        }
+
+        res.hosts.flush();
+        res.members.flush();
+
+        Ok(res)
+    }
+
+    pub fn get(&self, node: &NodeID) -> Option<CommunityId> {
+        self.hosts.get(node)
+    }
+
+    pub fn members(&self, community: &CommunityId) -> Option<Members> {
+        self.members.get(community)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, CommunityId)> + '_ {
+        self.hosts.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{Node, WebgraphWriter};
+
+    fn test_graph() -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        // two groups of hosts that all link to each other with a single link between them
+        for group in ["a", "b"] {
+            for i in 1..=4 {
+                for j in 1..=4 {
+                    if i != j {
+                        writer.insert(
+                            Node::from(format!("{group}{i}.com")),
+                            Node::from(format!("{group}{j}.com")),
+                            String::new(),
+                        );
+                    }
+                }
+            }
+        }
+
+        writer.insert(Node::from("a1.com"), Node::from("b1.com"), String::new());
+
+        writer.finalize()
+    }
+
+    #[test]
+    fn separate_groups() {
+        let graph = test_graph();
+        let communities = Communities::build(&graph, crate::gen_temp_path()).unwrap();
+
+        let community =
+            |host: &str| -> CommunityId { communities.get(&Node::from(host).id()).unwrap() };
+
+        for i in 2..=4 {
+            assert_eq!(community(&format!("a{i}.com")), community("a1.com"));
+            assert_eq!(community(&format!("b{i}.com")), community("b1.com"));
+        }
+
+        assert_ne!(community("a1.com"), community("b1.com"));
+
+        let members = communities.members(&community("a1.com")).unwrap();
+        assert_eq!(members.size, 4);
+        assert!(members.hosts.contains(&Node::from("a3.com").id()));
+
+        // a1.com also links to b1.com, so it is the most linked host of its community
+        assert_eq!(members.hosts[0], Node::from("a1.com").id());
+    }
+
+    #[test]
+    fn deterministic() {
+        let graph = test_graph();
+
+        let first = Communities::build(&graph, crate::gen_temp_path()).unwrap();
+        let second = Communities::build(&graph, crate::gen_temp_path()).unwrap();
+
+        assert_eq!(
+            first.iter().collect::<Vec<_>>(),
+            second.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn community_id_from_str() {
+        let id = CommunityId::from(Node::from("a.com").id());
+        assert_eq!(id.to_string().parse::<CommunityId>().unwrap(), id);
+        assert!("not a number".parse::<CommunityId>().is_err());
+    }
+}
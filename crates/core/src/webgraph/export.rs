@@ -0,0 +1,756 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Export the webgraph to formats that can be read by other graph tools.
+//!
+//! All files are written next to the output path with an extension that depends on the format:
+//! * `tsv`: `<output>.nodes.tsv` with a header and one node per line, and `<output>.edges.tsv`
+//!   with one `from\tto[\ttext\trel\tposition]` edge per line, where `rel` is the space
+//!   separated `rel` flags of the link. The edge list can be imported again with
+//!   [`super::import::from_edge_list`].
+//! * `graphml`: `<output>.graphml`.
+//! * `bvgraph`: `<output>.graph`, `<output>.offsets` and `<output>.properties` in the BVGraph
+//!   format of the WebGraph framework, together with `<output>.nodes.tsv` where the node on
+//!   line `i + 1` (after the header) is node `i` in the graph. The successor lists are gap
+//!   compressed, but reference and interval compression is not used.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::kv::{rocksdb_store::RocksDbStore, Kv};
+
+use super::{Edge, EdgeLabel, NodeID, Webgraph};
+
+/// The `k` used for the ζ codes of the residuals in the BVGraph format (the WebGraph default).
+const ZETA_K: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tsv,
+    GraphMl,
+    BvGraph,
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = match self {
+            Format::Tsv => "tsv",
+            Format::GraphMl => "graphml",
+            Format::BvGraph => "bvgraph",
+        };
+        write!(f, "{format}")
+    }
+}
+
+impl FromStr for Format {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tsv" => Ok(Format::Tsv),
+            "graphml" => Ok(Format::GraphMl),
+            "bvgraph" => Ok(Format::BvGraph),
+            _ => Err(crate::Error::UnknownCLIOption),
+        }
+    }
+}
+
+/// `<output>.<extension>` without replacing any extension that is already part of the output path.
+fn output_file(output: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(output.as_os_str());
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Tabs and newlines would break the columns of the tsv files.
+fn escape_tsv(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+fn int2nat(x: i64) -> u64 {
+    if x >= 0 {
+        (x as u64) << 1
+    } else {
+        (((-(x + 1)) as u64) << 1) | 1
+    }
+}
+
+/// Writes bits with the most significant bit first, like the `OutputBitStream` of WebGraph.
+struct BitWriter<W: Write> {
+    writer: W,
+    buffer: u8,
+    filled: u32,
+    written_bits: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: 0,
+            filled: 0,
+            written_bits: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.buffer = (self.buffer << 1) | bit as u8;
+        self.filled += 1;
+        self.written_bits += 1;
+
+        if self.filled == 8 {
+            self.writer.write_all(&[self.buffer])?;
+            self.buffer = 0;
+            self.filled = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Write the `len` least significant bits of `value`.
+    fn write_bits(&mut self, value: u64, len: u32) -> io::Result<()> {
+        for i in (0..len).rev() {
+            self.write_bit((value >> i) & 1 == 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_unary(&mut self, x: u64) -> io::Result<()> {
+        for _ in 0..x {
+            self.write_bit(false)?;
+        }
+
+        self.write_bit(true)
+    }
+
+    /// Elias γ code of `x + 1`.
+    fn write_gamma(&mut self, x: u64) -> io::Result<()> {
+        let x = x + 1;
+        let msb = 63 - x.leading_zeros();
+
+        self.write_unary(msb as u64)?;
+        self.write_bits(x, msb)
+    }
+
+    /// Boldi-Vigna ζ code of `x + 1`.
+    fn write_zeta(&mut self, x: u64, k: u32) -> io::Result<()> {
+        let x = x + 1;
+        let msb = 63 - x.leading_zeros();
+        let h = msb / k;
+
+        self.write_unary(h as u64)?;
+
+        let left = 1 << (h * k);
+        if x - left < left {
+            self.write_bits(x - left, h * k + k - 1)
+        } else {
+            self.write_bits(x, h * k + k)
+        }
+    }
+
+    fn written_bits(&self) -> u64 {
+        self.written_bits
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.filled > 0 {
+            self.writer.write_all(&[self.buffer << (8 - self.filled)])?;
+        }
+
+        self.writer.flush()
+    }
+}
+
+pub struct Exporter<'a> {
+    graph: &'a Webgraph,
+    labels: bool,
+    centralities: Vec<(String, RocksDbStore<NodeID, f64>)>,
+}
+
+impl<'a> Exporter<'a> {
+    pub fn new(graph: &'a Webgraph) -> Self {
+        Self {
+            graph,
+            labels: false,
+            centralities: Vec::new(),
+        }
+    }
+
+    /// Include the labels of the edges.
+    pub fn labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Include the centrality store at the path (e.g. `<centrality>/harmonic`) as a node column.
+    /// The column is named after the store.
+    pub fn centrality<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Err(anyhow!(
+                "centrality store {} does not exist",
+                path.display()
+            ));
+        }
+
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("invalid centrality store path {}", path.display()))?
+            .to_string();
+
+        self.centralities
+            .push((name, RocksDbStore::open_read_only(path)));
+
+        Ok(self)
+    }
+
+    pub fn export<P: AsRef<Path>>(&self, format: Format, output: P) -> Result<()> {
+        let output = output.as_ref();
+
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        match format {
+            Format::Tsv => self.export_tsv(output),
+            Format::GraphMl => self.export_graphml(output),
+            Format::BvGraph => self.export_bvgraph(output),
+        }
+    }
+
+    fn centrality_values<'b>(&'b self, node: &'b NodeID) -> impl Iterator<Item = f64> + 'b {
+        self.centralities
+            .iter()
+            .map(move |(_, store)| store.get(node).unwrap_or_default())
+    }
+
+    fn outgoing_edges(&self, node: &NodeID) -> Vec<Edge<EdgeLabel>> {
+        if self.labels {
+            self.graph.raw_outgoing_edges_with_labels(node)
+        } else {
+            self.graph
+                .raw_outgoing_edges(node)
+                .into_iter()
+                .map(|edge| Edge {
+                    from: edge.from,
+                    to: edge.to,
+                    label: EdgeLabel::default(),
+                })
+                .collect()
+        }
+    }
+
+    /// Write the nodes and their centralities and call `on_node` for each node in the order they are written.
+    fn write_nodes<F: FnMut(NodeID)>(&self, path: &Path, mut on_node: F) -> Result<()> {
+        let mut wrt = BufWriter::new(File::create(path)?);
+
+        write!(wrt, "name")?;
+        for (name, _) in &self.centralities {
+            write!(wrt, "\t{}", escape_tsv(name))?;
+        }
+        writeln!(wrt)?;
+
+        for (node, id) in self.graph.node_ids() {
+            write!(wrt, "{}", escape_tsv(&node.name))?;
+            for value in self.centrality_values(&id) {
+                write!(wrt, "\t{value}")?;
+            }
+            writeln!(wrt)?;
+
+            on_node(id);
+        }
+
+        wrt.flush()?;
+
+        Ok(())
+    }
+
+    fn export_tsv(&self, output: &Path) -> Result<()> {
+        self.write_nodes(&output_file(output, "nodes.tsv"), |_| {})?;
+
+        let mut wrt = BufWriter::new(File::create(output_file(output, "edges.tsv"))?);
+
+        for (node, id) in self.graph.node_ids() {
+            let from = escape_tsv(&node.name);
+
+            for edge in self.outgoing_edges(&id) {
+                let Some(to) = self.graph.id2node(&edge.to) else {
+                    continue;
+                };
+
+                write!(wrt, "{from}\t{}", escape_tsv(&to.name))?;
+
+                if self.labels {
+                    write!(
+                        wrt,
+                        "\t{}\t{}\t{}",
+                        escape_tsv(&edge.label.text),
+                        edge.label.rel,
+                        edge.label.position
+                    )?;
+                }
+
+                writeln!(wrt)?;
+            }
+        }
+
+        wrt.flush()?;
+
+        Ok(())
+    }
+
+    fn export_graphml(&self, output: &Path) -> Result<()> {
+        let mut wrt = BufWriter::new(File::create(output_file(output, "graphml"))?);
+
+        writeln!(wrt, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            wrt,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            wrt,
+            r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#
+        )?;
+        for (i, (name, _)) in self.centralities.iter().enumerate() {
+            writeln!(
+                wrt,
+                r#"  <key id="c{i}" for="node" attr.name="{}" attr.type="double"/>"#,
+                quick_xml::escape::escape(name)
+            )?;
+        }
+        if self.labels {
+            for key in ["text", "rel", "position"] {
+                writeln!(
+                    wrt,
+                    r#"  <key id="{key}" for="edge" attr.name="{key}" attr.type="string"/>"#
+                )?;
+            }
+        }
+        writeln!(wrt, r#"  <graph id="webgraph" edgedefault="directed">"#)?;
+
+        // edges are only written between the written nodes, so every edge refers to a node in the file
+        let mut nodes = Vec::new();
+
+        for (node, id) in self.graph.node_ids() {
+            nodes.push(id);
+
+            write!(
+                wrt,
+                r#"    <node id="n{}"><data key="name">{}</data>"#,
+                id.as_u64(),
+                quick_xml::escape::escape(&node.name)
+            )?;
+            for (i, value) in self.centrality_values(&id).enumerate() {
+                write!(wrt, r#"<data key="c{i}">{value}</data>"#)?;
+            }
+            writeln!(wrt, "</node>")?;
+        }
+
+        let written: HashSet<NodeID> = nodes.iter().copied().collect();
+
+        for id in &nodes {
+            for edge in self.outgoing_edges(id) {
+                if !written.contains(&edge.to) {
+                    continue;
+                }
+
+                write!(
+                    wrt,
+                    r#"    <edge source="n{}" target="n{}">"#,
+                    edge.from.as_u64(),
+                    edge.to.as_u64()
+                )?;
+
+                if self.labels {
+                    write!(
+                        wrt,
+                        r#"<data key="text">{}</data><data key="rel">{}</data><data key="position">{}</data>"#,
+                        quick_xml::escape::escape(&edge.label.text),
+                        edge.label.rel,
+                        edge.label.position
+                    )?;
+                }
+
+                writeln!(wrt, "</edge>")?;
+            }
+        }
+
+        writeln!(wrt, "  </graph>")?;
+        writeln!(wrt, "</graphml>")?;
+        wrt.flush()?;
+
+        Ok(())
+    }
+
+    fn export_bvgraph(&self, output: &Path) -> Result<()> {
+        if self.labels {
+            return Err(anyhow!(
+                "edge labels are not supported by the bvgraph format"
+            ));
+        }
+
+        let mut nodes = Vec::new();
+        self.write_nodes(&output_file(output, "nodes.tsv"), |id| nodes.push(id))?;
+
+        let index: HashMap<NodeID, u64> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i as u64))
+            .collect();
+
+        let mut graph = BitWriter::new(BufWriter::new(File::create(output_file(output, "graph"))?));
+        let mut offsets = BitWriter::new(BufWriter::new(File::create(output_file(
+            output, "offsets",
+        ))?));
+
+        let mut num_arcs = 0;
+        let mut last_offset = 0;
+
+        for (node, id) in nodes.iter().enumerate() {
+            offsets.write_gamma(graph.written_bits() - last_offset)?;
+            last_offset = graph.written_bits();
+
+            let mut successors: Vec<u64> = self
+                .graph
+                .raw_outgoing_edges(id)
+                .into_iter()
+                .filter_map(|edge| index.get(&edge.to).copied())
+                .collect();
+            successors.sort_unstable();
+            successors.dedup();
+
+            graph.write_gamma(successors.len() as u64)?;
+
+            let mut prev = None;
+            for successor in &successors {
+                match prev {
+                    None => graph.write_zeta(int2nat(*successor as i64 - node as i64), ZETA_K)?,
+                    Some(prev) => graph.write_zeta(successor - prev - 1, ZETA_K)?,
+                }
+
+                prev = Some(*successor);
+            }
+
+            num_arcs += successors.len();
+        }
+
+        offsets.write_gamma(graph.written_bits() - last_offset)?;
+
+        graph.finish()?;
+        offsets.finish()?;
+
+        let mut properties = BufWriter::new(File::create(output_file(output, "properties"))?);
+        writeln!(properties, "#BVGraph properties")?;
+        writeln!(properties, "graphclass=it.unimi.dsi.webgraph.BVGraph")?;
+        writeln!(properties, "version=0")?;
+        writeln!(properties, "nodes={}", nodes.len())?;
+        writeln!(properties, "arcs={num_arcs}")?;
+        writeln!(properties, "windowsize=0")?;
+        writeln!(properties, "maxrefcount=0")?;
+        writeln!(properties, "minintervallength=0")?;
+        writeln!(properties, "zetak={ZETA_K}")?;
+        writeln!(properties, "compressionflags=")?;
+        properties.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{Node, WebgraphWriter};
+    use crate::webpage::{LinkPosition, RelFlags};
+
+    fn test_graph() -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        writer.insert(
+            Node::from("a.com"),
+            Node::from("b.com"),
+            "a & b".to_string(),
+        );
+        writer.insert(
+            Node::from("a.com"),
+            Node::from("c.com"),
+            EdgeLabel {
+                text: "tab\there".to_string(),
+                rel: RelFlags {
+                    nofollow: true,
+                    ugc: true,
+                    ..Default::default()
+                },
+                position: LinkPosition::Footer,
+            },
+        );
+        writer.insert(Node::from("b.com"), Node::from("c.com"), String::new());
+        writer.insert(Node::from("c.com"), Node::from("a.com"), String::new());
+
+        writer.finalize()
+    }
+
+    fn bits(f: impl FnOnce(&mut BitWriter<&mut Vec<u8>>)) -> String {
+        let mut bytes = Vec::new();
+        let mut wrt = BitWriter::new(&mut bytes);
+        f(&mut wrt);
+        let len = wrt.written_bits() as usize;
+        wrt.finish().unwrap();
+
+        bytes
+            .iter()
+            .map(|byte| format!("{byte:08b}"))
+            .collect::<String>()[..len]
+            .to_string()
+    }
+
+    /// Reads the codes written by [`BitWriter`].
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn read_bit(&mut self) -> bool {
+            let bit = (self.bytes[self.pos / 8] >> (7 - self.pos % 8)) & 1 == 1;
+            self.pos += 1;
+            bit
+        }
+
+        fn read_bits(&mut self, len: u32) -> u64 {
+            (0..len).fold(0, |acc, _| (acc << 1) | self.read_bit() as u64)
+        }
+
+        fn read_unary(&mut self) -> u64 {
+            let mut x = 0;
+            while !self.read_bit() {
+                x += 1;
+            }
+            x
+        }
+
+        fn read_gamma(&mut self) -> u64 {
+            let msb = self.read_unary() as u32;
+            ((1 << msb) | self.read_bits(msb)) - 1
+        }
+
+        fn read_zeta(&mut self, k: u32) -> u64 {
+            let h = self.read_unary() as u32;
+            let left = 1 << (h * k);
+            let x = self.read_bits(h * k + k - 1);
+
+            let x = if x < left {
+                x + left
+            } else {
+                (x << 1) | self.read_bit() as u64
+            };
+
+            x - 1
+        }
+    }
+
+    fn node_names(output: &Path) -> Vec<String> {
+        std::fs::read_to_string(output_file(output, "nodes.tsv"))
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(bits(|w| w.write_gamma(0).unwrap()), "1");
+        assert_eq!(bits(|w| w.write_gamma(1).unwrap()), "010");
+        assert_eq!(bits(|w| w.write_gamma(3).unwrap()), "00100");
+
+        assert_eq!(bits(|w| w.write_zeta(0, 3).unwrap()), "100");
+        assert_eq!(bits(|w| w.write_zeta(1, 3).unwrap()), "1010");
+        assert_eq!(bits(|w| w.write_zeta(7, 3).unwrap()), "0100000");
+
+        assert_eq!(int2nat(0), 0);
+        assert_eq!(int2nat(-1), 1);
+        assert_eq!(int2nat(1), 2);
+        assert_eq!(int2nat(-2), 3);
+    }
+
+    #[test]
+    fn tsv() {
+        let graph = test_graph();
+        let output = crate::gen_temp_path().join("graph");
+
+        let harmonic = crate::gen_temp_path().join("harmonic");
+        let store = RocksDbStore::open(&harmonic);
+        store.insert(Node::from("a.com").id(), 0.5);
+        store.flush();
+        drop(store);
+
+        Exporter::new(&graph)
+            .labels(true)
+            .centrality(&harmonic)
+            .unwrap()
+            .export(Format::Tsv, &output)
+            .unwrap();
+
+        let mut nodes: Vec<_> = std::fs::read_to_string(output_file(&output, "nodes.tsv"))
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        nodes.sort();
+        assert_eq!(
+            nodes,
+            vec!["a.com\t0.5", "b.com\t0", "c.com\t0", "name\tharmonic"]
+        );
+
+        let mut edges: Vec<_> = std::fs::read_to_string(output_file(&output, "edges.tsv"))
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+        edges.sort();
+
+        assert_eq!(
+            edges,
+            vec![
+                "a.com\tb.com\ta & b\t\tbody",
+                "a.com\tc.com\ttab here\tnofollow ugc\tfooter",
+                "b.com\tc.com\t\t\tbody",
+                "c.com\ta.com\t\t\tbody",
+            ]
+        );
+    }
+
+    #[test]
+    fn graphml() {
+        let graph = test_graph();
+        let output = crate::gen_temp_path().join("graph");
+
+        Exporter::new(&graph)
+            .labels(true)
+            .export(Format::GraphMl, &output)
+            .unwrap();
+
+        let graphml = std::fs::read_to_string(output_file(&output, "graphml")).unwrap();
+
+        let a = Node::from("a.com").id().as_u64();
+        let b = Node::from("b.com").id().as_u64();
+
+        assert!(graphml.contains(&format!(
+            r#"<node id="n{a}"><data key="name">a.com</data></node>"#
+        )));
+        assert!(graphml.contains(&format!(
+            r#"<edge source="n{a}" target="n{b}"><data key="text">a &amp; b</data><data key="rel"></data><data key="position">body</data></edge>"#
+        )));
+        assert_eq!(graphml.matches("<edge ").count(), 4);
+
+        for edge in graphml.lines().filter(|line| line.contains("<edge ")) {
+            let target = edge.split(r#"target=""#).nth(1).unwrap();
+            let target = &target[..target.find('"').unwrap()];
+            assert!(graphml.contains(&format!(r#"<node id="{target}">"#)));
+        }
+    }
+
+    #[test]
+    fn bvgraph() {
+        let graph = test_graph();
+        let output = crate::gen_temp_path().join("graph");
+
+        assert!(Exporter::new(&graph)
+            .labels(true)
+            .export(Format::BvGraph, &output)
+            .is_err());
+
+        Exporter::new(&graph)
+            .export(Format::BvGraph, &output)
+            .unwrap();
+
+        let names = node_names(&output);
+        let bytes = std::fs::read(output_file(&output, "graph")).unwrap();
+        let mut reader = BitReader {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        let offset_bytes = std::fs::read(output_file(&output, "offsets")).unwrap();
+        let mut offsets = BitReader {
+            bytes: &offset_bytes,
+            pos: 0,
+        };
+
+        let mut edges = Vec::new();
+        let mut offset = 0;
+        for (node, name) in names.iter().enumerate() {
+            offset += offsets.read_gamma();
+            assert_eq!(offset, reader.pos as u64);
+
+            let outdegree = reader.read_gamma();
+
+            let mut prev = None;
+            for _ in 0..outdegree {
+                let successor = match prev {
+                    None => {
+                        let x = reader.read_zeta(ZETA_K);
+                        let diff = if x % 2 == 0 {
+                            (x / 2) as i64
+                        } else {
+                            -((x / 2) as i64) - 1
+                        };
+                        (node as i64 + diff) as u64
+                    }
+                    Some(prev) => prev + reader.read_zeta(ZETA_K) + 1,
+                };
+
+                edges.push((name.clone(), names[successor as usize].clone()));
+                prev = Some(successor);
+            }
+        }
+
+        offset += offsets.read_gamma();
+        assert_eq!(offset, reader.pos as u64);
+
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                ("a.com".to_string(), "b.com".to_string()),
+                ("a.com".to_string(), "c.com".to_string()),
+                ("b.com".to_string(), "c.com".to_string()),
+                ("c.com".to_string(), "a.com".to_string()),
+            ]
+        );
+
+        let properties = std::fs::read_to_string(output_file(&output, "properties")).unwrap();
+        assert!(properties.contains("nodes=3\n"));
+        assert!(properties.contains("arcs=4\n"));
+    }
+}
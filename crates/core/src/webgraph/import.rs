@@ -0,0 +1,191 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Build a webgraph from an edge list, e.g. one exported by [`super::export::Exporter`].
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{executor::Executor, webpage::RelFlags};
+
+use super::{Compression, EdgeLabel, Node, Webgraph, WebgraphWriter};
+
+fn parse_node(name: &str) -> Option<Node> {
+    let url = if name.contains("://") {
+        Url::parse(name)
+    } else {
+        Url::parse(&("http://".to_string() + name))
+    };
+
+    url.ok().map(Node::from)
+}
+
+/// Build a webgraph at `output` from the tab separated edge list at `edges` with one
+/// `from\tto[\ttext[\trel[\tposition]]]` edge per line. Empty lines and lines starting
+/// with `#` are skipped.
+/// If `hosts` is set, the nodes are converted to their hosts to build a host graph.
+pub fn from_edge_list<P: AsRef<Path>, Q: AsRef<Path>>(
+    edges: P,
+    output: Q,
+    hosts: bool,
+) -> Result<Webgraph> {
+    if output.as_ref().exists() {
+        return Err(anyhow!("output path already exists"));
+    }
+
+    let reader = BufReader::new(File::open(edges)?);
+    let mut writer = WebgraphWriter::new(output, Executor::single_thread(), Compression::Lz4);
+
+    let mut num_edges = 0;
+    let mut num_skipped = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut columns = line.split('\t');
+
+        let (Some(from), Some(to)) = (
+            columns.next().and_then(parse_node),
+            columns.next().and_then(parse_node),
+        ) else {
+            num_skipped += 1;
+            continue;
+        };
+
+        let label = EdgeLabel {
+            text: columns.next().unwrap_or_default().to_string(),
+            rel: columns.next().map(RelFlags::parse).unwrap_or_default(),
+            position: columns
+                .next()
+                .and_then(|position| position.parse().ok())
+                .unwrap_or_default(),
+        };
+
+        let (from, to) = if hosts {
+            (from.into_host(), to.into_host())
+        } else {
+            (from, to)
+        };
+
+        writer.insert(from, to, label);
+        num_edges += 1;
+    }
+
+    if num_skipped > 0 {
+        warn!("skipped {num_skipped} lines that did not contain a valid edge");
+    }
+
+    info!("imported {num_edges} edges");
+
+    Ok(writer.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::webgraph::export::{Exporter, Format};
+    use crate::webpage::LinkPosition;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        writer.insert(Node::from("a.com"), Node::from("b.com"), "ab".to_string());
+        writer.insert(
+            Node::from("a.com"),
+            Node::from("c.com/page"),
+            EdgeLabel {
+                text: "ac".to_string(),
+                rel: RelFlags {
+                    sponsored: true,
+                    ..Default::default()
+                },
+                position: LinkPosition::Nav,
+            },
+        );
+        writer.insert(Node::from("c.com/page"), Node::from("a.com"), String::new());
+
+        let graph = writer.finalize();
+
+        let output = crate::gen_temp_path().join("graph");
+        Exporter::new(&graph)
+            .labels(true)
+            .export(Format::Tsv, &output)
+            .unwrap();
+
+        let imported = from_edge_list(
+            output.with_extension("edges.tsv"),
+            crate::gen_temp_path(),
+            false,
+        )
+        .unwrap();
+
+        let mut edges = imported.outgoing_edges(Node::from("a.com"));
+        edges.sort_by(|a, b| a.to.cmp(&b.to));
+
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0].to, Node::from("b.com"));
+        assert_eq!(edges[0].label, EdgeLabel::from("ab"));
+        assert_eq!(edges[1].to, Node::from("c.com/page"));
+        assert_eq!(edges[1].label.text, "ac");
+        assert!(edges[1].label.rel.sponsored);
+        assert_eq!(edges[1].label.position, LinkPosition::Nav);
+
+        assert_eq!(
+            imported.raw_ingoing_edges(&Node::from("a.com").id()).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn hosts_and_invalid_lines() {
+        let path = crate::gen_temp_path().join("edges.tsv");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "# from\tto").unwrap();
+        writeln!(file, "a.com/1\tb.com/2\tlabel").unwrap();
+        writeln!(file, "https://a.com/3\tb.com").unwrap();
+        writeln!(file, "missing-target").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "http://[invalid\tb.com").unwrap();
+        drop(file);
+
+        let graph = from_edge_list(&path, crate::gen_temp_path(), true).unwrap();
+
+        let edges = graph.outgoing_edges(Node::from("a.com"));
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].to, Node::from("b.com"));
+        assert_eq!(graph.nodes().count(), 2);
+    }
+}
@@ -0,0 +1,181 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::anyhow;
+use utoipa::ToSchema;
+
+use crate::webpage::{Link, LinkPosition, RelFlags};
+
+use super::StoredLabel;
+
+// The stored label starts with a header byte in the range `0x80..=0xBF`, which can never be
+// the first byte of a utf-8 string. Graphs built before the header was introduced only stored
+// the anchor text, so labels without the header are decoded as text with the default flags.
+const HEADER: u8 = 0b1000_0000;
+const HEADER_MASK: u8 = 0b1100_0000;
+const NOFOLLOW: u8 = 1;
+const SPONSORED: u8 = 1 << 1;
+const UGC: u8 = 1 << 2;
+const POSITION_SHIFT: u8 = 3;
+
+/// The anchor text of a link together with its `rel` flags and where on the page it was found.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, ToSchema,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgeLabel {
+    pub text: String,
+    pub rel: RelFlags,
+    pub position: LinkPosition,
+}
+
+impl From<String> for EdgeLabel {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for EdgeLabel {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
+impl From<&Link> for EdgeLabel {
+    fn from(link: &Link) -> Self {
+        Self {
+            text: link.text.clone(),
+            rel: link.rel,
+            position: link.position,
+        }
+    }
+}
+
+impl StoredLabel for EdgeLabel {
+    fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut header = HEADER;
+
+        if self.rel.nofollow {
+            header |= NOFOLLOW;
+        }
+
+        if self.rel.sponsored {
+            header |= SPONSORED;
+        }
+
+        if self.rel.ugc {
+            header |= UGC;
+        }
+
+        let position: u8 = match self.position {
+            LinkPosition::Body => 0,
+            LinkPosition::Nav => 1,
+            LinkPosition::Footer => 2,
+        };
+        header |= position << POSITION_SHIFT;
+
+        let mut bytes = Vec::with_capacity(1 + self.text.len());
+        bytes.push(header);
+        bytes.extend_from_slice(self.text.as_bytes());
+
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        match bytes.first() {
+            Some(header) if header & HEADER_MASK == HEADER => {
+                let position = match (header >> POSITION_SHIFT) & 0b11 {
+                    0 => LinkPosition::Body,
+                    1 => LinkPosition::Nav,
+                    2 => LinkPosition::Footer,
+                    _ => return Err(anyhow!("invalid link position in edge label")),
+                };
+
+                Ok(Self {
+                    text: String::from_utf8(bytes[1..].to_vec())?,
+                    rel: RelFlags {
+                        nofollow: header & NOFOLLOW != 0,
+                        sponsored: header & SPONSORED != 0,
+                        ugc: header & UGC != 0,
+                    },
+                    position,
+                })
+            }
+            _ => Ok(Self::from(String::from_utf8(bytes.to_vec())?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let labels = [
+            EdgeLabel::default(),
+            EdgeLabel::from("æøå"),
+            EdgeLabel {
+                text: "sponsored footer".to_string(),
+                rel: RelFlags {
+                    nofollow: true,
+                    sponsored: true,
+                    ugc: false,
+                },
+                position: LinkPosition::Footer,
+            },
+            EdgeLabel {
+                text: String::new(),
+                rel: RelFlags {
+                    ugc: true,
+                    ..Default::default()
+                },
+                position: LinkPosition::Nav,
+            },
+        ];
+
+        for label in labels {
+            let bytes = label.to_bytes().unwrap();
+            assert_eq!(EdgeLabel::from_bytes(&bytes).unwrap(), label);
+        }
+    }
+
+    #[test]
+    fn text_only_labels() {
+        assert_eq!(
+            EdgeLabel::from_bytes("anchor text".as_bytes()).unwrap(),
+            EdgeLabel::from("anchor text")
+        );
+        assert_eq!(EdgeLabel::from_bytes(&[]).unwrap(), EdgeLabel::default());
+    }
+
+    #[test]
+    fn labels_from_text_only_segments() {
+        // older segments stored the labels as a bincode serialized `Vec<String>`
+        let bytes = bincode::serialize(&vec!["a".to_string(), String::new()]).unwrap();
+        let labels: Vec<Vec<u8>> = bincode::deserialize(&bytes).unwrap();
+
+        let labels: Vec<_> = labels
+            .iter()
+            .map(|label| EdgeLabel::from_bytes(label).unwrap())
+            .collect();
+
+        assert_eq!(labels, vec![EdgeLabel::from("a"), EdgeLabel::default()]);
+    }
+}
@@ -15,11 +15,11 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 mod segment;
 
-use std::collections::{BTreeMap, BinaryHeap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::{cmp, fs};
 
 use itertools::Itertools;
@@ -30,10 +30,20 @@ use utoipa::ToSchema;
 
 use crate::executor::Executor;
 use crate::intmap;
+use crate::webpage::url_canonicalizer;
 use crate::webpage::url_ext::UrlExt;
 
+pub mod anchor_text;
 pub mod centrality;
+pub mod community;
+pub mod export;
+pub mod import;
+mod label;
+pub mod shard;
+pub mod spam;
 mod store;
+mod tombstone;
+pub use self::label::EdgeLabel;
 use self::segment::{Segment, SegmentWriter};
 
 pub const MAX_LABEL_LENGTH: usize = 1024;
@@ -88,7 +98,8 @@ impl intmap::Key for NodeID {
     }
 }
 
-pub trait EdgeLabel
+/// How edge labels are serialized in the segments.
+pub trait StoredLabel
 where
     Self: Send + Sync + Sized,
 {
@@ -96,7 +107,7 @@ where
     fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self>;
 }
 
-impl EdgeLabel for String {
+impl StoredLabel for String {
     fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
         Ok(self.as_bytes().to_vec())
     }
@@ -106,7 +117,7 @@ impl EdgeLabel for String {
     }
 }
 
-impl EdgeLabel for () {
+impl StoredLabel for () {
     fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
         Ok(Vec::new())
     }
@@ -119,7 +130,7 @@ impl EdgeLabel for () {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Edge<L>
 where
-    L: EdgeLabel,
+    L: StoredLabel,
 {
     pub from: NodeID,
     pub to: NodeID,
@@ -129,7 +140,7 @@ where
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct InnerEdge<L>
 where
-    L: EdgeLabel,
+    L: StoredLabel,
 {
     pub from: FullNodeID,
     pub to: FullNodeID,
@@ -138,7 +149,7 @@ where
 
 impl<L> From<InnerEdge<L>> for Edge<L>
 where
-    L: EdgeLabel,
+    L: StoredLabel,
 {
     fn from(edge: InnerEdge<L>) -> Self {
         Edge {
@@ -154,7 +165,30 @@ where
 pub struct FullEdge {
     pub from: Node,
     pub to: Node,
-    pub label: String,
+    pub label: EdgeLabel,
+}
+
+/// An edge ordered by the ids of its source and target nodes.
+struct EdgeByNodes(Edge<EdgeLabel>);
+
+impl PartialEq for EdgeByNodes {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for EdgeByNodes {}
+
+impl PartialOrd for EdgeByNodes {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EdgeByNodes {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.0.from, self.0.to).cmp(&(other.0.from, other.0.to))
+    }
 }
 
 #[derive(
@@ -193,6 +227,17 @@ impl Node {
         }
     }
 
+    /// The registrable domain of the node, e.g. `example.com` for `blog.example.com/post`.
+    pub fn domain(&self) -> Option<String> {
+        let url = if self.name.contains("://") {
+            Url::parse(&self.name)
+        } else {
+            Url::parse(&("http://".to_string() + self.name.as_str()))
+        };
+
+        url.ok()?.root_domain().map(|domain| domain.to_string())
+    }
+
     pub fn id(&self) -> NodeID {
         let digest = md5::compute(self.name.as_bytes());
         u128::from_le_bytes(*digest).into()
@@ -230,50 +275,22 @@ impl From<Url> for Node {
     }
 }
 
+/// The name of the node for the url. Uses the canonical key of the url,
+/// so the ids in the graph match the ones used by the crawler and the indexer.
+///
+/// The [`NodeID`] of a node is a hash of this name, so any change to the canonicalization
+/// changes the ids. Webgraphs and the stores keyed by node ids (centrality, anchor texts etc.)
+/// built before such a change must be rebuilt.
 pub fn normalize_url(url: &Url) -> String {
-    let mut url = url.clone();
-    let allowed_queries: Vec<_> = url
-        .query_pairs()
-        .filter(|(key, _)| {
-            !key.starts_with("utm_")
-                && !key.starts_with("fbclid")
-                && !key.starts_with("gclid")
-                && !key.starts_with("msclkid")
-        })
-        .map(|(key, value)| (key.to_string(), value.to_string()))
-        .collect();
-
-    {
-        let mut queries = url.query_pairs_mut();
-        queries.clear();
-
-        if !allowed_queries.is_empty() {
-            queries.extend_pairs(allowed_queries);
-        }
-    }
-
-    if url.query().unwrap_or_default().is_empty() {
-        url.set_query(None);
-    }
-
-    let scheme = url.scheme();
-    let mut normalized = url
-        .as_str()
-        .strip_prefix(scheme)
-        .unwrap_or_default()
-        .strip_prefix("://")
-        .unwrap_or_default()
-        .to_string();
-
-    if let Some(stripped) = normalized.strip_prefix("www.") {
-        normalized = stripped.to_string();
-    }
+    url_canonicalizer::canonical_key(url)
+}
 
-    if let Some(prefix) = normalized.strip_suffix('/') {
-        normalized = prefix.to_string();
-    }
+/// The host in the name of a node, found without parsing the name as a url.
+fn node_host(name: &str) -> &str {
+    let name = name.split_once("://").map(|(_, rest)| rest).unwrap_or(name);
+    let end = name.find(['/', ':', '?', '#']).unwrap_or(name.len());
 
-    normalized
+    &name[..end]
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -299,6 +316,16 @@ impl Compression {
     }
 }
 
+/// Filters for the labelled edges of a node. The filters are applied while the edges
+/// are read from the segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EdgeFilter {
+    /// Only keep the edges where the anchor text contains this (case-insensitive).
+    pub text: Option<String>,
+    /// Skip the edges where the other node is on the same domain as the node.
+    pub exclude_same_domain: bool,
+}
+
 pub struct WebgraphBuilder {
     path: Box<Path>,
     executor: Executor,
@@ -335,6 +362,40 @@ pub trait ShortestPaths {
     fn raw_distances_with_max(&self, source: NodeID, max_dist: u8) -> BTreeMap<NodeID, u8>;
     fn raw_reversed_distances(&self, source: NodeID) -> BTreeMap<NodeID, u8>;
     fn reversed_distances(&self, source: Node) -> BTreeMap<Node, u8>;
+    /// The nodes on a shortest path from the source to the target, both included.
+    /// Paths longer than `max_dist` are not considered.
+    fn raw_shortest_path(
+        &self,
+        source: NodeID,
+        target: NodeID,
+        max_dist: Option<u8>,
+    ) -> Option<Vec<NodeID>>;
+    fn shortest_path(&self, source: Node, target: Node, max_dist: Option<u8>) -> Option<Vec<Node>>;
+    /// Like [`ShortestPaths::raw_shortest_path`], but searches from both ends at the same time
+    /// and gives up once more than `max_visited` nodes have been visited.
+    fn raw_bounded_shortest_path(
+        &self,
+        source: NodeID,
+        target: NodeID,
+        max_dist: u8,
+        max_visited: usize,
+    ) -> Option<Vec<NodeID>>;
+    fn bounded_shortest_path(
+        &self,
+        source: Node,
+        target: Node,
+        max_dist: u8,
+        max_visited: usize,
+    ) -> Option<Vec<Node>>;
+    /// The distances to the nodes at most `max_dist` links from the source. The search stops
+    /// once `max_nodes` nodes (including the source) have been found, so only the closest nodes
+    /// are included in large neighbourhoods.
+    fn raw_bounded_distances(
+        &self,
+        source: NodeID,
+        max_dist: u8,
+        max_nodes: usize,
+    ) -> BTreeMap<NodeID, u8>;
 }
 
 fn dijkstra_multi<F1, F2, L>(
@@ -344,7 +405,7 @@ fn dijkstra_multi<F1, F2, L>(
     max_dist: Option<u8>,
 ) -> BTreeMap<NodeID, u8>
 where
-    L: EdgeLabel,
+    L: StoredLabel,
     F1: Fn(NodeID) -> Vec<Edge<L>>,
     F2: Fn(&Edge<L>) -> NodeID,
 {
@@ -386,6 +447,239 @@ where
     distances
 }
 
+/// Like [`dijkstra_multi`] from a single source, but keeps track of the predecessors
+/// and stops as soon as the target is reached.
+fn dijkstra_path<F1, F2, L>(
+    source: NodeID,
+    target: NodeID,
+    node_edges: F1,
+    edge_node: F2,
+    max_dist: Option<u8>,
+) -> Option<Vec<NodeID>>
+where
+    L: StoredLabel,
+    F1: Fn(NodeID) -> Vec<Edge<L>>,
+    F2: Fn(&Edge<L>) -> NodeID,
+{
+    let mut distances: BTreeMap<NodeID, u8> = BTreeMap::default();
+    let mut predecessors: BTreeMap<NodeID, NodeID> = BTreeMap::default();
+
+    let mut queue = BinaryHeap::new();
+
+    queue.push(cmp::Reverse((0, source)));
+    distances.insert(source, 0);
+
+    while let Some(state) = queue.pop() {
+        let (cost, v) = state.0;
+
+        if v == target {
+            let mut path = vec![target];
+            let mut current = target;
+
+            while let Some(prev) = predecessors.get(&current) {
+                path.push(*prev);
+                current = *prev;
+            }
+
+            path.reverse();
+            return Some(path);
+        }
+
+        let current_dist = distances.get(&v).unwrap_or(&u8::MAX);
+
+        if cost > *current_dist {
+            continue;
+        }
+
+        if let Some(max_dist) = max_dist {
+            if cost >= max_dist {
+                continue;
+            }
+        }
+
+        for edge in node_edges(v) {
+            let next = edge_node(&edge);
+
+            if cost + 1 < *distances.get(&next).unwrap_or(&u8::MAX) {
+                let d = cost + 1;
+
+                queue.push(cmp::Reverse((d, next)));
+                distances.insert(next, d);
+                predecessors.insert(next, v);
+            }
+        }
+    }
+
+    None
+}
+
+/// Breadth first search from the source that stops once `max_nodes` nodes have been found.
+fn bounded_bfs<F>(
+    source: NodeID,
+    neighbours: F,
+    max_dist: u8,
+    max_nodes: usize,
+) -> BTreeMap<NodeID, u8>
+where
+    F: Fn(NodeID) -> Vec<NodeID>,
+{
+    let mut distances: BTreeMap<NodeID, u8> = BTreeMap::default();
+    distances.insert(source, 0);
+
+    let mut frontier = vec![source];
+
+    for dist in 1..=max_dist {
+        let mut next = Vec::new();
+
+        for node in frontier {
+            for neighbour in neighbours(node) {
+                if distances.contains_key(&neighbour) {
+                    continue;
+                }
+
+                if distances.len() >= max_nodes {
+                    return distances;
+                }
+
+                distances.insert(neighbour, dist);
+                next.push(neighbour);
+            }
+        }
+
+        if next.is_empty() {
+            break;
+        }
+
+        frontier = next;
+    }
+
+    distances
+}
+
+/// One side of a bidirectional search: the distance from the side's root
+/// and the predecessor (towards the root) of every visited node.
+struct SearchTree {
+    visited: BTreeMap<NodeID, (u8, NodeID)>,
+    frontier: Vec<NodeID>,
+    depth: u8,
+}
+
+impl SearchTree {
+    fn new(root: NodeID) -> Self {
+        let mut visited = BTreeMap::default();
+        visited.insert(root, (0, root));
+
+        Self {
+            visited,
+            frontier: vec![root],
+            depth: 0,
+        }
+    }
+
+    /// Visit the next level of the tree. Returns the node where the tree meets the other tree
+    /// with the shortest total distance, if any.
+    fn expand<F>(&mut self, neighbours: F, other: &SearchTree) -> Option<NodeID>
+    where
+        F: Fn(NodeID) -> Vec<NodeID>,
+    {
+        self.depth += 1;
+
+        let mut next = Vec::new();
+        let mut meeting: Option<(u8, NodeID)> = None;
+
+        for node in std::mem::take(&mut self.frontier) {
+            for neighbour in neighbours(node) {
+                if self.visited.contains_key(&neighbour) {
+                    continue;
+                }
+
+                self.visited.insert(neighbour, (self.depth, node));
+                next.push(neighbour);
+
+                if let Some((other_dist, _)) = other.visited.get(&neighbour) {
+                    let closer = match meeting {
+                        Some((best, _)) => *other_dist < best,
+                        None => true,
+                    };
+
+                    if closer {
+                        meeting = Some((*other_dist, neighbour));
+                    }
+                }
+            }
+        }
+
+        self.frontier = next;
+
+        meeting.map(|(_, node)| node)
+    }
+
+    /// The nodes from the node to the root of the tree, both included.
+    fn path_to_root(&self, mut node: NodeID) -> Vec<NodeID> {
+        let mut path = vec![node];
+
+        while let Some((_, prev)) = self.visited.get(&node) {
+            if *prev == node {
+                break;
+            }
+
+            path.push(*prev);
+            node = *prev;
+        }
+
+        path
+    }
+}
+
+/// Shortest path found by searching forwards from the source and backwards from the target
+/// one level at a time, always expanding the side with the smallest frontier.
+/// Gives up once more than `max_visited` nodes have been visited.
+fn bidirectional_path<F1, F2>(
+    source: NodeID,
+    target: NodeID,
+    outgoing: F1,
+    ingoing: F2,
+    max_dist: u8,
+    max_visited: usize,
+) -> Option<Vec<NodeID>>
+where
+    F1: Fn(NodeID) -> Vec<NodeID>,
+    F2: Fn(NodeID) -> Vec<NodeID>,
+{
+    if source == target {
+        return Some(vec![source]);
+    }
+
+    let mut forward = SearchTree::new(source);
+    let mut backward = SearchTree::new(target);
+
+    while forward.depth + backward.depth < max_dist {
+        if forward.frontier.is_empty() || backward.frontier.is_empty() {
+            return None;
+        }
+
+        let meeting = if forward.frontier.len() <= backward.frontier.len() {
+            forward.expand(&outgoing, &backward)
+        } else {
+            backward.expand(&ingoing, &forward)
+        };
+
+        if let Some(meeting) = meeting {
+            let mut path = forward.path_to_root(meeting);
+            path.reverse();
+            path.extend(backward.path_to_root(meeting).into_iter().skip(1));
+
+            return Some(path);
+        }
+
+        if forward.visited.len() + backward.visited.len() > max_visited {
+            return None;
+        }
+    }
+
+    None
+}
+
 impl ShortestPaths for Webgraph {
     fn distances(&self, source: Node) -> BTreeMap<Node, u8> {
         self.raw_distances(source.id())
@@ -427,6 +721,87 @@ impl ShortestPaths for Webgraph {
             .filter_map(|(id, dist)| self.id2node(&id).map(|node| (node, dist)))
             .collect()
     }
+
+    fn raw_shortest_path(
+        &self,
+        source: NodeID,
+        target: NodeID,
+        max_dist: Option<u8>,
+    ) -> Option<Vec<NodeID>> {
+        dijkstra_path(
+            source,
+            target,
+            |node| self.raw_outgoing_edges(&node),
+            |edge| edge.to,
+            max_dist,
+        )
+    }
+
+    fn shortest_path(&self, source: Node, target: Node, max_dist: Option<u8>) -> Option<Vec<Node>> {
+        self.raw_shortest_path(source.id(), target.id(), max_dist)?
+            .into_iter()
+            .map(|id| self.id2node(&id))
+            .collect()
+    }
+
+    fn raw_bounded_shortest_path(
+        &self,
+        source: NodeID,
+        target: NodeID,
+        max_dist: u8,
+        max_visited: usize,
+    ) -> Option<Vec<NodeID>> {
+        bidirectional_path(
+            source,
+            target,
+            |node| {
+                self.raw_outgoing_edges(&node)
+                    .into_iter()
+                    .map(|edge| edge.to)
+                    .collect()
+            },
+            |node| {
+                self.raw_ingoing_edges(&node)
+                    .into_iter()
+                    .map(|edge| edge.from)
+                    .collect()
+            },
+            max_dist,
+            max_visited,
+        )
+    }
+
+    fn bounded_shortest_path(
+        &self,
+        source: Node,
+        target: Node,
+        max_dist: u8,
+        max_visited: usize,
+    ) -> Option<Vec<Node>> {
+        self.raw_bounded_shortest_path(source.id(), target.id(), max_dist, max_visited)?
+            .into_iter()
+            .map(|id| self.id2node(&id))
+            .collect()
+    }
+
+    fn raw_bounded_distances(
+        &self,
+        source: NodeID,
+        max_dist: u8,
+        max_nodes: usize,
+    ) -> BTreeMap<NodeID, u8> {
+        bounded_bfs(
+            source,
+            |node| {
+                self.raw_outgoing_edges(&node)
+                    .into_iter()
+                    .map(|edge| edge.to)
+                    .collect()
+            },
+            max_dist,
+            max_nodes,
+        )
+    }
 }
 
 type SegmentID = String;
@@ -452,19 +827,28 @@ impl Meta {
         serde_json::from_str(&buf).unwrap_or_default()
     }
 
+    /// The metadata is written to a temporary file that replaces the old one,
+    /// so a reader never sees a partially written list of segments.
     fn save<P: AsRef<Path>>(&self, path: P) {
-        let mut writer = BufWriter::new(
-            File::options()
-                .create(true)
-                .write(true)
-                .read(true)
-                .truncate(true)
-                .open(path)
-                .unwrap(),
-        );
+        let tmp_path = path.as_ref().with_extension("json.tmp");
+
+        {
+            let mut writer = BufWriter::new(
+                File::options()
+                    .create(true)
+                    .write(true)
+                    .read(true)
+                    .truncate(true)
+                    .open(&tmp_path)
+                    .unwrap(),
+            );
+
+            let json = serde_json::to_string_pretty(&self).unwrap();
+            writer.write_all(json.as_bytes()).unwrap();
+            writer.flush().unwrap();
+        }
 
-        let json = serde_json::to_string_pretty(&self).unwrap();
-        writer.write_all(json.as_bytes()).unwrap();
+        fs::rename(tmp_path, path).unwrap();
     }
 }
 
@@ -583,7 +967,7 @@ impl Id2NodeDb {
 pub struct WebgraphWriter {
     pub path: String,
     segment: SegmentWriter,
-    insert_batch: Vec<InnerEdge<String>>,
+    insert_batch: Vec<InnerEdge<EdgeLabel>>,
     id2node: Id2NodeDb,
     executor: Executor,
     meta: Meta,
@@ -636,7 +1020,7 @@ impl WebgraphWriter {
         id
     }
 
-    pub fn insert(&mut self, from: Node, to: Node, label: String) {
+    pub fn insert(&mut self, from: Node, to: Node, label: impl Into<EdgeLabel>) {
         if from == to {
             return;
         }
@@ -646,10 +1030,13 @@ impl WebgraphWriter {
             self.id_or_assign(to.clone()),
         );
 
+        let mut label = label.into();
+        label.text = label.text.chars().take(MAX_LABEL_LENGTH).collect();
+
         let edge = InnerEdge {
             from: from_id,
             to: to_id,
-            label: label.chars().take(MAX_LABEL_LENGTH).collect(),
+            label,
         };
 
         self.insert_batch.push(edge);
@@ -659,13 +1046,46 @@ impl WebgraphWriter {
         }
     }
 
+    /// Remove all outgoing edges of the node. This also removes the edges from the graphs
+    /// that this graph is later merged into, as long as they were committed before the merge.
+    pub fn remove_outgoing(&mut self, from: &Node) {
+        let id = from.id();
+
+        self.insert_batch.retain(|edge| edge.from.id != id);
+        self.segment.remove_outgoing(&id);
+    }
+
+    /// Remove a single edge. Like [`WebgraphWriter::remove_outgoing`], the removal also applies
+    /// to graphs that this graph is later merged into.
+    pub fn remove_edge(&mut self, from: &Node, to: &Node) {
+        let (from, to) = (from.id(), to.id());
+
+        self.insert_batch
+            .retain(|edge| edge.from.id != from || edge.to.id != to);
+        self.segment.remove_edge(&from, &to);
+    }
+
+    /// Replace all outgoing edges of the node, e.g. when the page has been re-crawled.
+    pub fn replace_outgoing<L: Into<EdgeLabel>>(
+        &mut self,
+        from: Node,
+        edges: impl IntoIterator<Item = (Node, L)>,
+    ) {
+        self.remove_outgoing(&from);
+
+        for (to, label) in edges {
+            self.insert(from.clone(), to, label);
+        }
+    }
+
     pub fn commit(&mut self) {
         if !self.insert_batch.is_empty() {
             self.segment.insert(&self.insert_batch);
-            self.segment.flush();
             self.insert_batch.clear();
         }
 
+        self.segment.flush();
+
         self.save_metadata();
         self.id2node.flush();
     }
@@ -748,22 +1168,49 @@ impl Webgraph {
     }
 
     pub fn ingoing_edges(&self, node: Node) -> Vec<FullEdge> {
-        let dedup = |edges: &mut Vec<Edge<String>>| {
+        self.ingoing_edges_filtered(node, &EdgeFilter::default())
+    }
+
+    pub fn ingoing_edges_filtered(&self, node: Node, filter: &EdgeFilter) -> Vec<FullEdge> {
+        self.full_edges(self.raw_ingoing_edges_filtered(&node, filter))
+    }
+
+    /// Like [`Webgraph::ingoing_edges_filtered`], but without looking up the names of the nodes.
+    pub fn raw_ingoing_edges_filtered(
+        &self,
+        node: &Node,
+        filter: &EdgeFilter,
+    ) -> Vec<Edge<EdgeLabel>> {
+        let dedup = |edges: &mut Vec<Edge<EdgeLabel>>| {
             edges.sort_by_key(|e| e.from);
             edges.dedup_by_key(|e| e.from);
         };
 
+        let keep_node = self.node_filter(node, filter);
+        let keep = Self::label_filter(filter);
+
         self.inner_edges(
-            |segment| segment.ingoing_edges_with_label(&node.id()),
+            |segment| {
+                segment
+                    .ingoing_edges_with_label(&node.id(), &keep_node, &keep)
+                    .collect()
+            },
             dedup,
         )
-        .into_iter()
-        .map(|e| FullEdge {
-            from: self.id2node(&e.from).unwrap(),
-            to: self.id2node(&e.to).unwrap(),
-            label: e.label,
-        })
-        .collect()
+    }
+
+    /// Look up the names of the nodes of the edges. Edges with unknown nodes are skipped.
+    pub fn full_edges(&self, edges: impl IntoIterator<Item = Edge<EdgeLabel>>) -> Vec<FullEdge> {
+        edges
+            .into_iter()
+            .filter_map(|e| {
+                Some(FullEdge {
+                    from: self.id2node(&e.from)?,
+                    to: self.id2node(&e.to)?,
+                    label: e.label,
+                })
+            })
+            .collect()
     }
 
     pub fn raw_ingoing_edges_by_host(&self, host_node: &NodeID) -> Vec<Edge<()>> {
@@ -775,21 +1222,59 @@ impl Webgraph {
         self.inner_edges(|segment| segment.ingoing_edges_by_host(host_node), dedup)
     }
 
+    /// The first `limit` ingoing edges of all the pages on the host, ordered by the ids of
+    /// their source and target nodes. Only the nodes of the returned edges are looked up.
+    pub fn ingoing_edges_by_host(&self, host: Node, limit: usize) -> Vec<FullEdge> {
+        // the last edge to return is at the top of the heap
+        let mut heap = BinaryHeap::with_capacity(limit + 1);
+
+        for page in self.pages_by_host(&host.into_host().id()) {
+            for edge in self.raw_ingoing_edges_with_labels(&page) {
+                heap.push(EdgeByNodes(edge));
+
+                if heap.len() > limit {
+                    heap.pop();
+                }
+            }
+        }
+
+        self.full_edges(heap.into_sorted_vec().into_iter().map(|edge| edge.0))
+    }
+
     pub fn pages_by_host(&self, host_node: &NodeID) -> Vec<NodeID> {
-        let mut pages: Vec<_> = self
+        let pages_by_segment = self
             .executor
             .map(
-                |segment| segment.pages_by_host(host_node),
-                self.segments.iter(),
+                |(i, segment)| (i, segment.pages_by_host(host_node)),
+                self.segments.iter().enumerate(),
             )
-            .unwrap()
-            .into_iter()
-            .flatten()
-            .collect();
+            .unwrap();
+
+        let mut pages = Vec::new();
+        let mut maybe_deleted = Vec::new();
+
+        // pages are found by their ingoing edges, which might all have been deleted
+        // by the tombstones of a newer segment
+        for (i, segment_pages) in pages_by_segment {
+            if self.has_newer_tombstones(i) {
+                maybe_deleted.extend(segment_pages);
+            } else {
+                pages.extend(segment_pages);
+            }
+        }
 
         pages.sort();
         pages.dedup();
 
+        maybe_deleted.sort();
+        maybe_deleted.dedup();
+        maybe_deleted.retain(|page| {
+            pages.binary_search(page).is_err() && !self.raw_ingoing_edges(page).is_empty()
+        });
+
+        pages.extend(maybe_deleted);
+        pages.sort();
+
         pages
     }
 
@@ -799,35 +1284,55 @@ impl Webgraph {
             edges.dedup_by_key(|e| e.from);
         };
 
-        self.inner_edges(|segment| segment.ingoing_edges(node), dedup)
+        self.inner_edges(|segment| segment.ingoing_edges(node).collect(), dedup)
     }
 
-    pub fn raw_ingoing_edges_with_labels(&self, node: &NodeID) -> Vec<Edge<String>> {
-        let dedup = |edges: &mut Vec<Edge<String>>| {
+    pub fn raw_ingoing_edges_with_labels(&self, node: &NodeID) -> Vec<Edge<EdgeLabel>> {
+        let dedup = |edges: &mut Vec<Edge<EdgeLabel>>| {
             edges.sort_by_key(|e| e.from);
             edges.dedup_by_key(|e| e.from);
         };
 
-        self.inner_edges(|segment| segment.ingoing_edges_with_label(node), dedup)
+        self.inner_edges(
+            |segment| {
+                segment
+                    .ingoing_edges_with_label(node, |_| true, |_, _| true)
+                    .collect()
+            },
+            dedup,
+        )
     }
 
     pub fn outgoing_edges(&self, node: Node) -> Vec<FullEdge> {
-        let dedup = |edges: &mut Vec<Edge<String>>| {
+        self.outgoing_edges_filtered(node, &EdgeFilter::default())
+    }
+
+    pub fn outgoing_edges_filtered(&self, node: Node, filter: &EdgeFilter) -> Vec<FullEdge> {
+        self.full_edges(self.raw_outgoing_edges_filtered(&node, filter))
+    }
+
+    /// Like [`Webgraph::outgoing_edges_filtered`], but without looking up the names of the nodes.
+    pub fn raw_outgoing_edges_filtered(
+        &self,
+        node: &Node,
+        filter: &EdgeFilter,
+    ) -> Vec<Edge<EdgeLabel>> {
+        let dedup = |edges: &mut Vec<Edge<EdgeLabel>>| {
             edges.sort_by_key(|e| e.to);
             edges.dedup_by_key(|e| e.to);
         };
 
+        let keep_node = self.node_filter(node, filter);
+        let keep = Self::label_filter(filter);
+
         self.inner_edges(
-            |segment| segment.outgoing_edges_with_label(&node.id()),
+            |segment| {
+                segment
+                    .outgoing_edges_with_label(&node.id(), &keep_node, &keep)
+                    .collect()
+            },
             dedup,
         )
-        .into_iter()
-        .map(|e| FullEdge {
-            from: self.id2node(&e.from).unwrap(),
-            to: self.id2node(&e.to).unwrap(),
-            label: e.label,
-        })
-        .collect()
     }
 
     pub fn raw_outgoing_edges(&self, node: &NodeID) -> Vec<Edge<()>> {
@@ -836,37 +1341,352 @@ impl Webgraph {
             edges.dedup_by_key(|e| e.to);
         };
 
-        self.inner_edges(|segment| segment.outgoing_edges(node), dedup)
+        self.inner_edges(|segment| segment.outgoing_edges(node).collect(), dedup)
     }
 
-    fn inner_edges<F1, F2, L>(&self, loader: F1, dedup: F2) -> Vec<Edge<L>>
-    where
-        L: EdgeLabel,
-        F1: Sized + Sync + Fn(&Segment) -> Vec<Edge<L>>,
-        F2: Fn(&mut Vec<Edge<L>>),
-    {
-        let mut edges: Vec<_> = self
-            .executor
-            .map(loader, self.segments.iter())
-            .unwrap()
-            .into_iter()
-            .flatten()
-            .collect();
-
-        dedup(&mut edges);
+    pub fn raw_outgoing_edges_with_labels(&self, node: &NodeID) -> Vec<Edge<EdgeLabel>> {
+        let dedup = |edges: &mut Vec<Edge<EdgeLabel>>| {
+            edges.sort_by_key(|e| e.to);
+            edges.dedup_by_key(|e| e.to);
+        };
 
-        edges
+        self.inner_edges(
+            |segment| {
+                segment
+                    .outgoing_edges_with_label(node, |_| true, |_, _| true)
+                    .collect()
+            },
+            dedup,
+        )
     }
 
-    pub fn id2node(&self, id: &NodeID) -> Option<Node> {
-        self.id2node.get(id)
+    /// The part of the filter for the edges of the node that only depends on the other node
+    /// of the edge, so it can be applied before the labels are decoded.
+    fn node_filter<'a>(
+        &'a self,
+        node: &Node,
+        filter: &EdgeFilter,
+    ) -> impl Fn(&NodeID) -> bool + Send + Sync + 'a {
+        let domain = if filter.exclude_same_domain {
+            node.domain()
+        } else {
+            None
+        };
+        let subdomain_suffix = domain.as_ref().map(|domain| format!(".{domain}"));
+
+        // the same node is often linked from several segments
+        let same_domain_cache: Mutex<HashMap<NodeID, bool>> = Mutex::new(HashMap::new());
+
+        move |other| {
+            let (Some(domain), Some(subdomain_suffix)) = (&domain, &subdomain_suffix) else {
+                return true;
+            };
+
+            let cached = same_domain_cache
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(other)
+                .copied();
+
+            let same_domain = cached.unwrap_or_else(|| {
+                let same_domain = self.id2node(other).is_some_and(|other| {
+                    let host = node_host(&other.name);
+                    host == domain || host.ends_with(subdomain_suffix.as_str())
+                });
+
+                same_domain_cache
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(*other, same_domain);
+
+                same_domain
+            });
+
+            !same_domain
+        }
     }
 
-    pub fn nodes(&self) -> impl Iterator<Item = NodeID> + '_ {
-        self.id2node.keys()
+    /// The part of the filter for the edges that depends on their labels.
+    fn label_filter(filter: &EdgeFilter) -> impl Fn(&NodeID, &EdgeLabel) -> bool + Send + Sync {
+        let text = filter.text.as_ref().map(|text| text.to_lowercase());
+
+        move |_, label| match &text {
+            Some(text) => label.text.to_lowercase().contains(text.as_str()),
+            None => true,
+        }
     }
 
-    pub fn random_nodes_with_outgoing(&self, num: usize) -> Vec<NodeID> {
+    /// Stream the edges of the node that pass the filter one segment at a time, without
+    /// collecting them. The labels are only decoded if the filter needs them. An edge that is
+    /// stored in more than one segment is returned once for each of them.
+    fn stream_edges_filtered<'a>(
+        &'a self,
+        node: &Node,
+        filter: &EdgeFilter,
+        ingoing: bool,
+    ) -> impl Iterator<Item = Edge<()>> + 'a {
+        let id = node.id();
+        let needs_labels = filter.text.is_some();
+        let keep_node = Arc::new(self.node_filter(node, filter));
+        let keep_label = Arc::new(Self::label_filter(filter));
+
+        self.segments
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                let keep_node = Arc::clone(&keep_node);
+                let keep_label = Arc::clone(&keep_label);
+                let without_label = |edge: Edge<EdgeLabel>| Edge {
+                    from: edge.from,
+                    to: edge.to,
+                    label: (),
+                };
+
+                let edges: Box<dyn Iterator<Item = Edge<()>> + 'a> = match (ingoing, needs_labels) {
+                    (true, true) => Box::new(
+                        segment
+                            .ingoing_edges_with_label(
+                                &id,
+                                move |other| keep_node(other),
+                                move |other, label| keep_label(other, label),
+                            )
+                            .map(without_label),
+                    ),
+                    (true, false) => Box::new(
+                        segment
+                            .ingoing_edges(&id)
+                            .filter(move |edge| keep_node(&edge.from)),
+                    ),
+                    (false, true) => Box::new(
+                        segment
+                            .outgoing_edges_with_label(
+                                &id,
+                                move |other| keep_node(other),
+                                move |other, label| keep_label(other, label),
+                            )
+                            .map(without_label),
+                    ),
+                    (false, false) => Box::new(
+                        segment
+                            .outgoing_edges(&id)
+                            .filter(move |edge| keep_node(&edge.to)),
+                    ),
+                };
+
+                let check_deleted = self.has_newer_tombstones(i);
+
+                edges
+                    .filter(move |edge| !check_deleted || !self.is_deleted(i, &edge.from, &edge.to))
+            })
+    }
+
+    /// Like [`Webgraph::raw_ingoing_edges_filtered`], but the edges are streamed without their
+    /// labels instead of collected. An edge that is stored in more than one segment is returned
+    /// once for each of them. Use [`Webgraph::raw_ingoing_edges_from`] for the labels.
+    pub fn stream_ingoing_edges_filtered<'a>(
+        &'a self,
+        node: &Node,
+        filter: &EdgeFilter,
+    ) -> impl Iterator<Item = Edge<()>> + 'a {
+        self.stream_edges_filtered(node, filter, true)
+    }
+
+    /// Like [`Webgraph::raw_outgoing_edges_filtered`], but the edges are streamed without their
+    /// labels instead of collected. An edge that is stored in more than one segment is returned
+    /// once for each of them. Use [`Webgraph::raw_outgoing_edges_to`] for the labels.
+    pub fn stream_outgoing_edges_filtered<'a>(
+        &'a self,
+        node: &Node,
+        filter: &EdgeFilter,
+    ) -> impl Iterator<Item = Edge<()>> + 'a {
+        self.stream_edges_filtered(node, filter, false)
+    }
+
+    /// The ingoing edges of the node from the sources, with their labels from the newest
+    /// segment. Only the labels of these edges are decoded.
+    pub fn raw_ingoing_edges_from(
+        &self,
+        node: &NodeID,
+        sources: &HashSet<NodeID>,
+    ) -> Vec<Edge<EdgeLabel>> {
+        let dedup = |edges: &mut Vec<Edge<EdgeLabel>>| {
+            edges.sort_by_key(|e| e.from);
+            edges.dedup_by_key(|e| e.from);
+        };
+
+        self.inner_edges(
+            |segment| {
+                segment
+                    .ingoing_edges_with_label(node, |other| sources.contains(other), |_, _| true)
+                    .collect()
+            },
+            dedup,
+        )
+    }
+
+    /// The outgoing edges of the node to the targets, with their labels from the newest
+    /// segment. Only the labels of these edges are decoded.
+    pub fn raw_outgoing_edges_to(
+        &self,
+        node: &NodeID,
+        targets: &HashSet<NodeID>,
+    ) -> Vec<Edge<EdgeLabel>> {
+        let dedup = |edges: &mut Vec<Edge<EdgeLabel>>| {
+            edges.sort_by_key(|e| e.to);
+            edges.dedup_by_key(|e| e.to);
+        };
+
+        self.inner_edges(
+            |segment| {
+                segment
+                    .outgoing_edges_with_label(node, |other| targets.contains(other), |_, _| true)
+                    .collect()
+            },
+            dedup,
+        )
+    }
+
+    /// The id of the host of the node, found without parsing the name of the node as a url.
+    pub fn host_id(&self, node: &NodeID) -> Option<NodeID> {
+        let node = self.id2node(node)?;
+
+        Some(
+            Node {
+                name: node_host(&node.name).trim_start_matches("www.").to_string(),
+            }
+            .id(),
+        )
+    }
+
+    fn inner_edges<F1, F2, L>(&self, loader: F1, dedup: F2) -> Vec<Edge<L>>
+    where
+        L: StoredLabel,
+        F1: Sized + Sync + Fn(&Segment) -> Vec<Edge<L>>,
+        F2: Fn(&mut Vec<Edge<L>>),
+    {
+        // the newest segments come first, so the stable sort in `dedup` keeps their labels
+        // like `compact` does
+        let mut edges: Vec<_> = self
+            .executor
+            .map(
+                |(i, segment)| {
+                    let mut edges = loader(segment);
+
+                    if self.has_newer_tombstones(i) {
+                        edges.retain(|edge| !self.is_deleted(i, &edge.from, &edge.to));
+                    }
+
+                    edges
+                },
+                self.segments.iter().enumerate().rev(),
+            )
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+
+        dedup(&mut edges);
+
+        edges
+    }
+
+    fn has_tombstones(&self) -> bool {
+        self.segments.iter().any(|segment| segment.has_tombstones())
+    }
+
+    /// Whether any of the segments newer than the segment has tombstones. Otherwise none
+    /// of the edges in the segment can have been deleted.
+    fn has_newer_tombstones(&self, segment: usize) -> bool {
+        self.segments[segment + 1..]
+            .iter()
+            .any(|newer| newer.has_tombstones())
+    }
+
+    /// Whether the edge in the segment has been deleted by one of the newer segments.
+    fn is_deleted(&self, segment: usize, from: &NodeID, to: &NodeID) -> bool {
+        self.segments[segment + 1..]
+            .iter()
+            .any(|newer| newer.is_deleted(from, to))
+    }
+
+    /// Rewrite all segments into a single segment without the deleted edges.
+    /// The old segments are only removed after the metadata points to the new segment,
+    /// so the graph can be opened at any point during the compaction.
+    pub fn compact(&mut self) {
+        if self.segments.len() <= 1 {
+            return;
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut writer = SegmentWriter::open(
+            Path::new(&self.path).join("segments"),
+            id.clone(),
+            self.compression,
+        );
+
+        let mut batch = Vec::with_capacity(store::MAX_BATCH_SIZE);
+        let mut num_missing_nodes = 0;
+
+        // older segments are written first, so the labels from the newest segments are kept
+        for (i, segment) in self.segments.iter().enumerate() {
+            let check_deleted = self.has_newer_tombstones(i);
+
+            for edge in segment.edges_with_label() {
+                if check_deleted && self.is_deleted(i, &edge.from, &edge.to) {
+                    continue;
+                }
+
+                let (Some(from), Some(to)) = (self.id2node(&edge.from), self.id2node(&edge.to))
+                else {
+                    num_missing_nodes += 1;
+                    continue;
+                };
+
+                batch.push(InnerEdge {
+                    from: FullNodeID::from(from),
+                    to: FullNodeID::from(to),
+                    label: edge.label,
+                });
+
+                if batch.len() >= store::MAX_BATCH_SIZE {
+                    writer.insert(&batch);
+                    batch.clear();
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            writer.insert(&batch);
+        }
+
+        if num_missing_nodes > 0 {
+            tracing::warn!(
+                "dropped {} edges during compaction because their nodes are not in id2node",
+                num_missing_nodes
+            );
+        }
+
+        let old_segments = std::mem::replace(&mut self.segments, vec![writer.finalize()]);
+
+        self.meta.comitted_segments = vec![id];
+        self.save_metadata();
+
+        for segment in old_segments {
+            let path = segment.path();
+            drop(segment);
+            fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    pub fn id2node(&self, id: &NodeID) -> Option<Node> {
+        self.id2node.get(id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = NodeID> + '_ {
+        self.id2node.keys()
+    }
+
+    pub fn random_nodes_with_outgoing(&self, num: usize) -> Vec<NodeID> {
         let mut rng = rand::thread_rng();
         let mut nodes = self
             .edges()
@@ -894,13 +1714,44 @@ impl Webgraph {
     /// Some edges may be returned multiple times.
     /// This happens if they are present in more than one segment.
     pub fn edges(&self) -> impl Iterator<Item = Edge<()>> + '_ {
-        self.segments.iter().flat_map(|segment| segment.edges())
+        self.segments
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                let check_deleted = self.has_newer_tombstones(i);
+
+                segment
+                    .edges()
+                    .filter(move |edge| !check_deleted || !self.is_deleted(i, &edge.from, &edge.to))
+            })
+    }
+
+    /// Like [`Webgraph::edges`], but with the labels of the edges.
+    pub fn edges_with_label(&self) -> impl Iterator<Item = Edge<EdgeLabel>> + '_ {
+        self.segments
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                let check_deleted = self.has_newer_tombstones(i);
+
+                segment
+                    .edges_with_label()
+                    .filter(move |edge| !check_deleted || !self.is_deleted(i, &edge.from, &edge.to))
+            })
     }
 
     pub fn par_edges(&self) -> impl ParallelIterator<Item = Edge<()>> + '_ {
         self.segments
             .par_iter()
-            .flat_map(|segment| segment.edges().par_bridge())
+            .enumerate()
+            .flat_map(move |(i, segment)| {
+                let check_deleted = self.has_newer_tombstones(i);
+
+                segment
+                    .edges()
+                    .par_bridge()
+                    .filter(move |edge| !check_deleted || !self.is_deleted(i, &edge.from, &edge.to))
+            })
     }
 }
 
@@ -908,6 +1759,14 @@ impl Webgraph {
 mod test {
     use super::*;
 
+    #[test]
+    fn node_host_without_url_parsing() {
+        assert_eq!(node_host("a.com"), "a.com");
+        assert_eq!(node_host("a.com/page?q=1"), "a.com");
+        assert_eq!(node_host("a.com:8080/page"), "a.com");
+        assert_eq!(node_host("https://www.a.com/page"), "www.a.com");
+    }
+
     fn test_edges() -> Vec<(Node, Node, String)> {
         vec![
             (Node::from("A"), Node::from("B"), String::new()),
@@ -980,6 +1839,139 @@ mod test {
         assert_eq!(distances.get(&Node::from("B")), Some(&2));
     }
 
+    #[test]
+    fn shortest_path() {
+        let graph = test_graph();
+
+        assert_eq!(
+            graph.shortest_path(Node::from("D"), Node::from("B"), None),
+            Some(vec![
+                Node::from("D"),
+                Node::from("C"),
+                Node::from("A"),
+                Node::from("B")
+            ])
+        );
+        assert_eq!(
+            graph.shortest_path(Node::from("A"), Node::from("A"), None),
+            Some(vec![Node::from("A")])
+        );
+        assert_eq!(
+            graph.shortest_path(Node::from("D"), Node::from("B"), Some(2)),
+            None
+        );
+        assert_eq!(
+            graph.shortest_path(Node::from("A"), Node::from("D"), None),
+            None
+        );
+    }
+
+    #[test]
+    fn bounded_shortest_path() {
+        let graph = test_graph();
+
+        assert_eq!(
+            graph.bounded_shortest_path(Node::from("D"), Node::from("B"), 8, 100),
+            graph.shortest_path(Node::from("D"), Node::from("B"), None)
+        );
+        assert_eq!(
+            graph.bounded_shortest_path(Node::from("A"), Node::from("A"), 8, 100),
+            Some(vec![Node::from("A")])
+        );
+        assert_eq!(
+            graph.bounded_shortest_path(Node::from("D"), Node::from("B"), 2, 100),
+            None
+        );
+        assert_eq!(
+            graph.bounded_shortest_path(Node::from("A"), Node::from("D"), 8, 100),
+            None
+        );
+        assert_eq!(
+            graph.bounded_shortest_path(Node::from("D"), Node::from("B"), 8, 2),
+            None
+        );
+    }
+
+    #[test]
+    fn bounded_distances() {
+        let graph = test_graph();
+
+        let distances = graph.raw_bounded_distances(Node::from("D").id(), 8, 100);
+        assert_eq!(distances, graph.raw_distances(Node::from("D").id()));
+
+        let distances = graph.raw_bounded_distances(Node::from("D").id(), 1, 100);
+        assert_eq!(distances.len(), 2);
+
+        let distances = graph.raw_bounded_distances(Node::from("D").id(), 8, 3);
+        assert_eq!(distances.len(), 3);
+        assert_eq!(distances.get(&Node::from("A").id()), Some(&2));
+    }
+
+    #[test]
+    fn filtered_edges() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        writer.insert(
+            Node::from("blog.a.com/post"),
+            Node::from("a.com"),
+            "Home".to_string(),
+        );
+        writer.insert(
+            Node::from("b.com"),
+            Node::from("a.com"),
+            "A great site".to_string(),
+        );
+        writer.insert(
+            Node::from("c.com"),
+            Node::from("a.com"),
+            "another SITE".to_string(),
+        );
+
+        let graph = writer.finalize();
+
+        assert_eq!(graph.ingoing_edges(Node::from("a.com")).len(), 3);
+
+        let mut edges = graph.ingoing_edges_filtered(
+            Node::from("a.com"),
+            &EdgeFilter {
+                text: Some("site".to_string()),
+                exclude_same_domain: false,
+            },
+        );
+        edges.sort_by(|a, b| a.from.cmp(&b.from));
+
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0].from, Node::from("b.com"));
+        assert_eq!(edges[1].from, Node::from("c.com"));
+
+        let edges = graph.ingoing_edges_filtered(
+            Node::from("a.com"),
+            &EdgeFilter {
+                text: None,
+                exclude_same_domain: true,
+            },
+        );
+
+        assert_eq!(edges.len(), 2);
+        assert!(edges
+            .iter()
+            .all(|edge| edge.from != Node::from("blog.a.com/post")));
+
+        let edges = graph.outgoing_edges_filtered(
+            Node::from("blog.a.com/post"),
+            &EdgeFilter {
+                text: None,
+                exclude_same_domain: true,
+            },
+        );
+
+        assert!(edges.is_empty());
+    }
+
     #[test]
     fn merge() {
         let mut graphs = Vec::new();
@@ -1079,7 +2071,7 @@ mod test {
 
         assert_eq!(graph.segments.len(), 1);
         assert_eq!(
-            graph.outgoing_edges(Node::from("A"))[0].label,
+            graph.outgoing_edges(Node::from("A"))[0].label.text,
             "a".repeat(MAX_LABEL_LENGTH)
         );
     }
@@ -1120,5 +2112,244 @@ mod test {
                 Node::from("http://b.com/second")
             ]
         );
+
+        assert_eq!(graph.ingoing_edges_by_host(Node::from("b.com"), 1).len(), 1);
+
+        let mut res = graph
+            .ingoing_edges_by_host(Node::from("b.com"), 10)
+            .into_iter()
+            .map(|e| e.from)
+            .collect::<Vec<_>>();
+        res.sort();
+
+        assert_eq!(
+            res,
+            vec![
+                Node::from("http://a.com/first"),
+                Node::from("http://c.com/first")
+            ]
+        );
+    }
+
+    fn outgoing(graph: &Webgraph, node: &str) -> Vec<Node> {
+        let mut res: Vec<_> = graph
+            .outgoing_edges(Node::from(node))
+            .into_iter()
+            .map(|e| e.to)
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn replace_outgoing() {
+        let mut graph = test_graph();
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.replace_outgoing(
+            Node::from("A"),
+            vec![(Node::from("D"), "recrawled".to_string())],
+        );
+        graph.merge(writer.finalize());
+
+        assert_eq!(outgoing(&graph, "A"), vec![Node::from("D")]);
+        assert_eq!(
+            graph.outgoing_edges(Node::from("A"))[0].label.text,
+            "recrawled"
+        );
+        assert_eq!(
+            outgoing(&graph, "B"),
+            vec![Node::from("C")],
+            "other nodes should be unaffected"
+        );
+
+        let mut ingoing: Vec<_> = graph
+            .ingoing_edges(Node::from("C"))
+            .into_iter()
+            .map(|e| e.from)
+            .collect();
+        ingoing.sort();
+        assert_eq!(ingoing, vec![Node::from("B"), Node::from("D")]);
+
+        assert!(graph.raw_ingoing_edges(&Node::from("B").id()).is_empty());
+        assert_eq!(graph.edges().count(), 4);
+    }
+
+    #[test]
+    fn replace_outgoing_in_same_segment() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+
+        for (from, to, label) in test_edges() {
+            writer.insert(from, to, label);
+        }
+        writer.commit();
+
+        writer.replace_outgoing(Node::from("A"), vec![(Node::from("D"), String::new())]);
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+
+        let graph = writer.finalize();
+
+        assert_eq!(
+            outgoing(&graph, "A"),
+            vec![Node::from("B"), Node::from("D")]
+        );
+    }
+
+    #[test]
+    fn remove_edge() {
+        let mut graph = test_graph();
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.remove_edge(&Node::from("A"), &Node::from("B"));
+        graph.merge(writer.finalize());
+
+        assert_eq!(outgoing(&graph, "A"), vec![Node::from("C")]);
+        assert!(graph.raw_ingoing_edges(&Node::from("B").id()).is_empty());
+
+        // edges that are inserted again after being removed are visible
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.insert(Node::from("A"), Node::from("B"), String::new());
+        graph.merge(writer.finalize());
+
+        assert_eq!(
+            outgoing(&graph, "A"),
+            vec![Node::from("B"), Node::from("C")]
+        );
+    }
+
+    #[test]
+    fn tombstones_after_reopen() {
+        let mut graph = test_graph();
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.remove_outgoing(&Node::from("A"));
+        writer.remove_edge(&Node::from("D"), &Node::from("C"));
+        graph.merge(writer.finalize());
+
+        let path = graph.path.clone();
+        drop(graph);
+
+        let graph = WebgraphBuilder::new(path).single_threaded().open();
+
+        assert!(outgoing(&graph, "A").is_empty());
+        assert!(outgoing(&graph, "D").is_empty());
+        assert_eq!(outgoing(&graph, "B"), vec![Node::from("C")]);
+        assert_eq!(graph.edges().count(), 2);
+    }
+
+    #[test]
+    fn pages_by_host_without_live_edges() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.insert(
+            Node::from("http://a.com/"),
+            Node::from("http://b.com/first"),
+            String::new(),
+        );
+        writer.insert(
+            Node::from("http://a.com/"),
+            Node::from("http://b.com/second"),
+            String::new(),
+        );
+        let mut graph = writer.finalize();
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.remove_edge(
+            &Node::from("http://a.com/"),
+            &Node::from("http://b.com/second"),
+        );
+        graph.merge(writer.finalize());
+
+        assert_eq!(
+            graph.pages_by_host(&Node::from("b.com").id()),
+            vec![Node::from("http://b.com/first").id()]
+        );
+    }
+
+    #[test]
+    fn compaction() {
+        let mut graph = test_graph();
+
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            Executor::single_thread(),
+            Compression::default(),
+        );
+        writer.replace_outgoing(
+            Node::from("A"),
+            vec![(Node::from("D"), "recrawled".to_string())],
+        );
+        writer.insert(Node::from("B"), Node::from("C"), "new label".to_string());
+        graph.merge(writer.finalize());
+
+        // the newest label is used both before and after the compaction
+        assert_eq!(
+            graph.outgoing_edges(Node::from("B"))[0].label.text,
+            "new label"
+        );
+        assert_eq!(
+            graph
+                .ingoing_edges(Node::from("C"))
+                .into_iter()
+                .find(|edge| edge.from == Node::from("B"))
+                .unwrap()
+                .label
+                .text,
+            "new label"
+        );
+
+        let before: Vec<_> = ["A", "B", "C", "D"]
+            .into_iter()
+            .map(|node| outgoing(&graph, node))
+            .collect();
+
+        graph.compact();
+
+        assert_eq!(graph.segments.len(), 1);
+        assert!(!graph.has_tombstones());
+
+        let after: Vec<_> = ["A", "B", "C", "D"]
+            .into_iter()
+            .map(|node| outgoing(&graph, node))
+            .collect();
+        assert_eq!(before, after);
+        assert_eq!(
+            graph.outgoing_edges(Node::from("B"))[0].label.text,
+            "new label"
+        );
+
+        let path = graph.path.clone();
+        drop(graph);
+
+        let graph = WebgraphBuilder::new(path).single_threaded().open();
+        assert_eq!(outgoing(&graph, "A"), vec![Node::from("D")]);
+        assert_eq!(graph.edges().count(), 4);
     }
 }
@@ -18,15 +18,18 @@ use std::path::{Path, PathBuf};
 
 use super::{
     store::{EdgeStore, EdgeStoreWriter},
-    Compression, Edge, InnerEdge, NodeID,
+    tombstone::TombstoneStore,
+    Compression, Edge, EdgeLabel, InnerEdge, NodeID,
 };
 
 const ADJACENCY_STORE: &str = "adjacency";
 const REVERSED_ADJACENCY_STORE: &str = "reversed_adjacency";
+const TOMBSTONE_STORE: &str = "tombstones";
 
 pub struct SegmentWriter {
     adjacency: EdgeStoreWriter,
     reversed_adjacency: EdgeStoreWriter,
+    tombstones: TombstoneStore,
     id: String,
     folder_path: String,
 }
@@ -47,6 +50,7 @@ impl SegmentWriter {
                 compression,
                 true,
             ),
+            tombstones: TombstoneStore::open(folder_path.as_ref().join(&id).join(TOMBSTONE_STORE)),
             folder_path: folder_path
                 .as_ref()
                 .as_os_str()
@@ -63,6 +67,7 @@ impl SegmentWriter {
         Segment {
             adjacency: self.adjacency.finalize(),
             reversed_adjacency: self.reversed_adjacency.finalize(),
+            tombstones: self.tombstones,
             folder_path: self.folder_path,
             id: self.id,
         }
@@ -71,17 +76,43 @@ impl SegmentWriter {
     pub fn flush(&mut self) {
         self.adjacency.flush();
         self.reversed_adjacency.flush();
+        self.tombstones.flush();
     }
 
-    pub fn insert(&mut self, edges: &[InnerEdge<String>]) {
+    pub fn insert(&mut self, edges: &[InnerEdge<EdgeLabel>]) {
         self.adjacency.put(edges.iter());
         self.reversed_adjacency.put(edges.iter());
     }
+
+    /// Remove all outgoing edges of the node, both the ones written to this segment
+    /// and the ones in older segments.
+    pub fn remove_outgoing(&mut self, node: &NodeID) {
+        let edges: Vec<_> = self
+            .adjacency
+            .neighbours(node)
+            .into_iter()
+            .map(|to| (*node, to))
+            .collect();
+
+        self.adjacency.remove(edges.iter());
+        self.reversed_adjacency.remove(edges.iter());
+        self.tombstones.insert_node(node);
+    }
+
+    /// Remove the edge, both from this segment and from older segments.
+    pub fn remove_edge(&mut self, from: &NodeID, to: &NodeID) {
+        let edges = [(*from, *to)];
+
+        self.adjacency.remove(edges.iter());
+        self.reversed_adjacency.remove(edges.iter());
+        self.tombstones.insert_edge(from, to);
+    }
 }
 
 pub struct Segment {
     adjacency: EdgeStore,
     reversed_adjacency: EdgeStore,
+    tombstones: TombstoneStore,
     id: String,
     folder_path: String,
 }
@@ -102,6 +133,7 @@ impl Segment {
                 true,
                 compression,
             ),
+            tombstones: TombstoneStore::open(folder_path.as_ref().join(&id).join(TOMBSTONE_STORE)),
             folder_path: folder_path
                 .as_ref()
                 .as_os_str()
@@ -112,19 +144,42 @@ impl Segment {
         }
     }
 
-    pub fn outgoing_edges_with_label(&self, node: &NodeID) -> Vec<Edge<String>> {
-        self.adjacency.get_with_label(node)
+    /// The outgoing edges of the node where `keep_node` returns true for the target, and `keep`
+    /// returns true for the target and the label.
+    pub fn outgoing_edges_with_label<'a, N, F>(
+        &'a self,
+        node: &NodeID,
+        keep_node: N,
+        keep: F,
+    ) -> impl Iterator<Item = Edge<EdgeLabel>> + 'a
+    where
+        N: Fn(&NodeID) -> bool + 'a,
+        F: Fn(&NodeID, &EdgeLabel) -> bool + 'a,
+    {
+        self.adjacency.get_with_label(node, keep_node, keep)
     }
 
-    pub fn outgoing_edges(&self, node: &NodeID) -> Vec<Edge<()>> {
+    pub fn outgoing_edges<'a>(&'a self, node: &NodeID) -> impl Iterator<Item = Edge<()>> + 'a {
         self.adjacency.get_without_label(node)
     }
 
-    pub fn ingoing_edges_with_label(&self, node: &NodeID) -> Vec<Edge<String>> {
-        self.reversed_adjacency.get_with_label(node)
+    /// The ingoing edges of the node where `keep_node` returns true for the source, and `keep`
+    /// returns true for the source and the label.
+    pub fn ingoing_edges_with_label<'a, N, F>(
+        &'a self,
+        node: &NodeID,
+        keep_node: N,
+        keep: F,
+    ) -> impl Iterator<Item = Edge<EdgeLabel>> + 'a
+    where
+        N: Fn(&NodeID) -> bool + 'a,
+        F: Fn(&NodeID, &EdgeLabel) -> bool + 'a,
+    {
+        self.reversed_adjacency
+            .get_with_label(node, keep_node, keep)
     }
 
-    pub fn ingoing_edges(&self, node: &NodeID) -> Vec<Edge<()>> {
+    pub fn ingoing_edges<'a>(&'a self, node: &NodeID) -> impl Iterator<Item = Edge<()>> + 'a {
         self.reversed_adjacency.get_without_label(node)
     }
 
@@ -151,6 +206,20 @@ impl Segment {
     pub fn edges(&self) -> impl Iterator<Item = Edge<()>> + '_ + Send + Sync {
         self.adjacency.iter_without_label()
     }
+
+    pub fn edges_with_label(&self) -> impl Iterator<Item = Edge<EdgeLabel>> + '_ + Send + Sync {
+        self.adjacency.iter_with_label()
+    }
+
+    pub fn has_tombstones(&self) -> bool {
+        !self.tombstones.is_empty()
+    }
+
+    /// Whether the edge has been deleted by this segment.
+    /// Only relevant for edges in segments that are older than this one.
+    pub fn is_deleted(&self, from: &NodeID, to: &NodeID) -> bool {
+        self.tombstones.contains(from, to)
+    }
 }
 
 #[cfg(test)]
@@ -192,28 +261,28 @@ mod test {
         edges.push(InnerEdge {
             from: a.clone(),
             to: b.clone(),
-            label: String::new(),
+            label: EdgeLabel::default(),
         });
         edges.push(InnerEdge {
             from: b.clone(),
             to: c.clone(),
-            label: String::new(),
+            label: EdgeLabel::default(),
         });
         edges.push(InnerEdge {
             from: c.clone(),
             to: a.clone(),
-            label: String::new(),
+            label: EdgeLabel::default(),
         });
         edges.push(InnerEdge {
             from: a.clone(),
             to: c.clone(),
-            label: String::new(),
+            label: EdgeLabel::default(),
         });
 
         writer.insert(&edges);
         let segment = writer.finalize();
 
-        let mut out: Vec<_> = segment.outgoing_edges(&a.id);
+        let mut out: Vec<_> = segment.outgoing_edges(&a.id).collect();
 
         out.sort_by(|a, b| a.to.cmp(&b.to));
 
@@ -233,7 +302,7 @@ mod test {
             ]
         );
 
-        let mut out: Vec<_> = segment.outgoing_edges(&b.id);
+        let mut out: Vec<_> = segment.outgoing_edges(&b.id).collect();
         out.sort_by(|a, b| a.to.cmp(&b.to));
         assert_eq!(
             out,
@@ -244,7 +313,7 @@ mod test {
             },]
         );
 
-        let mut out: Vec<_> = segment.outgoing_edges(&c.id);
+        let mut out: Vec<_> = segment.outgoing_edges(&c.id).collect();
         out.sort_by(|a, b| a.to.cmp(&b.to));
         assert_eq!(
             out,
@@ -255,7 +324,7 @@ mod test {
             },]
         );
 
-        let out: Vec<_> = segment.ingoing_edges(&a.id);
+        let out: Vec<_> = segment.ingoing_edges(&a.id).collect();
         assert_eq!(
             out,
             vec![Edge {
@@ -265,7 +334,7 @@ mod test {
             },]
         );
 
-        let out: Vec<_> = segment.ingoing_edges(&b.id);
+        let out: Vec<_> = segment.ingoing_edges(&b.id).collect();
         assert_eq!(
             out,
             vec![Edge {
@@ -275,7 +344,7 @@ mod test {
             },]
         );
 
-        let mut out: Vec<_> = segment.ingoing_edges(&c.id);
+        let mut out: Vec<_> = segment.ingoing_edges(&c.id).collect();
         out.sort_by(|a, b| a.from.cmp(&b.from));
         assert_eq!(
             out,
@@ -0,0 +1,301 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Build a webgraph split into shards that can be served by different webgraph servers.
+//!
+//! Every edge is stored in the shard of the host of its source node, so all the outgoing
+//! edges of a page (and of every other page on the same host) are found in a single shard.
+//! The ingoing edges of a page can come from any host, so they have to be collected from
+//! all the shards.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::distributed::sonic::replication::ShardIdentifier;
+
+use super::{EdgeLabel, Node, NodeID, Webgraph, WebgraphWriter};
+
+/// A shard of the webgraph and the total number of shards the graph has been split into.
+/// An unsharded graph is the single shard `0` of `1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct WebgraphShard {
+    pub id: u64,
+    pub num_shards: u64,
+}
+
+impl Default for WebgraphShard {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            num_shards: 1,
+        }
+    }
+}
+
+impl ShardIdentifier for WebgraphShard {}
+
+impl WebgraphShard {
+    /// The shard that stores the outgoing edges of the pages on the host.
+    pub fn for_host(host: &NodeID, num_shards: u64) -> Self {
+        Self {
+            id: host.as_u64() % num_shards,
+            num_shards,
+        }
+    }
+
+    /// The shard that stores the outgoing edges of the node.
+    pub fn for_node(node: &Node, num_shards: u64) -> Self {
+        Self::for_host(&node.clone().into_host().id(), num_shards)
+    }
+
+    pub fn contains(&self, host: &NodeID) -> bool {
+        Self::for_host(host, self.num_shards) == *self
+    }
+}
+
+/// Writes a webgraph that is split into shards by the host of the source of each edge.
+/// The graph is sharded while it is built, so no single machine ever needs to hold the
+/// whole graph.
+pub struct ShardedWebgraphWriter {
+    num_shards: u64,
+    writers: Vec<WebgraphWriter>,
+}
+
+impl ShardedWebgraphWriter {
+    /// Open a writer for each of the `num_shards` shards at `path/shard_<id>`.
+    /// A graph with a single shard is written directly to `path`.
+    pub fn new<P, F>(path: P, num_shards: u64, open: F) -> Self
+    where
+        P: AsRef<Path>,
+        F: Fn(PathBuf) -> WebgraphWriter,
+    {
+        assert!(num_shards > 0, "the number of shards must be positive");
+
+        let writers = (0..num_shards)
+            .map(|id| open(Self::shard_path(&path, num_shards, id)))
+            .collect();
+
+        Self {
+            num_shards,
+            writers,
+        }
+    }
+
+    /// The path of the shard in a graph at `path` with `num_shards` shards.
+    pub fn shard_path<P: AsRef<Path>>(path: P, num_shards: u64, id: u64) -> PathBuf {
+        if num_shards == 1 {
+            path.as_ref().to_path_buf()
+        } else {
+            path.as_ref().join(format!("shard_{id}"))
+        }
+    }
+
+    fn writer(&mut self, from: &Node) -> &mut WebgraphWriter {
+        let shard = WebgraphShard::for_node(from, self.num_shards);
+        &mut self.writers[shard.id as usize]
+    }
+
+    pub fn insert(&mut self, from: Node, to: Node, label: impl Into<EdgeLabel>) {
+        self.writer(&from).insert(from, to, label);
+    }
+
+    /// Remove all outgoing edges of the node from the graphs that the shard is later merged into.
+    pub fn remove_outgoing(&mut self, from: &Node) {
+        self.writer(from).remove_outgoing(from);
+    }
+
+    pub fn commit(&mut self) {
+        for writer in &mut self.writers {
+            writer.commit();
+        }
+    }
+
+    /// The finalized shards, ordered by their id.
+    pub fn finalize(self) -> Vec<Webgraph> {
+        self.writers
+            .into_iter()
+            .map(|writer| writer.finalize())
+            .collect()
+    }
+}
+
+/// The paths of the shards of the graph at `path`, ordered by their id. A graph that has not
+/// been split into shards is its own single shard. Fails if some of the shards are missing.
+pub fn shard_paths<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let mut ids = Vec::new();
+
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let name = entry?.file_name();
+
+            if let Some(id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("shard_"))
+                .and_then(|id| id.parse::<u64>().ok())
+            {
+                ids.push(id);
+            }
+        }
+    }
+
+    if ids.is_empty() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    ids.sort_unstable();
+    let num_shards = ids.len() as u64;
+
+    if !ids.iter().copied().eq(0..num_shards) {
+        return Err(anyhow!(
+            "the graph at {} is missing some of its shards, found {:?}",
+            path.display(),
+            ids
+        ));
+    }
+
+    Ok((0..num_shards)
+        .map(|id| path.join(format!("shard_{id}")))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{executor::Executor, webgraph::Compression};
+
+    use super::*;
+
+    const EDGES: [(&str, &str); 7] = [
+        ("a.com/1", "b.com/1"),
+        ("a.com/2", "b.com/1"),
+        ("b.com/1", "c.com/1"),
+        ("c.com/1", "b.com/1"),
+        ("c.com/2", "a.com/1"),
+        ("d.com/1", "b.com/2"),
+        ("e.com/1", "b.com/1"),
+    ];
+
+    fn open_writer(path: PathBuf) -> WebgraphWriter {
+        WebgraphWriter::new(path, Executor::single_thread(), Compression::default())
+    }
+
+    #[test]
+    fn shard_by_source_host() {
+        let mut writer = open_writer(crate::gen_temp_path());
+        let mut sharded = ShardedWebgraphWriter::new(crate::gen_temp_path(), 3, open_writer);
+
+        for (from, to) in EDGES {
+            writer.insert(Node::from(from), Node::from(to), from.to_string());
+            sharded.insert(Node::from(from), Node::from(to), from.to_string());
+        }
+
+        let graph = writer.finalize();
+        let shards = sharded.finalize();
+
+        assert_eq!(shards.len(), 3);
+
+        for node in ["a.com/1", "a.com/2", "b.com/1", "c.com/1", "c.com/2"] {
+            let node = Node::from(node);
+            let owner = WebgraphShard::for_node(&node, 3);
+
+            for (id, shard) in shards.iter().enumerate() {
+                let edges = shard.outgoing_edges(node.clone());
+
+                if id as u64 == owner.id {
+                    assert_eq!(edges, graph.outgoing_edges(node.clone()));
+                } else {
+                    assert!(edges.is_empty());
+                }
+            }
+        }
+
+        let mut ingoing: Vec<_> = shards
+            .iter()
+            .flat_map(|shard| shard.ingoing_edges(Node::from("b.com/1")))
+            .collect();
+        ingoing.sort_by(|a, b| a.from.cmp(&b.from));
+
+        let mut expected = graph.ingoing_edges(Node::from("b.com/1"));
+        expected.sort_by(|a, b| a.from.cmp(&b.from));
+
+        assert_eq!(ingoing.len(), 4);
+        assert_eq!(ingoing, expected);
+    }
+
+    #[test]
+    fn single_shard_is_unsharded() {
+        let path = crate::gen_temp_path();
+        let mut sharded = ShardedWebgraphWriter::new(&path, 1, open_writer);
+
+        for (from, to) in EDGES {
+            sharded.insert(Node::from(from), Node::from(to), from.to_string());
+        }
+
+        let shards = sharded.finalize();
+
+        assert_eq!(shards.len(), 1);
+        assert_eq!(Path::new(&shards[0].path), path);
+        assert_eq!(shards[0].ingoing_edges(Node::from("b.com/1")).len(), 4);
+    }
+
+    #[test]
+    fn find_shard_paths() {
+        let path = crate::gen_temp_path();
+        let mut sharded = ShardedWebgraphWriter::new(&path, 3, open_writer);
+
+        for (from, to) in EDGES {
+            sharded.insert(Node::from(from), Node::from(to), from.to_string());
+        }
+
+        sharded.finalize();
+
+        assert_eq!(
+            shard_paths(&path).unwrap(),
+            vec![
+                path.join("shard_0"),
+                path.join("shard_1"),
+                path.join("shard_2")
+            ]
+        );
+
+        std::fs::remove_dir_all(path.join("shard_1")).unwrap();
+        assert!(shard_paths(&path).is_err());
+
+        let unsharded = crate::gen_temp_path();
+        ShardedWebgraphWriter::new(&unsharded, 1, open_writer).finalize();
+        assert_eq!(shard_paths(&unsharded).unwrap(), vec![unsharded]);
+    }
+
+    #[test]
+    fn contains() {
+        let host = Node::from("a.com").id();
+        let shard = WebgraphShard::for_host(&host, 4);
+
+        assert!(shard.contains(&host));
+        assert!(WebgraphShard::default().contains(&host));
+        assert_eq!(
+            (0..4)
+                .filter(|id| WebgraphShard {
+                    id: *id,
+                    num_shards: 4
+                }
+                .contains(&host))
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,514 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Link spam detection on the host graph.
+//!
+//! Each host gets a spam score between 0 (clean) and 1 (spam) which combines three estimates:
+//! * Spam mass: the fraction of the PageRank of a host that does not come from a core of
+//!   trusted hosts (TrustRank). Hosts with a high PageRank that is not explained by the trusted
+//!   hosts have most likely been boosted by link spam. The trusted hosts only cover a small part
+//!   of the good hosts, so the spam mass is calibrated against the typical spam mass of the graph.
+//! * Anti-TrustRank: the personalised PageRank on the reversed graph from a set of known spam
+//!   hosts. Hosts that link to spam are likely spam themselves.
+//! * Reciprocal clusters: hosts whose links mostly go to a densely interlinked group of hosts
+//!   on other domains that all link back, which is the typical structure of a link farm.
+//!
+//! The estimates are combined as if they were independent probabilities of the host being spam.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::Path,
+};
+
+use anyhow::Result;
+use tracing::info;
+
+use crate::kv::{rocksdb_store::RocksDbStore, Kv};
+
+use super::{
+    centrality::{
+        pagerank::{self, Direction, Teleport},
+        LinkWeights,
+    },
+    NodeID, Webgraph,
+};
+
+/// Only hosts with a PageRank of at least this percentile get a spam mass. The spam mass
+/// of hosts with a lower PageRank is too noisy to be meaningful.
+const MIN_PAGERANK_PERCENTILE: f64 = 0.5;
+
+/// A cluster must have at least this many reciprocal neighbours to be considered a link farm.
+const MIN_CLUSTER_SIZE: usize = 3;
+
+/// Only this many reciprocal neighbours are considered when calculating the density of a cluster.
+const MAX_CLUSTER_SIZE: usize = 1_000;
+
+/// The density of a cluster is estimated from the reciprocal neighbours of this many of its members.
+const MAX_SAMPLED_CLUSTER_MEMBERS: usize = 64;
+
+/// Hosts with more outgoing links than this are not considered part of a reciprocal cluster.
+/// Their links are too spread out to be dominated by a link farm, and loading the
+/// neighbourhood of every such host would dominate the time it takes to score the graph.
+const MAX_OUTGOING_DEGREE: usize = 10_000;
+
+/// The relative spam mass of the hosts with trusted hosts as the good core.
+fn spam_mass(graph: &Webgraph, trusted: &BTreeSet<NodeID>) -> BTreeMap<NodeID, f64> {
+    let trusted: BTreeSet<NodeID> = trusted
+        .iter()
+        .filter(|node| graph.id2node(node).is_some())
+        .copied()
+        .collect();
+
+    if trusted.is_empty() {
+        info!("None of the trusted hosts are in the graph, skipping spam mass");
+        return BTreeMap::new();
+    }
+
+    let weights = LinkWeights::default();
+    let pagerank =
+        pagerank::power_iteration(graph, Direction::Outgoing, Teleport::Uniform, &weights);
+    let trustrank = pagerank::power_iteration(
+        graph,
+        Direction::Outgoing,
+        Teleport::Seeds(trusted.clone()),
+        &weights,
+    );
+
+    // The trusted hosts are only a small sample of the good hosts, so the TrustRank is scaled such
+    // that the trusted hosts get the same total score as they have in the PageRank.
+    let trusted_pagerank: f64 = trusted.iter().filter_map(|node| pagerank.get(node)).sum();
+    let trusted_trustrank: f64 = trusted.iter().filter_map(|node| trustrank.get(node)).sum();
+
+    if trusted_trustrank == 0.0 {
+        return BTreeMap::new();
+    }
+
+    let scale = trusted_pagerank / trusted_trustrank;
+
+    let mut ranks: Vec<f64> = pagerank.values().copied().collect();
+    ranks.sort_by(f64::total_cmp);
+    let min_pagerank = ranks[((ranks.len() - 1) as f64 * MIN_PAGERANK_PERCENTILE) as usize];
+
+    let relative_mass: BTreeMap<NodeID, f64> = pagerank
+        .into_iter()
+        .filter(|(_, rank)| *rank >= min_pagerank && *rank > 0.0)
+        .map(|(node, rank)| {
+            let trust = trustrank.get(&node).copied().unwrap_or_default() * scale;
+            (node, ((rank - trust) / rank).clamp(0.0, 1.0))
+        })
+        .collect();
+
+    // Most of the good hosts are far from the few trusted hosts, so their relative mass is high
+    // as well. Only the mass above the median of the considered hosts is counted as spam mass.
+    let mut masses: Vec<f64> = relative_mass.values().copied().collect();
+    masses.sort_by(f64::total_cmp);
+
+    let baseline = match masses.get(masses.len() / 2) {
+        Some(baseline) if *baseline < 1.0 => *baseline,
+        _ => return BTreeMap::new(),
+    };
+
+    relative_mass
+        .into_iter()
+        .map(|(node, mass)| (node, ((mass - baseline) / (1.0 - baseline)).clamp(0.0, 1.0)))
+        .collect()
+}
+
+/// Anti-TrustRank propagated backwards from the spam hosts.
+fn anti_trustrank(graph: &Webgraph, spam: &BTreeSet<NodeID>) -> BTreeMap<NodeID, f64> {
+    let spam: BTreeSet<NodeID> = spam
+        .iter()
+        .filter(|node| graph.id2node(node).is_some())
+        .copied()
+        .collect();
+
+    if spam.is_empty() {
+        info!("None of the spam hosts are in the graph, skipping anti-trustrank");
+        return BTreeMap::new();
+    }
+
+    pagerank::normalize(pagerank::power_iteration(
+        graph,
+        Direction::Ingoing,
+        Teleport::Seeds(spam),
+        &LinkWeights::default(),
+    ))
+}
+
+/// The number of outgoing links of the host and the hosts it links to that link back.
+/// Hosts on the same registrable domain (e.g. the subdomains of a site that link to each other)
+/// are not counted as reciprocal neighbours. Returns `None` for hosts with too many outgoing links.
+fn reciprocal_neighbours(graph: &Webgraph, node: &NodeID) -> Option<(usize, BTreeSet<NodeID>)> {
+    let outgoing: BTreeSet<NodeID> = graph
+        .raw_outgoing_edges(node)
+        .into_iter()
+        .map(|edge| edge.to)
+        .filter(|to| to != node)
+        .collect();
+
+    if outgoing.len() > MAX_OUTGOING_DEGREE {
+        return None;
+    }
+
+    let domain = graph.id2node(node).and_then(|node| node.domain());
+
+    let reciprocal = graph
+        .raw_ingoing_edges(node)
+        .into_iter()
+        .map(|edge| edge.from)
+        .filter(|from| outgoing.contains(from))
+        .filter(|from| {
+            domain.is_none() || graph.id2node(from).and_then(|node| node.domain()) != domain
+        })
+        .collect();
+
+    Some((outgoing.len(), reciprocal))
+}
+
+/// How much the outgoing links of the host go to a dense cluster of hosts that all link back.
+fn reciprocal_cluster(graph: &Webgraph, node: &NodeID) -> f64 {
+    let Some((num_outgoing, reciprocal)) = reciprocal_neighbours(graph, node) else {
+        return 0.0;
+    };
+
+    if reciprocal.len() < MIN_CLUSTER_SIZE {
+        return 0.0;
+    }
+
+    let cluster: BTreeSet<NodeID> = reciprocal.into_iter().take(MAX_CLUSTER_SIZE).collect();
+
+    // the ids are hashes of the host names, so the first members are a pseudo-random sample
+    let sample: Vec<&NodeID> = cluster.iter().take(MAX_SAMPLED_CLUSTER_MEMBERS).collect();
+
+    let reciprocal_pairs: usize = sample
+        .iter()
+        .filter_map(|neighbour| reciprocal_neighbours(graph, neighbour))
+        .map(|(_, reciprocal)| reciprocal.intersection(&cluster).count())
+        .sum();
+
+    let k = cluster.len() as f64;
+    let density = reciprocal_pairs as f64 / (sample.len() as f64 * (k - 1.0));
+    let fraction = cluster.len() as f64 / num_outgoing as f64;
+
+    (density * fraction).clamp(0.0, 1.0)
+}
+
+/// Hosts with a spam score of at least this are labelled as spam.
+const SPAM_THRESHOLD: f64 = 0.8;
+
+/// Hosts with a spam score of at least this are labelled as suspicious.
+const SUSPICIOUS_THRESHOLD: f64 = 0.5;
+
+/// The label of the host that optics can match using `Spam("...")`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpamLabel {
+    Clean,
+    Suspicious,
+    Spam,
+}
+
+impl SpamLabel {
+    pub fn from_score(score: f64) -> Self {
+        if score >= SPAM_THRESHOLD {
+            SpamLabel::Spam
+        } else if score >= SUSPICIOUS_THRESHOLD {
+            SpamLabel::Suspicious
+        } else {
+            SpamLabel::Clean
+        }
+    }
+}
+
+impl Display for SpamLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            SpamLabel::Clean => "clean",
+            SpamLabel::Suspicious => "suspicious",
+            SpamLabel::Spam => "spam",
+        };
+        write!(f, "{label}")
+    }
+}
+
+/// The spam score of each host in the host graph.
+pub struct SpamScores {
+    inner: RocksDbStore<NodeID, f64>,
+}
+
+impl SpamScores {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            inner: RocksDbStore::open(path),
+        }
+    }
+
+    pub fn build<P: AsRef<Path>>(
+        graph: &Webgraph,
+        trusted: &BTreeSet<NodeID>,
+        spam: &BTreeSet<NodeID>,
+        output: P,
+    ) -> Result<Self> {
+        if output.as_ref().exists() {
+            return Err(anyhow::anyhow!("output path already exists"));
+        }
+
+        let spam_mass = spam_mass(graph, trusted);
+        info!("Spam mass calculated");
+
+        let anti_trustrank = anti_trustrank(graph, spam);
+        info!("Anti-TrustRank calculated");
+
+        let inner = RocksDbStore::open(output.as_ref());
+        let pb = indicatif::ProgressBar::new(graph.nodes().count() as u64);
+
+        for node in graph.nodes() {
+            pb.inc(1);
+
+            let estimates = [
+                spam_mass.get(&node).copied().unwrap_or_default(),
+                anti_trustrank.get(&node).copied().unwrap_or_default(),
+                reciprocal_cluster(graph, &node),
+            ];
+
+            let not_spam: f64 = estimates.iter().map(|p| 1.0 - p).product();
+            let score = 1.0 - not_spam;
+
+            if score > 0.0 {
+                inner.insert(node, score);
+            }
+        }
+
+        pb.finish_and_clear();
+        inner.flush();
+
+        Ok(Self { inner })
+    }
+
+    /// The spam score of the host. Hosts that are not in the store are considered clean.
+    pub fn get(&self, node: &NodeID) -> f64 {
+        self.inner.get(node).unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeID, f64)> + '_ {
+        self.inner.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webgraph::{Node, WebgraphWriter};
+
+    fn test_graph() -> Webgraph {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        for (from, to) in [
+            ("a.com", "b.com"),
+            ("b.com", "a.com"),
+            ("a.com", "c.com"),
+            ("b.com", "c.com"),
+            ("c.com", "a.com"),
+            ("c.com", "d.com"),
+            ("d.com", "a.com"),
+        ] {
+            writer.insert(Node::from(from), Node::from(to), String::new());
+        }
+
+        let farm: Vec<_> = (1..=5).map(|i| format!("f{i}.com")).collect();
+
+        for from in &farm {
+            for to in &farm {
+                if from != to {
+                    writer.insert(
+                        Node::from(from.as_str()),
+                        Node::from(to.as_str()),
+                        String::new(),
+                    );
+                }
+            }
+
+            writer.insert(
+                Node::from(from.as_str()),
+                Node::from("spam.com"),
+                String::new(),
+            );
+        }
+
+        writer.insert(Node::from("spam.com"), Node::from("f1.com"), String::new());
+
+        writer.finalize()
+    }
+
+    #[test]
+    fn link_farm() {
+        let graph = test_graph();
+
+        let trusted = [Node::from("a.com").id(), Node::from("b.com").id()]
+            .into_iter()
+            .collect();
+        let spam = [Node::from("spam.com").id()].into_iter().collect();
+
+        let scores = SpamScores::build(&graph, &trusted, &spam, crate::gen_temp_path()).unwrap();
+
+        assert!(scores.get(&Node::from("spam.com").id()) > 0.5);
+        assert!(scores.get(&Node::from("f1.com").id()) > 0.5);
+        assert!(scores.get(&Node::from("f3.com").id()) > 0.5);
+
+        assert!(scores.get(&Node::from("a.com").id()) < 0.1);
+        assert!(scores.get(&Node::from("b.com").id()) < 0.1);
+    }
+
+    #[test]
+    fn spam_mass_with_few_seeds() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        // a graph where a few hosts get most of the links
+        let num_good = 300;
+        let good: Vec<_> = (0..num_good).map(|i| format!("g{i}.com")).collect();
+
+        let mut state: u64 = 42;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state >> 33
+        };
+
+        for (i, from) in good.iter().enumerate() {
+            for _ in 0..5 {
+                let x = next() % num_good as u64;
+                let to = (x * x / num_good as u64) as usize;
+
+                if to != i {
+                    writer.insert(
+                        Node::from(from.as_str()),
+                        Node::from(good[to].as_str()),
+                        String::new(),
+                    );
+                }
+            }
+        }
+
+        // a link farm that boosts spam.com, which also got a link from one of the good hosts
+        for i in 0..30 {
+            let farm = format!("f{i}.com");
+            writer.insert(
+                Node::from(farm.as_str()),
+                Node::from("spam.com"),
+                String::new(),
+            );
+            writer.insert(
+                Node::from("spam.com"),
+                Node::from(farm.as_str()),
+                String::new(),
+            );
+        }
+        writer.insert(
+            Node::from(good[num_good - 1].as_str()),
+            Node::from("spam.com"),
+            String::new(),
+        );
+
+        let graph = writer.finalize();
+
+        // 2% of the good hosts are trusted
+        let trusted = (0..num_good)
+            .step_by(50)
+            .map(|i| Node::from(good[i].as_str()).id())
+            .collect();
+
+        let mass = spam_mass(&graph, &trusted);
+
+        assert_eq!(
+            SpamLabel::from_score(mass[&Node::from("spam.com").id()]),
+            SpamLabel::Spam
+        );
+
+        for host in &good {
+            let mass = mass
+                .get(&Node::from(host.as_str()).id())
+                .copied()
+                .unwrap_or_default();
+
+            assert_ne!(SpamLabel::from_score(mass), SpamLabel::Spam, "{host}");
+        }
+    }
+
+    #[test]
+    fn reciprocal_cluster_without_seeds() {
+        let graph = test_graph();
+
+        let scores = SpamScores::build(
+            &graph,
+            &BTreeSet::new(),
+            &BTreeSet::new(),
+            crate::gen_temp_path(),
+        )
+        .unwrap();
+
+        assert!(scores.get(&Node::from("f2.com").id()) > 0.5);
+        assert_eq!(scores.get(&Node::from("a.com").id()), 0.0);
+    }
+
+    #[test]
+    fn same_domain_is_not_a_cluster() {
+        let mut writer = WebgraphWriter::new(
+            crate::gen_temp_path(),
+            crate::executor::Executor::single_thread(),
+            crate::webgraph::Compression::default(),
+        );
+
+        let hosts: Vec<_> = ["www", "blog", "docs", "shop", "news"]
+            .iter()
+            .map(|sub| format!("{sub}.example.com"))
+            .collect();
+
+        for from in &hosts {
+            for to in &hosts {
+                if from != to {
+                    writer.insert(
+                        Node::from(from.as_str()),
+                        Node::from(to.as_str()),
+                        String::new(),
+                    );
+                }
+            }
+        }
+
+        let graph = writer.finalize();
+
+        let scores = SpamScores::build(
+            &graph,
+            &BTreeSet::new(),
+            &BTreeSet::new(),
+            crate::gen_temp_path(),
+        )
+        .unwrap();
+
+        for host in &hosts {
+            assert_eq!(scores.get(&Node::from(host.as_str()).id()), 0.0);
+        }
+    }
+}
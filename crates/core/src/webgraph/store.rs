@@ -20,7 +20,7 @@ use itertools::Itertools;
 use memmap2::Mmap;
 use rocksdb::BlockBasedOptions;
 
-use super::{Compression, Edge, EdgeLabel, FullNodeID, InnerEdge, NodeID};
+use super::{Compression, Edge, EdgeLabel, FullNodeID, InnerEdge, NodeID, StoredLabel};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 struct SerializedEdge {
@@ -31,6 +31,117 @@ struct SerializedEdge {
 
 pub const MAX_BATCH_SIZE: usize = 100_000;
 
+/// Size of the lengths in bincode serialized vectors.
+const SERIALIZED_LEN_BYTES: usize = u64::BITS as usize / 8;
+
+/// Decodes the labels of a node one at a time from the bincode serialized `Vec<Vec<u8>>`
+/// they are stored as, so the labels of all the edges are never in memory at the same time.
+#[derive(Default)]
+struct SerializedLabels {
+    bytes: Vec<u8>,
+    pos: usize,
+    remaining: u64,
+}
+
+impl SerializedLabels {
+    fn new(bytes: Vec<u8>) -> Self {
+        let mut labels = Self {
+            bytes,
+            pos: 0,
+            remaining: 0,
+        };
+
+        labels.remaining = labels.read_len().unwrap_or_default();
+
+        labels
+    }
+
+    fn read_len(&mut self) -> Option<u64> {
+        let end = self.pos + SERIALIZED_LEN_BYTES;
+        let len = u64::from_le_bytes(self.bytes.get(self.pos..end)?.try_into().ok()?);
+        self.pos = end;
+
+        Some(len)
+    }
+}
+
+impl SerializedLabels {
+    /// Skip the next `n` labels without decoding them.
+    fn skip_labels(&mut self, n: usize) {
+        for _ in 0..n {
+            if self.remaining == 0 {
+                return;
+            }
+
+            self.remaining -= 1;
+
+            match self.read_len() {
+                Some(len) => self.pos += len as usize,
+                None => self.remaining = 0,
+            }
+        }
+    }
+}
+
+impl Iterator for SerializedLabels {
+    type Item = anyhow::Result<EdgeLabel>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+
+        let Some(len) = self.read_len() else {
+            return Some(Err(anyhow::anyhow!("truncated edge labels")));
+        };
+
+        let end = self.pos + len as usize;
+        let Some(bytes) = self.bytes.get(self.pos..end) else {
+            return Some(Err(anyhow::anyhow!("truncated edge labels")));
+        };
+        self.pos = end;
+
+        Some(EdgeLabel::from_bytes(bytes))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl ExactSizeIterator for SerializedLabels {}
+
+/// Decodes the node ids one at a time from the bincode serialized `Vec<NodeID>`
+/// they are stored as.
+#[derive(Default)]
+struct SerializedNodes {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl SerializedNodes {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            pos: SERIALIZED_LEN_BYTES,
+        }
+    }
+}
+
+impl Iterator for SerializedNodes {
+    type Item = NodeID;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let end = self.pos + SERIALIZED_LEN_BYTES;
+        let id = u64::from_le_bytes(self.bytes.get(self.pos..end)?.try_into().ok()?);
+        self.pos = end;
+
+        Some(NodeID(id))
+    }
+}
+
 pub struct EdgeStoreWriter {
     reversed: bool,
     db: rocksdb::DB,
@@ -78,7 +189,7 @@ impl EdgeStoreWriter {
         }
     }
 
-    pub fn put<'a, L: EdgeLabel + 'a>(&'a self, edges: impl Iterator<Item = &'a InnerEdge<L>>) {
+    pub fn put<'a, L: StoredLabel + 'a>(&'a self, edges: impl Iterator<Item = &'a InnerEdge<L>>) {
         let mut batch = rocksdb::WriteBatch::default();
 
         let mut opts = rocksdb::WriteOptions::default();
@@ -94,19 +205,7 @@ impl EdgeStoreWriter {
             })
             .unwrap();
 
-            let key_bytes = if self.reversed {
-                [
-                    edge.to.id.as_u64().to_le_bytes(),
-                    edge.from.id.as_u64().to_le_bytes(),
-                ]
-                .concat()
-            } else {
-                [
-                    edge.from.id.as_u64().to_le_bytes(),
-                    edge.to.id.as_u64().to_le_bytes(),
-                ]
-                .concat()
-            };
+            let key_bytes = self.key(&edge.from.id, &edge.to.id);
 
             batch.put(key_bytes, value_bytes);
 
@@ -119,7 +218,58 @@ impl EdgeStoreWriter {
         self.db.write_opt(batch, &opts).unwrap();
     }
 
-    pub fn iter<L: EdgeLabel>(&self) -> impl Iterator<Item = InnerEdge<L>> + '_ + Send + Sync {
+    fn key(&self, from: &NodeID, to: &NodeID) -> Vec<u8> {
+        if self.reversed {
+            [to.as_u64().to_le_bytes(), from.as_u64().to_le_bytes()].concat()
+        } else {
+            [from.as_u64().to_le_bytes(), to.as_u64().to_le_bytes()].concat()
+        }
+    }
+
+    /// The nodes that are connected to the node by the edges written so far.
+    /// These are the outgoing nodes if the store is not reversed and the ingoing nodes otherwise.
+    pub fn neighbours(&self, node: &NodeID) -> Vec<NodeID> {
+        let prefix = node.as_u64().to_le_bytes();
+
+        self.db
+            .iterator(rocksdb::IteratorMode::From(
+                &prefix,
+                rocksdb::Direction::Forward,
+            ))
+            .map_while(|res| {
+                let (key, _) = res.ok()?;
+
+                if key[..u64::BITS as usize / 8] != prefix {
+                    return None;
+                }
+
+                Some(NodeID(u64::from_le_bytes(
+                    key[u64::BITS as usize / 8..].try_into().unwrap(),
+                )))
+            })
+            .collect()
+    }
+
+    /// Remove edges that have been written to the store.
+    pub fn remove<'a>(&self, edges: impl Iterator<Item = &'a (NodeID, NodeID)>) {
+        let mut batch = rocksdb::WriteBatch::default();
+
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.disable_wal(true);
+
+        for (from, to) in edges {
+            batch.delete(self.key(from, to));
+
+            if batch.len() >= MAX_BATCH_SIZE {
+                self.db.write_opt(batch, &opts).unwrap();
+                batch = rocksdb::WriteBatch::default();
+            }
+        }
+
+        self.db.write_opt(batch, &opts).unwrap();
+    }
+
+    pub fn iter<L: StoredLabel>(&self) -> impl Iterator<Item = InnerEdge<L>> + '_ + Send + Sync {
         let read_opts = rocksdb::ReadOptions::default();
 
         self.db
@@ -375,7 +525,7 @@ impl EdgeStore {
     /// The edges *must* have been de-duplicated by their from/to node.
     /// I.e. if the store is not reversed, there should only ever be a single
     /// put for each from node, and vice versa.
-    fn put(&mut self, edges: &[InnerEdge<String>]) {
+    fn put(&mut self, edges: &[InnerEdge<EdgeLabel>]) {
         if edges.is_empty() {
             return;
         }
@@ -399,7 +549,7 @@ impl EdgeStore {
         let mut edge_nodes = Vec::new();
 
         for edge in edges {
-            edge_labels.push(edge.label.clone());
+            edge_labels.push(edge.label.to_bytes().unwrap());
             edge_nodes.push(if self.reversed {
                 edge.from.id
             } else {
@@ -450,7 +600,7 @@ impl EdgeStore {
         path: P,
         compression: Compression,
         reversed: bool,
-        edges: impl Iterator<Item = InnerEdge<String>>,
+        edges: impl Iterator<Item = InnerEdge<EdgeLabel>>,
     ) -> Self {
         let mut s = Self::open(path, reversed, compression);
 
@@ -508,92 +658,127 @@ impl EdgeStore {
         self.edge_labels_len = self.edge_labels.len();
     }
 
-    pub fn get_with_label(&self, node: &NodeID) -> Vec<Edge<String>> {
-        let node_bytes = node.as_u64().to_le_bytes();
+    fn range(&self, cf: &str, node: &NodeID) -> Option<Range<usize>> {
+        let cf = self.ranges.cf_handle(cf).unwrap();
+        let bytes = self
+            .ranges
+            .get_cf(cf, node.as_u64().to_le_bytes())
+            .unwrap()?;
 
-        let node_cf = self.ranges.cf_handle("nodes").unwrap();
-        let edge_cf = self.ranges.cf_handle("labels").unwrap();
+        Some(bincode::deserialize::<Range<usize>>(&bytes).unwrap())
+    }
 
-        match (
-            self.ranges.get_cf(node_cf, node_bytes).unwrap(),
-            self.ranges.get_cf(edge_cf, node_bytes).unwrap(),
-        ) {
-            (Some(node_range_bytes), Some(edge_range_bytes)) => {
-                let node_range = bincode::deserialize::<Range<usize>>(&node_range_bytes).unwrap();
-                let edge_range = bincode::deserialize::<Range<usize>>(&edge_range_bytes).unwrap();
-
-                let edge_labels = &self.edge_labels[edge_range];
-                let edge_labels = self.compression.decompress(edge_labels);
-                let edge_labels: Vec<_> = bincode::deserialize(&edge_labels).unwrap();
-
-                let edge_nodes = &self.edge_nodes[node_range];
-                let edge_nodes = self.compression.decompress(edge_nodes);
-                let edge_nodes: Vec<_> = bincode::deserialize(&edge_nodes).unwrap();
-
-                edge_labels
-                    .into_iter()
-                    .zip_eq(edge_nodes)
-                    .map(|(label, other)| {
-                        if self.reversed {
-                            Edge {
-                                from: other,
-                                to: *node,
-                                label,
-                            }
-                        } else {
-                            Edge {
-                                from: *node,
-                                to: other,
-                                label,
-                            }
-                        }
-                    })
-                    .collect()
+    fn other_nodes(&self, node: &NodeID) -> SerializedNodes {
+        match self.range("nodes", node) {
+            Some(range) => {
+                SerializedNodes::new(self.compression.decompress(&self.edge_nodes[range]))
             }
-            _ => Vec::new(),
+            None => SerializedNodes::default(),
         }
     }
 
-    pub fn get_without_label(&self, node: &NodeID) -> Vec<Edge<()>> {
-        let node_bytes = node.as_u64().to_le_bytes();
-
-        let node_cf = self.ranges.cf_handle("nodes").unwrap();
-
-        match self.ranges.get_cf(node_cf, node_bytes).unwrap() {
-            Some(node_range_bytes) => {
-                let node_range = bincode::deserialize::<Range<usize>>(&node_range_bytes).unwrap();
-
-                let edge_nodes = &self.edge_nodes[node_range];
-                let edge_nodes = self.compression.decompress(edge_nodes);
-                let edge_nodes: Vec<_> = bincode::deserialize(&edge_nodes).unwrap();
-
-                edge_nodes
-                    .into_iter()
-                    .map(|other| {
-                        if self.reversed {
-                            Edge {
-                                from: other,
-                                to: *node,
-                                label: (),
-                            }
-                        } else {
-                            Edge {
-                                from: *node,
-                                to: other,
-                                label: (),
-                            }
-                        }
-                    })
-                    .collect()
+    fn edge(&self, node: NodeID, other: NodeID) -> Edge<()> {
+        if self.reversed {
+            Edge {
+                from: other,
+                to: node,
+                label: (),
+            }
+        } else {
+            Edge {
+                from: node,
+                to: other,
+                label: (),
             }
-            _ => Vec::new(),
         }
     }
 
+    /// The edges of the node with their labels. Only the edges where `keep_node` returns true
+    /// for the other node of the edge, and `keep` returns true for the other node and the label,
+    /// are returned. The labels are only decompressed once an edge passes `keep_node` and are
+    /// decoded one edge at a time, so edges that are filtered away are never collected.
+    pub fn get_with_label<'a, N, F>(
+        &'a self,
+        node: &NodeID,
+        keep_node: N,
+        keep: F,
+    ) -> impl Iterator<Item = Edge<EdgeLabel>> + 'a
+    where
+        N: Fn(&NodeID) -> bool + 'a,
+        F: Fn(&NodeID, &EdgeLabel) -> bool + 'a,
+    {
+        let node = *node;
+        let mut labels: Option<SerializedLabels> = None;
+        let mut next_label = 0;
+
+        self.other_nodes(&node)
+            .enumerate()
+            .filter_map(move |(i, other)| {
+                if !keep_node(&other) {
+                    return None;
+                }
+
+                let labels = labels.get_or_insert_with(|| match self.range("labels", &node) {
+                    Some(range) => {
+                        SerializedLabels::new(self.compression.decompress(&self.edge_labels[range]))
+                    }
+                    None => SerializedLabels::default(),
+                });
+
+                labels.skip_labels(i - next_label);
+                next_label = i + 1;
+
+                let label = match labels.next() {
+                    Some(Ok(label)) => label,
+                    Some(Err(err)) => {
+                        tracing::error!("failed to decode edge label: {}", err);
+                        return None;
+                    }
+                    None => {
+                        tracing::error!("missing edge label");
+                        return None;
+                    }
+                };
+
+                if !keep(&other, &label) {
+                    return None;
+                }
+
+                let edge = self.edge(node, other);
+
+                Some(Edge {
+                    from: edge.from,
+                    to: edge.to,
+                    label,
+                })
+            })
+    }
+
+    /// The edges of the node without their labels, so the labels are never decompressed.
+    pub fn get_without_label<'a>(&'a self, node: &NodeID) -> impl Iterator<Item = Edge<()>> + 'a {
+        let node = *node;
+
+        self.other_nodes(&node)
+            .map(move |other| self.edge(node, other))
+    }
+
     pub fn nodes_by_prefix(&self, prefix: &NodeID) -> Vec<NodeID> {
         self.prefixes.get(prefix)
     }
 
+    pub fn iter_with_label(&self) -> impl Iterator<Item = Edge<EdgeLabel>> + '_ + Send + Sync {
+        let node_cf = self.ranges.cf_handle("nodes").unwrap();
+
+        self.ranges
+            .iterator_cf(node_cf, rocksdb::IteratorMode::Start)
+            .flat_map(move |res| {
+                let (key, _) = res.unwrap();
+
+                let node = u64::from_le_bytes((*key).try_into().unwrap());
+                self.get_with_label(&NodeID(node), |_| true, |_, _| true)
+            })
+    }
+
     pub fn iter_without_label(&self) -> impl Iterator<Item = Edge<()>> + '_ + Send + Sync {
         let node_cf = self.ranges.cf_handle("nodes").unwrap();
 
@@ -602,29 +787,12 @@ impl EdgeStore {
             .flat_map(move |res| {
                 let (key, val) = res.unwrap();
 
-                let node = u64::from_le_bytes((*key).try_into().unwrap());
-                let node = NodeID(node);
+                let node = NodeID(u64::from_le_bytes((*key).try_into().unwrap()));
 
                 let node_range = bincode::deserialize::<Range<usize>>(&val).unwrap();
-                let edge_nodes = &self.edge_nodes[node_range];
-                let edge_nodes = self.compression.decompress(edge_nodes);
-                let edge_nodes: Vec<_> = bincode::deserialize(&edge_nodes).unwrap();
-
-                edge_nodes.into_iter().map(move |other| {
-                    if self.reversed {
-                        Edge {
-                            from: other,
-                            to: node,
-                            label: (),
-                        }
-                    } else {
-                        Edge {
-                            from: node,
-                            to: other,
-                            label: (),
-                        }
-                    }
-                })
+                let edge_nodes = self.compression.decompress(&self.edge_nodes[node_range]);
+
+                SerializedNodes::new(edge_nodes).map(move |other| self.edge(node, other))
             })
     }
 }
@@ -652,23 +820,74 @@ mod tests {
                 id: NodeID(1),
                 prefix: NodeID(0),
             },
-            label: "test".to_string(),
+            label: EdgeLabel::from("test"),
         };
 
         kv.put([e.clone()].iter());
 
         let store = kv.finalize();
 
-        let edges: Vec<_> = store.get_with_label(&NodeID(0));
+        let edges: Vec<_> = store
+            .get_with_label(&NodeID(0), |_| true, |_, _| true)
+            .collect();
 
         assert_eq!(edges.len(), 1);
         assert_eq!(&edges[0], &Edge::from(e.clone()));
 
-        let edges: Vec<_> = store.get_with_label(&NodeID(1));
+        let edges: Vec<_> = store
+            .get_with_label(&NodeID(1), |_| true, |_, _| true)
+            .collect();
 
         assert_eq!(edges.len(), 0);
     }
 
+    #[test]
+    fn test_filter() {
+        let kv: EdgeStoreWriter = EdgeStoreWriter::open(
+            crate::gen_temp_path().join("test-segment"),
+            Compression::default(),
+            false,
+        );
+
+        let edges: Vec<_> = (1..=3)
+            .map(|i| InnerEdge {
+                from: FullNodeID {
+                    id: NodeID(0),
+                    prefix: NodeID(0),
+                },
+                to: FullNodeID {
+                    id: NodeID(i),
+                    prefix: NodeID(i),
+                },
+                label: EdgeLabel::from(format!("label {i}")),
+            })
+            .collect();
+
+        kv.put(edges.iter());
+
+        let store = kv.finalize();
+
+        let res: Vec<_> = store
+            .get_with_label(
+                &NodeID(0),
+                |other| *other != NodeID(1),
+                |_, label| label.text != "label 3",
+            )
+            .collect();
+
+        assert_eq!(res, vec![Edge::from(edges[1].clone())]);
+
+        // the labels of the skipped edges are not decoded, but the labels stay aligned
+        let res: Vec<_> = store
+            .get_with_label(&NodeID(0), |other| *other == NodeID(3), |_, _| true)
+            .collect();
+
+        assert_eq!(res, vec![Edge::from(edges[2].clone())]);
+
+        let res: Vec<_> = store.get_without_label(&NodeID(0)).map(|e| e.to).collect();
+        assert_eq!(res, vec![NodeID(1), NodeID(2), NodeID(3)]);
+    }
+
     #[test]
     fn test_reversed() {
         let kv: EdgeStoreWriter = EdgeStoreWriter::open(
@@ -686,18 +905,44 @@ mod tests {
                 id: NodeID(1),
                 prefix: NodeID(0),
             },
-            label: "test".to_string(),
+            label: EdgeLabel::from("test"),
         };
 
         kv.put([e.clone()].iter());
 
         let store = kv.finalize();
 
-        let edges: Vec<_> = store.get_with_label(&NodeID(0));
+        let edges: Vec<_> = store
+            .get_with_label(&NodeID(0), |_| true, |_, _| true)
+            .collect();
         assert_eq!(edges.len(), 0);
 
-        let edges: Vec<_> = store.get_with_label(&NodeID(1));
+        let edges: Vec<_> = store
+            .get_with_label(&NodeID(1), |_| true, |_, _| true)
+            .collect();
         assert_eq!(edges.len(), 1);
         assert_eq!(&edges[0], &Edge::from(e.clone()));
     }
+
+    #[test]
+    fn serialized_labels() {
+        let labels = vec![
+            EdgeLabel::from("a"),
+            EdgeLabel::default(),
+            EdgeLabel::from("c"),
+        ];
+        let bytes: Vec<Vec<u8>> = labels.iter().map(|l| l.to_bytes().unwrap()).collect();
+
+        let decoded: Vec<_> = SerializedLabels::new(bincode::serialize(&bytes).unwrap())
+            .map(|label| label.unwrap())
+            .collect();
+        assert_eq!(decoded, labels);
+
+        assert_eq!(SerializedLabels::new(Vec::new()).count(), 0);
+
+        let mut skipped = SerializedLabels::new(bincode::serialize(&bytes).unwrap());
+        skipped.skip_labels(2);
+        assert_eq!(skipped.next().unwrap().unwrap(), labels[2]);
+        assert!(skipped.next().is_none());
+    }
 }
@@ -0,0 +1,96 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tombstones mark edges in older segments as deleted.
+//!
+//! A tombstone is either for a single edge or for all outgoing edges of a node
+//! (written when the outgoing edges of a page are replaced). The tombstones of a segment
+//! only apply to the segments that were committed before it, so edges that are inserted
+//! again after being deleted are still visible. Compaction applies the tombstones and
+//! removes them.
+
+use std::{collections::HashSet, path::Path};
+
+use super::NodeID;
+
+/// The tombstones are kept in memory as well, so checking an edge does not need a lookup
+/// in the database. There are far fewer tombstones than edges, since they are only written
+/// by incremental updates and are removed by compaction.
+pub struct TombstoneStore {
+    db: rocksdb::DB,
+    nodes: HashSet<NodeID>,
+    edges: HashSet<(NodeID, NodeID)>,
+}
+
+impl TombstoneStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        options.set_compression_type(rocksdb::DBCompressionType::Lz4);
+
+        let db = rocksdb::DB::open(&options, path).unwrap();
+
+        let id = |bytes: &[u8]| NodeID::from(u64::from_le_bytes(bytes.try_into().unwrap()));
+
+        let mut nodes = HashSet::new();
+        let mut edges = HashSet::new();
+
+        for (key, _) in db.iterator(rocksdb::IteratorMode::Start).flatten() {
+            match key.len() {
+                8 => {
+                    nodes.insert(id(&key));
+                }
+                16 => {
+                    edges.insert((id(&key[..8]), id(&key[8..])));
+                }
+                _ => tracing::warn!("skipping tombstone with invalid key length {}", key.len()),
+            }
+        }
+
+        Self { db, nodes, edges }
+    }
+
+    fn put(&mut self, key: &[u8]) {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.disable_wal(true);
+
+        self.db.put_opt(key, [], &opts).unwrap();
+    }
+
+    /// Delete all outgoing edges of the node.
+    pub fn insert_node(&mut self, node: &NodeID) {
+        self.put(&node.as_u64().to_le_bytes());
+        self.nodes.insert(*node);
+    }
+
+    /// Delete a single edge.
+    pub fn insert_edge(&mut self, from: &NodeID, to: &NodeID) {
+        self.put(&[from.as_u64().to_le_bytes(), to.as_u64().to_le_bytes()].concat());
+        self.edges.insert((*from, *to));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.edges.is_empty()
+    }
+
+    pub fn contains(&self, from: &NodeID, to: &NodeID) -> bool {
+        self.nodes.contains(from) || self.edges.contains(&(*from, *to))
+    }
+
+    pub fn flush(&self) {
+        self.db.flush().unwrap();
+    }
+}
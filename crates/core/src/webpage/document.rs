@@ -0,0 +1,457 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Extraction of documents that are not html (pdf, plain text and markdown).
+//!
+//! Every document is converted into a small html page with a title, a paragraph
+//! per block of text and an anchor per link. The page is then parsed like any other
+//! webpage, so language detection, text cleaning and link extraction are shared.
+use std::borrow::Cow;
+
+use pdf_extract::{Document, Object, PlainTextOutput};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+use url::Url;
+
+use crate::{warc::PayloadType, Result};
+
+use super::Html;
+
+/// Titles derived from the first line of a document are truncated to this many characters.
+const MAX_TITLE_CHARS: usize = 128;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to extract text from pdf: {0}")]
+    Pdf(String),
+}
+
+/// Parse the payload of a response as a webpage.
+pub fn parse(body: &[u8], url: &str, payload_type: &PayloadType) -> Result<Html> {
+    Html::parse(&to_html(body, payload_type)?, url)
+}
+
+/// Parse the payload of a response as a webpage without extracting the text.
+/// See [`Html::parse_without_text`].
+pub fn parse_without_text(body: &[u8], url: &str, payload_type: &PayloadType) -> Result<Html> {
+    Html::parse_without_text(&to_html(body, payload_type)?, url)
+}
+
+/// Convert the payload of a response into html.
+///
+/// Textual payloads are expected to be utf-8 and pdf payloads are the raw bytes of the document.
+/// Html and feeds are returned as-is.
+pub fn to_html<'a>(body: &'a [u8], payload_type: &PayloadType) -> Result<Cow<'a, str>> {
+    match payload_type {
+        PayloadType::Html | PayloadType::Rss | PayloadType::Atom => {
+            Ok(String::from_utf8_lossy(body))
+        }
+        PayloadType::Text => Ok(Cow::Owned(text_to_html(&String::from_utf8_lossy(body)))),
+        PayloadType::Markdown => Ok(Cow::Owned(markdown_to_html(&String::from_utf8_lossy(body)))),
+        PayloadType::Pdf => Ok(Cow::Owned(pdf_to_html(body)?)),
+    }
+}
+
+/// Convert the payload of a response into html on the blocking thread pool.
+///
+/// Extracting the text of a pdf is cpu heavy and would otherwise stall the async runtime.
+pub async fn to_html_blocking(body: Vec<u8>, payload_type: PayloadType) -> Result<String> {
+    tokio::task::spawn_blocking(move || to_html(&body, &payload_type).map(|html| html.into_owned()))
+        .await?
+}
+
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            c => res.push(c),
+        }
+    }
+
+    res
+}
+
+fn title_from_line(line: &str) -> Option<String> {
+    let line = line.trim();
+
+    if line.is_empty() {
+        None
+    } else {
+        Some(line.chars().take(MAX_TITLE_CHARS).collect())
+    }
+}
+
+/// Blocks of text separated by empty lines. Lines within a block are joined by a single space.
+fn text_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            if !current.is_empty() {
+                blocks.push(current.join(" "));
+                current.clear();
+            }
+        } else {
+            current.push(line);
+        }
+    }
+
+    if !current.is_empty() {
+        blocks.push(current.join(" "));
+    }
+
+    blocks
+}
+
+#[derive(Default)]
+struct HtmlBuilder {
+    title: Option<String>,
+    body: String,
+}
+
+impl HtmlBuilder {
+    /// Add a paragraph of text. Urls in the text are turned into links.
+    fn paragraph(&mut self, text: &str) {
+        self.body.push_str("<p>");
+
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 {
+                self.body.push(' ');
+            }
+
+            let trimmed = word.trim_end_matches(['.', ',', ';', ':', ')']);
+
+            if (trimmed.starts_with("http://") || trimmed.starts_with("https://"))
+                && Url::parse(trimmed).is_ok()
+            {
+                self.link(trimmed, trimmed);
+                self.body.push_str(&escape(&word[trimmed.len()..]));
+            } else {
+                self.body.push_str(&escape(word));
+            }
+        }
+
+        self.body.push_str("</p>");
+    }
+
+    fn link(&mut self, href: &str, text: &str) {
+        self.body.push_str("<a href=\"");
+        self.body.push_str(&escape(href));
+        self.body.push_str("\">");
+        self.body.push_str(&escape(text));
+        self.body.push_str("</a>");
+    }
+
+    fn build(self) -> String {
+        let mut res = String::from("<!DOCTYPE html><html><head>");
+
+        if let Some(title) = self.title {
+            res.push_str("<title>");
+            res.push_str(&escape(&title));
+            res.push_str("</title>");
+        }
+
+        res.push_str("</head><body>");
+        res.push_str(&self.body);
+        res.push_str("</body></html>");
+
+        res
+    }
+}
+
+fn text_to_html(text: &str) -> String {
+    let mut builder = HtmlBuilder {
+        title: text.lines().find_map(title_from_line),
+        ..Default::default()
+    };
+
+    for block in text_blocks(text) {
+        builder.paragraph(&block);
+    }
+
+    builder.build()
+}
+
+/// The text of the first top-level heading, or the first heading of any level
+/// if the document has no top-level heading.
+fn markdown_title(markdown: &str) -> Option<String> {
+    let mut best: Option<(HeadingLevel, String)> = None;
+    let mut current: Option<(HeadingLevel, String)> = None;
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading(level, _, _)) => current = Some((level, String::new())),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading)) = current.as_mut() {
+                    heading.push_str(&text);
+                }
+            }
+            Event::End(Tag::Heading(_, _, _)) => {
+                if let Some((level, heading)) = current.take() {
+                    let is_better = match &best {
+                        Some((best_level, _)) => level < *best_level,
+                        None => true,
+                    };
+
+                    if is_better && !heading.trim().is_empty() {
+                        best = Some((level, heading));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    best.and_then(|(_, heading)| title_from_line(&heading))
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let mut builder = HtmlBuilder {
+        title: markdown_title(markdown).or_else(|| markdown.lines().find_map(title_from_line)),
+        ..Default::default()
+    };
+
+    pulldown_cmark::html::push_html(&mut builder.body, Parser::new_ext(markdown, Options::all()));
+
+    builder.build()
+}
+
+fn pdf_title(doc: &Document) -> Option<String> {
+    doc.trailer
+        .get_deref(b"Info", doc)
+        .and_then(Object::as_dict)
+        .and_then(|info| info.get_deref(b"Title", doc))
+        .and_then(pdf_extract::decode_text_string)
+        .ok()
+        .and_then(|title| title_from_line(&title))
+}
+
+/// Targets of the uri link annotations in the document.
+fn pdf_links(doc: &Document) -> Vec<String> {
+    doc.get_pages()
+        .into_values()
+        .flat_map(|page| doc.get_page_annotations(page).unwrap_or_default())
+        .filter_map(|annotation| {
+            annotation
+                .get_deref(b"A", doc)
+                .and_then(Object::as_dict)
+                .and_then(|action| action.get_deref(b"URI", doc))
+                .and_then(Object::as_str)
+                .ok()
+        })
+        .map(|uri| String::from_utf8_lossy(uri).trim().to_string())
+        .filter(|uri| Url::parse(uri).is_ok())
+        .collect()
+}
+
+fn pdf_text(doc: &Document) -> Result<String> {
+    let mut text = String::new();
+
+    // the text extraction panics on some malformed documents, which would otherwise take
+    // down the entire worker.
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pdf_extract::output_doc(doc, &mut PlainTextOutput::new(&mut text))
+    }))
+    .map_err(|_| Error::Pdf("text extraction panicked".to_string()))?
+    .map_err(|err| Error::Pdf(err.to_string()))?;
+
+    Ok(text)
+}
+
+fn pdf_to_html(bytes: &[u8]) -> Result<String> {
+    let mut doc = Document::load_mem(bytes).map_err(|err| Error::Pdf(err.to_string()))?;
+
+    if doc.is_encrypted() {
+        // many pdfs are encrypted with an empty password to restrict editing
+        doc.decrypt("").map_err(|err| Error::Pdf(err.to_string()))?;
+    }
+
+    let text = pdf_text(&doc)?;
+
+    let mut builder = HtmlBuilder {
+        title: pdf_title(&doc).or_else(|| text.lines().find_map(title_from_line)),
+        ..Default::default()
+    };
+
+    for block in text_blocks(&text) {
+        builder.paragraph(&block);
+    }
+
+    for link in pdf_links(&doc) {
+        builder.link(&link, &link);
+    }
+
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use pdf_extract::{content::Content, content::Operation, dictionary, Stream, StringFormat};
+
+    use super::*;
+
+    const TEXT: &str = "The quick brown fox jumps over the lazy dog and keeps running through the forest until it reaches the river. There it stops to drink some water before it continues its long journey home.";
+
+    fn pdf(title: &str, text: &str, link: &str) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! {
+                "F1" => font_id,
+            },
+        });
+
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![50.into(), 700.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+
+        let annotation_id = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Link",
+            "Rect" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "A" => dictionary! {
+                "S" => "URI",
+                "URI" => Object::String(link.as_bytes().to_vec(), StringFormat::Literal),
+            },
+        });
+
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Annots" => vec![annotation_id.into()],
+        });
+
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::string_literal(title),
+        });
+
+        doc.trailer.set("Root", catalog_id);
+        doc.trailer.set("Info", info_id);
+
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn plain_text() {
+        let text = format!("A plain text document\n\n{TEXT}\nSee https://example.com/other.\n");
+        let html = parse(
+            text.as_bytes(),
+            "https://example.com/doc.txt",
+            &PayloadType::Text,
+        )
+        .unwrap();
+
+        assert_eq!(html.title(), Some("A plain text document".to_string()));
+        assert_eq!(html.lang(), Some(&whatlang::Lang::Eng));
+        assert!(html.clean_text().unwrap().contains("quick brown fox"));
+
+        let links: Vec<_> = html
+            .all_links()
+            .into_iter()
+            .map(|link| link.destination.to_string())
+            .collect();
+        assert_eq!(links, vec!["https://example.com/other".to_string()]);
+    }
+
+    #[test]
+    fn markdown() {
+        let markdown = format!(
+            "Some intro\n\n## Subheading\n\n# The `real` title\n\n{TEXT}\n\nRead the [docs](/docs) for more.\n"
+        );
+        let html = parse(
+            markdown.as_bytes(),
+            "https://example.com/README.md",
+            &PayloadType::Markdown,
+        )
+        .unwrap();
+
+        assert_eq!(html.title(), Some("The real title".to_string()));
+        assert_eq!(html.lang(), Some(&whatlang::Lang::Eng));
+        assert!(html.clean_text().unwrap().contains("quick brown fox"));
+
+        let links: Vec<_> = html
+            .all_links()
+            .into_iter()
+            .map(|link| (link.destination.to_string(), link.text))
+            .collect();
+        assert_eq!(
+            links,
+            vec![("https://example.com/docs".to_string(), "docs".to_string())]
+        );
+    }
+
+    #[test]
+    fn pdf_document() {
+        let bytes = pdf("A pdf document", TEXT, "https://example.com/linked");
+
+        let html = parse(&bytes, "https://example.com/doc.pdf", &PayloadType::Pdf).unwrap();
+
+        assert_eq!(html.title(), Some("A pdf document".to_string()));
+        assert_eq!(html.lang(), Some(&whatlang::Lang::Eng));
+        assert!(html.clean_text().unwrap().contains("quick brown fox"));
+
+        let links: Vec<_> = html
+            .all_links()
+            .into_iter()
+            .map(|link| link.destination.to_string())
+            .collect();
+        assert_eq!(links, vec!["https://example.com/linked".to_string()]);
+    }
+
+    #[test]
+    fn invalid_pdf() {
+        assert!(to_html(b"not a pdf", &PayloadType::Pdf).is_err());
+    }
+}
@@ -34,7 +34,7 @@ use super::{find_recipe_first_ingredient_tag_id, schema_org, Html};
 use crate::schema::{Field, FLOAT_SCALING};
 
 impl Html {
-    fn pretokenize_title(&self) -> Result<PreTokenizedString> {
+    pub(crate) fn pretokenize_title(&self) -> Result<PreTokenizedString> {
         let title = self.title();
 
         if title.is_none() {
@@ -56,35 +56,43 @@ impl Html {
         Ok(self.pretokenize_string(all_text, TextField::AllBody))
     }
 
-    fn pretokenize_clean_text(&self) -> PreTokenizedString {
+    pub(crate) fn pretokenize_clean_text(&self) -> PreTokenizedString {
         let clean_text = self.clean_text().cloned().unwrap_or_default();
         self.pretokenize_string(clean_text, TextField::CleanBody)
     }
 
-    fn pretokenize_url(&self) -> PreTokenizedString {
-        let url = self.url().to_string();
+    pub(crate) fn pretokenize_url(&self) -> PreTokenizedString {
+        let url = self.canonical().to_string();
         self.pretokenize_string(url, TextField::Url)
     }
 
-    fn pretokenize_domain(&self) -> PreTokenizedString {
-        let domain = self.url().root_domain().unwrap_or_default().to_string();
+    pub(crate) fn pretokenize_domain(&self) -> PreTokenizedString {
+        let domain = self
+            .canonical()
+            .root_domain()
+            .unwrap_or_default()
+            .to_string();
 
         self.pretokenize_string(domain, TextField::Domain)
     }
 
-    fn pretokenize_site(&self) -> PreTokenizedString {
-        let site = self.url().normalized_host().unwrap_or_default().to_string();
+    pub(crate) fn pretokenize_site(&self) -> PreTokenizedString {
+        let site = self
+            .canonical()
+            .normalized_host()
+            .unwrap_or_default()
+            .to_string();
 
         self.pretokenize_string(site, TextField::SiteWithout)
     }
 
-    fn pretokenize_description(&self) -> PreTokenizedString {
+    pub(crate) fn pretokenize_description(&self) -> PreTokenizedString {
         let text = self.description().unwrap_or_default();
 
         self.pretokenize_string(text, TextField::Description)
     }
 
-    fn pretokenize_microformats(&self) -> PreTokenizedString {
+    pub(crate) fn pretokenize_microformats(&self) -> PreTokenizedString {
         let mut text = String::new();
 
         for microformat in self.microformats().iter() {
@@ -95,6 +103,38 @@ impl Html {
         self.pretokenize_string(text, TextField::MicroformatTags)
     }
 
+    pub(crate) fn pretokenize_url_for_site_operator(&self) -> PreTokenizedString {
+        self.pretokenize_string_with(
+            self.canonical().to_string(),
+            tokenizer::Tokenizer::SiteOperator(tokenizer::SiteOperatorUrlTokenizer),
+        )
+    }
+
+    pub(crate) fn pretokenize_schema_json(schemas: Vec<schema_org::Item>) -> PreTokenizedString {
+        match schema_org::flattened_json(schemas) {
+            Ok(mut f) => {
+                let mut tokens = Vec::new();
+
+                {
+                    let mut stream = f.token_stream();
+
+                    while let Some(token) = stream.next() {
+                        tokens.push(token.clone());
+                    }
+                }
+
+                PreTokenizedString {
+                    text: f.text().to_string(),
+                    tokens,
+                }
+            }
+            Err(_) => PreTokenizedString {
+                text: String::new(),
+                tokens: Vec::new(),
+            },
+        }
+    }
+
     fn pretokenize_string(&self, text: String, field: TextField) -> PreTokenizedString {
         self.pretokenize_string_with(text, field.indexing_tokenizer())
     }
@@ -144,10 +184,7 @@ impl Html {
         let site = self.pretokenize_site();
         let description = self.pretokenize_description();
         let microformats = self.pretokenize_microformats();
-        let url_for_site_operator = self.pretokenize_string_with(
-            self.url().to_string(),
-            tokenizer::Tokenizer::SiteOperator(tokenizer::SiteOperatorUrlTokenizer),
-        );
+        let url_for_site_operator = self.pretokenize_url_for_site_operator();
 
         let domain_name = self
             .url()
@@ -166,38 +203,17 @@ impl Html {
 
         let schema_json = serde_json::to_string(&schemas).ok().unwrap_or_default();
 
-        let pretokenized_schema_json = match schema_org::flattened_json(schemas) {
-            Ok(mut f) => {
-                let mut tokens = Vec::new();
-
-                {
-                    let mut stream = f.token_stream();
-
-                    while let Some(token) = stream.next() {
-                        tokens.push(token.clone());
-                    }
-                }
-
-                PreTokenizedString {
-                    text: f.text().to_string(),
-                    tokens,
-                }
-            }
-            Err(_) => PreTokenizedString {
-                text: String::new(),
-                tokens: Vec::new(),
-            },
-        };
+        let pretokenized_schema_json = Self::pretokenize_schema_json(schemas);
 
-        let site_hash = split_u128(hash(self.url().normalized_host().unwrap_or_default()).0);
+        let site_hash = split_u128(hash(self.canonical().normalized_host().unwrap_or_default()).0);
 
-        let mut url_without_query = self.url().clone();
+        let mut url_without_query = self.canonical().clone();
         url_without_query.set_query(None);
 
         let url_without_query_hash = split_u128(hash(url_without_query.as_str()).0);
-        let url_hash = split_u128(hash(self.url().as_str()).0);
+        let url_hash = split_u128(hash(self.canonical().as_str()).0);
 
-        let tld = self.url().tld().unwrap_or_default();
+        let tld = self.canonical().tld().unwrap_or_default();
         let url_without_tld = self
             .url()
             .host_str()
@@ -205,13 +221,13 @@ impl Html {
             .trim_end_matches(&tld)
             .to_string()
             + "/"
-            + self.url().path()
+            + self.canonical().path()
             + "?"
-            + self.url().query().unwrap_or_default();
+            + self.canonical().query().unwrap_or_default();
 
         let url_without_tld_hash = split_u128(hash(url_without_tld).0);
 
-        let domain_hash = split_u128(hash(self.url().root_domain().unwrap_or_default()).0);
+        let domain_hash = split_u128(hash(self.canonical().root_domain().unwrap_or_default()).0);
         let title_hash = split_u128(hash(self.title().unwrap_or_default()).0);
 
         for field in schema
@@ -281,7 +297,7 @@ impl Html {
                     doc.add_pre_tokenized_text(tantivy_field, url_for_site_operator.clone())
                 }
                 Field::Text(TextField::UrlNoTokenizer) => {
-                    let url = self.url().to_string();
+                    let url = self.canonical().to_string();
 
                     doc.add_pre_tokenized_text(
                         tantivy_field,
@@ -554,10 +570,16 @@ impl Html {
                 Field::Text(TextField::BacklinkText)
                 | Field::Text(TextField::SafetyClassification)
                 | Field::Text(TextField::InsertionTimestamp)
+                | Field::Text(TextField::HostSpamLabel)
+                | Field::Text(TextField::HostCommunity)
                 | Field::Fast(FastField::HostCentrality)
                 | Field::Fast(FastField::HostCentralityRank)
                 | Field::Fast(FastField::PageCentrality)
                 | Field::Fast(FastField::PageCentralityRank)
+                | Field::Fast(FastField::HostPageRank)
+                | Field::Fast(FastField::HostPersonalizedPageRank)
+                | Field::Fast(FastField::PagePageRank)
+                | Field::Fast(FastField::HostSpamScore)
                 | Field::Fast(FastField::FetchTimeMs)
                 | Field::Fast(FastField::PreComputedScore)
                 | Field::Fast(FastField::Region)
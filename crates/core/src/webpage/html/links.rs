@@ -14,10 +14,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use kuchiki::iter::NodeEdge;
+use kuchiki::{iter::NodeEdge, Attributes, ElementData};
 use url::Url;
 
-use crate::webpage::{url_ext::UrlExt, Link};
+use crate::webpage::{url_ext::UrlExt, Link, LinkPosition, RelFlags};
 
 use super::Html;
 
@@ -36,6 +36,20 @@ pub struct ImageLink {
     pub description: Option<String>,
 }
 
+/// The landmark region started by the element, if any.
+fn landmark(element: &ElementData) -> Option<LinkPosition> {
+    let attributes = element.attributes.borrow();
+    let role = attributes.get("role").map(|role| role.to_ascii_lowercase());
+
+    match (&*element.name.local, role.as_deref()) {
+        ("footer", _) | (_, Some("contentinfo")) => Some(LinkPosition::Footer),
+        ("nav" | "header", _) | (_, Some("navigation" | "banner" | "menu" | "menubar")) => {
+            Some(LinkPosition::Nav)
+        }
+        _ => None,
+    }
+}
+
 impl Html {
     pub fn favicon(&self) -> Option<FaviconLink> {
         for node in self.root.select("link").unwrap() {
@@ -124,35 +138,35 @@ impl Html {
 
         let mut links = Vec::new();
         let mut open_links = Vec::new();
+        let mut landmarks = Vec::new();
 
         for edge in self.root.traverse() {
             match edge {
                 NodeEdge::Start(node) => {
                     if let Some(element) = node.as_element() {
+                        if let Some(landmark) = landmark(element) {
+                            landmarks.push(landmark);
+                        }
+
                         if &element.name.local == "a" {
-                            open_links.push((String::new(), element.attributes.clone()));
+                            let position = landmarks.last().copied().unwrap_or_default();
+                            open_links.push((String::new(), element.attributes.clone(), position));
                         }
                     }
                 }
                 NodeEdge::End(node) => {
                     if let Some(element) = node.as_element() {
+                        if landmark(element).is_some() {
+                            landmarks.pop();
+                        }
+
                         if &element.name.local == "a" {
-                            if let Some((text, attributes)) = open_links.pop() {
-                                if let Some(dest) = attributes.borrow().get("href") {
-                                    if dest.starts_with("mailto:") || dest.starts_with("tel:") {
-                                        continue;
-                                    }
-
-                                    if let Ok(dest) =
-                                        Url::parse(dest).or_else(|_| self.url().join(dest))
-                                    {
-                                        links.push(Link {
-                                            source: self.url().clone(),
-                                            destination: dest,
-                                            text: text.trim().to_string(),
-                                        });
-                                    }
-                                }
+                            if let Some((text, attributes, position)) = open_links.pop() {
+                                links.extend(self.anchor_link(
+                                    text,
+                                    &attributes.borrow(),
+                                    position,
+                                ));
                             }
                         }
                     }
@@ -162,7 +176,7 @@ impl Html {
                         let text = raw_text.trim();
 
                         if !text.is_empty() {
-                            for (link_text, _) in &mut open_links {
+                            for (link_text, _, _) in &mut open_links {
                                 link_text.push('\n');
                                 link_text.push_str(text);
                             }
@@ -172,29 +186,37 @@ impl Html {
             }
         }
 
-        while let Some((text, attributes)) = open_links.pop() {
-            if let Some(rel) = attributes.borrow().get("rel") {
-                if rel.contains("nofollow") || rel.contains("sponsored") || rel.contains("ugc") {
-                    continue;
-                }
-            }
+        while let Some((text, attributes, position)) = open_links.pop() {
+            links.extend(self.anchor_link(text, &attributes.borrow(), position));
+        }
 
-            if let Some(dest) = attributes.borrow().get("href") {
-                if dest.starts_with("mailto:") || dest.starts_with("tel:") {
-                    continue;
-                }
+        links
+    }
 
-                if let Ok(dest) = Url::parse(dest).or_else(|_| self.url().join(dest)) {
-                    links.push(Link {
-                        source: self.url().clone(),
-                        destination: dest,
-                        text: text.trim().to_string(),
-                    });
-                }
-            }
+    fn anchor_link(
+        &self,
+        text: String,
+        attributes: &Attributes,
+        position: LinkPosition,
+    ) -> Option<Link> {
+        let dest = attributes.get("href")?;
+
+        if dest.starts_with("mailto:") || dest.starts_with("tel:") {
+            return None;
         }
 
-        links
+        let destination = Url::parse(dest).or_else(|_| self.url().join(dest)).ok()?;
+
+        Some(Link {
+            source: self.canonical().clone(),
+            destination,
+            text: text.trim().to_string(),
+            rel: attributes
+                .get("rel")
+                .map(RelFlags::parse)
+                .unwrap_or_default(),
+            position,
+        })
     }
 
     fn links_tag(&self) -> Vec<Link> {
@@ -205,9 +227,11 @@ impl Html {
                 if let Some(href) = element.attributes.borrow().get("href") {
                     if let Ok(href) = Url::parse(href).or_else(|_| self.url().join(href)) {
                         links.push(Link {
-                            source: self.url().clone(),
+                            source: self.canonical().clone(),
                             destination: href,
                             text: String::new(),
+                            rel: RelFlags::default(),
+                            position: LinkPosition::default(),
                         });
                     }
                 }
@@ -240,9 +264,11 @@ impl Html {
                                 .or_else(|_| self.url().join(content.as_str()))
                             {
                                 return Some(Link {
-                                    source: self.url().clone(),
+                                    source: self.canonical().clone(),
                                     destination,
                                     text: String::new(),
+                                    rel: RelFlags::default(),
+                                    position: LinkPosition::default(),
                                 });
                             }
                         }
@@ -264,9 +290,11 @@ impl Html {
                                 .or_else(|_| self.url().join(content.as_str()))
                             {
                                 return Some(Link {
-                                    source: self.url().clone(),
+                                    source: self.canonical().clone(),
                                     destination,
                                     text: String::new(),
+                                    rel: RelFlags::default(),
+                                    position: LinkPosition::default(),
                                 });
                             }
                         }
@@ -290,9 +318,11 @@ impl Html {
 
                     if script_url.root_domain() != self.url().root_domain() {
                         Some(Link {
-                            source: self.url().clone(),
+                            source: self.canonical().clone(),
                             destination: script_url,
                             text: String::new(),
+                            rel: RelFlags::default(),
+                            position: LinkPosition::default(),
                         })
                     } else {
                         None
@@ -460,4 +490,65 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn rel_and_position() {
+        let html = r#"
+    <html>
+        <head></head>
+        <body>
+            <nav><a href="/about">About</a></nav>
+            <div role="navigation"><a href="/contact">Contact</a></div>
+            <p>
+                <a href="https://a.com">A</a>
+                <a href="https://b.com" rel="NoFollow noopener">B</a>
+                <a href="https://c.com" rel="sponsored ugc">C</a>
+            </p>
+            <footer><a href="/privacy">Privacy</a></footer>
+        </body>
+    </html>
+        "#;
+        let html = Html::parse(html, "https://example.com").unwrap();
+
+        let links: Vec<_> = html
+            .anchor_links()
+            .into_iter()
+            .map(|link| (link.text, link.rel, link.position))
+            .collect();
+
+        assert_eq!(
+            links,
+            vec![
+                ("About".to_string(), RelFlags::default(), LinkPosition::Nav),
+                (
+                    "Contact".to_string(),
+                    RelFlags::default(),
+                    LinkPosition::Nav
+                ),
+                ("A".to_string(), RelFlags::default(), LinkPosition::Body),
+                (
+                    "B".to_string(),
+                    RelFlags {
+                        nofollow: true,
+                        ..Default::default()
+                    },
+                    LinkPosition::Body
+                ),
+                (
+                    "C".to_string(),
+                    RelFlags {
+                        sponsored: true,
+                        ugc: true,
+                        ..Default::default()
+                    },
+                    LinkPosition::Body
+                ),
+                (
+                    "Privacy".to_string(),
+                    RelFlags::default(),
+                    LinkPosition::Footer
+                ),
+            ]
+        );
+    }
 }
@@ -24,7 +24,7 @@ use whatlang::Lang;
 
 use self::robots_meta::RobotsMeta;
 
-use super::{adservers::AD_SERVERS, schema_org, Meta, Script};
+use super::{adservers::AD_SERVERS, schema_org, url_canonicalizer, Meta, Script};
 
 use super::url_ext::UrlExt;
 
@@ -40,7 +40,10 @@ pub static URL_REGEX: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(
 
 #[derive(Debug)]
 pub struct Html {
+    /// The url the page was fetched from. Relative links are resolved against it.
     url: Url,
+    /// The url that identifies the page. See [`Html::canonical`].
+    canonical: Url,
     root: NodeRef, // this is reference counted (cheap to clone)
     all_text: Option<String>,
     clean_text: Option<String>,
@@ -65,43 +68,24 @@ impl Html {
     pub fn parse_without_text(html: &str, url: &str) -> Result<Self> {
         let root = kuchiki::parse_html().one(html);
 
-        let mut url = Url::parse(url)?;
-        url.set_fragment(None); // remove fragment (e.g. #comments
+        let url = url_canonicalizer::canonicalize(&Url::parse(url)?);
 
         let mut res = Self {
             root,
             all_text: None,
             clean_text: None,
             lang: None,
+            canonical: url.clone(),
             url,
             robots: None,
         };
 
-        let queries: Vec<_> = res
-            .url
-            .query_pairs()
-            .filter(|(key, _)| !key.starts_with("utm_"))
-            .map(|(key, value)| (key.to_string(), value.to_string()))
-            .collect();
-
-        {
-            let mut query_mut = res.url.query_pairs_mut();
-            query_mut.clear();
-            if !queries.is_empty() {
-                query_mut.extend_pairs(queries);
-            }
-        }
-
         if let Some(canonical) = res.canonical_url() {
             if canonical.root_domain() == res.url.root_domain() {
-                res.url = canonical;
+                res.canonical = url_canonicalizer::canonicalize(&canonical);
             }
         }
 
-        if res.url.query().unwrap_or_default().is_empty() {
-            res.url.set_query(None);
-        }
-
         res.robots = res.parse_robots_meta();
 
         Ok(res)
@@ -111,6 +95,7 @@ impl Html {
         self.lang.as_ref()
     }
 
+    /// The canonical url declared by the page with a `<link rel="canonical">` tag.
     pub fn canonical_url(&self) -> Option<Url> {
         let mut canonical_url = None;
 
@@ -168,6 +153,13 @@ impl Html {
         &self.url
     }
 
+    /// The url that identifies the page in the index and the webgraph. This is the canonical
+    /// url declared by the page if it is on the same domain as the fetched url, so the
+    /// duplicates of a page get the same id.
+    pub fn canonical(&self) -> &Url {
+        &self.canonical
+    }
+
     pub fn metadata(&self) -> Vec<Meta> {
         let mut metas = Vec::new();
 
@@ -443,7 +435,7 @@ impl Html {
     }
 
     pub fn is_homepage(&self) -> bool {
-        self.url().is_homepage()
+        self.canonical().is_homepage()
     }
 }
 
@@ -495,7 +487,7 @@ mod tests {
     use crate::{
         rake::RakeModel,
         schema::create_schema,
-        webpage::{url_ext::UrlExt, Link},
+        webpage::{url_ext::UrlExt, Link, LinkPosition, RelFlags},
     };
 
     use super::*;
@@ -529,7 +521,9 @@ mod tests {
             vec![Link {
                 source: Url::parse("https://www.example.com/whatever").unwrap(),
                 destination: Url::parse("https://example.com").unwrap(),
-                text: "Link to example".to_string()
+                text: "Link to example".to_string(),
+                rel: RelFlags::default(),
+                position: LinkPosition::Body,
             }]
         );
         assert_eq!(webpage.clean_text(), Some(&CONTENT.to_string()));
@@ -974,7 +968,9 @@ mod tests {
             vec![Link {
                 source: Url::parse("https://www.example.com/whatever").unwrap(),
                 destination: Url::parse("https://example.com").unwrap(),
-                text: "Link to example".to_string()
+                text: "Link to example".to_string(),
+                rel: RelFlags::default(),
+                position: LinkPosition::Body,
             },]
         );
     }
@@ -1010,9 +1006,13 @@ mod tests {
         );
 
         assert_eq!(
-            html.url(),
+            html.canonical(),
             &Url::parse("https://example.com/canonical.html").unwrap()
         );
+        assert_eq!(
+            html.url(),
+            &Url::parse("https://www.example.com/whatever").unwrap()
+        );
 
         let html = Html::parse(
             r#"
@@ -1056,6 +1056,37 @@ mod tests {
             html.url(),
             &Url::parse("https://www.example.com/whatever").unwrap()
         );
+        assert_eq!(html.canonical(), html.url());
+    }
+
+    #[test]
+    fn links_resolve_against_fetched_url() {
+        let html = Html::parse(
+            r#"
+            <html>
+                <head>
+                    <link rel="canonical" href="https://example.com/articles/canonical" />
+                </head>
+                <body>
+                    <a href="related">Related</a>
+                </body>
+            </html>
+        "#,
+            "https://example.com/blog/post",
+        )
+        .unwrap();
+
+        let links = html.anchor_links();
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(
+            links[0].destination,
+            Url::parse("https://example.com/blog/related").unwrap()
+        );
+        assert_eq!(
+            links[0].source,
+            Url::parse("https://example.com/articles/canonical").unwrap()
+        );
     }
 
     #[test]
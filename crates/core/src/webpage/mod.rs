@@ -17,7 +17,7 @@
 use crate::{
     rake::RakeModel,
     schema::{FastField, TextField},
-    webgraph::NodeID,
+    webgraph::{community::CommunityId, spam::SpamLabel, NodeID},
     Result,
 };
 use chrono::{DateTime, Utc};
@@ -31,11 +31,13 @@ use crate::schema::{Field, FLOAT_SCALING};
 use self::region::Region;
 
 mod adservers;
+pub mod document;
 mod html;
 mod just_text;
 pub mod region;
 pub mod safety_classifier;
 pub mod schema_org;
+pub mod url_canonicalizer;
 pub mod url_ext;
 pub use self::html::Html;
 
@@ -47,12 +49,18 @@ pub struct Webpage {
     pub host_centrality_rank: f64,
     pub page_centrality: f64,
     pub page_centrality_rank: f64,
+    pub host_pagerank: f64,
+    pub host_personalized_pagerank: f64,
+    pub page_pagerank: f64,
+    pub host_spam_score: f64,
+    pub host_community: Option<CommunityId>,
     pub fetch_time_ms: u64,
     pub pre_computed_score: f64,
     pub node_id: Option<NodeID>,
     pub dmoz_description: Option<String>,
     pub safety_classification: Option<safety_classifier::Label>,
     pub inserted_at: DateTime<Utc>,
+    pub dense_embedding: Option<Vec<f32>>,
 }
 
 #[cfg(test)]
@@ -65,12 +73,18 @@ impl Default for Webpage {
             host_centrality_rank: u64::MAX as f64,
             page_centrality: Default::default(),
             page_centrality_rank: u64::MAX as f64,
+            host_pagerank: Default::default(),
+            host_personalized_pagerank: Default::default(),
+            page_pagerank: Default::default(),
+            host_spam_score: Default::default(),
+            host_community: Default::default(),
             fetch_time_ms: Default::default(),
             pre_computed_score: Default::default(),
             node_id: Default::default(),
             dmoz_description: Default::default(),
             safety_classification: Default::default(),
             inserted_at: Utc::now(),
+            dense_embedding: Default::default(),
         }
     }
 }
@@ -188,6 +202,50 @@ impl Webpage {
             self.page_centrality_rank as u64,
         );
 
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::HostPageRank).name())
+                .expect("Failed to get host_pagerank field"),
+            (self.host_pagerank * FLOAT_SCALING as f64) as u64,
+        );
+
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::HostPersonalizedPageRank).name())
+                .expect("Failed to get host_personalized_pagerank field"),
+            (self.host_personalized_pagerank * FLOAT_SCALING as f64) as u64,
+        );
+
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::PagePageRank).name())
+                .expect("Failed to get page_pagerank field"),
+            (self.page_pagerank * FLOAT_SCALING as f64) as u64,
+        );
+
+        doc.add_u64(
+            schema
+                .get_field(Field::Fast(FastField::HostSpamScore).name())
+                .expect("Failed to get host_spam_score field"),
+            (self.host_spam_score * FLOAT_SCALING as f64) as u64,
+        );
+
+        doc.add_text(
+            schema
+                .get_field(Field::Text(TextField::HostSpamLabel).name())
+                .expect("Failed to get host_spam_label field"),
+            SpamLabel::from_score(self.host_spam_score).to_string(),
+        );
+
+        doc.add_text(
+            schema
+                .get_field(Field::Text(TextField::HostCommunity).name())
+                .expect("Failed to get host_community field"),
+            self.host_community
+                .map(|community| community.to_string())
+                .unwrap_or_default(),
+        );
+
         doc.add_u64(
             schema
                 .get_field(Field::Fast(FastField::FetchTimeMs).name())
@@ -242,6 +300,123 @@ pub struct Link {
     pub source: Url,
     pub destination: Url,
     pub text: String,
+    pub rel: RelFlags,
+    pub position: LinkPosition,
+}
+
+/// The `rel` values of a link that tell search engines not to (fully) endorse the destination.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct RelFlags {
+    pub nofollow: bool,
+    pub sponsored: bool,
+    pub ugc: bool,
+}
+
+impl RelFlags {
+    /// Parse the space separated values of a `rel` attribute.
+    pub fn parse(rel: &str) -> Self {
+        let mut flags = Self::default();
+
+        for value in rel.split_ascii_whitespace() {
+            match value.to_ascii_lowercase().as_str() {
+                "nofollow" => flags.nofollow = true,
+                "sponsored" => flags.sponsored = true,
+                "ugc" => flags.ugc = true,
+                _ => {}
+            }
+        }
+
+        flags
+    }
+
+    /// Whether any of the flags are set.
+    pub fn is_qualified(&self) -> bool {
+        self.nofollow || self.sponsored || self.ugc
+    }
+}
+
+impl std::fmt::Display for RelFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values: Vec<_> = [
+            (self.nofollow, "nofollow"),
+            (self.sponsored, "sponsored"),
+            (self.ugc, "ugc"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, value)| value)
+        .collect();
+
+        write!(f, "{}", values.join(" "))
+    }
+}
+
+/// The landmark region of the page a link is placed in.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkPosition {
+    /// `<nav>`, `<header>` or an element with a navigation role.
+    Nav,
+    #[default]
+    Body,
+    /// `<footer>` or an element with the `contentinfo` role.
+    Footer,
+}
+
+impl LinkPosition {
+    /// Navigation and footer links are mostly repeated on every page of a site.
+    pub fn is_boilerplate(&self) -> bool {
+        !matches!(self, LinkPosition::Body)
+    }
+}
+
+impl std::fmt::Display for LinkPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let position = match self {
+            LinkPosition::Nav => "nav",
+            LinkPosition::Body => "body",
+            LinkPosition::Footer => "footer",
+        };
+        write!(f, "{position}")
+    }
+}
+
+impl std::str::FromStr for LinkPosition {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nav" => Ok(LinkPosition::Nav),
+            "body" => Ok(LinkPosition::Body),
+            "footer" => Ok(LinkPosition::Footer),
+            _ => Err(crate::Error::ParsingError(format!(
+                "unknown link position: {s}"
+            ))),
+        }
+    }
 }
 
 pub type Meta = HashMap<String, String>;
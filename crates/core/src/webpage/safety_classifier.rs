@@ -19,6 +19,7 @@ use std::fs::OpenOptions;
 use std::path::Path;
 
 use itertools::Itertools;
+use serde::{de::DeserializeOwned, Serialize};
 
 use crate::naive_bayes;
 use crate::Result;
@@ -58,16 +59,17 @@ impl TryFrom<&str> for Label {
 impl naive_bayes::Label for Label {}
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
-pub struct Datapoint {
-    pub label: Label,
+pub struct Datapoint<L = Label> {
+    pub label: L,
     pub text: String,
 }
 
-pub fn load_dataset<P: AsRef<Path>>(path: P) -> Result<Vec<Datapoint>> {
+/// Load a csv file with a `label` and a `text` column.
+pub fn load_dataset<L: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<Vec<Datapoint<L>>> {
     let mut datapoints = Vec::new();
     let mut reader = csv::Reader::from_path(path)?;
     for result in reader.deserialize() {
-        let datapoint: Datapoint = result?;
+        let datapoint: Datapoint<L> = result?;
         datapoints.push(datapoint);
     }
     Ok(datapoints)
@@ -93,36 +95,80 @@ pub struct Evaluation {
     pub f1: f64,
 }
 
+/// Naive bayes text classifier. The safety classifier uses the [`Label`]s,
+/// but the model can be trained on any set of labels.
 #[derive(serde::Deserialize, serde::Serialize)]
-pub struct Model {
-    pipeline: naive_bayes::Pipeline<Label>,
+pub struct Model<L: naive_bayes::Label = Label> {
+    pipeline: naive_bayes::Pipeline<L>,
 }
 
-impl Default for Model {
+impl<L: naive_bayes::Label> Default for Model<L> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Model {
+impl<L: naive_bayes::Label> Model<L> {
     pub fn new() -> Self {
         let pipeline = naive_bayes::Pipeline::new();
         Self { pipeline }
     }
 
-    pub fn fit(&mut self, datapoints: &[Datapoint]) {
+    pub fn fit(&mut self, datapoints: &[Datapoint<L>]) {
         let datapoints: Vec<_> = datapoints
             .iter()
-            .map(|datapoint| (normalize(&datapoint.text), datapoint.label))
+            .map(|datapoint| (normalize(&datapoint.text), datapoint.label.clone()))
             .collect();
         self.pipeline.fit(&datapoints);
     }
 
-    pub fn predict_text(&self, text: &str) -> naive_bayes::Prediction<Label> {
+    pub fn predict_text(&self, text: &str) -> naive_bayes::Prediction<L> {
         let text = normalize(text);
         self.pipeline.predict(&text)
     }
 
+    /// Fraction of the datapoints that are classified correctly.
+    pub fn accuracy(&self, datapoints: &[Datapoint<L>]) -> f64 {
+        if datapoints.is_empty() {
+            return 0.0;
+        }
+
+        let correct = datapoints
+            .iter()
+            .filter(|datapoint| self.predict_text(&datapoint.text).label == datapoint.label)
+            .count();
+
+        correct as f64 / datapoints.len() as f64
+    }
+
+    pub fn save<P: AsRef<Path>>(self, path: P) -> Result<()>
+    where
+        Self: Serialize,
+    {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        bincode::serialize_into(file, &self)?;
+
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self>
+    where
+        Self: DeserializeOwned,
+    {
+        let file = OpenOptions::new().read(true).open(path)?;
+
+        let model = bincode::deserialize_from(file)?;
+
+        Ok(model)
+    }
+}
+
+impl Model {
     pub fn predict(&self, page: &crate::webpage::Webpage) -> naive_bayes::Prediction<Label> {
         let text = normalize(&page_text(page));
         self.predict_text(&text)
@@ -167,24 +213,4 @@ impl Model {
             f1,
         }
     }
-
-    pub fn save<P: AsRef<Path>>(self, path: P) -> Result<()> {
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)?;
-
-        bincode::serialize_into(file, &self)?;
-
-        Ok(())
-    }
-
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = OpenOptions::new().read(true).open(path)?;
-
-        let model = bincode::deserialize_from(file)?;
-
-        Ok(model)
-    }
 }
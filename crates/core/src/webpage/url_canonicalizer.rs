@@ -0,0 +1,294 @@
+// Stract is an open source web search engine.
+// Copyright (C) 2023 Stract ApS
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Canonicalisation of urls.
+//!
+//! The crawler, the webgraph and the indexer all use the same canonicalisation,
+//! so the same page gets the same id no matter where the url was found.
+//!
+//! There are two levels of canonicalisation:
+//! * [`canonicalize`] returns a url that can still be fetched. It removes the fragment,
+//!   tracking parameters and default ports.
+//! * [`canonical_key`] returns the identity of a url. In addition to the above, it ignores
+//!   the scheme, a leading `www.`, index files, trailing slashes and the order of the query parameters.
+//!   Two urls with the same key are considered the same page.
+use std::collections::{HashMap, HashSet};
+
+use url::Url;
+
+/// Query parameters that are removed from all urls.
+const DENIED_PARAMS: &[&str] = &[
+    "fbclid",
+    "gclid",
+    "dclid",
+    "msclkid",
+    "yclid",
+    "igshid",
+    "mc_cid",
+    "mc_eid",
+    "_ga",
+    "_gl",
+    "_hsenc",
+    "_hsmi",
+    "ref_src",
+    "phpsessid",
+    "jsessionid",
+    "sessionid",
+];
+
+/// Query parameters starting with one of these prefixes are removed from all urls.
+const DENIED_PARAM_PREFIXES: &[&str] = &["utm_", "pk_", "mtm_"];
+
+/// Hosts where only the listed query parameters are meaningful.
+const ALLOWED_PARAMS: &[(&str, &[&str])] = &[
+    ("youtube.com", &["v", "list"]),
+    ("news.ycombinator.com", &["id", "p"]),
+];
+
+/// Files that are served for the directory they are in.
+const INDEX_FILES: &[&str] = &["index.html", "index.htm", "index.php"];
+
+static CANONICALIZER: once_cell::sync::Lazy<UrlCanonicalizer> =
+    once_cell::sync::Lazy::new(UrlCanonicalizer::default);
+
+/// Canonicalize the url with the default canonicalizer. See [`UrlCanonicalizer::canonicalize`].
+pub fn canonicalize(url: &Url) -> Url {
+    CANONICALIZER.canonicalize(url)
+}
+
+/// The canonical key of the url with the default canonicalizer. See [`UrlCanonicalizer::key`].
+pub fn canonical_key(url: &Url) -> String {
+    CANONICALIZER.key(url)
+}
+
+pub struct UrlCanonicalizer {
+    denied_params: HashSet<String>,
+    denied_prefixes: Vec<String>,
+    /// host (without `www.`) -> the only parameters that are kept for the host.
+    allowed_params: HashMap<String, HashSet<String>>,
+}
+
+impl Default for UrlCanonicalizer {
+    fn default() -> Self {
+        Self {
+            denied_params: DENIED_PARAMS.iter().map(|p| p.to_string()).collect(),
+            denied_prefixes: DENIED_PARAM_PREFIXES
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            allowed_params: ALLOWED_PARAMS
+                .iter()
+                .map(|(host, params)| {
+                    (
+                        host.to_string(),
+                        params.iter().map(|p| p.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl UrlCanonicalizer {
+    /// Remove the parameter from all urls.
+    pub fn with_denied_param(mut self, param: &str) -> Self {
+        self.denied_params.insert(param.to_ascii_lowercase());
+        self
+    }
+
+    /// Only keep the listed parameters for urls on the host.
+    pub fn with_allowed_params(mut self, host: &str, params: &[&str]) -> Self {
+        self.allowed_params.insert(
+            host.trim_start_matches("www.").to_string(),
+            params.iter().map(|p| p.to_string()).collect(),
+        );
+        self
+    }
+
+    fn is_allowed(&self, allowed: Option<&HashSet<String>>, param: &str) -> bool {
+        if let Some(allowed) = allowed {
+            return allowed.contains(param);
+        }
+
+        let param = param.to_ascii_lowercase();
+
+        !self.denied_params.contains(&param)
+            && !self
+                .denied_prefixes
+                .iter()
+                .any(|prefix| param.starts_with(prefix.as_str()))
+    }
+
+    /// Remove the fragment and tracking parameters from the url. Default ports are
+    /// already removed when the url is parsed. The path is left untouched, so the
+    /// canonical url can still be fetched and relative links can be resolved against it.
+    pub fn canonicalize(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        url.set_fragment(None);
+
+        if url.query().is_some() {
+            let allowed = url
+                .host_str()
+                .and_then(|host| self.allowed_params.get(host.trim_start_matches("www.")));
+
+            let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+            let num_pairs = pairs.len();
+
+            let kept: Vec<_> = pairs
+                .into_iter()
+                .filter(|(key, _)| self.is_allowed(allowed, key))
+                .collect();
+
+            if kept.is_empty() {
+                url.set_query(None);
+            } else if kept.len() < num_pairs {
+                url.query_pairs_mut().clear().extend_pairs(kept);
+            }
+        }
+
+        url
+    }
+
+    /// The identity of the url. Urls with the same key are considered the same page.
+    pub fn key(&self, url: &Url) -> String {
+        let mut url = self.canonicalize(url);
+
+        let index_file = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|last| INDEX_FILES.contains(last))
+            .map(|last| last.len());
+
+        if let Some(len) = index_file {
+            let path = url.path().to_string();
+            url.set_path(&path[..path.len() - len]);
+        }
+
+        let path = url.path().to_string();
+        if path.len() > 1 && path.ends_with('/') {
+            url.set_path(path.trim_end_matches('/'));
+        }
+
+        if url.query().is_some() {
+            let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+            pairs.sort();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+
+        let scheme = url.scheme();
+        let mut key = url
+            .as_str()
+            .strip_prefix(scheme)
+            .unwrap_or_default()
+            .strip_prefix("://")
+            .unwrap_or_default()
+            .to_string();
+
+        if let Some(stripped) = key.strip_prefix("www.") {
+            key = stripped.to_string();
+        }
+
+        if let Some(stripped) = key.strip_suffix('/') {
+            key = stripped.to_string();
+        }
+
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canon(url: &str) -> String {
+        canonicalize(&Url::parse(url).unwrap()).to_string()
+    }
+
+    fn key(url: &str) -> String {
+        canonical_key(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn tracking_params() {
+        assert_eq!(
+            canon("https://example.com/a?utm_source=x&id=2&fbclid=abc#comments"),
+            "https://example.com/a?id=2"
+        );
+        assert_eq!(
+            canon("https://example.com/a?UTM_Medium=x&PHPSESSID=1"),
+            "https://example.com/a"
+        );
+
+        // untouched queries keep their encoding
+        assert_eq!(
+            canon("https://example.com/a?q=hello%20world"),
+            "https://example.com/a?q=hello%20world"
+        );
+    }
+
+    #[test]
+    fn allowed_params() {
+        assert_eq!(
+            canon("https://www.youtube.com/watch?v=abc&feature=share&t=10"),
+            "https://www.youtube.com/watch?v=abc"
+        );
+
+        let canonicalizer = UrlCanonicalizer::default()
+            .with_allowed_params("www.example.com", &["page"])
+            .with_denied_param("ref");
+
+        assert_eq!(
+            canonicalizer
+                .canonicalize(&Url::parse("https://example.com/?page=2&sort=asc").unwrap())
+                .as_str(),
+            "https://example.com/?page=2"
+        );
+        assert_eq!(
+            canonicalizer
+                .canonicalize(&Url::parse("https://other.com/?ref=a&b=c").unwrap())
+                .as_str(),
+            "https://other.com/?b=c"
+        );
+    }
+
+    #[test]
+    fn default_port() {
+        assert_eq!(canon("https://example.com:443/a"), "https://example.com/a");
+        assert_eq!(canon("http://example.com:80/a"), "http://example.com/a");
+        assert_eq!(
+            canon("https://example.com:8080/a"),
+            "https://example.com:8080/a"
+        );
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(key("https://www.example.com/"), "example.com");
+        assert_eq!(key("http://example.com"), "example.com");
+        assert_eq!(key("https://example.com/a/"), "example.com/a");
+        assert_eq!(key("https://example.com/a/index.html"), "example.com/a");
+        assert_eq!(key("https://example.com/index.php"), "example.com");
+        assert_eq!(
+            key("https://example.com/a?b=2&a=1&utm_source=x#top"),
+            "example.com/a?a=1&b=2"
+        );
+        assert_eq!(
+            key("https://example.com:443/a"),
+            key("http://www.example.com/a/")
+        );
+        assert_ne!(key("https://example.com/a"), key("https://example.com/b"));
+    }
+}
@@ -2,6 +2,9 @@ pub fn token_docs(token: &optics::Token) -> Option<&'static str> {
     Some(match token {
         optics::Token::DiscardNonMatching => "All results that does not match any of the rules in the optic will be discarded.",
 
+        optics::Token::Diversity => "`Diversity(...)` diversifies the top results using maximal marginal relevance. The number in `...` is a lambda between 0 and 1 \
+        where 1 ranks purely by relevance and lower values increasingly penalise results that are similar to results ranked above them.",
+
         optics::Token::Rule => "A rule specifies how a particular search result should be treated. \
         It consists of a `Matches` block and an optional `Action`. Any search result that matches the `Matches` block \
         will have the `Action` applied to it. The action can either `Boost`, `Downrank` or `Discard` a result. An empty `Action` is \
@@ -64,6 +67,14 @@ pub fn token_docs(token: &optics::Token) -> Option<&'static str> {
         As an example, `Schema(\"BlogPosting\")` matches all pages that contains the https://schema.org/BlogPosting entity. Note that `Schema` \
         does not support the pattern syntax, but only simple strings.",
 
+        optics::Token::Spam => "`Spam(\"...\")` matches any search result from a host with the spam label defined in `\"...\"`. \
+        The label is one of `spam`, `suspicious` or `clean` and is derived from the link spam analysis of the host graph. \
+        As an example, `Rule { Matches { Spam(\"spam\") }, Action(Discard) }` removes all results from link farms.",
+
+        optics::Token::Community => "`Community(\"...\")` matches any search result from a host in the community with the id defined in `\"...\"`. \
+        The communities are groups of hosts that link densely to each other in the host graph, and the id of a host's community can be found \
+        through the webgraph API. As an example, `Rule { Matches { Community(\"1234\") }, Action(Boost(2)) }` boosts all results from the community.",
+
         optics::Token::Ranking => "When results are ranked we take a weighted sum of various signals to give each webpage a score for the specific query. \
         The top scored results are then presented to the user. `Ranking` allows you to alter the weight of all the `Signal`s and text `Field`s.",
 
@@ -59,6 +59,7 @@ pub struct RawOptic {
     pub rankings: Vec<RankingCoeff>,
     pub host_preferences: Vec<RawHostPreference>,
     pub discard_non_matching: bool,
+    pub diversity: Option<f64>,
 }
 
 impl From<Vec<RawOpticBlock>> for RawOptic {
@@ -67,6 +68,7 @@ impl From<Vec<RawOpticBlock>> for RawOptic {
         let mut rankings = Vec::new();
         let mut host_preferences = Vec::new();
         let mut discard_non_matching = false;
+        let mut diversity = None;
 
         for block in blocks {
             match block {
@@ -74,6 +76,7 @@ impl From<Vec<RawOpticBlock>> for RawOptic {
                 RawOpticBlock::Rule(rule) => rules.push(rule),
                 RawOpticBlock::HostPreference(pref) => host_preferences.push(pref),
                 RawOpticBlock::DiscardNonMatching => discard_non_matching = true,
+                RawOpticBlock::Diversity(lambda) => diversity = Some(lambda),
             }
         }
 
@@ -82,6 +85,7 @@ impl From<Vec<RawOpticBlock>> for RawOptic {
             rules,
             host_preferences,
             discard_non_matching,
+            diversity,
         }
     }
 }
@@ -92,6 +96,7 @@ pub enum RawOpticBlock {
     HostPreference(RawHostPreference),
     Ranking(RankingCoeff),
     DiscardNonMatching,
+    Diversity(f64),
 }
 
 #[derive(Debug, PartialEq)]
@@ -119,6 +124,8 @@ pub enum RawMatchPart {
     Content(String),
     MicroformatTag(String),
     Schema(String),
+    Spam(String),
+    Community(String),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -216,6 +223,7 @@ mod tests {
                 ],
                 host_preferences: vec![],
                 discard_non_matching: false,
+                diversity: None,
             }
         );
     }
@@ -260,6 +268,7 @@ mod tests {
                 rankings: vec![],
                 host_preferences: vec![],
                 discard_non_matching: false,
+                diversity: None,
             }
         );
     }
@@ -305,10 +314,27 @@ mod tests {
                 rankings: vec![],
                 host_preferences: vec![],
                 discard_non_matching: true,
+                diversity: None,
             }
         );
     }
 
+    #[test]
+    fn diversity() {
+        let optic = parse(
+            r#"
+            Diversity(0.7);
+            Ranking(Signal("bm25"), 100);
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(optic.diversity, Some(0.7));
+        assert_eq!(optic.rankings.len(), 1);
+
+        assert!(parse("Diversity(\"high\");").is_err());
+    }
+
     #[test]
     fn quickstart_parse() {
         assert!(parse(include_str!("../testcases/samples/quickstart.optic")).is_ok());
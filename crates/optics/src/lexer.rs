@@ -30,6 +30,7 @@ pub enum Token<'a> {
     CloseParenthesis,
 
     DiscardNonMatching,
+    Diversity,
     Rule,
     RankingPipeline,
     Ranking,
@@ -45,6 +46,8 @@ pub enum Token<'a> {
     Content,
     MicroformatTag,
     Schema,
+    Spam,
+    Community,
     Action,
     Boost,
     Downrank,
@@ -66,6 +69,7 @@ impl<'a> Display for Token<'a> {
             Token::OpenParenthesis => f.write_str("("),
             Token::CloseParenthesis => f.write_str(")"),
             Token::DiscardNonMatching => f.write_str("DiscardNonMatching"),
+            Token::Diversity => f.write_str("Diversity"),
             Token::Rule => f.write_str("Rule"),
             Token::RankingPipeline => f.write_str("RankingPipeline"),
             Token::Ranking => f.write_str("Ranking"),
@@ -81,6 +85,8 @@ impl<'a> Display for Token<'a> {
             Token::Content => f.write_str("Content"),
             Token::MicroformatTag => f.write_str("MicroformatTag"),
             Token::Schema => f.write_str("Schema"),
+            Token::Spam => f.write_str("Spam"),
+            Token::Community => f.write_str("Community"),
             Token::Action => f.write_str("Action"),
             Token::Boost => f.write_str("Boost"),
             Token::Downrank => f.write_str("Downrank"),
@@ -120,6 +126,8 @@ enum Outer<'a> {
 
     #[token("DiscardNonMatching")]
     DiscardNonMatching,
+    #[token("Diversity")]
+    Diversity,
     #[token("Rule")]
     Rule,
     #[token("RankingPipeline")]
@@ -150,6 +158,10 @@ enum Outer<'a> {
     MicroformatTag,
     #[token("Schema")]
     Schema,
+    #[token("Spam")]
+    Spam,
+    #[token("Community")]
+    Community,
     #[token("Action")]
     Action,
     #[token("Boost")]
@@ -318,6 +330,8 @@ impl<'source> Iterator for LexerBridge<'source> {
                 Outer::Content => Some(Ok((s.start, Token::Content, s.end))),
                 Outer::MicroformatTag => Some(Ok((s.start, Token::MicroformatTag, s.end))),
                 Outer::Schema => Some(Ok((s.start, Token::Schema, s.end))),
+                Outer::Spam => Some(Ok((s.start, Token::Spam, s.end))),
+                Outer::Community => Some(Ok((s.start, Token::Community, s.end))),
                 Outer::Action => Some(Ok((s.start, Token::Action, s.end))),
                 Outer::Boost => Some(Ok((s.start, Token::Boost, s.end))),
                 Outer::Downrank => Some(Ok((s.start, Token::Downrank, s.end))),
@@ -326,6 +340,7 @@ impl<'source> Iterator for LexerBridge<'source> {
                 Outer::Dislike => Some(Ok((s.start, Token::Dislike, s.end))),
                 Outer::Number(n) => Some(Ok((s.start, Token::Number(n), s.end))),
                 Outer::DiscardNonMatching => Some(Ok((s.start, Token::DiscardNonMatching, s.end))),
+                Outer::Diversity => Some(Ok((s.start, Token::Diversity, s.end))),
             }
         } else {
             None
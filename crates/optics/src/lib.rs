@@ -105,6 +105,7 @@ impl TryFrom<RawOptic> for Optic {
             rules,
             rankings: raw.rankings,
             discard_non_matching: raw.discard_non_matching,
+            diversity: raw.diversity,
             host_rankings: HostRankings {
                 liked: liked_hosts,
                 disliked: disliked_hosts,
@@ -163,6 +164,8 @@ impl Display for Matching {
             MatchLocation::Content => "Content",
             MatchLocation::MicroformatTag => "MicroformatTag",
             MatchLocation::Schema => "Schema",
+            MatchLocation::Spam => "Spam",
+            MatchLocation::Community => "Community",
         };
         write!(f, "{}(\"", s)?;
 
@@ -187,11 +190,16 @@ impl TryFrom<RawMatchPart> for Matching {
             RawMatchPart::Content(s) => (s, MatchLocation::Content),
             RawMatchPart::MicroformatTag(s) => (s, MatchLocation::MicroformatTag),
             RawMatchPart::Schema(s) => (s, MatchLocation::Schema),
+            RawMatchPart::Spam(s) => (s, MatchLocation::Spam),
+            RawMatchPart::Community(s) => (s, MatchLocation::Community),
         };
 
         let mut pattern = Vec::new();
 
-        if matches!(&loc, MatchLocation::Schema) {
+        if matches!(
+            &loc,
+            MatchLocation::Schema | MatchLocation::Spam | MatchLocation::Community
+        ) {
             for tok in PatternToken::lex(&s) {
                 match tok {
                     PatternToken::Raw(s) => pattern.push(PatternPart::Raw(s)),
@@ -296,6 +304,10 @@ pub enum MatchLocation {
     Content,
     MicroformatTag,
     Schema,
+    /// The spam label of the host (`spam`, `suspicious` or `clean`).
+    Spam,
+    /// The id of the community of the host.
+    Community,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
@@ -325,6 +337,10 @@ pub struct Optic {
     pub host_rankings: HostRankings,
     pub rules: Vec<Rule>,
     pub discard_non_matching: bool,
+    /// The lambda used to diversify the top results with maximal marginal relevance.
+    /// A lambda of 1.0 ranks purely by relevance while lower values favour diversity.
+    #[serde(default)]
+    pub diversity: Option<f64>,
 }
 
 impl Optic {
@@ -339,6 +355,10 @@ impl Display for Optic {
             writeln!(f, "DiscardNonMatching;")?;
         }
 
+        if let Some(lambda) = self.diversity {
+            writeln!(f, "Diversity({});", lambda)?;
+        }
+
         for rule in &self.rules {
             write!(f, "{}", rule)?;
         }
@@ -558,6 +578,7 @@ mod tests {
                 action: Action::Boost(0),
             }],
             discard_non_matching: true,
+            diversity: Some(0.5),
         };
 
         let exported = optic.to_string();
@@ -129,15 +129,32 @@ export const api = {
     },
     options?: ApiOptions,
   ) => sse<string>('GET', `/beta/api/summarize?${new URLSearchParams(query)}`, options),
+  webgraphHostCommunity: (
+    query: {
+      host: string;
+    },
+    options?: ApiOptions,
+  ) =>
+    requestJson<Community | null>(
+      'POST',
+      `/beta/api/webgraph/host/community?${new URLSearchParams(query)}`,
+      options,
+    ),
   webgraphHostIngoing: (
     query: {
+      cursor?: string;
+      excludeSameDomain?: boolean;
       host: string;
+      limit?: number;
+      text?: string;
     },
     options?: ApiOptions,
   ) =>
-    requestJson<FullEdge[]>(
+    requestJson<LinksPage>(
       'POST',
-      `/beta/api/webgraph/host/ingoing?${new URLSearchParams(query)}`,
+      `/beta/api/webgraph/host/ingoing?${new URLSearchParams(
+        query as Record<string, string>,
+      )}`,
       options,
     ),
   webgraphHostKnows: (
@@ -151,45 +168,90 @@ export const api = {
       `/beta/api/webgraph/host/knows?${new URLSearchParams(query)}`,
       options,
     ),
+  webgraphHostNeighbourhood: (
+    query: {
+      host: string;
+      maxDistance?: number;
+    },
+    options?: ApiOptions,
+  ) =>
+    requestJson<Neighbourhood>(
+      'POST',
+      `/beta/api/webgraph/host/neighbourhood?${new URLSearchParams(
+        query as Record<string, string>,
+      )}`,
+      options,
+    ),
   webgraphHostOutgoing: (
     query: {
+      cursor?: string;
+      excludeSameDomain?: boolean;
       host: string;
+      limit?: number;
+      text?: string;
     },
     options?: ApiOptions,
   ) =>
-    requestJson<FullEdge[]>(
+    requestJson<LinksPage>(
       'POST',
-      `/beta/api/webgraph/host/outgoing?${new URLSearchParams(query)}`,
+      `/beta/api/webgraph/host/outgoing?${new URLSearchParams(
+        query as Record<string, string>,
+      )}`,
+      options,
+    ),
+  webgraphHostPath: (
+    query: {
+      from: string;
+      to: string;
+    },
+    options?: ApiOptions,
+  ) =>
+    requestJson<Node[]>(
+      'POST',
+      `/beta/api/webgraph/host/path?${new URLSearchParams(query)}`,
       options,
     ),
   webgraphHostSimilar: (body: SimilarHostsParams, options?: ApiOptions) =>
     requestJson<ScoredHost[]>('POST', `/beta/api/webgraph/host/similar`, body, options),
   webgraphPageIngoing: (
     query: {
+      cursor?: string;
+      excludeSameDomain?: boolean;
       page: string;
+      limit?: number;
+      text?: string;
     },
     options?: ApiOptions,
   ) =>
-    requestJson<FullEdge[]>(
+    requestJson<LinksPage>(
       'POST',
-      `/beta/api/webgraph/page/ingoing?${new URLSearchParams(query)}`,
+      `/beta/api/webgraph/page/ingoing?${new URLSearchParams(
+        query as Record<string, string>,
+      )}`,
       options,
     ),
   webgraphPageOutgoing: (
     query: {
+      cursor?: string;
+      excludeSameDomain?: boolean;
       page: string;
+      limit?: number;
+      text?: string;
     },
     options?: ApiOptions,
   ) =>
-    requestJson<FullEdge[]>(
+    requestJson<LinksPage>(
       'POST',
-      `/beta/api/webgraph/page/outgoing?${new URLSearchParams(query)}`,
+      `/beta/api/webgraph/page/outgoing?${new URLSearchParams(
+        query as Record<string, string>,
+      )}`,
       options,
     ),
 };
 
 export type ApiSearchQuery = {
   countResults?: boolean;
+  diversity?: number;
   flattenResponse?: boolean;
   hostRankings?: HostRankings;
   numResults?: number;
@@ -199,6 +261,7 @@ export type ApiSearchQuery = {
   returnRankingSignals?: boolean;
   safeSearch?: boolean;
   selectedRegion?: Region;
+  signalCoefficientProfile?: string;
 };
 export type ApiSearchResult =
   | (WebsitesResult & {
@@ -233,6 +296,12 @@ export type CodeOrText =
       type: 'text';
       value: string;
     };
+export type Community = {
+  hosts: string[];
+  id: string;
+  name: string;
+  size: number;
+};
 export type Definition = string;
 export type DisplayedAnswer = {
   answer: string;
@@ -274,6 +343,11 @@ export type DisplayedWebpage = {
   title: string;
   url: string;
 };
+export type EdgeLabel = {
+  position: LinkPosition;
+  rel: RelFlags;
+  text: string;
+};
 export type EntitySnippet = {
   fragments: EntitySnippetFragment[];
 };
@@ -294,7 +368,7 @@ export type ExploreExportOpticParams = {
 };
 export type FullEdge = {
   from: Node;
-  label: string;
+  label: EdgeLabel;
   to: Node;
 };
 export type HighlightedSpellCorrection = {
@@ -318,6 +392,20 @@ export type KnowsHost =
       type: 'unknown';
     };
 export type Lemma = string;
+export type LinkPosition = 'nav' | 'body' | 'footer';
+export const LINK_POSITIONS = ['nav', 'body', 'footer'] satisfies LinkPosition[];
+export type LinksPage = {
+  links: FullEdge[];
+  nextCursor?: string;
+};
+export type Neighbourhood = {
+  edges: FullEdge[];
+  nodes: NeighbourhoodNode[];
+};
+export type NeighbourhoodNode = {
+  distance: number;
+  node: Node;
+};
 export type Node = {
   name: string;
 };
@@ -335,6 +423,11 @@ export type PartOfSpeechMeaning = {
 };
 export type Region = 'All' | 'Denmark' | 'France' | 'Germany' | 'Spain' | 'US';
 export const REGIONS = ['All', 'Denmark', 'France', 'Germany', 'Spain', 'US'] satisfies Region[];
+export type RelFlags = {
+  nofollow: boolean;
+  sponsored: boolean;
+  ugc: boolean;
+};
 export type RichSnippet = {
   answers: StackOverflowAnswer[];
   question: StackOverflowQuestion;